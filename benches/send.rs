@@ -0,0 +1,129 @@
+//! Counts heap allocations made per request by `Mpesa::send`, failing if they exceed
+//! `ALLOCATION_BUDGET`.
+//!
+//! Run with `cargo bench --bench send`. The numbers include the allocations made by
+//! reqwest/hyper and the mock server, so compare them between revisions rather than
+//! reading them as absolute values.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use mpesa::{ApiEnvironment, CommandId, Mpesa};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: usize = 500;
+/// Allocations per request when urls were joined and bodies serialized to a `serde_json::Value`
+/// then to a fresh buffer for every request
+const BASELINE_ALLOCATIONS: usize = 224;
+/// Allocations per request allowed now that urls are joined when the client is built and bodies
+/// are serialized once into a reused buffer
+const ALLOCATION_BUDGET: usize = 200;
+
+#[derive(Clone)]
+struct BenchEnvironment {
+    server_url: String,
+}
+
+impl ApiEnvironment for BenchEnvironment {
+    fn base_url(&self) -> &str {
+        &self.server_url
+    }
+
+    fn get_certificate(&self) -> &str {
+        include_str!("../src/certificates/sandbox")
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .mount(&server)
+        .await;
+
+    let client = Mpesa::new(
+        "consumer_key",
+        "consumer_secret",
+        BenchEnvironment {
+            server_url: server.uri(),
+        },
+    );
+
+    let send = || async {
+        client
+            .express_request()
+            .business_short_code("174379")
+            .transaction_type(CommandId::BusinessBuyGoods)
+            .party_a("254708374149")
+            .party_b("174379")
+            .account_ref("test")
+            .phone_number("254708374149")
+            .amount(500)
+            .try_callback_url("https://test.example.com/api")
+            .unwrap()
+            .build()
+            .unwrap()
+            .send()
+            .await
+            .unwrap()
+    };
+
+    // Warm up the token cache and the connection pool
+    send().await;
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        send().await;
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let per_request = allocations / ITERATIONS;
+
+    println!(
+        "express_request send: {per_request} allocations/request (baseline {BASELINE_ALLOCATIONS}), {:?}/request",
+        elapsed / ITERATIONS as u32
+    );
+    assert!(
+        per_request <= ALLOCATION_BUDGET,
+        "{per_request} allocations/request exceed the budget of {ALLOCATION_BUDGET}"
+    );
+}
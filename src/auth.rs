@@ -1,41 +1,72 @@
+use std::time::{Duration, Instant, SystemTime};
+
 use cached::proc_macro::cached;
 use serde::{Deserialize, Serialize};
 use serde_aux::field_attributes::deserialize_number_from_string;
 
+use crate::constants::REDACTED;
+use crate::credentials::Credentials;
+use crate::paths;
 use crate::{Mpesa, MpesaError, MpesaResult, ResponseError};
 
-const AUTHENTICATION_URL: &str = "/oauth/v1/generate?grant_type=client_credentials";
-
 #[cached(
-    size = 1,
+    size = 64,
     time = 3600,
-    key = "String",
+    key = "u64",
     result = true,
-    convert = r#"{ format!("{}", client.consumer_key()) }"#
+    convert = r#"{ credentials.cache_key() }"#
 )]
-pub(crate) async fn auth(client: &Mpesa) -> MpesaResult<String> {
-    let url = format!("{}{}", client.base_url, AUTHENTICATION_URL);
-
+pub(crate) async fn auth(client: &Mpesa, credentials: &Credentials) -> MpesaResult<AccessToken> {
+    let fetched_at = Instant::now();
     let response = client
-        .http_client
-        .get(&url)
-        .basic_auth(client.consumer_key(), Some(&client.consumer_secret()))
-        .send()
+        .send_with_retries(client.retry_policies.auth, paths::AUTH, |url| {
+            client
+                .http_client
+                .get(url)
+                .query(&[("grant_type", "client_credentials")])
+                .basic_auth(
+                    credentials.consumer_key(),
+                    Some(credentials.consumer_secret()),
+                )
+                .send()
+        })
         .await?;
 
     if response.status().is_success() {
         let value = response.json::<AuthenticationResponse>().await?;
-        let access_token = value.access_token;
 
-        return Ok(access_token);
+        return Ok(AccessToken {
+            token: value.access_token,
+            fetched_at,
+            lifetime: Duration::from_secs(value.expires_in),
+        });
     }
 
     let error = response.json::<ResponseError>().await?;
     Err(MpesaError::Service(error))
 }
 
+/// Access token cached for a set of credentials, with the lifetime Safaricom issued it with
+#[derive(Clone)]
+pub(crate) struct AccessToken {
+    pub(crate) token: String,
+    /// When the token was requested, so that its expiry errs on the early side
+    fetched_at: Instant,
+    /// The `expires_in` of the `AuthenticationResponse`
+    lifetime: Duration,
+}
+
+impl AccessToken {
+    /// Time left before the token expires, `None` if it has expired
+    pub(crate) fn expires_in(&self) -> Option<Duration> {
+        self.lifetime
+            .checked_sub(self.fetched_at.elapsed())
+            .filter(|expires_in| !expires_in.is_zero())
+    }
+}
+
 /// Response returned from the authentication function
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct AuthenticationResponse {
     /// Access token which is used as the Bearer-Auth-Token
     pub access_token: String,
@@ -44,13 +75,35 @@ pub struct AuthenticationResponse {
     pub expires_in: u64,
 }
 
+impl std::fmt::Debug for AuthenticationResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthenticationResponse")
+            .field("access_token", &REDACTED)
+            .field("expires_in", &self.expires_in)
+            .finish()
+    }
+}
+
 impl std::fmt::Display for AuthenticationResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "token :{} expires in: {}",
-            self.access_token, self.expires_in
-        )
+        write!(f, "token :{} expires in: {}", REDACTED, self.expires_in)
+    }
+}
+
+/// Information about the access token cached for a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TokenInfo {
+    /// Time left before the cached token expires, `None` if no token is cached
+    pub expires_in: Option<Duration>,
+    /// Point in time at which the cached token expires, `None` if no token is cached
+    pub expires_at: Option<SystemTime>,
+}
+
+impl TokenInfo {
+    /// Returns `true` if a token is cached and has not expired yet
+    pub fn is_cached(&self) -> bool {
+        self.expires_in.is_some()
     }
 }
 
@@ -107,14 +160,13 @@ mod tests {
             .mount(&server)
             .await;
 
-        auth_prime_cache(&client).await.unwrap();
+        let credentials = Credentials::new("test_api_key".to_owned(), "test_public_key".to_owned());
+        auth_prime_cache(&client, &credentials).await.unwrap();
 
         let mut cache = AUTH.lock().await;
 
-        assert!(cache
-            .cache_get(&client.consumer_key().to_string())
-            .is_some());
+        assert!(cache.cache_get(&credentials.cache_key()).is_some());
         assert_eq!(cache.cache_hits().unwrap(), 1);
-        assert_eq!(cache.cache_capacity().unwrap(), 1);
+        assert_eq!(cache.cache_capacity().unwrap(), 64);
     }
 }
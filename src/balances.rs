@@ -0,0 +1,393 @@
+//! Balances of several shortcodes, polled with the Account Balance API
+//!
+//! The Account Balance API answers asynchronously: the request is accepted with a
+//! `ConversationID` and the balances are posted later to its `ResultURL`. [`BalancePoller`]
+//! sends an account balance request for each of its (initiator, shortcode) pairs on a schedule,
+//! matches the results it is handed to the shortcode they were requested for, and keeps the
+//! latest balances of each shortcode for [`BalancePoller::snapshot`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! use mpesa::balances::BalancePoller;
+//!
+//! let poller = Arc::new(
+//!     BalancePoller::new(client)
+//!         .shortcode("testapi496", "600496")
+//!         .shortcode("testapi497", "600497")
+//!         .result_url("https://example.com/balances/result")
+//!         .timeout_url("https://example.com/balances/timeout"),
+//! );
+//! tokio::spawn({
+//!     let poller = Arc::clone(&poller);
+//!     async move { poller.run(Duration::from_secs(15 * 60)).await }
+//! });
+//!
+//! // in the handler of the `ResultURL`
+//! poller.on_result(&result)?;
+//!
+//! // in a dashboard
+//! let snapshot = poller.snapshot();
+//! println!("{} KES available", snapshot.total_available("Utility Account", "KES"));
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::time::MissedTickBehavior;
+
+use crate::callbacks::{value_to_string, ResultCallback};
+use crate::services::{AccountBalanceResponse, CallbackUrls};
+use crate::{Mpesa, MpesaError, MpesaResult};
+
+/// Balance of one of the accounts of a shortcode, such as its `Utility Account`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AccountBalance {
+    /// e.g. `Working Account`, `Utility Account` or `Charges Paid Account`
+    pub account: String,
+    pub currency: String,
+    pub current: f64,
+    pub available: f64,
+    pub reserved: f64,
+    pub uncleared: f64,
+}
+
+impl AccountBalance {
+    /// Parses the `AccountBalance` parameter of the result of an account balance request, e.g.
+    /// `Working Account|KES|46713.00|46713.00|0.00|0.00&Utility Account|KES|49217.00|49217.00|0.00|0.00`
+    ///
+    /// # Errors
+    /// Returns `MpesaError::Message` if an account is not made of a name, a currency and four
+    /// amounts
+    pub fn parse_all(balances: &str) -> MpesaResult<Vec<Self>> {
+        balances
+            .split('&')
+            .filter(|account| !account.trim().is_empty())
+            .map(|account| {
+                let fields: Vec<_> = account.split('|').map(str::trim).collect();
+                let [name, currency, current, available, reserved, uncleared] = fields[..] else {
+                    return Err(MpesaError::Message("Invalid account balance"));
+                };
+                let amount = |amount: &str| {
+                    amount
+                        .parse()
+                        .map_err(|_| MpesaError::Message("Invalid account balance amount"))
+                };
+                Ok(AccountBalance {
+                    account: name.to_owned(),
+                    currency: currency.to_owned(),
+                    current: amount(current)?,
+                    available: amount(available)?,
+                    reserved: amount(reserved)?,
+                    uncleared: amount(uncleared)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The latest balances of a shortcode
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct ShortcodeBalance {
+    pub shortcode: String,
+    pub initiator_name: String,
+    pub accounts: Vec<AccountBalance>,
+    /// Time the balances were read in the format `YYYYMMDDHHmmss`, from `BOCompletedTime`
+    pub completed_time: Option<String>,
+    /// `ConversationID` of the request the balances are the result of
+    pub conversation_id: String,
+    /// Unix timestamp in seconds of the request
+    pub requested_at: i64,
+    /// Unix timestamp in seconds of the result
+    pub received_at: i64,
+}
+
+impl ShortcodeBalance {
+    /// Returns the balance of the account named `account`, e.g. `Utility Account`
+    pub fn account(&self, account: &str) -> Option<&AccountBalance> {
+        self.accounts
+            .iter()
+            .find(|balance| balance.account == account)
+    }
+}
+
+/// The latest balances of the shortcodes of a `BalancePoller`, as returned by `snapshot`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceSnapshot {
+    balances: BTreeMap<String, ShortcodeBalance>,
+}
+
+impl BalanceSnapshot {
+    /// Returns the balances of `shortcode`, `None` if no result was received for it yet
+    pub fn get(&self, shortcode: &str) -> Option<&ShortcodeBalance> {
+        self.balances.get(shortcode)
+    }
+
+    /// Returns the balances of every shortcode a result was received for, by shortcode
+    pub fn iter(&self) -> impl Iterator<Item = &ShortcodeBalance> {
+        self.balances.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.balances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.balances.is_empty()
+    }
+
+    /// Sums the available balance of the accounts named `account` in `currency` across
+    /// shortcodes
+    pub fn total_available(&self, account: &str, currency: &str) -> f64 {
+        self.iter()
+            .filter_map(|balance| balance.account(account))
+            .filter(|balance| balance.currency == currency)
+            .map(|balance| balance.available)
+            .sum()
+    }
+
+    /// Returns the balances received before the unix timestamp `before`, in seconds
+    pub fn stale(&self, before: i64) -> impl Iterator<Item = &ShortcodeBalance> {
+        self.iter()
+            .filter(move |balance| balance.received_at < before)
+    }
+}
+
+/// Outcome of the account balance request of a shortcode, as returned by `BalancePoller::poll`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct BalanceRequest {
+    pub initiator_name: String,
+    pub shortcode: String,
+    pub result: MpesaResult<AccountBalanceResponse>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    initiator_name: String,
+    shortcode: String,
+    requested_at: i64,
+}
+
+/// Polls the balances of several shortcodes and keeps the latest balances of each
+#[derive(Debug)]
+pub struct BalancePoller {
+    client: Mpesa,
+    shortcodes: Vec<(String, String)>,
+    result_url: Option<String>,
+    timeout_url: Option<String>,
+    result_timeout: Duration,
+    pending: Mutex<HashMap<String, Pending>>,
+    balances: RwLock<BTreeMap<String, ShortcodeBalance>>,
+}
+
+impl BalancePoller {
+    /// Creates a poller without shortcodes, forgetting the requests whose result has not been
+    /// received within an hour
+    pub fn new(client: Mpesa) -> Self {
+        BalancePoller {
+            client,
+            shortcodes: vec![],
+            result_url: None,
+            timeout_url: None,
+            result_timeout: Duration::from_secs(60 * 60),
+            pending: Mutex::default(),
+            balances: RwLock::default(),
+        }
+    }
+
+    /// Adds a shortcode to poll the balances of, with the initiator allowed to query them
+    pub fn shortcode(
+        mut self,
+        initiator_name: impl Into<String>,
+        shortcode: impl Into<String>,
+    ) -> Self {
+        self.shortcodes
+            .push((initiator_name.into(), shortcode.into().trim().to_owned()));
+        self
+    }
+
+    /// Adds the `ResultURL` of the requests. Defaults to the url set with
+    /// `MpesaBuilder::result_url`
+    pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+        self.result_url = Some(result_url.into());
+        self
+    }
+
+    /// Adds the `QueueTimeOutURL` of the requests. Defaults to the url set with
+    /// `MpesaBuilder::timeout_url`
+    pub fn timeout_url(mut self, timeout_url: impl Into<String>) -> Self {
+        self.timeout_url = Some(timeout_url.into());
+        self
+    }
+
+    /// Adds the `QueueTimeOutURL` and `ResultURL` of the requests
+    pub fn callback_urls(self, urls: CallbackUrls<'_>) -> Self {
+        self.timeout_url(urls.timeout_url)
+            .result_url(urls.result_url)
+    }
+
+    /// Sets how long a request is waited on for its result before it is forgotten, after which
+    /// its result is ignored. Defaults to an hour.
+    pub fn result_timeout(mut self, result_timeout: Duration) -> Self {
+        self.result_timeout = result_timeout;
+        self
+    }
+
+    /// Sends an account balance request for every shortcode, returning the outcome of each
+    /// request in the order the shortcodes were added. A failed request does not stop the
+    /// others.
+    pub async fn poll(&self) -> Vec<BalanceRequest> {
+        let expired = now() - self.result_timeout.as_secs() as i64;
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, pending| pending.requested_at >= expired);
+
+        let mut requests = Vec::with_capacity(self.shortcodes.len());
+        for (initiator_name, shortcode) in &self.shortcodes {
+            let mut request = self
+                .client
+                .account_balance(initiator_name)
+                .party_a(shortcode);
+            if let Some(result_url) = &self.result_url {
+                request = request.result_url(result_url);
+            }
+            if let Some(timeout_url) = &self.timeout_url {
+                request = request.timeout_url(timeout_url);
+            }
+            let requested_at = now();
+            let result = request.send().await;
+            if let Ok(response) = &result {
+                self.pending
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(
+                        response.conversation_id.clone(),
+                        Pending {
+                            initiator_name: initiator_name.clone(),
+                            shortcode: shortcode.clone(),
+                            requested_at,
+                        },
+                    );
+            }
+            requests.push(BalanceRequest {
+                initiator_name: initiator_name.clone(),
+                shortcode: shortcode.clone(),
+                result,
+            });
+        }
+        requests
+    }
+
+    /// Polls the balances every `interval`, starting right away, until the client is shut down.
+    /// Requests that fail are sent again at the next poll.
+    pub async fn run(&self, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if self.client.is_shut_down() {
+                return;
+            }
+            self.poll().await;
+        }
+    }
+
+    /// Handles the result of an account balance request sent by the poller, returning the
+    /// balances it updated. Returns `None` for failed requests, for results of unknown or
+    /// forgotten requests, and for results older than the balances already kept.
+    ///
+    /// # Errors
+    /// Returns `MpesaError::Message` if the balances of a successful result cannot be parsed
+    pub fn on_result(&self, result: &ResultCallback) -> MpesaResult<Option<ShortcodeBalance>> {
+        let Some(pending) = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&result.conversation_id)
+        else {
+            return Ok(None);
+        };
+        if !result.is_success() {
+            return Ok(None);
+        }
+
+        let accounts = result
+            .parameter("AccountBalance")
+            .map(value_to_string)
+            .ok_or(MpesaError::Message("The result has no AccountBalance"))?;
+        let balance = ShortcodeBalance {
+            accounts: AccountBalance::parse_all(&accounts)?,
+            completed_time: result.parameter("BOCompletedTime").map(value_to_string),
+            conversation_id: result.conversation_id.clone(),
+            shortcode: pending.shortcode,
+            initiator_name: pending.initiator_name,
+            requested_at: pending.requested_at,
+            received_at: now(),
+        };
+
+        let mut balances = self.balances.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(latest) = balances.get(&balance.shortcode) {
+            if latest.requested_at > balance.requested_at {
+                return Ok(None);
+            }
+        }
+        balances.insert(balance.shortcode.clone(), balance.clone());
+        Ok(Some(balance))
+    }
+
+    /// Returns the latest balances of `shortcode`, `None` if no result was received for it yet
+    pub fn balance(&self, shortcode: &str) -> Option<ShortcodeBalance> {
+        self.balances
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(shortcode.trim())
+            .cloned()
+    }
+
+    /// Returns the latest balances of every shortcode
+    pub fn snapshot(&self) -> BalanceSnapshot {
+        BalanceSnapshot {
+            balances: self
+                .balances
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_balances_are_parsed() {
+        let accounts = AccountBalance::parse_all(
+            "Working Account|KES|46713.00|46713.00|0.00|0.00&Charges Paid Account|KES|-220.00|-220.00|0.00|0.00",
+        )
+        .unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].account, "Working Account");
+        assert_eq!(accounts[0].currency, "KES");
+        assert_eq!(accounts[0].available, 46713.0);
+        assert_eq!(accounts[1].current, -220.0);
+
+        assert!(AccountBalance::parse_all("").unwrap().is_empty());
+        assert!(AccountBalance::parse_all("Working Account|KES|46713.00").is_err());
+        assert!(AccountBalance::parse_all("Working Account|KES|a|0.00|0.00|0.00").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Address book of the phone numbers payouts are sent to
+//!
+//! A mistyped digit in the phone number of a B2C payment sends the money to a stranger, and
+//! reversing it depends on their goodwill. A [`BeneficiaryBook`] holds the beneficiaries whose
+//! number was checked when they were added, such as employees or agents, with a display name and
+//! an optional limit on the amount of each payout. A B2C request built with
+//! `B2cBuilder::only_known_beneficiaries` is refused before it is sent if its `PartyB` is not in
+//! the book or its amount is over the limit of the beneficiary.
+//!
+//! ```rust
+//! use mpesa::beneficiary::{Beneficiary, BeneficiaryBook};
+//!
+//! let book = BeneficiaryBook::default()
+//!     .with(Beneficiary::new("0712345678", "Jane Wanjiru").unwrap().max_amount(5000));
+//! assert_eq!(book.get("+254712345678").unwrap().name, "Jane Wanjiru");
+//! assert!(book.check("254712345678", 2500).is_ok());
+//! assert!(book.check("254712345678", 7500).is_err());
+//! assert!(book.check("254712345679", 100).is_err());
+//! ```
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::validator::{normalize_msisdn, PartyType};
+use crate::{MpesaError, MpesaResult};
+
+/// A phone number payouts can be sent to
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Beneficiary {
+    /// Phone number in the `254712345678` format
+    pub msisdn: String,
+    pub name: String,
+    /// Largest amount of a single payout, `None` for no limit
+    pub max_amount: Option<f64>,
+}
+
+impl Beneficiary {
+    /// Creates a beneficiary from a phone number in any of the formats accepted by
+    /// `normalize_msisdn`
+    ///
+    /// # Errors
+    /// Returns `MpesaError::Message` if `phone_number` is not a valid phone number or `name` is
+    /// blank
+    pub fn new(phone_number: &str, name: impl Into<String>) -> MpesaResult<Self> {
+        if PartyType::of(phone_number) != Some(PartyType::Msisdn) {
+            return Err(MpesaError::Message(
+                "The phone number of a beneficiary is invalid",
+            ));
+        }
+        let name = name.into().trim().to_owned();
+        if name.is_empty() {
+            return Err(MpesaError::Message("The name of a beneficiary is required"));
+        }
+        Ok(Beneficiary {
+            msisdn: normalize_msisdn(phone_number).into_owned(),
+            name,
+            max_amount: None,
+        })
+    }
+
+    /// Sets the largest amount of a single payout to the beneficiary
+    pub fn max_amount(mut self, max_amount: impl Into<f64>) -> Self {
+        self.max_amount = Some(max_amount.into());
+        self
+    }
+}
+
+/// Beneficiaries by phone number
+#[derive(Debug, Default)]
+pub struct BeneficiaryBook {
+    beneficiaries: RwLock<HashMap<String, Beneficiary>>,
+}
+
+impl BeneficiaryBook {
+    /// Adds `beneficiary` to the book
+    pub fn with(self, beneficiary: Beneficiary) -> Self {
+        self.insert(beneficiary);
+        self
+    }
+
+    /// Adds or replaces `beneficiary`
+    pub fn insert(&self, beneficiary: Beneficiary) {
+        self.beneficiaries
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(beneficiary.msisdn.clone(), beneficiary);
+    }
+
+    /// Removes the beneficiary with `phone_number`, returning it if it was in the book
+    pub fn remove(&self, phone_number: &str) -> Option<Beneficiary> {
+        self.beneficiaries
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(normalize_msisdn(phone_number).as_ref())
+    }
+
+    /// Returns the beneficiary with `phone_number`, in any of the formats accepted by
+    /// `normalize_msisdn`
+    pub fn get(&self, phone_number: &str) -> Option<Beneficiary> {
+        self.beneficiaries
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(normalize_msisdn(phone_number).as_ref())
+            .cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.beneficiaries
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks that a payout of `amount` can be sent to `phone_number`, returning its beneficiary
+    ///
+    /// # Errors
+    /// Returns `MpesaError::UnknownBeneficiary` if `phone_number` is not in the book, and
+    /// `MpesaError::BeneficiaryLimitExceeded` if `amount` is over the limit of its beneficiary
+    pub fn check(&self, phone_number: &str, amount: impl Into<f64>) -> MpesaResult<Beneficiary> {
+        let amount = amount.into();
+        let beneficiary = self
+            .get(phone_number)
+            .ok_or_else(|| MpesaError::UnknownBeneficiary(phone_number.trim().to_owned()))?;
+        if let Some(max_amount) = beneficiary.max_amount {
+            if amount > max_amount {
+                return Err(MpesaError::BeneficiaryLimitExceeded {
+                    msisdn: beneficiary.msisdn,
+                    amount,
+                    max_amount,
+                });
+            }
+        }
+        Ok(beneficiary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beneficiaries_are_found_by_any_format_of_their_number() {
+        let book = BeneficiaryBook::default()
+            .with(Beneficiary::new(" 0712345678", " Jane Wanjiru ").unwrap())
+            .with(
+                Beneficiary::new("+254112345678", "Otieno Agencies")
+                    .unwrap()
+                    .max_amount(1000),
+            );
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.get("712345678").unwrap().name, "Jane Wanjiru");
+        assert_eq!(book.get("0112345678").unwrap().msisdn, "254112345678");
+
+        assert!(book.check("254712345678", 1_000_000).is_ok());
+        assert!(matches!(
+            book.check("0112345678", 1000.5),
+            Err(MpesaError::BeneficiaryLimitExceeded { max_amount, .. }) if max_amount == 1000.0
+        ));
+        assert!(matches!(
+            book.check("0712345670", 10),
+            Err(MpesaError::UnknownBeneficiary(msisdn)) if msisdn == "0712345670"
+        ));
+
+        assert!(book.remove("+254712345678").is_some());
+        assert!(book.get("0712345678").is_none());
+
+        assert!(Beneficiary::new("600496", "ACME LTD").is_err());
+        assert!(Beneficiary::new("0712345678", " ").is_err());
+    }
+}
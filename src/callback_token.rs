@@ -0,0 +1,184 @@
+//! Signed tokens authenticating callback urls
+//!
+//! Safaricom does not sign the callbacks it posts, so anyone who learns a callback url can post
+//! forged results to it. A [`CallbackSigner`] adds a `token` query parameter to callback urls,
+//! an HMAC-SHA256 of the path of the url, the shortcode and an expiry under a key only known to
+//! the application, and checks it when the callback is received. Safaricom posts callbacks to the
+//! url as registered, query included, so only callbacks for urls the application generated carry
+//! a valid token, and the token of a url is refused on any other path.
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use mpesa::callback_token::CallbackSigner;
+//!
+//! let signer = CallbackSigner::new("a long random key from the environment");
+//! let url = signer
+//!     .sign_url("https://pay.example.com/mpesa/stk", "174379", Duration::from_secs(3600))
+//!     .unwrap();
+//!
+//! // in the callback handler, with the path and query of the request
+//! let query = url.split_once('?').unwrap().1;
+//! assert_eq!(signer.verify_query("/mpesa/stk", query).unwrap(), "174379");
+//! assert!(signer.verify_query("/mpesa/result", query).is_err());
+//! ```
+//!
+//! With the `server` feature, `Server::callback_signer` refuses callbacks without a valid token.
+//! Tokens of C2B urls, which stay registered until they are replaced, need an expiry far enough in
+//! the future to outlive the registration. Since tokens are bound to the path of the url, a proxy
+//! in front of the server must not rewrite it.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use url::Url;
+
+use crate::{MpesaError, MpesaResult};
+
+/// Name of the query parameter carrying the token
+pub const TOKEN_PARAM: &str = "token";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Generates and verifies the tokens of callback urls with a secret key
+#[derive(Debug, Clone)]
+pub struct CallbackSigner {
+    key: Secret<String>,
+}
+
+impl CallbackSigner {
+    /// Creates a signer with `key`, which should be at least 32 random characters
+    pub fn new(key: impl Into<String>) -> Self {
+        CallbackSigner {
+            key: Secret::new(key.into()),
+        }
+    }
+
+    /// Returns a token for the callbacks of `short_code`, valid until `expires_at`, that is not
+    /// bound to a path
+    pub fn token(&self, short_code: &str, expires_at: SystemTime) -> String {
+        self.path_token("", short_code, expires_at)
+    }
+
+    /// Adds a token for the callbacks of `short_code` to the path of `url`, valid for `ttl`, to
+    /// the query of `url`
+    ///
+    /// # Errors
+    /// Returns a `Message` if `url` is not a valid url
+    pub fn sign_url(&self, url: &str, short_code: &str, ttl: Duration) -> MpesaResult<String> {
+        let mut url = Url::parse(url).map_err(|_| MpesaError::Message("Invalid callback url"))?;
+        let token = self.path_token(url.path(), short_code, SystemTime::now() + ttl);
+        url.query_pairs_mut().append_pair(TOKEN_PARAM, &token);
+        Ok(url.into())
+    }
+
+    /// Checks a token generated by `token`, returning the shortcode it was generated for
+    ///
+    /// # Errors
+    /// Returns a `Message` if the token is malformed, was not generated with the key of the signer
+    /// or has expired
+    pub fn verify(&self, token: &str) -> MpesaResult<String> {
+        self.verify_path_token("", token)
+    }
+
+    /// Checks the token in the query string of a callback request to `path`, returning the
+    /// shortcode it was generated for
+    ///
+    /// # Errors
+    /// Returns a `Message` if the query has no token or its token is not valid for `path`
+    pub fn verify_query(&self, path: &str, query: &str) -> MpesaResult<String> {
+        let token = url::form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == TOKEN_PARAM)
+            .ok_or(MpesaError::Message("Missing callback token"))?
+            .1;
+        self.verify_path_token(path, &token)
+    }
+
+    fn path_token(&self, path: &str, short_code: &str, expires_at: SystemTime) -> String {
+        let expires_at = expires_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let payload = format!("{short_code}.{expires_at}");
+        let signature = self.mac(path, &payload).finalize().into_bytes();
+        format!("{payload}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn verify_path_token(&self, path: &str, token: &str) -> MpesaResult<String> {
+        let invalid = || MpesaError::Message("Invalid callback token");
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let (short_code, expires_at) = payload.rsplit_once('.').ok_or_else(invalid)?;
+        let expires_at: u64 = expires_at.parse().map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        self.mac(path, payload)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        if UNIX_EPOCH + Duration::from_secs(expires_at) < SystemTime::now() {
+            return Err(MpesaError::Message("Callback token has expired"));
+        }
+        Ok(short_code.to_owned())
+    }
+
+    /// The MAC of `payload` for `path`, whose trailing slashes are ignored
+    fn mac(&self, path: &str, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(path.trim_end_matches('/').as_bytes());
+        mac.update(b"\n");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_verified() {
+        let signer = CallbackSigner::new("secret");
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let token = signer.token("600496", expires_at);
+
+        assert_eq!(signer.verify(&token).unwrap(), "600496");
+        assert!(CallbackSigner::new("other").verify(&token).is_err());
+        assert!(signer.verify(&token.replace("600496", "600497")).is_err());
+        assert!(signer.verify("600496").is_err());
+        assert!(signer.verify("").is_err());
+    }
+
+    #[test]
+    fn test_expired_tokens_are_rejected() {
+        let signer = CallbackSigner::new("secret");
+        let token = signer.token("600496", SystemTime::now() - Duration::from_secs(1));
+        assert_eq!(
+            signer.verify(&token).unwrap_err().to_string(),
+            "Callback token has expired"
+        );
+    }
+
+    #[test]
+    fn test_signed_urls_keep_their_query() {
+        let signer = CallbackSigner::new("secret");
+        let url = signer
+            .sign_url(
+                "https://pay.example.com/mpesa/result?order=1029",
+                "600496",
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        let query = Url::parse(&url).unwrap().query().unwrap().to_owned();
+
+        assert!(query.starts_with("order=1029&token=600496."));
+        assert_eq!(
+            signer.verify_query("/mpesa/result/", &query).unwrap(),
+            "600496"
+        );
+        assert!(signer.verify_query("/mpesa/timeout", &query).is_err());
+        assert!(signer.verify_query("/mpesa/result", "order=1029").is_err());
+    }
+}
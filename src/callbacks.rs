@@ -0,0 +1,1218 @@
+//! Payloads Safaricom posts to the callback urls registered with a request
+//!
+//! - `StkCallback`: sent to the `CallBackURL` of an M-Pesa Express request
+//! - `C2bTransaction`: sent to the `ValidationURL` and `ConfirmationURL` registered with C2B Register
+//! - `ResultCallback`: sent to the `ResultURL` and `QueueTimeOutURL` of B2C, B2B, transaction reversal,
+//!   transaction status and account balance requests
+//! - `B2bResult` and `B2cResult`: a `ResultCallback` of a B2B or B2C payment, with its result
+//!   parameters extracted
+//!
+//! `parse_lenient` accepts any of them without failing, for endpoints that must acknowledge every
+//! callback and set aside the ones they cannot handle. `read_callback` reads and parses a callback
+//! from an `AsyncRead` such as a request body, within the size and time of its `BodyLimits`.
+
+use std::time::Duration;
+
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_aux::field_attributes::deserialize_string_from_number;
+use serde_json::Value;
+
+use crate::metadata::Metadata;
+use crate::{ExpressResultCode, MpesaError, MpesaResult};
+
+/// Result of an M-Pesa Express (STK push) request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct StkCallback {
+    #[serde(rename = "MerchantRequestID")]
+    pub merchant_request_id: String,
+    #[serde(rename = "CheckoutRequestID")]
+    pub checkout_request_id: String,
+    /// `0` if the customer completed the payment
+    pub result_code: i32,
+    pub result_desc: String,
+    /// Details of the payment, only present if it was successful
+    #[serde(
+        default,
+        rename = "CallbackMetadata",
+        deserialize_with = "items",
+        serialize_with = "serialize_items",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "ItemsSchema"))]
+    pub callback_metadata: Vec<CallbackItem>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct StkCallbackEnvelope {
+    #[serde(rename = "Body")]
+    body: StkCallbackBody,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct StkCallbackBody {
+    #[serde(rename = "stkCallback")]
+    stk_callback: StkCallback,
+}
+
+impl StkCallback {
+    /// Parses the body of a request made to the `CallBackURL`, `{"Body": {"stkCallback": {..}}}`
+    pub fn from_json(body: &[u8]) -> MpesaResult<Self> {
+        let envelope: StkCallbackEnvelope = serde_json::from_slice(body)?;
+        Ok(envelope.body.stk_callback)
+    }
+
+    /// Serializes the callback in the shape it was posted in, so that it can be parsed again with
+    /// `from_json`
+    pub fn to_json(&self) -> MpesaResult<Vec<u8>> {
+        Ok(serde_json::to_vec(
+            &serde_json::json!({ "Body": { "stkCallback": self } }),
+        )?)
+    }
+
+    /// Returns `true` if the customer completed the payment
+    pub fn is_success(&self) -> bool {
+        self.result_code == 0
+    }
+
+    /// The `ResultCode` of the payment
+    pub fn result(&self) -> ExpressResultCode {
+        ExpressResultCode::from(self.result_code)
+    }
+
+    /// Returns `true` if the customer completed the payment, same as `is_success`
+    pub fn is_paid(&self) -> bool {
+        self.result() == ExpressResultCode::Success
+    }
+
+    /// Returns `true` if the customer cancelled the payment request on their phone
+    pub fn is_cancelled_by_user(&self) -> bool {
+        self.result() == ExpressResultCode::CancelledByUser
+    }
+
+    /// Returns `true` if the customer did not respond to the payment request in time, either
+    /// because their phone could not be reached or because the request expired
+    pub fn is_timed_out(&self) -> bool {
+        matches!(
+            self.result(),
+            ExpressResultCode::UserUnreachable | ExpressResultCode::TransactionExpired
+        )
+    }
+
+    /// Why the payment failed, `None` if it was completed. Falls back to the `ResultDesc` for
+    /// result codes without a known description
+    pub fn failure_reason(&self) -> Option<&str> {
+        if self.is_paid() {
+            return None;
+        }
+        Some(self.result().description().unwrap_or(&self.result_desc))
+    }
+
+    /// Looks up the value of a `CallbackMetadata` item by name
+    pub fn metadata(&self, name: &str) -> Option<&Value> {
+        self.callback_metadata
+            .iter()
+            .find(|item| item.name == name)
+            .and_then(|item| item.value.as_ref())
+    }
+
+    /// Amount paid by the customer
+    pub fn amount(&self) -> Option<f64> {
+        self.metadata("Amount").and_then(Value::as_f64)
+    }
+
+    /// M-Pesa receipt number of the payment, e.g. `NLJ7RT61SV`
+    pub fn mpesa_receipt_number(&self) -> Option<&str> {
+        self.metadata("MpesaReceiptNumber").and_then(Value::as_str)
+    }
+
+    /// Phone number that made the payment, in the format `2547XXXXXXXX`
+    pub fn phone_number(&self) -> Option<String> {
+        self.metadata("PhoneNumber").map(value_to_string)
+    }
+
+    /// Time of the payment in the format `YYYYMMDDHHmmss`
+    pub fn transaction_date(&self) -> Option<String> {
+        self.metadata("TransactionDate").map(value_to_string)
+    }
+}
+
+/// A named value of the `CallbackMetadata` of an `StkCallback`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct CallbackItem {
+    pub name: String,
+    /// Absent for some items, such as `Balance`
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+/// A C2B payment, sent to the `ValidationURL` before it is completed and to the `ConfirmationURL`
+/// once it has been
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct C2bTransaction {
+    /// `Pay Bill` or `Buy Goods`
+    pub transaction_type: String,
+    #[serde(rename = "TransID")]
+    pub trans_id: String,
+    /// Time of the payment in the format `YYYYMMDDHHmmss`
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub trans_time: String,
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub trans_amount: String,
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub business_short_code: String,
+    #[serde(default)]
+    pub bill_ref_number: String,
+    #[serde(default)]
+    pub invoice_number: String,
+    #[serde(default)]
+    pub org_account_balance: String,
+    #[serde(default, rename = "ThirdPartyTransID")]
+    pub third_party_trans_id: String,
+    /// Phone number that made the payment, masked or hashed by Safaricom on some shortcodes
+    #[serde(rename = "MSISDN", deserialize_with = "deserialize_string_from_number")]
+    pub msisdn: String,
+    #[serde(default)]
+    pub first_name: String,
+    #[serde(default)]
+    pub middle_name: String,
+    #[serde(default)]
+    pub last_name: String,
+}
+
+impl C2bTransaction {
+    /// The first, middle and last names of the customer that are present, joined with spaces.
+    /// Empty if Safaricom sent none, as it does on shortcodes that hide customer details.
+    pub fn full_name(&self) -> String {
+        [&self.first_name, &self.middle_name, &self.last_name]
+            .into_iter()
+            .filter(|name| !name.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A C2B payment posted to the `ConfirmationURL`, once it has been completed
+pub type C2bConfirmation = C2bTransaction;
+
+/// Reasons for rejecting a C2B payment from the `ValidationURL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum C2bRejection {
+    InvalidMsisdn,
+    InvalidAccountNumber,
+    InvalidAmount,
+    InvalidKycDetails,
+    InvalidShortcode,
+    Other,
+}
+
+impl C2bRejection {
+    /// The result code Daraja expects for the rejection
+    pub fn code(&self) -> &'static str {
+        match self {
+            C2bRejection::InvalidMsisdn => "C2B00011",
+            C2bRejection::InvalidAccountNumber => "C2B00012",
+            C2bRejection::InvalidAmount => "C2B00013",
+            C2bRejection::InvalidKycDetails => "C2B00014",
+            C2bRejection::InvalidShortcode => "C2B00015",
+            C2bRejection::Other => "C2B00016",
+        }
+    }
+}
+
+/// Response to a request made to the `ValidationURL`, accepting or rejecting the payment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct C2bValidationResponse {
+    pub result_code: String,
+    pub result_desc: String,
+}
+
+impl C2bValidationResponse {
+    /// Lets the payment go through
+    pub fn accept() -> Self {
+        C2bValidationResponse {
+            result_code: "0".to_owned(),
+            result_desc: "Accepted".to_owned(),
+        }
+    }
+
+    /// Cancels the payment
+    pub fn reject(reason: C2bRejection) -> Self {
+        C2bValidationResponse {
+            result_code: reason.code().to_owned(),
+            result_desc: "Rejected".to_owned(),
+        }
+    }
+}
+
+/// Result of an asynchronous request such as B2C, B2B, transaction reversal, transaction status or
+/// account balance
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct ResultCallback {
+    pub result_type: i32,
+    /// `0` if the request succeeded. Numeric for most results but some failures are reported
+    /// with alphanumeric codes such as `SFC_IC0003`
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub result_code: String,
+    pub result_desc: String,
+    #[serde(rename = "OriginatorConversationID", alias = "OriginatorCoversationID")]
+    pub originator_conversation_id: String,
+    #[serde(rename = "ConversationID")]
+    pub conversation_id: String,
+    #[serde(default, rename = "TransactionID")]
+    pub transaction_id: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "parameters",
+        serialize_with = "serialize_parameters",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "ResultParametersSchema"))]
+    pub result_parameters: Vec<ResultParameter>,
+    #[serde(
+        default,
+        deserialize_with = "reference_items",
+        serialize_with = "serialize_reference_items",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "ReferenceDataSchema"))]
+    pub reference_data: Vec<ResultParameter>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct ResultCallbackEnvelope {
+    #[serde(rename = "Result")]
+    result: ResultCallback,
+}
+
+impl ResultCallback {
+    /// Parses the body of a request made to the `ResultURL`, `{"Result": {..}}`
+    pub fn from_json(body: &[u8]) -> MpesaResult<Self> {
+        let envelope: ResultCallbackEnvelope = serde_json::from_slice(body)?;
+        Ok(envelope.result)
+    }
+
+    /// Serializes the callback in the shape it was posted in, so that it can be parsed again with
+    /// `from_json`
+    pub fn to_json(&self) -> MpesaResult<Vec<u8>> {
+        Ok(serde_json::to_vec(&serde_json::json!({ "Result": self }))?)
+    }
+
+    /// Returns `true` if the request succeeded
+    pub fn is_success(&self) -> bool {
+        self.result_code == "0"
+    }
+
+    /// Returns `true` if the request was rejected as a duplicate of a transaction already
+    /// processed, with the `DuplicateDetected` result code `15`
+    pub fn is_duplicate(&self) -> bool {
+        self.result_code == "15"
+    }
+
+    /// Looks up the value of a `ResultParameters` item by key
+    pub fn parameter(&self, key: &str) -> Option<&Value> {
+        self.result_parameters
+            .iter()
+            .find(|parameter| parameter.key == key)
+            .and_then(|parameter| parameter.value.as_ref())
+    }
+
+    /// Parses the `Metadata` sent in the `Occasion` or `Remarks` of the request, when the API
+    /// echoes them in the `ReferenceData` or `ResultParameters` of its result
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.reference_data
+            .iter()
+            .chain(&self.result_parameters)
+            .filter(|item| item.key == "Occasion" || item.key == "Remarks")
+            .filter_map(|item| item.value.as_ref()?.as_str())
+            .find_map(Metadata::parse)
+    }
+}
+
+/// Result of a B2B payment, with the parameters specific to B2B extracted from its
+/// `ResultParameters`. Parameters are `None` when absent, as they are when the payment failed.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct B2bResult {
+    /// The result as posted, for its conversation ids and the parameters not extracted below
+    pub result: ResultCallback,
+    /// Balance of the debited account, e.g. `Working Account|KES|46713.00|46713.00|0.00|0.00`
+    pub debit_account_balance: Option<String>,
+    pub amount: Option<f64>,
+    /// Charges of the payment, from `FeesPaid` or `DebitPartyCharges`,
+    /// e.g. `Business Pay Bill Charge|KES|77.00`
+    pub fees_paid: Option<String>,
+    /// Time the payment was completed in the format `YYYYMMDDHHmmss`
+    pub trans_completed_time: Option<String>,
+    /// Shortcode and name of the receiving organization, e.g. `603094 - Safaricom3117`
+    pub receiver_party_public_name: Option<String>,
+}
+
+impl B2bResult {
+    /// Parses the body of a request made to the `ResultURL` of a B2B request, `{"Result": {..}}`
+    pub fn from_json(body: &[u8]) -> MpesaResult<Self> {
+        ResultCallback::from_json(body).map(Self::from)
+    }
+
+    /// Returns `true` if the payment succeeded
+    pub fn is_success(&self) -> bool {
+        self.result.is_success()
+    }
+}
+
+impl From<ResultCallback> for B2bResult {
+    fn from(result: ResultCallback) -> Self {
+        let string = |key| result.parameter(key).map(value_to_string);
+        B2bResult {
+            debit_account_balance: string("DebitAccountBalance"),
+            amount: number_parameter(&result, "Amount"),
+            fees_paid: string("FeesPaid").or_else(|| string("DebitPartyCharges")),
+            trans_completed_time: string("TransCompletedTime"),
+            receiver_party_public_name: string("ReceiverPartyPublicName"),
+            result,
+        }
+    }
+}
+
+/// Result of a transfer from the MMF account to the utility account, made with
+/// `Mpesa::transfer_mmf_to_utility`, which is posted as the result of a B2B request
+pub type MmfTransferResult = B2bResult;
+
+/// Result of a B2C payment, with the parameters specific to B2C extracted from its
+/// `ResultParameters`. Parameters are `None` when absent, as they are when the payment failed.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct B2cResult {
+    /// The result as posted, for its conversation ids and the parameters not extracted below
+    pub result: ResultCallback,
+    pub amount: Option<f64>,
+    /// M-Pesa receipt number of the payment, e.g. `NLJ41HAY6Q`
+    pub transaction_receipt: Option<String>,
+    /// Phone number and name of the recipient, e.g. `254708374149 - John Doe`
+    pub receiver_party_public_name: Option<String>,
+    /// Time the payment was completed in the format `DD.MM.YYYY HH:mm:ss`
+    pub transaction_completed_date_time: Option<String>,
+    /// Whether the recipient is a registered M-Pesa customer, from `B2CRecipientIsRegisteredCustomer`
+    pub recipient_is_registered_customer: Option<bool>,
+    /// Available balance of the utility account after the payment
+    pub utility_account_available_funds: Option<f64>,
+    /// Available balance of the working account after the payment
+    pub working_account_available_funds: Option<f64>,
+}
+
+impl B2cResult {
+    /// Parses the body of a request made to the `ResultURL` of a B2C request, `{"Result": {..}}`
+    pub fn from_json(body: &[u8]) -> MpesaResult<Self> {
+        ResultCallback::from_json(body).map(Self::from)
+    }
+
+    /// Returns `true` if the payment succeeded
+    pub fn is_success(&self) -> bool {
+        self.result.is_success()
+    }
+}
+
+impl From<ResultCallback> for B2cResult {
+    fn from(result: ResultCallback) -> Self {
+        let string = |key| result.parameter(key).map(value_to_string);
+        B2cResult {
+            amount: number_parameter(&result, "TransactionAmount"),
+            transaction_receipt: string("TransactionReceipt"),
+            receiver_party_public_name: string("ReceiverPartyPublicName"),
+            transaction_completed_date_time: string("TransactionCompletedDateTime"),
+            recipient_is_registered_customer: string("B2CRecipientIsRegisteredCustomer")
+                .map(|registered| registered == "Y"),
+            utility_account_available_funds: number_parameter(
+                &result,
+                "B2CUtilityAccountAvailableFunds",
+            ),
+            working_account_available_funds: number_parameter(
+                &result,
+                "B2CWorkingAccountAvailableFunds",
+            ),
+            result,
+        }
+    }
+}
+
+/// Reads a `ResultParameters` item sent either as a number or as a string, such as `"190.00"`
+fn number_parameter(result: &ResultCallback, key: &str) -> Option<f64> {
+    match result.parameter(key)? {
+        Value::String(number) => number.parse().ok(),
+        number => number.as_f64(),
+    }
+}
+
+/// A key-value pair of the `ResultParameters` or `ReferenceData` of a `ResultCallback`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct ResultParameter {
+    pub key: String,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+/// A callback of any kind, as recognised by `parse_lenient`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Callback {
+    Stk(StkCallback),
+    C2b(C2bTransaction),
+    Result(ResultCallback),
+}
+
+/// Best-effort interpretation of a callback payload, returned by `parse_lenient`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LenientCallback {
+    /// The typed callback, `None` if the payload could not be parsed as one
+    pub callback: Option<Callback>,
+    /// The payload as JSON, `None` if it is not valid JSON
+    pub raw: Option<Value>,
+    /// Why the payload could not be parsed, set whenever `callback` is `None`
+    pub error: Option<String>,
+}
+
+impl LenientCallback {
+    /// Returns `true` if the payload was parsed as a typed callback
+    pub fn is_parsed(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    fn unparsed(raw: Option<Value>, error: impl ToString) -> Self {
+        LenientCallback {
+            callback: None,
+            raw,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Parses a callback of any kind without ever failing.
+///
+/// The kind of callback is recognised from the shape of the payload: `{"Body": {"stkCallback": ..}}`
+/// for M-Pesa Express, `{"Result": ..}` for results and timeouts, and an object with a `TransID`
+/// for C2B payments. Payloads that are not valid JSON, are of an unknown shape or are missing
+/// fields are returned with `callback` set to `None` and the reason in `error`, so that they can
+/// be acknowledged and stored for later inspection instead of being rejected, which would make
+/// Safaricom retry them.
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::callbacks::{parse_lenient, Callback};
+///
+/// fn on_callback(body: &[u8]) {
+///     let parsed = parse_lenient(body);
+///     match parsed.callback {
+///         Some(Callback::Stk(callback)) => println!("paid {:?}", callback.amount()),
+///         Some(_) => {}
+///         None => eprintln!("quarantined callback: {:?}", parsed.error),
+///     }
+/// }
+/// ```
+pub fn parse_lenient(body: &[u8]) -> LenientCallback {
+    let raw: Value = match serde_json::from_slice(body) {
+        Ok(raw) => raw,
+        Err(e) => return LenientCallback::unparsed(None, e),
+    };
+
+    let callback = if raw.pointer("/Body/stkCallback").is_some() {
+        StkCallbackEnvelope::deserialize(&raw)
+            .map(|envelope| Callback::Stk(envelope.body.stk_callback))
+    } else if raw.get("Result").is_some() {
+        ResultCallbackEnvelope::deserialize(&raw).map(|envelope| Callback::Result(envelope.result))
+    } else if raw.get("TransID").is_some() {
+        C2bTransaction::deserialize(&raw).map(Callback::C2b)
+    } else {
+        return LenientCallback::unparsed(Some(raw), "unknown callback payload");
+    };
+
+    match callback {
+        Ok(callback) => LenientCallback {
+            callback: Some(callback),
+            raw: Some(raw),
+            error: None,
+        },
+        Err(e) => LenientCallback::unparsed(Some(raw), e),
+    }
+}
+
+/// Limits on the body of a callback, past which it is refused rather than parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    /// Largest body in bytes
+    pub max_len: usize,
+    /// Time allowed to read the whole body, so that a client sending it a few bytes at a time
+    /// cannot hold a connection open
+    pub timeout: Duration,
+}
+
+impl Default for BodyLimits {
+    /// 64 KiB, many times the size of the largest callbacks, read within 10 seconds
+    fn default() -> Self {
+        BodyLimits {
+            max_len: 64 * 1024,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl BodyLimits {
+    /// Checks the length of a body that has already been read
+    ///
+    /// # Errors
+    /// Returns `MpesaError::BodyTooLarge` if `body` is over `max_len`
+    pub fn check(&self, body: &[u8]) -> MpesaResult<()> {
+        if body.len() > self.max_len {
+            return Err(MpesaError::BodyTooLarge(self.max_len));
+        }
+        Ok(())
+    }
+}
+
+/// Callback payloads `read_callback` can parse
+pub trait CallbackPayload: Sized {
+    /// Parses the body of a callback
+    fn parse(body: &[u8]) -> MpesaResult<Self>;
+}
+
+impl CallbackPayload for StkCallback {
+    fn parse(body: &[u8]) -> MpesaResult<Self> {
+        StkCallback::from_json(body)
+    }
+}
+
+impl CallbackPayload for C2bTransaction {
+    fn parse(body: &[u8]) -> MpesaResult<Self> {
+        Ok(serde_json::from_slice(body)?)
+    }
+}
+
+impl CallbackPayload for ResultCallback {
+    fn parse(body: &[u8]) -> MpesaResult<Self> {
+        ResultCallback::from_json(body)
+    }
+}
+
+impl CallbackPayload for B2bResult {
+    fn parse(body: &[u8]) -> MpesaResult<Self> {
+        B2bResult::from_json(body)
+    }
+}
+
+impl CallbackPayload for B2cResult {
+    fn parse(body: &[u8]) -> MpesaResult<Self> {
+        B2cResult::from_json(body)
+    }
+}
+
+/// Reads the body of a callback from `reader` until its end, within `limits`
+///
+/// # Errors
+/// Returns `MpesaError::BodyTooLarge` if the body is over `limits.max_len`, without reading
+/// further, `MpesaError::DeadlineExceeded` if it is not read within `limits.timeout` and
+/// `MpesaError::IoError` if reading fails
+#[cfg(any(feature = "client", feature = "server"))]
+pub async fn read_body<R>(reader: R, limits: BodyLimits) -> MpesaResult<Vec<u8>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut body = Vec::new();
+    // One byte past the limit tells an oversized body from one of exactly `max_len`
+    let mut reader = reader.take(limits.max_len as u64 + 1);
+    let read = reader.read_to_end(&mut body);
+    tokio::time::timeout(limits.timeout, read)
+        .await
+        .map_err(|_| MpesaError::DeadlineExceeded)??;
+    limits.check(&body)?;
+    Ok(body)
+}
+
+/// Reads the body of a callback from `reader` within `limits` and parses it
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::callbacks::{read_callback, BodyLimits, ResultCallback};
+/// use mpesa::MpesaError;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let body = vec![b' '; 128 * 1024];
+/// let result = read_callback::<ResultCallback, _>(&body[..], BodyLimits::default()).await;
+/// assert!(matches!(result, Err(MpesaError::BodyTooLarge(_))));
+/// # }
+/// ```
+///
+/// # Errors
+/// Returns the errors of `read_body`, and `MpesaError::ParseError` if the body is not a
+/// callback of type `T`
+#[cfg(any(feature = "client", feature = "server"))]
+pub async fn read_callback<T, R>(reader: R, limits: BodyLimits) -> MpesaResult<T>
+where
+    T: CallbackPayload,
+    R: tokio::io::AsyncRead + Unpin,
+{
+    T::parse(&read_body(reader, limits).await?)
+}
+
+/// Daraja sends a single object instead of an array when a list has one element
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        }
+    }
+}
+
+/// Deserializes `{"Item": [..]}`
+fn items<'de, D>(deserializer: D) -> Result<Vec<CallbackItem>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Items {
+        #[serde(rename = "Item")]
+        items: OneOrMany<CallbackItem>,
+    }
+    Ok(Items::deserialize(deserializer)?.items.into())
+}
+
+/// Deserializes `{"ResultParameter": [..]}`
+fn parameters<'de, D>(deserializer: D) -> Result<Vec<ResultParameter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Parameters {
+        #[serde(rename = "ResultParameter")]
+        parameters: OneOrMany<ResultParameter>,
+    }
+    Ok(Parameters::deserialize(deserializer)?.parameters.into())
+}
+
+/// Deserializes `{"ReferenceItem": [..]}`
+fn reference_items<'de, D>(deserializer: D) -> Result<Vec<ResultParameter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct ReferenceData {
+        #[serde(rename = "ReferenceItem")]
+        items: OneOrMany<ResultParameter>,
+    }
+    Ok(ReferenceData::deserialize(deserializer)?.items.into())
+}
+
+/// Serializes `items` as `{"<name>": [..]}`, the inverse of the deserializers above
+fn wrapped<T, S>(name: &'static str, items: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(name, items)?;
+    map.end()
+}
+
+fn serialize_items<S: Serializer>(
+    items: &[CallbackItem],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    wrapped("Item", items, serializer)
+}
+
+fn serialize_parameters<S: Serializer>(
+    parameters: &[ResultParameter],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    wrapped("ResultParameter", parameters, serializer)
+}
+
+fn serialize_reference_items<S: Serializer>(
+    items: &[ResultParameter],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    wrapped("ReferenceItem", items, serializer)
+}
+
+/// `{"Item": [..]}`, as serialized by `serialize_items`
+#[cfg(feature = "schema")]
+#[derive(schemars::JsonSchema)]
+#[schemars(rename = "CallbackMetadata")]
+#[allow(dead_code)]
+struct ItemsSchema {
+    #[serde(rename = "Item")]
+    items: Vec<CallbackItem>,
+}
+
+/// `{"ResultParameter": [..]}`, as serialized by `serialize_parameters`
+#[cfg(feature = "schema")]
+#[derive(schemars::JsonSchema)]
+#[schemars(rename = "ResultParameters")]
+#[allow(dead_code)]
+struct ResultParametersSchema {
+    #[serde(rename = "ResultParameter")]
+    parameters: Vec<ResultParameter>,
+}
+
+/// `{"ReferenceItem": [..]}`, as serialized by `serialize_reference_items`
+#[cfg(feature = "schema")]
+#[derive(schemars::JsonSchema)]
+#[schemars(rename = "ReferenceData")]
+#[allow(dead_code)]
+struct ReferenceDataSchema {
+    #[serde(rename = "ReferenceItem")]
+    items: Vec<ResultParameter>,
+}
+
+/// JSON Schemas of the callback payloads, keyed by the name of their type: `StkCallback`,
+/// `C2bTransaction` and `ResultCallback`.
+///
+/// The schemas describe the payloads in the shape they are serialized by `to_json` and forwarded by
+/// `forward::Forwarder`, so that consumers written in other languages can validate them.
+#[cfg(feature = "schema")]
+pub fn json_schemas() -> std::collections::BTreeMap<&'static str, schemars::Schema> {
+    let generator = || {
+        schemars::generate::SchemaSettings::default()
+            .for_serialize()
+            .into_generator()
+    };
+    [
+        (
+            "StkCallback",
+            generator().into_root_schema_for::<StkCallbackEnvelope>(),
+        ),
+        (
+            "C2bTransaction",
+            generator().into_root_schema_for::<C2bTransaction>(),
+        ),
+        (
+            "ResultCallback",
+            generator().into_root_schema_for::<ResultCallbackEnvelope>(),
+        ),
+    ]
+    .into()
+}
+
+/// Formats numbers, such as phone numbers, and strings alike
+pub(crate) fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_stk_callback_is_parsed() {
+        let body = json!({
+            "Body": {
+                "stkCallback": {
+                    "MerchantRequestID": "29115-34620561-1",
+                    "CheckoutRequestID": "ws_CO_191220191020363925",
+                    "ResultCode": 0,
+                    "ResultDesc": "The service request is processed successfully.",
+                    "CallbackMetadata": {
+                        "Item": [
+                            { "Name": "Amount", "Value": 1.00 },
+                            { "Name": "MpesaReceiptNumber", "Value": "NLJ7RT61SV" },
+                            { "Name": "Balance" },
+                            { "Name": "TransactionDate", "Value": 20191219102115u64 },
+                            { "Name": "PhoneNumber", "Value": 254708374149u64 }
+                        ]
+                    }
+                }
+            }
+        });
+        let callback = StkCallback::from_json(body.to_string().as_bytes()).unwrap();
+
+        assert!(callback.is_success());
+        assert_eq!(callback.checkout_request_id, "ws_CO_191220191020363925");
+        assert_eq!(callback.amount(), Some(1.0));
+        assert_eq!(callback.mpesa_receipt_number(), Some("NLJ7RT61SV"));
+        assert_eq!(callback.phone_number().as_deref(), Some("254708374149"));
+        assert_eq!(
+            callback.transaction_date().as_deref(),
+            Some("20191219102115")
+        );
+        assert_eq!(callback.metadata("Balance"), None);
+    }
+
+    #[test]
+    fn test_stk_callback_is_serialized_in_the_posted_shape() {
+        let body = json!({
+            "Body": {
+                "stkCallback": {
+                    "MerchantRequestID": "29115-34620561-1",
+                    "CheckoutRequestID": "ws_CO_191220191020363925",
+                    "ResultCode": 0,
+                    "ResultDesc": "The service request is processed successfully.",
+                    "CallbackMetadata": {
+                        "Item": [
+                            { "Name": "Amount", "Value": 1.0 },
+                            { "Name": "Balance", "Value": null }
+                        ]
+                    }
+                }
+            }
+        });
+        let callback = StkCallback::from_json(body.to_string().as_bytes()).unwrap();
+        let json: Value = serde_json::from_slice(&callback.to_json().unwrap()).unwrap();
+
+        assert_eq!(json, body);
+    }
+
+    #[test]
+    fn test_failed_stk_callback_has_no_metadata() {
+        let body = json!({
+            "Body": {
+                "stkCallback": {
+                    "MerchantRequestID": "29115-34620561-1",
+                    "CheckoutRequestID": "ws_CO_191220191020363925",
+                    "ResultCode": 1032,
+                    "ResultDesc": "Request cancelled by user."
+                }
+            }
+        });
+        let callback = StkCallback::from_json(body.to_string().as_bytes()).unwrap();
+
+        assert!(!callback.is_success());
+        assert!(callback.callback_metadata.is_empty());
+        assert!(callback.is_cancelled_by_user());
+        assert!(!callback.is_timed_out());
+        assert_eq!(
+            callback.failure_reason(),
+            Some("The request was cancelled by the user")
+        );
+    }
+
+    #[test]
+    fn test_result_callback_is_parsed() {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "TransactionID": "NLJ41HAY6Q",
+                "ResultParameters": {
+                    "ResultParameter": [
+                        { "Key": "TransactionAmount", "Value": 10 },
+                        { "Key": "TransactionReceipt", "Value": "NLJ41HAY6Q" }
+                    ]
+                },
+                "ReferenceData": {
+                    "ReferenceItem": {
+                        "Key": "QueueTimeoutURL",
+                        "Value": "https://internalsandbox.safaricom.co.ke/mpesa/b2cresults/v1/submit"
+                    }
+                }
+            }
+        });
+        let callback = ResultCallback::from_json(body.to_string().as_bytes()).unwrap();
+
+        assert!(callback.is_success());
+        assert_eq!(callback.transaction_id.as_deref(), Some("NLJ41HAY6Q"));
+        assert_eq!(callback.parameter("TransactionAmount"), Some(&json!(10)));
+        assert_eq!(callback.reference_data.len(), 1);
+
+        let reparsed = ResultCallback::from_json(&callback.to_json().unwrap()).unwrap();
+        assert_eq!(reparsed.conversation_id, callback.conversation_id);
+        assert_eq!(reparsed.parameter("TransactionAmount"), Some(&json!(10)));
+        assert_eq!(reparsed.reference_data.len(), 1);
+    }
+
+    #[test]
+    fn test_result_callback_metadata_is_parsed_from_the_occasion() {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "ReferenceData": {
+                    "ReferenceItem": [
+                        { "Key": "QueueTimeoutURL", "Value": "https://example.com/timeout" },
+                        { "Key": "Occasion", "Value": "order=A-1029;tenant=acme" }
+                    ]
+                }
+            }
+        });
+        let callback = ResultCallback::from_json(body.to_string().as_bytes()).unwrap();
+
+        let metadata = callback.metadata().unwrap();
+        assert_eq!(metadata.get("order"), Some("A-1029"));
+        assert_eq!(metadata.get("tenant"), Some("acme"));
+    }
+
+    #[test]
+    #[cfg(feature = "schema")]
+    fn test_callback_schemas_describe_the_posted_shape() {
+        let schemas = json_schemas();
+        assert_eq!(
+            schemas.keys().copied().collect::<Vec<_>>(),
+            ["C2bTransaction", "ResultCallback", "StkCallback"]
+        );
+
+        let stk = serde_json::to_value(&schemas["StkCallback"]).unwrap();
+        assert_eq!(stk["required"], json!(["Body"]));
+        assert_eq!(
+            stk["$defs"]["CallbackMetadata"]["properties"]["Item"]["type"],
+            "array"
+        );
+
+        let result = serde_json::to_value(&schemas["ResultCallback"]).unwrap();
+        assert_eq!(result["required"], json!(["Result"]));
+        let properties = &result["$defs"]["ResultCallback"]["properties"];
+        assert_eq!(
+            properties["ResultParameters"]["$ref"],
+            "#/$defs/ResultParameters"
+        );
+        assert_eq!(properties["ReferenceData"]["$ref"], "#/$defs/ReferenceData");
+    }
+
+    #[test]
+    fn test_result_callback_accepts_alphanumeric_result_codes() {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": "SFC_IC0003",
+                "ResultDesc": "The initiator information is invalid.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581"
+            }
+        });
+        let callback = ResultCallback::from_json(body.to_string().as_bytes()).unwrap();
+
+        assert!(!callback.is_success());
+        assert_eq!(callback.result_code, "SFC_IC0003");
+        assert!(callback.result_parameters.is_empty());
+    }
+
+    #[test]
+    fn test_b2b_result_parameters_are_extracted() {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully",
+                "OriginatorConversationID": "626f6ddf-ab37-4650-b882-b1de92ec9aa4",
+                "ConversationID": "AG_20181005_00004d7ee675c0c7ee0b",
+                "TransactionID": "QKA81LK5CY",
+                "ResultParameters": {
+                    "ResultParameter": [
+                        { "Key": "DebitAccountBalance", "Value": "{Amount={CurrencyCode=KES, MinimumAmount=618683, BasicAmount=6186.83}}" },
+                        { "Key": "Amount", "Value": "190.00" },
+                        { "Key": "DebitPartyAffectedAccountBalance", "Value": "Working Account|KES|346131.83|6186.83|340000.00|0.00" },
+                        { "Key": "TransCompletedTime", "Value": 20221110110717u64 },
+                        { "Key": "DebitPartyCharges", "Value": "Business Pay Bill Charge|KES|77.00" },
+                        { "Key": "ReceiverPartyPublicName", "Value": "000000– Biller Companty" },
+                        { "Key": "Currency", "Value": "KES" }
+                    ]
+                },
+                "ReferenceData": {
+                    "ReferenceItem": { "Key": "BillReferenceNumber", "Value": "19008" }
+                }
+            }
+        });
+        let result = B2bResult::from_json(body.to_string().as_bytes()).unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(result.result.transaction_id.as_deref(), Some("QKA81LK5CY"));
+        assert_eq!(result.amount, Some(190.0));
+        assert_eq!(
+            result.fees_paid.as_deref(),
+            Some("Business Pay Bill Charge|KES|77.00")
+        );
+        assert_eq!(
+            result.trans_completed_time.as_deref(),
+            Some("20221110110717")
+        );
+        assert_eq!(
+            result.receiver_party_public_name.as_deref(),
+            Some("000000– Biller Companty")
+        );
+        assert!(result.debit_account_balance.is_some());
+    }
+
+    #[test]
+    fn test_lenient_parsing_recognises_every_kind_of_callback() {
+        let stk = json!({
+            "Body": {
+                "stkCallback": {
+                    "MerchantRequestID": "29115-34620561-1",
+                    "CheckoutRequestID": "ws_CO_191220191020363925",
+                    "ResultCode": 1032,
+                    "ResultDesc": "Request cancelled by user."
+                }
+            }
+        });
+        let result = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581"
+            }
+        });
+        let c2b = json!({
+            "TransactionType": "Pay Bill",
+            "TransID": "RKTQDM7W6S",
+            "TransTime": "20191122063845",
+            "TransAmount": "10",
+            "BusinessShortCode": "600638",
+            "MSISDN": "254708374149"
+        });
+
+        let parsed = parse_lenient(stk.to_string().as_bytes());
+        assert!(matches!(parsed.callback, Some(Callback::Stk(_))));
+        assert_eq!(parsed.raw, Some(stk));
+        assert!(parsed.error.is_none());
+        assert!(matches!(
+            parse_lenient(result.to_string().as_bytes()).callback,
+            Some(Callback::Result(_))
+        ));
+        assert!(matches!(
+            parse_lenient(c2b.to_string().as_bytes()).callback,
+            Some(Callback::C2b(_))
+        ));
+    }
+
+    #[test]
+    fn test_lenient_parsing_never_fails() {
+        let parsed = parse_lenient(b"<html>Bad Gateway</html>");
+        assert!(!parsed.is_parsed());
+        assert!(parsed.raw.is_none());
+        assert!(parsed.error.is_some());
+
+        let parsed = parse_lenient(br#"{"Body": {"stkCallback": {"ResultCode": 0}}}"#);
+        assert!(!parsed.is_parsed());
+        assert!(parsed.raw.is_some());
+        assert!(parsed.error.unwrap().contains("MerchantRequestID"));
+
+        let parsed = parse_lenient(br#"{"hello": "world"}"#);
+        assert!(!parsed.is_parsed());
+        assert_eq!(parsed.error.as_deref(), Some("unknown callback payload"));
+    }
+
+    #[test]
+    fn test_c2b_transaction_is_parsed() {
+        let transaction: C2bTransaction = serde_json::from_value(json!({
+            "TransactionType": "Pay Bill",
+            "TransID": "RKTQDM7W6S",
+            "TransTime": "20191122063845",
+            "TransAmount": "10",
+            "BusinessShortCode": "600638",
+            "BillRefNumber": "invoice008",
+            "InvoiceNumber": "",
+            "OrgAccountBalance": "",
+            "ThirdPartyTransID": "",
+            "MSISDN": "25470****149",
+            "FirstName": "John"
+        }))
+        .unwrap();
+
+        assert_eq!(transaction.trans_id, "RKTQDM7W6S");
+        assert_eq!(transaction.trans_amount, "10");
+        assert_eq!(transaction.bill_ref_number, "invoice008");
+        assert_eq!(transaction.last_name, "");
+        assert_eq!(transaction.full_name(), "John");
+    }
+
+    #[test]
+    fn test_c2b_validation_response_is_serialized() {
+        assert_eq!(
+            serde_json::to_value(C2bValidationResponse::accept()).unwrap(),
+            json!({ "ResultCode": "0", "ResultDesc": "Accepted" })
+        );
+        assert_eq!(
+            serde_json::to_value(C2bValidationResponse::reject(
+                C2bRejection::InvalidAccountNumber
+            ))
+            .unwrap(),
+            json!({ "ResultCode": "C2B00012", "ResultDesc": "Rejected" })
+        );
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    #[tokio::test]
+    async fn test_callbacks_are_read_within_their_limits() {
+        use tokio::io::AsyncWriteExt;
+
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "29464-48063588-1",
+                "ConversationID": "AG_20230206_201056794190723278ff"
+            }
+        })
+        .to_string();
+        let limits = BodyLimits {
+            max_len: body.len(),
+            timeout: Duration::from_millis(50),
+        };
+        let result: ResultCallback = read_callback(body.as_bytes(), limits).await.unwrap();
+        assert_eq!(result.conversation_id, "AG_20230206_201056794190723278ff");
+
+        let limits = BodyLimits {
+            max_len: body.len() - 1,
+            ..limits
+        };
+        assert!(matches!(
+            read_body(body.as_bytes(), limits).await,
+            Err(MpesaError::BodyTooLarge(_))
+        ));
+
+        // A body whose end never arrives
+        let (mut writer, reader) = tokio::io::duplex(64);
+        writer.write_all(b"{").await.unwrap();
+        assert!(matches!(
+            read_body(reader, limits).await,
+            Err(MpesaError::DeadlineExceeded)
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+/// What happens to a request whose `send` future is dropped, or whose `CancellationToken` is
+/// cancelled, before its response is received, set per service with `MpesaBuilder::on_cancel`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum OnCancel {
+    /// The request is aborted where it stands. A request aborted after reaching the Safaricom
+    /// API may still be processed, leaving the payment in an unknown state until its result
+    /// callback arrives.
+    #[default]
+    Abort,
+    /// The request carries on in the background until its response is received, so that it
+    /// lands in the `IdempotencyStore` and on the tracing span of the request, and is waited on
+    /// by `Mpesa::shutdown`
+    Complete,
+}
+
+/// A token cancelling the requests of the clients returned by `Mpesa::with_cancellation`, for
+/// instance when the HTTP client of a checkout disconnects. Cancelled requests fail with
+/// `MpesaError::Cancelled`, and are aborted or completed in the background according to the
+/// `OnCancel` of their service.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the requests in flight with the token and those sent with it afterwards
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Completes once the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            // Created before checking the flag so that a `cancel` in between still wakes it
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_every_waiter() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        let waiters = async {
+            tokio::join!(token.cancelled(), clone.cancelled());
+        };
+        let cancel = async {
+            tokio::task::yield_now().await;
+            assert!(!token.is_cancelled());
+            clone.cancel();
+        };
+        tokio::join!(waiters, cancel);
+        assert!(token.is_cancelled());
+
+        // Completes right away once cancelled
+        token.cancelled().await;
+    }
+}
@@ -1,42 +1,252 @@
-use std::cell::RefCell;
-use std::time::Duration;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 use cached::Cached;
-use openssl::base64;
-use openssl::rsa::Padding;
-use openssl::x509::X509;
-use reqwest::Client as HttpClient;
-use secrecy::{ExposeSecret, Secret};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-
-use crate::auth::AUTH;
-use crate::environment::ApiEnvironment;
+#[cfg(feature = "openssl")]
+use openssl::{base64, rsa::Padding, x509::X509};
+use reqwest::header::HeaderMap;
+#[cfg(feature = "__request")]
+use reqwest::header::CONTENT_TYPE;
+#[cfg(feature = "__tls")]
+use reqwest::Certificate;
+#[cfg(feature = "native-tls")]
+use reqwest::Identity;
+use reqwest::{Client as HttpClient, Proxy, StatusCode, Url};
+#[cfg(feature = "openssl")]
+use secrecy::ExposeSecret;
+use secrecy::Secret;
+#[cfg(feature = "__request")]
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::auth::{TokenInfo, AUTH};
+#[cfg(feature = "bill_manager")]
+use crate::callbacks::C2bTransaction;
+use crate::cancellation::OnCancel;
+#[cfg(any(feature = "b2b", feature = "b2c"))]
+use crate::constants::CommandId;
+#[cfg(feature = "openssl")]
+use crate::constants::SANDBOX_INITIATOR_PASSWORD;
+use crate::constants::{Service, ServiceCategory, REDACTED};
+use crate::credentials::{CredentialPool, CredentialSelection, Credentials};
+use crate::environment::{ApiEnvironment, Environment};
+#[cfg(feature = "openssl")]
+use crate::health::CertificateValidity;
+use crate::health::{HealthCheck, Preflight, PreflightCheck, PreflightResult};
+use crate::id::IdStrategy;
+#[cfg(feature = "openssl")]
+use crate::initiators::{InitiatorPool, WeightedInitiator};
+use crate::lanes::Priority;
+use crate::metrics::{Counter, MetricsSink};
+use crate::paths::Endpoint;
+#[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+use crate::queue::{self, Delivery, Queue, QueuedRequest, Replayed};
+use crate::retry::{self, RetryPolicies, RetryPolicy};
+#[cfg(feature = "account_balance")]
+use crate::services::AccountBalanceBuilder;
+#[cfg(feature = "b2c")]
+use crate::services::B2cBuilder;
+#[cfg(feature = "c2b_simulate")]
+use crate::services::C2bSimulateBuilder;
+#[cfg(feature = "express_request")]
+use crate::services::StkPushGuard;
+#[cfg(feature = "transaction_status")]
+use crate::services::TransactionStatusBuilder;
+#[cfg(feature = "b2b")]
+use crate::services::{B2bBuilder, MmfTransferBuilder};
+#[cfg(feature = "c2b_register")]
+use crate::services::{BulkC2bRegisterBuilder, C2bRegisterBuilder};
+#[cfg(feature = "bill_manager")]
 use crate::services::{
-    AccountBalanceBuilder, B2bBuilder, B2cBuilder, BulkInvoiceBuilder, C2bRegisterBuilder,
-    C2bSimulateBuilder, CancelInvoiceBuilder, DynamicQR, DynamicQRBuilder, MpesaExpress,
-    MpesaExpressBuilder, OnboardBuilder, OnboardModifyBuilder, ReconciliationBuilder,
-    SingleInvoiceBuilder, TransactionReversal, TransactionReversalBuilder,
-    TransactionStatusBuilder,
+    BulkInvoiceBuilder, CancelInvoiceBuilder, OnboardBuilder, OnboardModifyBuilder,
+    ReconciliationBuilder, ReconciliationResponse, SingleInvoiceBuilder,
+};
+#[cfg(feature = "dynamic_qr")]
+use crate::services::{DynamicQR, DynamicQRBuilder};
+#[cfg(feature = "express_request")]
+use crate::services::{MpesaExpress, MpesaExpressBuilder};
+#[cfg(feature = "transaction_reversal")]
+use crate::services::{TransactionReversal, TransactionReversalBuilder};
+use crate::shutdown::InFlight;
+use crate::status::{MaintenanceSchedule, MaintenanceWindow};
+#[cfg(any(
+    feature = "bill_manager",
+    feature = "c2b_register",
+    feature = "express_request"
+))]
+use crate::storage::Storage;
+#[cfg(all(feature = "openssl", any(test, feature = "test-utils")))]
+use crate::test_utils::CredentialSigner;
+#[cfg(feature = "test-utils")]
+use crate::test_utils::{Chaos, ChaosInjector};
+#[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+use crate::validator::normalize_msisdn;
+#[cfg(any(feature = "c2b_register", feature = "express_request"))]
+use crate::validator::validate_callback_url;
+#[cfg(any(feature = "b2b", feature = "b2c"))]
+use crate::validator::validate_parties;
+use crate::{auth, MpesaError, MpesaResult};
+#[cfg(feature = "__request")]
+use crate::{
+    cancellation::CancellationToken,
+    idempotency::{DynIdempotencyStore, IdempotencyStore},
+    json,
+    lanes::Lanes,
+    quota::{self, Quota, Quotas},
+    shadow::Shadow,
+    validator::is_sandbox_test_number,
+    ResponseError,
 };
-use crate::{auth, MpesaError, MpesaResult, ResponseError};
 
-/// Source: [test credentials](https://developer.safaricom.co.ke/test_credentials)
-const DEFAULT_INITIATOR_PASSWORD: &str = "Safaricom999!*!";
+#[cfg(feature = "openssl")]
+const DEFAULT_INITIATOR_PASSWORD: &str = SANDBOX_INITIATOR_PASSWORD;
 /// Get current package version from metadata
 const CARGO_PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Error code returned by the Safaricom API when the bearer token is invalid or has been revoked
+#[cfg(feature = "__request")]
+const INVALID_ACCESS_TOKEN_ERROR_CODE: &str = "404.001.03";
+/// Response code of requests rejected as a duplicate of a transaction already processed
+#[cfg(feature = "__request")]
+const DUPLICATE_DETECTED_CODE: &str = "15";
+/// Default time allowed to establish a connection
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time allowed for a whole request, including reading the response body
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Request body fields that hold a phone number or shortcode
+#[cfg(feature = "__request")]
+const PARTY_FIELDS: [&str; 10] = [
+    "BusinessShortCode",
+    "PartyA",
+    "PartyB",
+    "PhoneNumber",
+    "Msisdn",
+    "ShortCode",
+    "ReceiverParty",
+    "CPI",
+    "shortcode",
+    "billedPhoneNumber",
+];
+
+/// The initiator password, and the security credential generated from it for the current
+/// certificate, which is reused until the password is rotated
+pub(crate) struct Initiator {
+    #[cfg_attr(not(feature = "openssl"), allow(dead_code))]
+    password: Option<Secret<String>>,
+    #[cfg(feature = "openssl")]
+    security_credential: Option<Secret<String>>,
+}
+
+impl Initiator {
+    pub(crate) fn new(password: Option<Secret<String>>) -> Self {
+        Initiator {
+            password,
+            #[cfg(feature = "openssl")]
+            security_credential: None,
+        }
+    }
+
+    /// If `None`, the default sandbox password from the test credentials is used
+    #[cfg(feature = "openssl")]
+    fn password(&self) -> &str {
+        self.password
+            .as_ref()
+            .map_or(DEFAULT_INITIATOR_PASSWORD, |password| {
+                password.expose_secret()
+            })
+    }
+}
 
 /// Mpesa client that will facilitate communication with the Safaricom API
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Mpesa {
-    consumer_key: String,
-    consumer_secret: Secret<String>,
-    initiator_password: RefCell<Option<Secret<String>>>,
+    credentials: Arc<CredentialPool>,
+    initiator: Arc<RwLock<Initiator>>,
+    #[cfg(feature = "openssl")]
+    initiators: Arc<InitiatorPool>,
     pub(crate) base_url: String,
+    fallback_base_urls: Vec<String>,
+    urls: Arc<EndpointUrls>,
+    /// Domains pinned with `MpesaBuilder::resolve`, which are not looked up with DNS
+    pinned_domains: Vec<String>,
+    /// Whether requests go through a proxy set with `MpesaBuilder::proxy`, which may resolve the
+    /// hostnames of the Safaricom API itself
+    proxied: bool,
+    #[cfg(feature = "openssl")]
     certificate: String,
+    #[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+    normalize_msisdn: bool,
+    #[cfg(feature = "__request")]
+    reject_sandbox_test_numbers: bool,
+    #[cfg(feature = "__request")]
+    pub(crate) fixed_decimal_amounts: bool,
+    /// Whether the client calls the production environment, where sandbox-only APIs do not exist
+    #[cfg(any(feature = "__request", feature = "openssl"))]
+    production: bool,
+    #[cfg(any(
+        feature = "b2b",
+        feature = "b2c",
+        feature = "bill_manager",
+        feature = "express_request"
+    ))]
+    pub(crate) validation: bool,
+    #[cfg(any(feature = "c2b_register", feature = "express_request"))]
+    allow_private_callback_urls: bool,
+    id_strategy: IdStrategy,
+    default_urls: DefaultUrls,
+    api_versions: HashMap<Service, u8>,
+    on_cancel: HashMap<Service, OnCancel>,
+    #[cfg(feature = "__request")]
+    cancellation: Option<CancellationToken>,
+    #[cfg(feature = "__request")]
+    quotas: Arc<Quotas>,
+    pub(crate) retry_policies: RetryPolicies,
+    #[cfg(feature = "__request")]
+    idempotency_store: Option<Arc<dyn DynIdempotencyStore>>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    idempotency_key: Option<Arc<str>>,
+    #[cfg(feature = "express_request")]
+    pub(crate) stk_push_guard: Option<Arc<StkPushGuard>>,
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    queue: Option<Arc<Queue>>,
+    #[cfg(feature = "__request")]
+    shadow: Option<Arc<Shadow>>,
+    maintenance: Arc<RwLock<MaintenanceSchedule>>,
+    in_flight: Arc<InFlight>,
+    #[cfg(feature = "__request")]
+    lanes: Option<Arc<Lanes>>,
+    priority: Priority,
+    #[cfg(all(feature = "openssl", any(test, feature = "test-utils")))]
+    credential_signer: Option<Arc<dyn CredentialSigner>>,
+    #[cfg(feature = "test-utils")]
+    chaos: Option<Arc<ChaosInjector>>,
     pub(crate) http_client: HttpClient,
 }
 
+impl fmt::Debug for Mpesa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mpesa")
+            .field("consumer_key", &REDACTED)
+            .field("consumer_secret", &REDACTED)
+            .field("credentials", &self.credentials.len())
+            .field("credential_selection", &self.credentials.selection())
+            .field("initiator_password", &REDACTED)
+            .field("base_url", &self.base_url)
+            .field("fallback_base_urls", &self.fallback_base_urls)
+            .field("id_strategy", &self.id_strategy)
+            .field("default_urls", &self.default_urls)
+            .field("api_versions", &self.api_versions)
+            .field("on_cancel", &self.on_cancel)
+            .field("retry_policies", &self.retry_policies)
+            .field("idempotency_key", &self.idempotency_key)
+            .field("priority", &self.priority)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Mpesa {
     /// Constructs a new `Mpesa` client.
     ///
@@ -65,59 +275,391 @@ impl Mpesa {
         consumer_secret: S,
         environment: impl ApiEnvironment,
     ) -> Self {
-        let http_client = HttpClient::builder()
-            .connect_timeout(Duration::from_secs(10))
-            .user_agent(format!("mpesa-rust@{CARGO_PACKAGE_VERSION}"))
+        Self::builder(consumer_key, consumer_secret, environment)
             .build()
-            .expect("Error building http client");
+            .expect("Error building http client")
+    }
 
-        let base_url = environment.base_url().to_owned();
-        let certificate = environment.get_certificate().to_owned();
+    /// Constructs a client for the Safaricom sandbox, preset with the initiator password and the
+    /// M-Pesa Express passkey from the sandbox test credentials.
+    ///
+    /// The test MSISDN and shortcodes are available as [`SANDBOX_TEST_MSISDN`](crate::SANDBOX_TEST_MSISDN),
+    /// [`SANDBOX_EXPRESS_SHORTCODE`](crate::SANDBOX_EXPRESS_SHORTCODE) and
+    /// [`SANDBOX_SHORTCODES`](crate::SANDBOX_SHORTCODES).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use mpesa::{Mpesa, SANDBOX_EXPRESS_SHORTCODE, SANDBOX_TEST_MSISDN};
+    ///
+    /// let client = Mpesa::sandbox("consumer_key", "consumer_secret");
+    /// let request = client
+    ///     .express_request()
+    ///     .business_short_code(SANDBOX_EXPRESS_SHORTCODE)
+    ///     .phone_number(SANDBOX_TEST_MSISDN)
+    ///     .party_a(SANDBOX_TEST_MSISDN)
+    ///     .party_b(SANDBOX_EXPRESS_SHORTCODE);
+    /// ```
+    /// # Panics
+    /// This method can panic if a TLS backend cannot be initialized for the internal http_client
+    pub fn sandbox<S: Into<String>>(consumer_key: S, consumer_secret: S) -> Self {
+        let builder = Self::builder(consumer_key, consumer_secret, Environment::Sandbox);
+        #[cfg(feature = "openssl")]
+        let builder = builder.initiator_password(SANDBOX_INITIATOR_PASSWORD);
+        builder.build().expect("Error building http client")
+    }
 
-        Self {
-            consumer_key: consumer_key.into(),
-            consumer_secret: Secret::new(consumer_secret.into()),
-            initiator_password: RefCell::new(None),
-            base_url,
-            certificate,
-            http_client,
+    /// Creates a `MpesaBuilder` for configuring the client before constructing it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use mpesa::{Environment, Mpesa};
+    ///
+    /// let client = Mpesa::builder("consumer_key", "consumer_secret", Environment::Sandbox)
+    ///     .connect_timeout(Duration::from_secs(5))
+    ///     .timeout(Duration::from_secs(20))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder<S: Into<String>>(
+        consumer_key: S,
+        consumer_secret: S,
+        environment: impl ApiEnvironment,
+    ) -> MpesaBuilder {
+        MpesaBuilder::new(consumer_key, consumer_secret, environment)
+    }
+
+    /// Sets the initiator password of a client built without one
+    #[deprecated(
+        note = "use `MpesaBuilder::initiator_password` or `Mpesa::rotate_initiator_password`"
+    )]
+    pub fn set_initiator_password<S: Into<String>>(&self, initiator_password: S) {
+        self.rotate_initiator_password(initiator_password);
+    }
+
+    /// Replaces the initiator password, e.g. after it has been changed on the M-Pesa portal,
+    /// without rebuilding the client. The security credential generated from the previous
+    /// password is discarded, and requests sent after this call use the new password.
+    /// The password is shared with the clones of the client.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use mpesa::{Environment, Mpesa};
+    ///
+    /// let client = Mpesa::builder("consumer_key", "consumer_secret", Environment::Production)
+    ///     .initiator_password("your_initiator_password")
+    ///     .build()
+    ///     .unwrap();
+    /// client.rotate_initiator_password("your_new_initiator_password");
+    /// ```
+    pub fn rotate_initiator_password<S: Into<String>>(&self, initiator_password: S) {
+        let mut initiator = self
+            .initiator
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *initiator = Initiator::new(Some(Secret::new(initiator_password.into())));
+    }
+
+    /// Generates a correlation identifier in the format chosen with `MpesaBuilder::id_strategy`,
+    /// suitable for keying requests and their callbacks in your own records
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use mpesa::{Environment, IdStrategy, Mpesa};
+    ///
+    /// let client = Mpesa::builder("consumer_key", "consumer_secret", Environment::Sandbox)
+    ///     .id_strategy(IdStrategy::UuidV7)
+    ///     .build()
+    ///     .unwrap();
+    /// let id = client.generate_id();
+    /// ```
+    pub fn generate_id(&self) -> String {
+        self.id_strategy.generate()
+    }
+
+    /// Returns the maintenance window of the Safaricom API in progress, if any
+    pub fn maintenance_window(&self) -> Option<MaintenanceWindow> {
+        self.maintenance
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .window_at(SystemTime::now())
+            .cloned()
+    }
+
+    /// Returns `true` if the Safaricom API is down for planned maintenance
+    pub fn is_maintenance_window(&self) -> bool {
+        self.maintenance_window().is_some()
+    }
+
+    /// Replaces the planned maintenance windows of the Safaricom API, e.g. when new ones are
+    /// announced. The schedule is shared with the clones of the client.
+    pub fn set_maintenance_schedule(&self, schedule: MaintenanceSchedule) {
+        *self
+            .maintenance
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = schedule;
+    }
+
+    /// Fetches a status document listing the planned maintenance windows of the Safaricom API
+    /// from `url`, and replaces the schedule of the client with it. See
+    /// `MaintenanceSchedule::from_json` for its format.
+    ///
+    /// # Errors
+    /// Returns a `NetworkError` if the document cannot be fetched, and a `ParseError` if it is not
+    /// valid
+    pub async fn fetch_maintenance_schedule(&self, url: &str) -> MpesaResult<MaintenanceSchedule> {
+        let body = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let schedule = MaintenanceSchedule::from_json(&body)?;
+        self.set_maintenance_schedule(schedule.clone());
+        Ok(schedule)
+    }
+
+    /// Returns a client sending its requests with the idempotency `key`: the first accepted
+    /// request is sent to the Safaricom API, and later ones with the same key return its
+    /// response, as kept by the `MpesaBuilder::idempotency_store`.
+    ///
+    /// Keys are meant for a single request, such as the payment of an order. A request sent with a
+    /// key that is in flight fails with `MpesaError::IdempotencyConflict`, one sent without a
+    /// store with `MpesaError::Message`.
+    pub fn idempotent(&self, key: impl Into<String>) -> Mpesa {
+        Mpesa {
+            idempotency_key: Some(key.into().into()),
+            ..self.clone()
         }
     }
 
-    /// Gets the initiator password
-    /// If `None`, the default password is `"Safcom496!"`
-    pub(crate) fn initiator_password(&self) -> String {
-        self.initiator_password
-            .borrow()
-            .as_ref()
-            .map(|password| password.expose_secret().into())
-            .unwrap_or(DEFAULT_INITIATOR_PASSWORD.to_owned())
+    /// Returns the requests queued while the Safaricom API was down, oldest first
+    ///
+    /// # Errors
+    /// Returns a `MpesaError::Message` if the client has no `MpesaBuilder::degradation_queue`, and
+    /// the error of the storage if it cannot be read
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    pub async fn queued_requests(&self) -> MpesaResult<Vec<QueuedRequest>> {
+        self.degradation_queue()?.requests().await
     }
 
-    /// Get the consumer key
-    pub(crate) fn consumer_key(&self) -> &str {
-        &self.consumer_key
+    /// Sends the queued requests in the order they were queued, removing them from the queue
+    /// whether the Safaricom API accepts or rejects them. Stops at the first request that fails
+    /// because the API still cannot be reached, which stays queued along with the ones after it.
+    /// A request that times out is removed with its error, as the API may have processed it.
+    ///
+    /// The queue should be flushed by a single instance of an application, since requests are only
+    /// removed once they are sent.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError::Message` if the client has no `MpesaBuilder::degradation_queue`, and
+    /// the error of the storage if it cannot be read or updated
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    pub async fn flush_queue(&self) -> MpesaResult<Vec<Replayed>> {
+        let queue = self.degradation_queue()?;
+        let mut replayed = vec![];
+        for request in queue.requests().await? {
+            let result = self
+                .send_with_meta(Request {
+                    method: reqwest::Method::POST,
+                    service: request.service,
+                    path: Cow::Owned(request.path.clone()),
+                    body: &request.body,
+                })
+                .await
+                .map(|sent| sent.response);
+            // A request that timed out is not kept, as the API may have processed it
+            if matches!(&result, Err(e) if queue::never_reached(e)) {
+                break;
+            }
+            queue.remove(&request.id).await?;
+            replayed.push(Replayed { request, result });
+        }
+        Ok(replayed)
     }
 
-    /// Get the consumer secret
-    pub(crate) fn consumer_secret(&self) -> &str {
-        self.consumer_secret.expose_secret()
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    fn degradation_queue(&self) -> MpesaResult<&Queue> {
+        self.queue.as_deref().ok_or(MpesaError::Message(
+            "Queuing requests requires an MpesaBuilder::degradation_queue",
+        ))
     }
 
-    /// Optional in development but required for production for the following apis:
-    /// - `account_balance`
-    /// - `b2b`
-    /// - `b2c`
-    /// - `transaction_reversal`
-    /// - `transaction_status`
+    /// Returns a client sending its requests with `priority`. Once the
+    /// `MpesaBuilder::max_concurrent_requests` of the client and its clones are in flight, waiting
+    /// requests are sent by priority: customer-facing STK pushes can be sent with
+    /// `Priority::High` ahead of batch jobs sent with `Priority::Low`. Requests are sent with
+    /// `Priority::Normal` by default.
+    pub fn with_priority(&self, priority: Priority) -> Mpesa {
+        Mpesa {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a client whose requests are cancelled by `token`, failing with
+    /// `MpesaError::Cancelled`. Whether a cancelled request is aborted or completed in the
+    /// background is set per service with `MpesaBuilder::on_cancel`.
+    #[cfg(feature = "__request")]
+    pub fn with_cancellation(&self, token: CancellationToken) -> Mpesa {
+        Mpesa {
+            cancellation: Some(token),
+            ..self.clone()
+        }
+    }
+
+    /// Stops sending requests and waits up to `timeout` for the requests in flight to complete,
+    /// for instance before a process is replaced during a rolling deploy.
+    ///
+    /// The client and its clones are shut down together: requests sent afterwards fail with
+    /// `MpesaError::ShutDown`.
+    ///
+    /// # Errors
+    /// Returns `MpesaError::DeadlineExceeded` if requests are still in flight after `timeout`
+    pub async fn shutdown(&self, timeout: Duration) -> MpesaResult<()> {
+        self.in_flight.shutdown(timeout).await
+    }
+
+    /// Returns `true` once `shutdown` has been called on the client or one of its clones
+    pub fn is_shut_down(&self) -> bool {
+        self.in_flight.is_closed()
+    }
+
+    /// Returns the number of requests being sent by the client and its clones
+    pub fn in_flight_requests(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Returns the version of the API called for `service`
+    pub fn api_version(&self, service: Service) -> u8 {
+        self.api_versions
+            .get(&service)
+            .copied()
+            .unwrap_or_else(|| service.default_api_version())
+    }
+
+    /// Returns the path of `endpoint` called by the client, at the version of its API set with
+    /// `MpesaBuilder::api_version`
+    pub fn endpoint_path(&self, endpoint: Endpoint) -> Cow<'static, str> {
+        match endpoint.service() {
+            Some(service) => self.api_path(service, endpoint.path()),
+            None => Cow::Borrowed(endpoint.path()),
+        }
+    }
+
+    /// Returns `path` with its version segment, e.g. `v1` in `mpesa/stkpush/v1/processrequest`,
+    /// replaced by the version configured for `service`
+    pub(crate) fn api_path(&self, service: Service, path: &'static str) -> Cow<'static, str> {
+        let default = service.default_api_version();
+        let version = self.api_version(service);
+        if version == default {
+            return Cow::Borrowed(path);
+        }
+
+        let default = format!("v{default}");
+        let version = format!("v{version}");
+        Cow::Owned(
+            path.split('/')
+                .map(|segment| {
+                    if segment == default {
+                        &version
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+
+    /// Resolves a url of `kind` given to a request builder: `None` falls back to the default url
+    /// set on the client, and a url without a scheme, such as `b2c`, is joined onto it
+    #[cfg(any(
+        feature = "account_balance",
+        feature = "b2b",
+        feature = "b2c",
+        feature = "express_request",
+        feature = "transaction_reversal",
+        feature = "transaction_status"
+    ))]
+    pub(crate) fn resolve_url<'a>(
+        &self,
+        kind: UrlKind,
+        url: Option<&'a str>,
+    ) -> Option<Cow<'a, str>> {
+        match (url, self.default_urls.get(kind)) {
+            (Some(url), Some(base)) if !url.contains("://") => {
+                Some(Cow::Owned(join_url(base, url)))
+            }
+            (Some(url), _) => Some(Cow::Borrowed(url)),
+            (None, base) => base.map(|base| Cow::Owned(base.to_owned())),
+        }
+    }
+
+    /// Returns `phone_number` normalized to the `2547XXXXXXXX` format if the client was built with
+    /// `MpesaBuilder::normalize_msisdn`, otherwise returns it unchanged
+    #[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+    pub(crate) fn msisdn<'a>(&self, phone_number: &'a str) -> Cow<'a, str> {
+        if self.normalize_msisdn {
+            normalize_msisdn(phone_number)
+        } else {
+            Cow::Borrowed(phone_number)
+        }
+    }
+
+    /// Checks that Safaricom can deliver callbacks to `url`, unless the client was built with
+    /// `MpesaBuilder::allow_private_callback_urls`
+    #[cfg(any(feature = "c2b_register", feature = "express_request"))]
+    pub(crate) fn check_callback_url(&self, url: &str) -> MpesaResult<()> {
+        if self.allow_private_callback_urls {
+            return Ok(());
+        }
+        validate_callback_url(url)
+    }
+
+    /// Checks that `party_a` and `party_b` are of the types required by `command_id`, unless
+    /// validation is disabled
+    #[cfg(any(feature = "b2b", feature = "b2c"))]
+    pub(crate) fn check_parties(
+        &self,
+        command_id: CommandId,
+        party_a: &str,
+        party_b: &str,
+    ) -> MpesaResult<()> {
+        if !self.validation {
+            return Ok(());
+        }
+        validate_parties(command_id, party_a, party_b)
+    }
+
+    /// Checks if the client can be authenticated with each of its credentials
+    pub async fn is_connected(&self) -> bool {
+        for credentials in self.credentials.iter() {
+            if self.auth_with(credentials).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Runs diagnostics suitable for readiness probes: the latency of a fresh authentication
+    /// round trip, the expiry of the cached token, the base url and the validity window of the
+    /// environment's certificate.
     ///
-    /// You will need to call this method and set your production initiator password.
-    /// If in development, a default initiator password from the test credentials is already pre-set
+    /// The authentication request always goes to the Safaricom API and does not touch the
+    /// token cache.
     ///
     /// # Example
     ///
     /// ```rust
-    /// use mpesa::{Mpesa, Environment};
+    /// use mpesa::{Environment, Mpesa};
     ///
     /// #[tokio::main]
     /// async fn main() {
@@ -128,17 +670,136 @@ impl Mpesa {
     ///         dotenvy::var("CONSUMER_SECRET").unwrap(),
     ///         Environment::Sandbox,
     ///     );
-    ///     client.set_initiator_password("your_initiator_password");
-    ///     assert!(client.is_connected().await);
+    ///
+    ///     let health = client.health_check().await;
+    ///     assert!(health.is_healthy());
     /// }
     /// ```
-    pub fn set_initiator_password<S: Into<String>>(&self, initiator_password: S) {
-        *self.initiator_password.borrow_mut() = Some(Secret::new(initiator_password.into()));
+    pub async fn health_check(&self) -> HealthCheck {
+        let started_at = Instant::now();
+        let auth = auth::auth_no_cache(self, self.credentials.primary())
+            .await
+            .map(|_| started_at.elapsed());
+
+        HealthCheck {
+            auth,
+            token: self.token_info().await,
+            base_url: self.base_url.clone(),
+            #[cfg(feature = "openssl")]
+            certificate_validity: CertificateValidity::from_pem(&self.certificate),
+        }
     }
 
-    /// Checks if the client can be authenticated
-    pub async fn is_connected(&self) -> bool {
-        self.auth().await.is_ok()
+    /// Checks the configuration of the client, to be run when a service boots so that a
+    /// misconfiguration fails its deploy rather than the first payment of a customer:
+    /// - the certificate of the environment parses and holds a RSA public key
+    /// - the initiator password, and the password of every initiator registered with
+    ///   `MpesaBuilder::initiator`, encrypts into a security credential. In production, the
+    ///   initiator password must have been set.
+    /// - an access token is issued for every consumer key/secret pair, bypassing the token cache
+    /// - the hosts of the base urls and of the default callback urls resolve. Hosts pinned with
+    ///   `MpesaBuilder::resolve` are not looked up, nor are those of the base urls when requests
+    ///   go through a `MpesaBuilder::proxy`.
+    ///
+    /// Every check is made, whether or not the ones before it passed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use mpesa::{Environment, Mpesa};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let client = Mpesa::new("consumer_key", "consumer_secret", Environment::Sandbox);
+    ///
+    ///     let preflight = client.preflight().await;
+    ///     if !preflight.is_ok() {
+    ///         eprint!("{preflight}");
+    ///         std::process::exit(1);
+    ///     }
+    /// }
+    /// ```
+    pub async fn preflight(&self) -> Preflight {
+        let mut results = vec![];
+        let mut check = |check: PreflightCheck, result: MpesaResult<()>| {
+            results.push(PreflightResult { check, result });
+        };
+
+        #[cfg(feature = "openssl")]
+        {
+            check(PreflightCheck::Certificate, self.check_certificate());
+            let password_set = self
+                .initiator
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .password
+                .is_some();
+            let result = if self.production && !password_set {
+                Err(MpesaError::Message(
+                    "The initiator password must be set with `MpesaBuilder::initiator_password` in production",
+                ))
+            } else {
+                self.gen_security_credentials().map(drop)
+            };
+            check(PreflightCheck::InitiatorPassword(None), result);
+            for initiator in self.initiators.iter() {
+                check(
+                    PreflightCheck::InitiatorPassword(Some(initiator.name().to_owned())),
+                    self.security_credential_for(initiator.name()).map(drop),
+                );
+            }
+        }
+
+        for (index, credentials) in self.credentials.iter().enumerate() {
+            let result = auth::auth_no_cache(self, credentials).await.map(drop);
+            check(PreflightCheck::Authentication(index), result);
+        }
+
+        for (host, port) in self.preflight_hosts() {
+            let result = match tokio::net::lookup_host((host.as_str(), port)).await {
+                Ok(mut addrs) => addrs
+                    .next()
+                    .map(drop)
+                    .ok_or(MpesaError::Message("The host resolves to no address")),
+                Err(e) => Err(MpesaError::IoError(e)),
+            };
+            check(PreflightCheck::Dns(host), result);
+        }
+
+        Preflight { results }
+    }
+
+    #[cfg(feature = "openssl")]
+    fn check_certificate(&self) -> MpesaResult<()> {
+        X509::from_pem(self.certificate.as_bytes())?
+            .public_key()?
+            .rsa()?;
+        Ok(())
+    }
+
+    /// The hosts, and their ports, looked up by `preflight`
+    fn preflight_hosts(&self) -> Vec<(String, u16)> {
+        let base_urls = std::iter::once(&self.base_url)
+            .chain(&self.fallback_base_urls)
+            .filter(|_| !self.proxied);
+        let default_urls = [UrlKind::Result, UrlKind::Timeout, UrlKind::Callback]
+            .into_iter()
+            .filter_map(|kind| self.default_urls.get(kind));
+
+        let mut hosts: Vec<(String, u16)> = vec![];
+        for url in base_urls.map(String::as_str).chain(default_urls) {
+            let Ok(url) = url::Url::parse(url) else {
+                continue;
+            };
+            let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+                continue;
+            };
+            let host = (host.to_owned(), port);
+            if !self.pinned_domains.contains(&host.0) && !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        hosts
     }
 
     /// This API generates the tokens for authenticating your API calls. This is the first API you will engage with within the set of APIs available because all the other APIs require authentication information from this API to work.
@@ -146,128 +807,338 @@ impl Mpesa {
     /// Safaricom API docs [reference](https://developer.safaricom.co.ke/APIs/Authorization)
     ///
     /// Returns auth token as a `String` that is ttl-cached in memory for subsequent requests.
+    /// When the client has several credentials, the next one is picked according to its
+    /// `CredentialSelection`.
     ///
     /// # Errors
     /// Returns a `MpesaError` on failure
+    #[cfg(test)]
     pub(crate) async fn auth(&self) -> MpesaResult<String> {
-        if let Some(token) = AUTH.lock().await.cache_get(&self.consumer_key) {
-            return Ok(token.to_owned());
+        self.auth_with(self.credentials.select()).await
+    }
+
+    /// Returns an auth token for `credentials`. Tokens are cached per consumer key.
+    async fn auth_with(&self, credentials: &Credentials) -> MpesaResult<String> {
+        {
+            let mut cache = AUTH.lock().await;
+            // Expired tokens are only removed from the store of the cache once they are looked up,
+            // and are left out of `key_order`
+            let cached = cache
+                .get_store()
+                .key_order()
+                .any(|key| *key == credentials.cache_key());
+            match cache.cache_get(&credentials.cache_key()) {
+                Some(token) if token.expires_in().is_some() => {
+                    self.count(Counter::AuthCacheHits);
+                    return Ok(token.token.to_owned());
+                }
+                // Issued with a shorter lifetime than that of the cache
+                Some(_) => {
+                    cache.cache_remove(&credentials.cache_key());
+                }
+                None => {}
+            }
+            let full = !cached
+                && cache
+                    .cache_capacity()
+                    .is_some_and(|capacity| cache.cache_size() >= capacity);
+            if cached || full {
+                self.count(Counter::AuthCacheEvictions);
+            }
         }
+        self.count(Counter::AuthCacheMisses);
 
         // Generate a new access token
-        let new_token = auth::auth(self).await?;
+        let new_token = auth::auth(self, credentials).await?;
 
         // Double-check if the access token is cached by another thread
-        if let Some(token) = AUTH.lock().await.cache_get(&self.consumer_key) {
-            return Ok(token.to_owned());
+        let mut cache = AUTH.lock().await;
+        if let Some(token) = cache
+            .cache_get(&credentials.cache_key())
+            .filter(|token| token.expires_in().is_some())
+        {
+            return Ok(token.token.to_owned());
         }
 
         // Cache the new token
-        AUTH.lock()
+        let token = new_token.token.to_owned();
+        cache.cache_set(credentials.cache_key(), new_token);
+
+        Ok(token)
+    }
+
+    /// Returns whether an access token is cached for this client's primary credentials, those it
+    /// was created with, and when it expires.
+    ///
+    /// Useful for exporting token expiry as a metric. Tokens can be pre-warmed, for example at deploy
+    /// time, by calling `is_connected`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use mpesa::{Environment, Mpesa};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let client = Mpesa::new("consumer_key", "consumer_secret", Environment::Sandbox);
+    ///     assert!(!client.token_info().await.is_cached());
+    /// }
+    /// ```
+    pub async fn token_info(&self) -> TokenInfo {
+        let cache = AUTH.lock().await;
+        let lifespan = Duration::from_secs(cache.cache_lifespan().unwrap_or_default());
+
+        // The token is dropped from the cache once either the lifetime it was issued with or the
+        // lifespan of the cache has elapsed
+        let expires_in = cache
+            .key_order()
+            .zip(cache.value_order())
+            .find(|(key, _)| **key == self.credentials.primary().cache_key())
+            .and_then(|(_, (created_at, token))| {
+                let cached_for = lifespan.saturating_sub(created_at.elapsed());
+                token
+                    .expires_in()
+                    .map(|expires_in| expires_in.min(cached_for))
+            })
+            .filter(|expires_in| !expires_in.is_zero());
+
+        TokenInfo {
+            expires_in,
+            expires_at: expires_in.map(|expires_in| SystemTime::now() + expires_in),
+        }
+    }
+
+    /// Evicts the access token cached for `credentials` so that the next request re-authenticates
+    #[cfg(feature = "__request")]
+    async fn invalidate_auth(&self, credentials: &Credentials) {
+        if AUTH
+            .lock()
             .await
-            .cache_set(self.consumer_key.clone(), new_token.to_owned());
+            .cache_remove(&credentials.cache_key())
+            .is_some()
+        {
+            self.count(Counter::AuthCacheEvictions);
+        }
+    }
 
-        Ok(new_token)
+    /// Reports `counter` to the `MpesaBuilder::metrics_sink`, if any
+    fn count(&self, counter: Counter) {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(counter);
+        }
     }
 
     #[cfg(feature = "b2c")]
     #[doc = include_str!("../docs/client/b2c.md")]
-    pub fn b2c<'a>(&'a self, initiator_name: &'a str) -> B2cBuilder {
+    pub fn b2c<'a>(&'a self, initiator_name: &'a str) -> B2cBuilder<'a> {
         B2cBuilder::new(self, initiator_name)
     }
 
+    /// Creates a `B2cBuilder` sent as one of the initiators registered with
+    /// `MpesaBuilder::initiator`, picked at random in proportion to their weights. The initiator
+    /// picked is in the `initiator` of the response of `send_with_meta`.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if no initiator with a weight above `0` was registered
+    #[cfg(feature = "b2c")]
+    pub fn b2c_routed(&self) -> MpesaResult<B2cBuilder<'_>> {
+        Ok(B2cBuilder::new(self, self.route_initiator()?))
+    }
+
     #[cfg(feature = "b2b")]
     #[doc = include_str!("../docs/client/b2b.md")]
-    pub fn b2b<'a>(&'a self, initiator_name: &'a str) -> B2bBuilder {
+    pub fn b2b<'a>(&'a self, initiator_name: &'a str) -> B2bBuilder<'a> {
         B2bBuilder::new(self, initiator_name)
     }
 
+    /// Creates a `B2bBuilder` sent as one of the initiators registered with
+    /// `MpesaBuilder::initiator`, picked at random in proportion to their weights. The initiator
+    /// picked is in the `initiator` of the response of `send_with_meta`.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if no initiator with a weight above `0` was registered
+    #[cfg(feature = "b2b")]
+    pub fn b2b_routed(&self) -> MpesaResult<B2bBuilder<'_>> {
+        Ok(B2bBuilder::new(self, self.route_initiator()?))
+    }
+
+    /// Picks the initiator of a routed request
+    #[cfg(any(feature = "b2b", feature = "b2c"))]
+    fn route_initiator(&self) -> MpesaResult<&str> {
+        self.initiators
+            .select()
+            .map(WeightedInitiator::name)
+            .ok_or(MpesaError::Message(
+                "No initiator with a weight above 0 was registered with `MpesaBuilder::initiator`",
+            ))
+    }
+
+    #[cfg(feature = "b2b")]
+    #[doc = include_str!("../docs/client/mmf_transfer.md")]
+    pub fn transfer_mmf_to_utility<'a>(
+        &'a self,
+        initiator_name: &'a str,
+    ) -> MmfTransferBuilder<'a> {
+        MmfTransferBuilder::new(self, initiator_name)
+    }
+
     #[cfg(feature = "bill_manager")]
     #[doc = include_str!("../docs/client/bill_manager/onboard.md")]
-    pub fn onboard(&self) -> OnboardBuilder {
+    pub fn onboard(&self) -> OnboardBuilder<'_> {
         OnboardBuilder::new(self)
     }
 
     #[cfg(feature = "bill_manager")]
     #[doc = include_str!("../docs/client/bill_manager/onboard_modify.md")]
-    pub fn onboard_modify(&self) -> OnboardModifyBuilder {
+    pub fn onboard_modify(&self) -> OnboardModifyBuilder<'_> {
         OnboardModifyBuilder::new(self)
     }
 
     #[cfg(feature = "bill_manager")]
     #[doc = include_str!("../docs/client/bill_manager/bulk_invoice.md")]
-    pub fn bulk_invoice(&self) -> BulkInvoiceBuilder {
+    pub fn bulk_invoice(&self) -> BulkInvoiceBuilder<'_> {
         BulkInvoiceBuilder::new(self)
     }
 
     #[cfg(feature = "bill_manager")]
     #[doc = include_str!("../docs/client/bill_manager/single_invoice.md")]
-    pub fn single_invoice(&self) -> SingleInvoiceBuilder {
+    pub fn single_invoice(&self) -> SingleInvoiceBuilder<'_> {
         SingleInvoiceBuilder::new(self)
     }
 
     #[cfg(feature = "bill_manager")]
     #[doc = include_str!("../docs/client/bill_manager/reconciliation.md")]
-    pub fn reconciliation(&self) -> ReconciliationBuilder {
+    pub fn reconciliation(&self) -> ReconciliationBuilder<'_> {
         ReconciliationBuilder::new(self)
     }
 
+    /// Sends the customer of a C2B payment received at the `ConfirmationURL` an e-receipt for it,
+    /// through a Bill Manager reconciliation request filled in by
+    /// `ReconciliationBuilder::c2b_confirmation`
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the payment is malformed or the request fails
+    #[cfg(feature = "bill_manager")]
+    pub async fn send_e_receipt(
+        &self,
+        transaction: &C2bTransaction,
+    ) -> MpesaResult<ReconciliationResponse> {
+        self.reconciliation()
+            .c2b_confirmation(transaction)?
+            .send()
+            .await
+    }
+
     #[cfg(feature = "bill_manager")]
     #[doc = include_str!("../docs/client/bill_manager/cancel_invoice.md")]
-    pub fn cancel_invoice(&self) -> CancelInvoiceBuilder {
+    pub fn cancel_invoice(&self) -> CancelInvoiceBuilder<'_> {
         CancelInvoiceBuilder::new(self)
     }
 
     #[cfg(feature = "c2b_register")]
     #[doc = include_str!("../docs/client/c2b_register.md")]
-    pub fn c2b_register(&self) -> C2bRegisterBuilder {
+    pub fn c2b_register(&self) -> C2bRegisterBuilder<'_> {
         C2bRegisterBuilder::new(self)
     }
 
+    /// Creates a `BulkC2bRegisterBuilder` registering the validation and confirmation urls of
+    /// several shortcodes at once, e.g. to (re)register every paybill managed by an aggregator
+    #[cfg(feature = "c2b_register")]
+    pub fn c2b_register_bulk(&self) -> BulkC2bRegisterBuilder<'_> {
+        BulkC2bRegisterBuilder::new(self)
+    }
+
     #[cfg(feature = "c2b_simulate")]
     #[doc = include_str!("../docs/client/c2b_simulate.md")]
-    pub fn c2b_simulate(&self) -> C2bSimulateBuilder {
+    pub fn c2b_simulate(&self) -> C2bSimulateBuilder<'_> {
         C2bSimulateBuilder::new(self)
     }
 
     #[cfg(feature = "account_balance")]
     #[doc = include_str!("../docs/client/account_balance.md")]
-    pub fn account_balance<'a>(&'a self, initiator_name: &'a str) -> AccountBalanceBuilder {
+    pub fn account_balance<'a>(&'a self, initiator_name: &'a str) -> AccountBalanceBuilder<'a> {
         AccountBalanceBuilder::new(self, initiator_name)
     }
 
     #[cfg(feature = "express_request")]
     #[doc = include_str!("../docs/client/express_request.md")]
-    pub fn express_request(&self) -> MpesaExpressBuilder {
+    pub fn express_request(&self) -> MpesaExpressBuilder<'_> {
         MpesaExpress::builder(self)
     }
 
     #[cfg(feature = "transaction_reversal")]
     #[doc = include_str!("../docs/client/transaction_reversal.md")]
-    pub fn transaction_reversal(&self) -> TransactionReversalBuilder {
+    pub fn transaction_reversal(&self) -> TransactionReversalBuilder<'_> {
         TransactionReversal::builder(self)
     }
 
     #[cfg(feature = "transaction_status")]
     #[doc = include_str!("../docs/client/transaction_status.md")]
-    pub fn transaction_status<'a>(&'a self, initiator_name: &'a str) -> TransactionStatusBuilder {
+    pub fn transaction_status<'a>(
+        &'a self,
+        initiator_name: &'a str,
+    ) -> TransactionStatusBuilder<'a> {
         TransactionStatusBuilder::new(self, initiator_name)
     }
 
     #[cfg(feature = "dynamic_qr")]
     #[doc = include_str!("../docs/client/dynamic_qr.md")]
-    pub fn dynamic_qr(&self) -> DynamicQRBuilder {
+    pub fn dynamic_qr(&self) -> DynamicQRBuilder<'_> {
         DynamicQR::builder(self)
     }
 
     /// Generates security credentials
     /// M-Pesa Core authenticates a transaction by decrypting the security credentials.
     /// Security credentials are generated by encrypting the base64 encoded initiator password with M-Pesa’s public key, a X509 certificate.
-    /// Returns base64 encoded string.
+    /// Returns base64 encoded string, generated once per initiator password.
     ///
     /// # Errors
     /// Returns `EncryptionError` variant of `MpesaError`
+    #[cfg(feature = "openssl")]
     pub(crate) fn gen_security_credentials(&self) -> MpesaResult<String> {
+        self.security_credential(&self.initiator)
+    }
+
+    /// Generates the security credential of `initiator_name`, from its own password if it was
+    /// registered with `MpesaBuilder::initiator` and from the initiator password of the client
+    /// otherwise
+    ///
+    /// # Errors
+    /// Returns `EncryptionError` variant of `MpesaError`
+    #[cfg(feature = "openssl")]
+    pub(crate) fn security_credential_for(&self, initiator_name: &str) -> MpesaResult<String> {
+        match self.initiators.get(initiator_name) {
+            Some(initiator) => self.security_credential(&initiator.initiator),
+            None => self.gen_security_credentials(),
+        }
+    }
+
+    #[cfg(feature = "openssl")]
+    fn security_credential(&self, lock: &RwLock<Initiator>) -> MpesaResult<String> {
+        let initiator = lock.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(security_credential) = &initiator.security_credential {
+            return Ok(security_credential.expose_secret().clone());
+        }
+        drop(initiator);
+
+        // Generated under the write lock so that a credential is never cached for a password
+        // that has been rotated in the meantime
+        let mut initiator = lock.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(security_credential) = &initiator.security_credential {
+            return Ok(security_credential.expose_secret().clone());
+        }
+        let security_credential = self.encrypt_initiator_password(initiator.password())?;
+        initiator.security_credential = Some(Secret::new(security_credential.clone()));
+        Ok(security_credential)
+    }
+
+    #[cfg(feature = "openssl")]
+    fn encrypt_initiator_password(&self, initiator_password: &str) -> MpesaResult<String> {
+        #[cfg(any(test, feature = "test-utils"))]
+        if let Some(signer) = &self.credential_signer {
+            return signer.sign(initiator_password);
+        }
+
         let pem = self.certificate.as_bytes();
         let cert = X509::from_pem(pem)?;
         // getting the public and rsa keys
@@ -277,65 +1148,1453 @@ impl Mpesa {
         let buf_len = pub_key.size();
         let mut buffer = vec![0; buf_len];
 
-        rsa_key.public_encrypt(
-            self.initiator_password().as_bytes(),
-            &mut buffer,
-            Padding::PKCS1,
-        )?;
+        rsa_key.public_encrypt(initiator_password.as_bytes(), &mut buffer, Padding::PKCS1)?;
         Ok(base64::encode_block(&buffer))
     }
 
-    /// Sends a request to the Safaricom API
-    /// This method is used by all the builders to send requests to the
-    /// Safaricom API
-    pub(crate) async fn send<Req, Res>(&self, req: Request<Req>) -> MpesaResult<Res>
+    /// Sends a request built by `request` to the primary base url, failing over to each of the
+    /// fallback base urls in order when a connection cannot be established.
+    ///
+    /// Only connection errors trigger a failover since the request is guaranteed not to have
+    /// reached the server; any other error is returned as is.
+    pub(crate) async fn send_with_failover<F, Fut>(
+        &self,
+        path: &str,
+        request: F,
+    ) -> reqwest::Result<reqwest::Response>
     where
-        Req: Serialize + Send,
-        Res: DeserializeOwned,
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = reqwest::Result<reqwest::Response>>,
     {
-        let url = format!("{}/{}", self.base_url, req.path);
-
-        let res = self
-            .http_client
-            .request(req.method, url)
-            .bearer_auth(self.auth().await?)
-            .json(&req.body)
-            .send()
-            .await?;
+        let urls = self.urls.get(path);
+        let mut urls = urls.iter();
+        let mut url = urls.next().expect("the primary base url is always set");
 
-        if res.status().is_success() {
-            let body = res.json().await?;
-            Ok(body)
-        } else {
-            let err = res.json::<ResponseError>().await?;
-            Err(MpesaError::Service(err))
+        loop {
+            #[cfg(all(feature = "tracing", feature = "__request"))]
+            crate::telemetry::record_url(url.as_str());
+            #[cfg(feature = "test-utils")]
+            if let Some(chaos) = &self.chaos {
+                if let Some(res) = chaos.inject().await {
+                    return Ok(res);
+                }
+            }
+            match request(url.clone()).await {
+                Err(e) if e.is_connect() => match urls.next() {
+                    Some(next) => url = next,
+                    None => return Err(e),
+                },
+                res => return res,
+            }
         }
     }
-}
 
-pub struct Request<Body: Serialize + Send> {
-    pub method: reqwest::Method,
-    pub path: &'static str,
-    pub body: Body,
+    /// Sends a request built by `request` with `send_with_failover`, sending it again according
+    /// to `policy` when it fails with a transient error, unless the Safaricom API is down for
+    /// maintenance
+    pub(crate) async fn send_with_retries<F, Fut>(
+        &self,
+        policy: RetryPolicy,
+        path: &str,
+        request: F,
+    ) -> reqwest::Result<reqwest::Response>
+    where
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let res = self.send_with_failover(path, &request).await;
+            let transient = match &res {
+                Ok(res) => retry::is_transient_status(res.status()),
+                Err(e) => retry::is_transient_error(e),
+            };
+            match policy.delay(attempt) {
+                Some(delay) if transient && !self.is_maintenance_window() => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return res,
+            }
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::Sandbox;
+#[cfg(feature = "__request")]
+impl Mpesa {
+    /// Returns the maintenance window in progress if `res` failed with a transient error
+    fn failed_during_maintenance(
+        &self,
+        res: &reqwest::Result<reqwest::Response>,
+    ) -> Option<MaintenanceWindow> {
+        let transient = match res {
+            Ok(res) => retry::is_transient_status(res.status()),
+            Err(e) => retry::is_transient_error(e),
+        };
+        if transient {
+            self.maintenance_window()
+        } else {
+            None
+        }
+    }
 
-    #[test]
-    fn test_setting_initator_password() {
-        let client = Mpesa::new("consumer_key", "consumer_secret", Sandbox);
-        assert_eq!(client.initiator_password(), DEFAULT_INITIATOR_PASSWORD);
-        client.set_initiator_password("foo_bar");
-        assert_eq!(client.initiator_password(), "foo_bar".to_string());
+    /// Sends a request to the Safaricom API, returning the HTTP metadata of the response along
+    /// with it. This method is used by all the builders to send requests to the Safaricom API
+    pub(crate) async fn send_with_meta<Req, Res>(
+        &self,
+        req: Request<Req>,
+    ) -> MpesaResult<WithMeta<Res>>
+    where
+        Req: Serialize + Send,
+        Res: DeserializeOwned,
+    {
+        // The body is serialized once, the steps of the pipeline below all share these bytes
+        let req = Request {
+            method: req.method,
+            service: req.service,
+            path: req.path,
+            body: json::Body::new(&req.body, self.fixed_decimal_amounts)?,
+        };
+        self.send_json_with_meta(req).await
     }
 
-    #[derive(Clone)]
-    struct TestEnvironment;
+    /// Sends a request whose body is already serialized, see `send_with_meta`
+    async fn send_json_with_meta<Res>(&self, req: Request<json::Body>) -> MpesaResult<WithMeta<Res>>
+    where
+        Res: DeserializeOwned,
+    {
+        if self.production && req.service.is_sandbox_only() {
+            return Err(MpesaError::SandboxOnly(req.service));
+        }
+        let _in_flight = self.in_flight.enter()?;
+        let client_request_id = self.id_strategy.generate();
 
-    impl ApiEnvironment for TestEnvironment {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+
+            let span = crate::telemetry::request_span(
+                &req.method,
+                &req.path,
+                req.body.value(),
+                &client_request_id,
+            );
+            let res = self
+                .send_cancellable(req)
+                .instrument(span.clone())
+                .await
+                .and_then(|res| WithMeta::deserialize(res, client_request_id));
+            if let Err(e) = &res {
+                crate::telemetry::record_error(&span, e);
+            }
+            res
+        }
+        #[cfg(not(feature = "tracing"))]
+        WithMeta::deserialize(self.send_cancellable(req).await?, client_request_id)
+    }
+
+    /// Sends a request, queuing it instead of failing if the Safaricom API cannot be reached. This
+    /// method is used by the `send_or_queue` methods of the builders of money-agnostic requests
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    pub(crate) async fn send_or_queue<Req, Res>(
+        &self,
+        req: Request<Req>,
+    ) -> MpesaResult<Delivery<Res>>
+    where
+        Req: Serialize + Send,
+        Res: DeserializeOwned,
+    {
+        let queue = self.queue.as_ref().ok_or(MpesaError::Message(
+            "Queuing requests requires an MpesaBuilder::degradation_queue",
+        ))?;
+        let service = req.service;
+        let path = req.path.clone().into_owned();
+        let req = Request {
+            method: req.method,
+            service,
+            path: req.path,
+            body: json::Body::new(&req.body, self.fixed_decimal_amounts)?,
+        };
+        let body = req.body.clone();
+
+        match self.send_json_with_meta(req).await {
+            Ok(sent) => Ok(Delivery::Sent(sent.response)),
+            Err(e) if queue::never_reached(&e) => {
+                let request = QueuedRequest::new(
+                    self.id_strategy.generate(),
+                    service,
+                    path,
+                    body.into_value(),
+                );
+                queue.push(&request).await?;
+                Ok(Delivery::Queued(request))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends a request with `send_idempotent`, racing it against the `CancellationToken` of the
+    /// client. With `OnCancel::Complete`, the request is sent from a task of its own, which
+    /// carries on when the token is cancelled or the returned future is dropped.
+    async fn send_cancellable(
+        &self,
+        req: Request<json::Body>,
+    ) -> MpesaResult<(serde_json::Value, Option<ResponseMeta>)> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(MpesaError::Cancelled);
+        }
+        let on_cancel = self.on_cancel.get(&req.service).copied();
+        if on_cancel.unwrap_or_default() == OnCancel::Abort {
+            let Some(token) = &self.cancellation else {
+                return self.send_idempotent(req).await;
+            };
+            return tokio::select! {
+                res = self.send_idempotent(req) => res,
+                () = token.cancelled() => Err(MpesaError::Cancelled),
+            };
+        }
+
+        let req = Request {
+            method: req.method,
+            service: req.service,
+            path: req.path.into_owned().into(),
+            body: req.body,
+        };
+        let client = self.clone();
+        let send = async move {
+            // Counted again in case the caller goes away before the request completes
+            let _in_flight = client.in_flight.enter()?;
+            client.send_idempotent(req).await
+        };
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::in_current_span(send);
+        let task = tokio::spawn(send);
+        let completed = async {
+            task.await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+        };
+        match &self.cancellation {
+            Some(token) => tokio::select! {
+                res = completed => res,
+                () = token.cancelled() => Err(MpesaError::Cancelled),
+            },
+            None => completed.await,
+        }
+    }
+
+    /// Sends a request with the idempotency key of the client, if any, returning the response
+    /// kept for the key instead if there is one, without metadata
+    async fn send_idempotent(
+        &self,
+        req: Request<json::Body>,
+    ) -> MpesaResult<(serde_json::Value, Option<ResponseMeta>)> {
+        let Some(key) = &self.idempotency_key else {
+            let (response, meta) = self.send_request(req).await?;
+            return Ok((response, Some(meta)));
+        };
+        let store = self.idempotency_store.as_ref().ok_or(MpesaError::Message(
+            "Idempotency keys require an MpesaBuilder::idempotency_store",
+        ))?;
+
+        if let Some(response) = store.get(key).await? {
+            return Ok((response, None));
+        }
+        if !store.reserve(key).await? {
+            // The request may have completed since `get`
+            return match store.get(key).await? {
+                Some(response) => Ok((response, None)),
+                None => Err(MpesaError::IdempotencyConflict(key.to_string())),
+            };
+        }
+
+        let originator_conversation_id = req
+            .body
+            .value()
+            .get("OriginatorConversationID")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        match self.send_request(req).await {
+            Ok((response, meta)) => {
+                store.save(key, &response).await?;
+                Ok((response, Some(meta)))
+            }
+            // The request was accepted before, by a send with this key whose response was lost
+            Err(MpesaError::Duplicate {
+                original_conversation_id: Some(conversation_id),
+            }) => {
+                let response = serde_json::json!({
+                    "ConversationID": conversation_id,
+                    "OriginatorConversationID": originator_conversation_id,
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Duplicate of an accepted request",
+                });
+                store.save(key, &response).await?;
+                Ok((response, None))
+            }
+            Err(e) => {
+                store.release(key).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sends a request, mirroring it to the environment of the `MpesaBuilder::shadow` if any
+    async fn send_request(
+        &self,
+        req: Request<json::Body>,
+    ) -> MpesaResult<(serde_json::Value, ResponseMeta)> {
+        let Some(shadow) = &self.shadow else {
+            return self.send_unshadowed(req).await;
+        };
+        let method = req.method.clone();
+        let service = req.service;
+        let path = req.path.clone().into_owned();
+        let body = req.body.clone();
+        Arc::clone(shadow)
+            .mirror(method, service, path, body, self.send_unshadowed(req))
+            .await
+    }
+
+    pub(crate) async fn send_unshadowed(
+        &self,
+        req: Request<json::Body>,
+    ) -> MpesaResult<(serde_json::Value, ResponseMeta)> {
+        if self.reject_sandbox_test_numbers && contains_sandbox_test_number(req.body.value()) {
+            return Err(MpesaError::Message(
+                "Request contains a Safaricom sandbox test phone number or shortcode",
+            ));
+        }
+
+        let _lane = match &self.lanes {
+            Some(lanes) => Some(lanes.acquire(self.priority).await),
+            None => None,
+        };
+
+        let shortcode = if self.quotas.by_shortcode() {
+            quota::shortcode(req.body.value())
+        } else {
+            None
+        };
+        self.quotas
+            .acquire(shortcode.as_deref())
+            .map_err(MpesaError::QuotaExceeded)?;
+
+        let credentials = self.credentials.select();
+        let policy = self.retry_policies.get(req.service.category());
+        let mut retried = false;
+        let started = Instant::now();
+
+        loop {
+            let token = self.auth_with(credentials).await?;
+
+            let res = self
+                .send_with_retries(policy, &req.path, |url| {
+                    self.http_client
+                        .request(req.method.clone(), url)
+                        .bearer_auth(&token)
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(req.body.bytes())
+                        .send()
+                })
+                .await;
+            if let Some(window) = self.failed_during_maintenance(&res) {
+                #[cfg(feature = "tracing")]
+                crate::telemetry::record_maintenance(&window);
+                return Err(MpesaError::Maintenance(window));
+            }
+            let res = res?;
+
+            #[cfg(feature = "tracing")]
+            crate::telemetry::record_status(res.status());
+
+            if res.status().is_success() {
+                let meta = ResponseMeta {
+                    status: res.status(),
+                    headers: res.headers().clone(),
+                    latency: started.elapsed(),
+                };
+                let body: serde_json::Value = res.json().await?;
+                #[cfg(feature = "tracing")]
+                crate::telemetry::record_response(&body);
+                if let Some(duplicate) = duplicate_error(&body) {
+                    return Err(duplicate);
+                }
+                return Ok((body, meta));
+            }
+
+            let status = res.status();
+            let body: serde_json::Value = res.json().await?;
+            if let Some(duplicate) = duplicate_error(&body) {
+                return Err(duplicate);
+            }
+            let err: ResponseError = serde_json::from_value(body)?;
+
+            // The token can be revoked on Safaricom's side before its ttl elapses, in which
+            // case we get a fresh one and retry the request once
+            if !retried
+                && (status == StatusCode::UNAUTHORIZED
+                    || err.error_code == INVALID_ACCESS_TOKEN_ERROR_CODE)
+            {
+                self.invalidate_auth(credentials).await;
+                retried = true;
+                continue;
+            }
+
+            return Err(MpesaError::Service(err));
+        }
+    }
+
+    /// Renders a request as a curl command sending it to the primary base url, with the access
+    /// token read from the `MPESA_ACCESS_TOKEN` environment variable.
+    /// This method is used by the builders' `to_curl` methods
+    pub(crate) fn to_curl<Req>(&self, req: &Request<Req>) -> MpesaResult<String>
+    where
+        Req: Serialize + Send,
+    {
+        let mut curl = format!(
+            "curl -X {} {} \\\n  -H \"Authorization: Bearer $MPESA_ACCESS_TOKEN\"",
+            req.method,
+            shell_quote(self.urls.get(&req.path)[0].as_str()),
+        );
+        if req.method != reqwest::Method::GET {
+            curl.push_str(" \\\n  -H 'Content-Type: application/json' \\\n  -d ");
+            let body = json::to_bytes(&req.body, self.fixed_decimal_amounts)?;
+            curl.push_str(&shell_quote(&String::from_utf8_lossy(&body)));
+        }
+        Ok(curl)
+    }
+}
+
+/// Returns a `MpesaError::Duplicate` if `body`, a response or an error payload, carries the
+/// `DuplicateDetected` code
+#[cfg(feature = "__request")]
+fn duplicate_error(body: &serde_json::Value) -> Option<MpesaError> {
+    let code = ["ResponseCode", "errorCode"]
+        .iter()
+        .find_map(|field| body.get(*field))?;
+    let is_duplicate = match code {
+        serde_json::Value::String(code) => code == DUPLICATE_DETECTED_CODE,
+        serde_json::Value::Number(code) => code.to_string() == DUPLICATE_DETECTED_CODE,
+        _ => false,
+    };
+    is_duplicate.then(|| MpesaError::Duplicate {
+        original_conversation_id: ["ConversationID", "conversationId"]
+            .iter()
+            .find_map(|field| body.get(*field)?.as_str())
+            .map(str::to_owned),
+    })
+}
+
+/// Quotes `value` for a POSIX shell
+#[cfg(feature = "__request")]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Returns `true` if any of the phone number or shortcode fields of a request body hold a
+/// sandbox test value
+#[cfg(feature = "__request")]
+fn contains_sandbox_test_number(body: &serde_json::Value) -> bool {
+    match body {
+        serde_json::Value::Array(items) => items.iter().any(contains_sandbox_test_number),
+        serde_json::Value::Object(fields) => PARTY_FIELDS
+            .iter()
+            .filter_map(|field| fields.get(*field))
+            .any(|value| match value {
+                serde_json::Value::String(value) => is_sandbox_test_number(value),
+                serde_json::Value::Number(value) => is_sandbox_test_number(&value.to_string()),
+                _ => false,
+            }),
+        _ => false,
+    }
+}
+
+/// Joins `path` onto `base_url`
+fn join_url(base_url: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    let mut url = String::with_capacity(base_url.len() + 1 + path.len());
+    url.push_str(base_url);
+    url.push('/');
+    url.push_str(path);
+    url
+}
+
+/// The urls of the Daraja endpoints on the base url and each fallback base url, joined once when
+/// the client is built rather than for every request
+#[derive(Debug, Default)]
+struct EndpointUrls {
+    base_urls: Vec<Url>,
+    endpoints: HashMap<Cow<'static, str>, Vec<Url>>,
+}
+
+impl EndpointUrls {
+    fn new<'a>(
+        base_urls: impl IntoIterator<Item = &'a String>,
+        paths: impl IntoIterator<Item = Cow<'static, str>>,
+    ) -> MpesaResult<Self> {
+        let base_urls = base_urls
+            .into_iter()
+            .map(|base_url| Url::parse(base_url))
+            .collect::<Result<Vec<_>, _>>()?;
+        let endpoints = paths
+            .into_iter()
+            .map(|path| {
+                let urls = base_urls
+                    .iter()
+                    .map(|base| join_path(base, &path))
+                    .collect();
+                (path, urls)
+            })
+            .collect();
+        Ok(EndpointUrls {
+            base_urls,
+            endpoints,
+        })
+    }
+
+    /// The urls of `path`, on the base url first then on each fallback base url. Those of paths
+    /// other than the endpoints', such as the paths of requests queued by an older version of the
+    /// crate, are joined on the fly.
+    fn get(&self, path: &str) -> Cow<'_, [Url]> {
+        match self.endpoints.get(path) {
+            Some(urls) => Cow::Borrowed(urls),
+            None => Cow::Owned(
+                self.base_urls
+                    .iter()
+                    .map(|base| join_path(base, path))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Joins `path` onto the path of `base`
+fn join_path(base: &Url, path: &str) -> Url {
+    let mut url = base.clone();
+    url.set_path(&join_url(base.path().trim_end_matches('/'), path));
+    url
+}
+
+/// Urls Safaricom posts the outcome of requests to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UrlKind {
+    /// `ResultURL` of the requests with an asynchronous result
+    Result,
+    /// `QueueTimeOutURL` of the requests with an asynchronous result
+    Timeout,
+    /// `CallBackURL` of M-Pesa Express requests
+    Callback,
+}
+
+/// Returns the url of `kind` set on `client` for a request builder generated by `derive_builder`
+/// whose url `field` was not set
+#[cfg(any(feature = "express_request", feature = "transaction_reversal"))]
+pub(crate) fn default_url(
+    client: Option<&Mpesa>,
+    kind: UrlKind,
+    field: &'static str,
+) -> MpesaResult<url::Url> {
+    let url = client
+        .and_then(|client| client.resolve_url(kind, None))
+        .ok_or(MpesaError::BuilderError(
+            crate::BuilderError::UninitializedField(field),
+        ))?;
+    Ok(url::Url::parse(&url)?)
+}
+
+/// Joins `path` onto the url of `kind` set on `client`, for the `*_path` setters of the request
+/// builders generated by `derive_builder`
+#[cfg(any(feature = "express_request", feature = "transaction_reversal"))]
+pub(crate) fn url_from_path(
+    client: Option<&Mpesa>,
+    kind: UrlKind,
+    path: &str,
+) -> MpesaResult<url::Url> {
+    let url = client
+        .and_then(|client| client.resolve_url(kind, Some(path)))
+        .unwrap_or(Cow::Borrowed(path));
+    Ok(url::Url::parse(&url)?)
+}
+
+/// Urls used by the request builders when none are given to them
+#[derive(Debug, Clone, Default)]
+struct DefaultUrls {
+    result: Option<String>,
+    timeout: Option<String>,
+    callback: Option<String>,
+}
+
+impl DefaultUrls {
+    fn get(&self, kind: UrlKind) -> Option<&str> {
+        match kind {
+            UrlKind::Result => self.result.as_deref(),
+            UrlKind::Timeout => self.timeout.as_deref(),
+            UrlKind::Callback => self.callback.as_deref(),
+        }
+    }
+
+    fn set(&mut self, kind: UrlKind, url: String) {
+        let url = Some(url.trim_end_matches('/').to_owned());
+        match kind {
+            UrlKind::Result => self.result = url,
+            UrlKind::Timeout => self.timeout = url,
+            UrlKind::Callback => self.callback = url,
+        }
+    }
+}
+
+/// Builder for the `Mpesa` client
+#[derive(Debug)]
+pub struct MpesaBuilder {
+    credentials: Vec<Credentials>,
+    credential_selection: CredentialSelection,
+    base_url: String,
+    #[cfg(feature = "openssl")]
+    certificate: String,
+    fallback_base_urls: Vec<String>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    #[cfg(feature = "native-tls")]
+    identity: Option<Identity>,
+    #[cfg(feature = "__tls")]
+    root_certificates: Vec<Certificate>,
+    resolve_overrides: Vec<(String, Vec<SocketAddr>)>,
+    proxies: Vec<Proxy>,
+    #[cfg(all(feature = "danger_accept_invalid_certs", feature = "__tls"))]
+    accept_invalid_certs: bool,
+    #[cfg(feature = "compression")]
+    compression: bool,
+    #[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+    normalize_msisdn: bool,
+    #[cfg(feature = "__request")]
+    reject_sandbox_test_numbers: bool,
+    #[cfg(feature = "__request")]
+    fixed_decimal_amounts: bool,
+    #[cfg(any(
+        feature = "b2b",
+        feature = "b2c",
+        feature = "bill_manager",
+        feature = "express_request"
+    ))]
+    validation: bool,
+    allow_private_callback_urls: bool,
+    id_strategy: IdStrategy,
+    default_urls: DefaultUrls,
+    api_versions: HashMap<Service, u8>,
+    on_cancel: HashMap<Service, OnCancel>,
+    initiator_password: Option<Secret<String>>,
+    #[cfg(feature = "openssl")]
+    initiators: Vec<WeightedInitiator>,
+    #[cfg(all(feature = "openssl", any(test, feature = "test-utils")))]
+    credential_signer: Option<Arc<dyn CredentialSigner>>,
+    #[cfg(feature = "test-utils")]
+    chaos: Option<Chaos>,
+    #[cfg(feature = "__request")]
+    quota: Option<Quota>,
+    #[cfg(feature = "__request")]
+    shortcode_quotas: HashMap<String, Quota>,
+    #[cfg(feature = "__request")]
+    max_concurrent_requests: Option<usize>,
+    retry_policies: RetryPolicies,
+    #[cfg(feature = "__request")]
+    idempotency_store: Option<Arc<dyn DynIdempotencyStore>>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    #[cfg(feature = "express_request")]
+    stk_push_guard: Option<Arc<StkPushGuard>>,
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    queue: Option<Arc<Queue>>,
+    #[cfg(feature = "__request")]
+    shadow: Option<Shadow>,
+    maintenance: MaintenanceSchedule,
+}
+
+impl MpesaBuilder {
+    /// Creates a new `MpesaBuilder` with the default timeouts:
+    /// a connect timeout of `10` seconds and a total request timeout of `30` seconds
+    pub fn new<S: Into<String>>(
+        consumer_key: S,
+        consumer_secret: S,
+        environment: impl ApiEnvironment,
+    ) -> MpesaBuilder {
+        MpesaBuilder {
+            credentials: vec![Credentials::new(
+                consumer_key.into(),
+                consumer_secret.into(),
+            )],
+            credential_selection: CredentialSelection::default(),
+            base_url: environment.base_url().trim_end_matches('/').to_owned(),
+            fallback_base_urls: vec![],
+            #[cfg(feature = "openssl")]
+            certificate: environment.get_certificate().to_owned(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            #[cfg(feature = "native-tls")]
+            identity: None,
+            #[cfg(feature = "__tls")]
+            root_certificates: vec![],
+            resolve_overrides: vec![],
+            proxies: vec![],
+            #[cfg(all(feature = "danger_accept_invalid_certs", feature = "__tls"))]
+            accept_invalid_certs: false,
+            #[cfg(feature = "compression")]
+            compression: true,
+            #[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+            normalize_msisdn: false,
+            #[cfg(feature = "__request")]
+            reject_sandbox_test_numbers: false,
+            #[cfg(feature = "__request")]
+            fixed_decimal_amounts: false,
+            #[cfg(any(
+                feature = "b2b",
+                feature = "b2c",
+                feature = "bill_manager",
+                feature = "express_request"
+            ))]
+            validation: true,
+            allow_private_callback_urls: false,
+            id_strategy: IdStrategy::default(),
+            default_urls: DefaultUrls::default(),
+            api_versions: HashMap::new(),
+            on_cancel: HashMap::new(),
+            initiator_password: None,
+            #[cfg(feature = "openssl")]
+            initiators: vec![],
+            #[cfg(all(feature = "openssl", any(test, feature = "test-utils")))]
+            credential_signer: None,
+            #[cfg(feature = "test-utils")]
+            chaos: None,
+            #[cfg(feature = "__request")]
+            quota: None,
+            #[cfg(feature = "__request")]
+            shortcode_quotas: HashMap::new(),
+            #[cfg(feature = "__request")]
+            max_concurrent_requests: None,
+            retry_policies: RetryPolicies::default(),
+            #[cfg(feature = "__request")]
+            idempotency_store: None,
+            metrics: None,
+            #[cfg(feature = "express_request")]
+            stk_push_guard: None,
+            #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+            queue: None,
+            #[cfg(feature = "__request")]
+            shadow: None,
+            maintenance: MaintenanceSchedule::default(),
+        }
+    }
+
+    /// Sets the maximum time allowed to establish a connection to the Safaricom API.
+    /// Defaults to `10` seconds.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> MpesaBuilder {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets the maximum time allowed for a whole request, from connecting until the
+    /// response body has been read. Defaults to `30` seconds.
+    pub fn timeout(mut self, timeout: Duration) -> MpesaBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a base url to fail over to when the environment's base url, and any fallback base
+    /// urls added before this one, cannot be reached.
+    /// Useful when requests are routed through regional gateways or internal API proxies.
+    pub fn fallback_base_url<S: Into<String>>(mut self, base_url: S) -> MpesaBuilder {
+        self.fallback_base_urls
+            .push(base_url.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Sets the client certificate presented to servers that require mutual TLS, such as
+    /// enterprise gateways in front of the Safaricom API. Requires the `native-tls` backend.
+    #[cfg(feature = "native-tls")]
+    pub fn identity(mut self, identity: Identity) -> MpesaBuilder {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Adds a trusted root certificate, for gateways whose server certificate is issued by a
+    /// private certificate authority.
+    #[cfg(feature = "__tls")]
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> MpesaBuilder {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Resolves `domain` to `addr` instead of looking it up with DNS, e.g. to pin
+    /// `api.safaricom.co.ke` to the addresses whitelisted for the egress of an on-premise
+    /// deployment. The port of `addr` is ignored in favor of the port of the base url.
+    pub fn resolve(self, domain: &str, addr: SocketAddr) -> MpesaBuilder {
+        self.resolve_to_addrs(domain, &[addr])
+    }
+
+    /// Resolves `domain` to any of `addrs` instead of looking it up with DNS, replacing the
+    /// addresses set before for the same domain
+    pub fn resolve_to_addrs(mut self, domain: &str, addrs: &[SocketAddr]) -> MpesaBuilder {
+        self.resolve_overrides.retain(|(d, _)| d != domain);
+        self.resolve_overrides
+            .push((domain.to_owned(), addrs.to_vec()));
+        self
+    }
+
+    /// Sends requests through `proxy` rather than the proxies of the `HTTP_PROXY` and
+    /// `HTTPS_PROXY` environment variables, for deployments whose egress goes through a proxy.
+    /// With the `socks` feature, `proxy` can be a SOCKS5 proxy such as the local end of an SSH
+    /// tunnel, e.g. `Proxy::all("socks5h://127.0.0.1:1080")`, the `socks5h` scheme resolving
+    /// hostnames on the far side of the tunnel. Proxies are tried in the order they were added.
+    pub fn proxy(mut self, proxy: Proxy) -> MpesaBuilder {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Disables the verification of server certificates, for local simulators of the Safaricom API
+    /// serving self-signed certificates, e.g. in a docker-compose setup.
+    ///
+    /// # Warning
+    ///
+    /// This exposes every request, including the credentials they carry, to anyone able to
+    /// intercept the connection. Only use it against local gateways: building a client for the
+    /// production environment with this enabled fails.
+    #[cfg(all(feature = "danger_accept_invalid_certs", feature = "__tls"))]
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> MpesaBuilder {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Asks for gzip or brotli compressed responses with the `Accept-Encoding` header, and
+    /// decompresses responses sent with a `Content-Encoding`. Responses the Safaricom API sends
+    /// uncompressed are read as is. Enabled by default with the `compression` feature.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: bool) -> MpesaBuilder {
+        self.compression = compression;
+        self
+    }
+
+    /// Normalizes phone numbers passed to the express request, C2B simulate and B2C builders
+    /// from the `07XXXXXXXX`, `7XXXXXXXX` and `+2547XXXXXXXX` formats to `2547XXXXXXXX` before
+    /// they are validated and sent, instead of only rejecting the formats the API does not accept.
+    /// Disabled by default.
+    #[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+    pub fn normalize_msisdn(mut self, normalize_msisdn: bool) -> MpesaBuilder {
+        self.normalize_msisdn = normalize_msisdn;
+        self
+    }
+
+    /// Rejects requests carrying one of the Safaricom sandbox test phone numbers or shortcodes,
+    /// see `validator::is_sandbox_test_number`. Recommended for production clients, where such
+    /// values are a sign of a misconfiguration. Disabled by default.
+    #[cfg(feature = "__request")]
+    pub fn reject_sandbox_test_numbers(mut self, reject: bool) -> MpesaBuilder {
+        self.reject_sandbox_test_numbers = reject;
+        self
+    }
+
+    /// Writes the amounts of request bodies with two decimals, e.g. `"Amount": 1000.00`, rather
+    /// than with as many decimals as needed to round-trip them, or in scientific notation for
+    /// very large or small amounts, both of which Daraja rejects. Amounts that are `NaN` or
+    /// infinite are rejected whether or not this is enabled. Disabled by default.
+    #[cfg(feature = "__request")]
+    pub fn fixed_decimal_amounts(mut self, fixed_decimal_amounts: bool) -> MpesaBuilder {
+        self.fixed_decimal_amounts = fixed_decimal_amounts;
+        self
+    }
+
+    /// Sets the `ResultURL` of the requests whose builders are not given one, e.g.
+    /// `https://pay.example.com/mpesa/result`. A builder can also be given only a suffix, such as
+    /// `b2c`, which is joined onto this url.
+    pub fn default_result_url<S: Into<String>>(mut self, url: S) -> MpesaBuilder {
+        self.default_urls.set(UrlKind::Result, url.into());
+        self
+    }
+
+    /// Sets the `QueueTimeOutURL` of the requests whose builders are not given one.
+    /// A builder can also be given only a suffix, which is joined onto this url.
+    pub fn default_timeout_url<S: Into<String>>(mut self, url: S) -> MpesaBuilder {
+        self.default_urls.set(UrlKind::Timeout, url.into());
+        self
+    }
+
+    /// Sets the `CallBackURL` of the M-Pesa Express requests whose builders are not given one.
+    /// The builders' `callback_path` joins a suffix onto this url.
+    pub fn default_callback_url<S: Into<String>>(mut self, url: S) -> MpesaBuilder {
+        self.default_urls.set(UrlKind::Callback, url.into());
+        self
+    }
+
+    /// Enables or disables client-side validation of the format of inputs, such as phone numbers,
+    /// email addresses and billing periods, for gateways that already validate them upstream.
+    /// Checks for missing required fields are always performed. Enabled by default.
+    #[cfg(any(
+        feature = "b2b",
+        feature = "b2c",
+        feature = "bill_manager",
+        feature = "express_request"
+    ))]
+    pub fn validation(mut self, validation: bool) -> MpesaBuilder {
+        self.validation = validation;
+        self
+    }
+
+    /// Allows callback urls that Safaricom cannot reach, over http or to `localhost` and private
+    /// addresses, which are rejected by default since callbacks sent to them are silently
+    /// dropped. Meant for testing in the sandbox against a local simulator: building a client
+    /// for the production environment with them allowed fails.
+    pub fn allow_private_callback_urls(mut self, allow: bool) -> MpesaBuilder {
+        self.allow_private_callback_urls = allow;
+        self
+    }
+
+    /// Registers an additional consumer key/secret pair.
+    /// Requests are spread across all registered credentials according to the
+    /// `credential_selection`, and an access token is cached for each pair independently.
+    pub fn credentials<S: Into<String>>(
+        mut self,
+        consumer_key: S,
+        consumer_secret: S,
+    ) -> MpesaBuilder {
+        self.credentials.push(Credentials::new(
+            consumer_key.into(),
+            consumer_secret.into(),
+        ));
+        self
+    }
+
+    /// Sets how credentials are picked when several are registered.
+    /// Defaults to `CredentialSelection::RoundRobin`.
+    pub fn credential_selection(mut self, selection: CredentialSelection) -> MpesaBuilder {
+        self.credential_selection = selection;
+        self
+    }
+
+    /// Sets the format of the correlation identifiers generated by the client.
+    /// Defaults to `IdStrategy::UuidV4`; use `IdStrategy::UuidV7` (or `IdStrategy::Ulid` with the
+    /// `ulid` feature) for identifiers that sort chronologically when stored.
+    pub fn id_strategy(mut self, id_strategy: IdStrategy) -> MpesaBuilder {
+        self.id_strategy = id_strategy;
+        self
+    }
+
+    /// Sets the version of the API called for `service`, e.g. `3` to send M-Pesa Express
+    /// requests to `mpesa/stkpush/v3/processrequest`, so that services can be migrated one at a
+    /// time as Safaricom releases new versions. Defaults to `Service::default_api_version`.
+    pub fn api_version(mut self, service: Service, version: u8) -> MpesaBuilder {
+        self.api_versions.insert(service, version);
+        self
+    }
+
+    /// Sets what happens to the requests to `service` whose `send` future is dropped, or whose
+    /// `CancellationToken` is cancelled, before their response is received. Defaults to
+    /// `OnCancel::Abort`; `OnCancel::Complete` suits payments, which are better completed than
+    /// left in an unknown state when the caller goes away, e.g.
+    /// `.on_cancel(Service::B2c, OnCancel::Complete)`.
+    pub fn on_cancel(mut self, service: Service, on_cancel: OnCancel) -> MpesaBuilder {
+        self.on_cancel.insert(service, on_cancel);
+        self
+    }
+
+    /// Sets the initiator password, required in production for the following apis:
+    /// - `account_balance`
+    /// - `b2b`
+    /// - `b2c`
+    /// - `transaction_reversal`
+    /// - `transaction_status`
+    ///
+    /// Defaults to the initiator password from the sandbox test credentials.
+    /// It can be changed later on with `Mpesa::rotate_initiator_password`.
+    pub fn initiator_password<S: Into<String>>(mut self, initiator_password: S) -> MpesaBuilder {
+        self.initiator_password = Some(Secret::new(initiator_password.into()));
+        self
+    }
+
+    /// Registers the initiator `name` with its own password, for businesses spreading their B2C
+    /// and B2B payments across several initiators for throughput. Requests built with
+    /// `Mpesa::b2c_routed` or `Mpesa::b2b_routed` are sent as one of the registered initiators,
+    /// picked at random in proportion to its `weight`; an initiator with a weight of `0` is only
+    /// used when named explicitly, e.g. while it is being retired.
+    ///
+    /// Requests naming a registered initiator, such as `client.b2c("ops_2")`, use its password
+    /// rather than the `initiator_password` of the client.
+    #[cfg(feature = "openssl")]
+    pub fn initiator<S: Into<String>>(mut self, name: S, password: S, weight: u32) -> MpesaBuilder {
+        let name = name.into();
+        self.initiators.retain(|initiator| initiator.name() != name);
+        self.initiators.push(WeightedInitiator::new(
+            name,
+            Secret::new(password.into()),
+            weight,
+        ));
+        self
+    }
+
+    /// Replaces the encryption of the initiator password with the certificate of the
+    /// environment by `signer`, e.g. a `StubSigner` to make the `SecurityCredential` of request
+    /// payloads deterministic in tests. Not meant to be used in production.
+    #[cfg(all(feature = "openssl", any(test, feature = "test-utils")))]
+    pub fn credential_signer(mut self, signer: impl CredentialSigner + 'static) -> MpesaBuilder {
+        self.credential_signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Injects the latency and failures of `chaos` into every request of the client, to test how
+    /// the code using it copes with a slow or failing Safaricom API. Not meant to be used in
+    /// production.
+    #[cfg(feature = "test-utils")]
+    pub fn chaos(mut self, chaos: Chaos) -> MpesaBuilder {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Limits the number of requests sent by the client, and by its clones, to `quota`.
+    /// Requests over the quota fail with `MpesaError::QuotaExceeded` without being sent.
+    /// Unlimited by default.
+    #[cfg(feature = "__request")]
+    pub fn quota(mut self, quota: Quota) -> MpesaBuilder {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Limits the number of requests made for `shortcode` to `quota`, on top of the quota set
+    /// with `quota`, since Safaricom throttles some products per shortcode, e.g. M-Pesa Express
+    /// requests per paybill. A request is made for the `BusinessShortCode` of M-Pesa Express
+    /// requests, and for the `ShortCode`, `PartyA` or `ReceiverParty` of the others.
+    #[cfg(feature = "__request")]
+    pub fn shortcode_quota<S: Into<String>>(mut self, shortcode: S, quota: Quota) -> MpesaBuilder {
+        self.shortcode_quotas.insert(shortcode.into(), quota);
+        self
+    }
+
+    /// Limits the number of requests sent at once by the client, and by its clones, to `limit`.
+    /// Requests over the limit wait for one to complete, and are then sent by the priority set
+    /// with `Mpesa::with_priority`, so that latency sensitive requests are not held up behind a
+    /// batch job. Unlimited by default.
+    #[cfg(feature = "__request")]
+    pub fn max_concurrent_requests(mut self, limit: usize) -> MpesaBuilder {
+        self.max_concurrent_requests = Some(limit);
+        self
+    }
+
+    /// Reports the hits, misses and evictions of the access token cache to `sink`, see
+    /// `metrics::Counter`
+    pub fn metrics_sink(mut self, sink: impl MetricsSink + 'static) -> MpesaBuilder {
+        self.metrics = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets the retry policy of access token requests.
+    /// Defaults to `3` retries, waiting `200` milliseconds before the first one.
+    pub fn auth_retry_policy(mut self, policy: RetryPolicy) -> MpesaBuilder {
+        self.retry_policies.auth = policy;
+        self
+    }
+
+    /// Sets the retry policy of the requests to the APIs in `category`.
+    ///
+    /// Queries are retried `2` times by default, waiting `500` milliseconds before the first
+    /// retry. Payments are never retried by default since a request that timed out may still
+    /// have been processed, in which case sending it again would move the money twice. Neither
+    /// are notifications, which would send invoices or e-receipts to customers twice.
+    pub fn retry_policy(mut self, category: ServiceCategory, policy: RetryPolicy) -> MpesaBuilder {
+        self.retry_policies.set(category, policy);
+        self
+    }
+
+    /// Sets the planned maintenance windows of the Safaricom API, during which failed requests are
+    /// not retried and fail with `MpesaError::Maintenance`. See the `status` module.
+    pub fn maintenance_schedule(mut self, schedule: MaintenanceSchedule) -> MpesaBuilder {
+        self.maintenance = schedule;
+        self
+    }
+
+    /// Sets the store keeping the responses of the requests sent with an idempotency key, see
+    /// `Mpesa::idempotent`. Keys cannot be used without a store.
+    #[cfg(feature = "__request")]
+    pub fn idempotency_store(mut self, store: impl IdempotencyStore + 'static) -> MpesaBuilder {
+        self.idempotency_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Refuses to send an STK push identical to one sent within `window`, with the same phone
+    /// number, amount and account reference, failing with `MpesaError::DuplicateStkPush`
+    /// instead. This keeps a double-clicked checkout button from prompting the customer twice.
+    /// The pushes are kept in `storage`, which instances of an application can share.
+    #[cfg(feature = "express_request")]
+    pub fn stk_push_guard(
+        mut self,
+        storage: impl Storage + 'static,
+        window: Duration,
+    ) -> MpesaBuilder {
+        self.stk_push_guard = Some(Arc::new(StkPushGuard::new(Arc::new(storage), window)));
+        self
+    }
+
+    /// Sets the storage of the requests queued by `send_or_queue` while the Safaricom API is
+    /// down, see the `queue` module. Requests cannot be queued without it.
+    #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+    pub fn degradation_queue(mut self, storage: impl Storage + 'static) -> MpesaBuilder {
+        self.queue = Some(Arc::new(Queue::new(Arc::new(storage))));
+        self
+    }
+
+    /// Mirrors every request to the environment of `shadow`, comparing the responses of the two
+    /// environments, see the `shadow` module
+    #[cfg(feature = "__request")]
+    pub fn shadow(mut self, shadow: Shadow) -> MpesaBuilder {
+        self.shadow = Some(shadow);
+        self
+    }
+
+    /// Builds the `Mpesa` client
+    ///
+    /// # Errors
+    /// Returns a `NetworkError` if a TLS backend cannot be initialized for the internal http client,
+    /// or if it rejects the client identity, a `BuilderError` if a base url is not a valid url,
+    /// and a `Message` if invalid certificates or private
+    /// callback urls are accepted for the production environment, or if the maximum number of
+    /// concurrent requests is `0`
+    pub fn build(self) -> MpesaResult<Mpesa> {
+        let mut http_client = HttpClient::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .user_agent(format!("mpesa-rust@{CARGO_PACKAGE_VERSION}"));
+
+        #[cfg(feature = "native-tls")]
+        if let Some(identity) = self.identity {
+            http_client = http_client.identity(identity);
+        }
+        #[cfg(feature = "__tls")]
+        for certificate in self.root_certificates {
+            http_client = http_client.add_root_certificate(certificate);
+        }
+        for (domain, addrs) in &self.resolve_overrides {
+            http_client = http_client.resolve_to_addrs(domain, addrs);
+        }
+        let proxied = !self.proxies.is_empty();
+        for proxy in self.proxies {
+            http_client = http_client.proxy(proxy);
+        }
+        let production = std::iter::once(&self.base_url)
+            .chain(&self.fallback_base_urls)
+            .any(|base_url| base_url == Environment::Production.base_url());
+        if self.allow_private_callback_urls && production {
+            return Err(MpesaError::Message(
+                "Private callback urls cannot be allowed in production",
+            ));
+        }
+        #[cfg(feature = "__request")]
+        if self.max_concurrent_requests == Some(0) {
+            return Err(MpesaError::Message(
+                "The maximum number of concurrent requests must be greater than 0",
+            ));
+        }
+        #[cfg(all(feature = "danger_accept_invalid_certs", feature = "__tls"))]
+        if self.accept_invalid_certs {
+            if production {
+                return Err(MpesaError::Message(
+                    "Invalid certificates cannot be accepted in production",
+                ));
+            }
+            http_client = http_client.danger_accept_invalid_certs(true);
+        }
+        #[cfg(feature = "compression")]
+        {
+            http_client = http_client.gzip(self.compression).brotli(self.compression);
+        }
+
+        let http_client = http_client.build()?;
+
+        let mut client = Mpesa {
+            credentials: Arc::new(CredentialPool::new(
+                self.credentials,
+                self.credential_selection,
+            )),
+            initiator: Arc::new(RwLock::new(Initiator::new(self.initiator_password))),
+            #[cfg(feature = "openssl")]
+            initiators: Arc::new(InitiatorPool::new(self.initiators)),
+            base_url: self.base_url,
+            fallback_base_urls: self.fallback_base_urls,
+            urls: Arc::default(),
+            pinned_domains: self
+                .resolve_overrides
+                .into_iter()
+                .map(|(domain, _)| domain)
+                .collect(),
+            proxied,
+            #[cfg(feature = "openssl")]
+            certificate: self.certificate,
+            #[cfg(any(feature = "b2c", feature = "c2b_simulate", feature = "express_request"))]
+            normalize_msisdn: self.normalize_msisdn,
+            #[cfg(feature = "__request")]
+            reject_sandbox_test_numbers: self.reject_sandbox_test_numbers,
+            #[cfg(feature = "__request")]
+            fixed_decimal_amounts: self.fixed_decimal_amounts,
+            #[cfg(any(feature = "__request", feature = "openssl"))]
+            production,
+            #[cfg(any(
+                feature = "b2b",
+                feature = "b2c",
+                feature = "bill_manager",
+                feature = "express_request"
+            ))]
+            validation: self.validation,
+            #[cfg(any(feature = "c2b_register", feature = "express_request"))]
+            allow_private_callback_urls: self.allow_private_callback_urls,
+            id_strategy: self.id_strategy,
+            default_urls: self.default_urls,
+            api_versions: self.api_versions,
+            on_cancel: self.on_cancel,
+            #[cfg(feature = "__request")]
+            cancellation: None,
+            #[cfg(feature = "__request")]
+            quotas: Arc::new(Quotas::new(self.quota, self.shortcode_quotas)),
+            retry_policies: self.retry_policies,
+            #[cfg(feature = "__request")]
+            idempotency_store: self.idempotency_store,
+            metrics: self.metrics,
+            idempotency_key: None,
+            #[cfg(feature = "express_request")]
+            stk_push_guard: self.stk_push_guard,
+            #[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+            queue: self.queue,
+            #[cfg(feature = "__request")]
+            shadow: self.shadow.map(Arc::new),
+            maintenance: Arc::new(RwLock::new(self.maintenance)),
+            in_flight: Arc::default(),
+            #[cfg(feature = "__request")]
+            lanes: self.max_concurrent_requests.map(Lanes::new),
+            priority: Priority::default(),
+            #[cfg(all(feature = "openssl", any(test, feature = "test-utils")))]
+            credential_signer: self.credential_signer,
+            #[cfg(feature = "test-utils")]
+            chaos: self.chaos.map(|chaos| Arc::new(ChaosInjector::new(chaos))),
+            http_client,
+        };
+        let urls = EndpointUrls::new(
+            std::iter::once(&client.base_url).chain(&client.fallback_base_urls),
+            Endpoint::ALL.map(|endpoint| client.endpoint_path(endpoint)),
+        )?;
+        client.urls = Arc::new(urls);
+        Ok(client)
+    }
+}
+
+/// Runs `request`, the `send` future of a request builder, until `deadline`, failing with
+/// `MpesaError::DeadlineExceeded` once it has passed. The deadline covers fetching the access
+/// token and any retries: the request is dropped at the deadline, including a pending token
+/// fetch or retry.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::{Duration, Instant};
+///
+/// use mpesa::{Environment, Mpesa};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = Mpesa::new("consumer_key", "consumer_secret", Environment::Sandbox);
+///
+///     let request = client
+///         .c2b_register()
+///         .short_code("600496")
+///         .confirmation_url("https://example.com/confirmation")
+///         .validation_url("https://example.com/validation");
+///     let response = mpesa::with_deadline(Instant::now() + Duration::from_secs(5), request.send())
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub async fn with_deadline<T>(
+    deadline: Instant,
+    request: impl Future<Output = MpesaResult<T>>,
+) -> MpesaResult<T> {
+    tokio::time::timeout_at(deadline.into(), request)
+        .await
+        .unwrap_or(Err(MpesaError::DeadlineExceeded))
+}
+
+/// Headers Safaricom and its gateway identify a request with, in the order they are looked up
+const REQUEST_ID_HEADERS: [&str; 3] = ["x-request-id", "x-correlation-id", "x-amzn-requestid"];
+
+/// HTTP details of a successful response of the Safaricom API, such as the request id to quote
+/// in Daraja support tickets
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ResponseMeta {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Time taken from sending the request to receiving the response, including fetching the
+    /// access token and any retries
+    pub latency: Duration,
+}
+
+impl ResponseMeta {
+    /// Returns the value of the header `name`, if it is valid text
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    /// Returns the id the response was tagged with, from the first of the `x-request-id`,
+    /// `x-correlation-id` and `x-amzn-requestid` headers present
+    pub fn request_id(&self) -> Option<&str> {
+        REQUEST_ID_HEADERS.iter().find_map(|name| self.header(name))
+    }
+}
+
+/// A response of the Safaricom API along with its HTTP metadata, returned by `send_with_meta`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WithMeta<T> {
+    pub response: T,
+    /// `None` when the response was kept by the `IdempotencyStore` of the client rather than
+    /// received from the Safaricom API
+    pub meta: Option<ResponseMeta>,
+    /// Identifier generated by the client for the request, in the format of the
+    /// `MpesaBuilder::id_strategy`, unlike the `ConversationID` generated by the Safaricom API.
+    /// It is recorded as `mpesa.client_request_id` on the tracing span of the request, along with
+    /// its error if it failed, to link the logs of a request from start to end.
+    pub client_request_id: String,
+    /// The initiator a B2C or B2B request was sent as, such as the one picked for a request built
+    /// with `Mpesa::b2c_routed`. `None` for other requests.
+    pub initiator: Option<String>,
+}
+
+#[cfg(feature = "__request")]
+impl<T: DeserializeOwned> WithMeta<T> {
+    fn deserialize(
+        (response, meta): (serde_json::Value, Option<ResponseMeta>),
+        client_request_id: String,
+    ) -> MpesaResult<Self> {
+        Ok(WithMeta {
+            response: serde_json::from_value(response)?,
+            meta,
+            client_request_id,
+            initiator: None,
+        })
+    }
+}
+
+#[cfg(feature = "__request")]
+pub struct Request<Body> {
+    pub method: reqwest::Method,
+    pub service: Service,
+    pub path: Cow<'static, str>,
+    pub body: Body,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sandbox;
+
+    #[test]
+    #[cfg(feature = "openssl")]
+    fn test_setting_initator_password() {
+        let client = Mpesa::new("consumer_key", "consumer_secret", Sandbox);
+        assert_eq!(
+            client.initiator.read().unwrap().password(),
+            DEFAULT_INITIATOR_PASSWORD
+        );
+        let client = Mpesa::builder("consumer_key", "consumer_secret", Sandbox)
+            .initiator_password("foo_bar")
+            .build()
+            .unwrap();
+        assert_eq!(client.initiator.read().unwrap().password(), "foo_bar");
+    }
+
+    #[test]
+    #[cfg(feature = "openssl")]
+    fn test_credential_signer_replaces_encryption() {
+        use crate::test_utils::StubSigner;
+
+        let client = Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+            .credential_signer(StubSigner::new("security_credential"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.gen_security_credentials().unwrap(),
+            "security_credential"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "openssl")]
+    fn test_rotating_initiator_password_discards_security_credential() {
+        let client = Mpesa::builder("consumer_key", "consumer_secret", Sandbox)
+            .initiator_password("foo_bar")
+            .build()
+            .unwrap();
+        let clone = client.clone();
+        let security_credential = client.gen_security_credentials().unwrap();
+        assert_eq!(
+            client.gen_security_credentials().unwrap(),
+            security_credential
+        );
+
+        client.rotate_initiator_password("bar_baz");
+        assert_eq!(clone.initiator.read().unwrap().password(), "bar_baz");
+        assert!(clone
+            .initiator
+            .read()
+            .unwrap()
+            .security_credential
+            .is_none());
+    }
+
+    #[test]
+    #[cfg(all(feature = "b2c", feature = "openssl"))]
+    fn test_registered_initiators_use_their_own_password() {
+        #[derive(Debug)]
+        struct EchoSigner;
+
+        impl CredentialSigner for EchoSigner {
+            fn sign(&self, initiator_password: &str) -> MpesaResult<String> {
+                Ok(initiator_password.to_owned())
+            }
+        }
+
+        let client = Mpesa::builder("consumer_key", "consumer_secret", Sandbox)
+            .initiator_password("default_pw")
+            .initiator("ops_1", "ops_1_pw", 1)
+            .initiator("ops_2", "ops_2_pw", 0)
+            .credential_signer(EchoSigner)
+            .build()
+            .unwrap();
+        assert_eq!(client.security_credential_for("ops_2").unwrap(), "ops_2_pw");
+        assert_eq!(
+            client.security_credential_for("testapi").unwrap(),
+            "default_pw"
+        );
+        for _ in 0..10 {
+            assert_eq!(client.route_initiator().unwrap(), "ops_1");
+        }
+
+        let client = Mpesa::builder("consumer_key", "consumer_secret", Sandbox)
+            .build()
+            .unwrap();
+        assert!(client.b2c_routed().is_err());
+    }
+
+    #[test]
+    fn test_sandbox_client() {
+        let client = Mpesa::sandbox("consumer_key", "consumer_secret");
+        assert_eq!(client.base_url, Sandbox.base_url());
+        #[cfg(feature = "openssl")]
+        assert_eq!(
+            client.initiator.read().unwrap().password(),
+            SANDBOX_INITIATOR_PASSWORD
+        );
+    }
+
+    #[derive(Clone)]
+    struct TestEnvironment;
+
+    impl ApiEnvironment for TestEnvironment {
         fn base_url(&self) -> &str {
             "https://example.com"
         }
@@ -350,10 +2609,367 @@ mod tests {
     fn test_custom_environment() {
         let client = Mpesa::new("consumer_key", "consumer_secret", TestEnvironment);
         assert_eq!(&client.base_url, "https://example.com");
+        #[cfg(feature = "openssl")]
         assert_eq!(&client.certificate, "certificate");
     }
 
+    #[derive(Clone)]
+    struct MockEnvironment(String);
+
+    impl ApiEnvironment for MockEnvironment {
+        fn base_url(&self) -> &str {
+            &self.0
+        }
+
+        fn get_certificate(&self) -> &str {
+            "certificate"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_fails_over_to_the_next_base_url() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "fallback_token",
+                "expires_in": "3600"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Nothing listens on port 1, so connecting to the primary base url fails
+        let client = Mpesa::builder(
+            "failover_consumer_key",
+            "consumer_secret",
+            MockEnvironment("http://127.0.0.1:1".to_owned()),
+        )
+        .fallback_base_url("http://127.0.0.1:2")
+        .fallback_base_url(server.uri())
+        .build()
+        .unwrap();
+
+        assert_eq!(client.auth().await.unwrap(), "fallback_token");
+    }
+
+    #[tokio::test]
+    async fn test_request_fails_after_timeout() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+            .mount(&server)
+            .await;
+
+        let client = Mpesa::builder(
+            "timeout_consumer_key",
+            "consumer_secret",
+            MockEnvironment(server.uri()),
+        )
+        .timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+        match client.auth().await {
+            Err(MpesaError::NetworkError(e)) => assert!(e.is_timeout()),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_rotated_and_cached_independently() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for (key, token) in [("rotation_key_a", "token_a"), ("rotation_key_b", "token_b")] {
+            let basic = basic_auth_header(key, "secret");
+            Mock::given(method("GET"))
+                .and(header("Authorization", basic.as_str()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "access_token": token,
+                    "expires_in": "3600"
+                })))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let client = Mpesa::builder("rotation_key_a", "secret", MockEnvironment(server.uri()))
+            .credentials("rotation_key_b", "secret")
+            .build()
+            .unwrap();
+
+        let mut tokens = vec![];
+        for _ in 0..4 {
+            tokens.push(client.auth().await.unwrap());
+        }
+        assert_eq!(tokens, ["token_a", "token_b", "token_a", "token_b"]);
+    }
+
+    #[tokio::test]
+    async fn test_token_cache_counters_are_reported() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        use crate::metrics::MemoryMetrics;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "metrics_token",
+                "expires_in": "3600"
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let metrics = Arc::new(MemoryMetrics::default());
+        let client = Mpesa::builder("metrics_key", "secret", MockEnvironment(server.uri()))
+            .metrics_sink(Arc::clone(&metrics))
+            .build()
+            .unwrap();
+        let credentials = client.credentials.primary();
+
+        for _ in 0..3 {
+            client.auth().await.unwrap();
+        }
+        client.invalidate_auth(credentials).await;
+        client.auth().await.unwrap();
+
+        assert_eq!(metrics.get(Counter::AuthCacheHits), 2);
+        assert_eq!(metrics.get(Counter::AuthCacheMisses), 2);
+        assert_eq!(metrics.get(Counter::AuthCacheEvictions), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_tokens_are_counted_as_evictions() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        use crate::metrics::MemoryMetrics;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "expired_token",
+                "expires_in": "0"
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let metrics = Arc::new(MemoryMetrics::default());
+        let client = Mpesa::builder("expiry_key", "secret", MockEnvironment(server.uri()))
+            .metrics_sink(Arc::clone(&metrics))
+            .build()
+            .unwrap();
+
+        client.auth().await.unwrap();
+        client.auth().await.unwrap();
+
+        assert_eq!(metrics.get(Counter::AuthCacheHits), 0);
+        assert_eq!(metrics.get(Counter::AuthCacheMisses), 2);
+        assert_eq!(metrics.get(Counter::AuthCacheEvictions), 1);
+    }
+
+    /// The `Authorization` header value reqwest sends for the given basic auth credentials
+    fn basic_auth_header(username: &str, password: &str) -> String {
+        let request = HttpClient::new()
+            .get("http://localhost")
+            .basic_auth(username, Some(password))
+            .build()
+            .unwrap();
+        request.headers()[reqwest::header::AUTHORIZATION]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    #[cfg(all(feature = "native-tls", feature = "openssl"))]
+    fn test_build_with_client_identity() {
+        use openssl::asn1::Asn1Time;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::x509::X509NameBuilder;
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "mpesa-client").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = cert.build().to_pem().unwrap();
+
+        let identity =
+            Identity::from_pkcs8_pem(&cert, &key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let root_certificate = Certificate::from_pem(&cert).unwrap();
+
+        assert!(
+            Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+                .identity(identity)
+                .add_root_certificate(root_certificate)
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "danger_accept_invalid_certs", feature = "__tls"))]
+    fn test_invalid_certs_are_not_accepted_in_production() {
+        assert!(
+            Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+                .danger_accept_invalid_certs(true)
+                .build()
+                .is_ok()
+        );
+        assert!(
+            Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+                .fallback_base_url(Environment::Production.base_url())
+                .danger_accept_invalid_certs(true)
+                .build()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_debug_output_redacts_secrets() {
+        let client = Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+            .initiator_password("initiator_password")
+            .build()
+            .unwrap();
+        let debug = format!("{client:?}");
+        assert!(debug.contains("https://example.com"));
+        assert!(!debug.contains("consumer_key\""));
+        assert!(!debug.contains("consumer_secret\""));
+        assert!(!debug.contains("initiator_password\""));
+    }
+
+    #[test]
+    fn test_sandbox_test_numbers_are_found_in_request_bodies() {
+        use serde_json::json;
+
+        assert!(contains_sandbox_test_number(
+            &json!({ "PartyA": "254708374149", "Amount": 1 })
+        ));
+        assert!(contains_sandbox_test_number(&json!([
+            { "billedPhoneNumber": "0712345678" },
+            { "billedPhoneNumber": "0708374149" },
+        ])));
+        assert!(contains_sandbox_test_number(&json!({ "CPI": 174379 })));
+        assert!(!contains_sandbox_test_number(
+            &json!({ "PartyA": "254712345678", "AccountReference": "174379" })
+        ));
+    }
+
+    #[test]
+    fn test_url_joins_base_url_and_path() {
+        let url = join_url("https://example.com", "mpesa/b2c/v1/paymentrequest");
+        assert_eq!(url, "https://example.com/mpesa/b2c/v1/paymentrequest");
+        assert_eq!(url.capacity(), url.len());
+        assert_eq!(
+            join_url("https://example.com", "/oauth/v1/generate"),
+            "https://example.com/oauth/v1/generate"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_urls_are_joined_once_on_every_base_url() {
+        let client = Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+            .fallback_base_url("https://fallback.example.com/gateway/")
+            .api_version(Service::B2c, 3)
+            .build()
+            .unwrap();
+        let urls = |path| {
+            client
+                .urls
+                .get(path)
+                .iter()
+                .map(Url::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(matches!(
+            client.urls.get(crate::paths::AUTH),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            urls("mpesa/b2c/v3/paymentrequest"),
+            [
+                "https://example.com/mpesa/b2c/v3/paymentrequest",
+                "https://fallback.example.com/gateway/mpesa/b2c/v3/paymentrequest"
+            ]
+        );
+        assert!(matches!(client.urls.get("custom/path"), Cow::Owned(_)));
+        assert_eq!(
+            urls("custom/path"),
+            [
+                "https://example.com/custom/path",
+                "https://fallback.example.com/gateway/custom/path"
+            ]
+        );
+        assert!(
+            Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+                .fallback_base_url("not a url")
+                .build()
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "b2c")]
+    fn test_urls_are_resolved_against_the_default_urls() {
+        let client = Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+            .default_result_url("https://pay.example.com/mpesa/result")
+            .build()
+            .unwrap();
+        let resolve = |kind, url| client.resolve_url(kind, url).map(Cow::into_owned);
+
+        assert_eq!(
+            resolve(UrlKind::Result, None).as_deref(),
+            Some("https://pay.example.com/mpesa/result")
+        );
+        assert_eq!(
+            resolve(UrlKind::Result, Some("/b2c")).as_deref(),
+            Some("https://pay.example.com/mpesa/result/b2c")
+        );
+        assert_eq!(
+            resolve(UrlKind::Result, Some("https://other.example.com/result")).as_deref(),
+            Some("https://other.example.com/result")
+        );
+        assert_eq!(resolve(UrlKind::Timeout, None), None);
+    }
+
+    #[test]
+    fn test_api_path_uses_the_configured_version() {
+        let client = Mpesa::builder("consumer_key", "consumer_secret", TestEnvironment)
+            .api_version(Service::BillManager, 2)
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.api_path(Service::BillManager, "v1/billmanager-invoice/optin"),
+            "v2/billmanager-invoice/optin"
+        );
+        assert_eq!(
+            client.api_path(Service::B2c, "mpesa/b2c/v1/paymentrequest"),
+            "mpesa/b2c/v1/paymentrequest"
+        );
+    }
+
     #[test]
+    #[cfg(feature = "openssl")]
     #[should_panic]
     fn test_gen_security_credentials_fails_with_invalid_pem() {
         let client = Mpesa::new("consumer_key", "consumer_secret", TestEnvironment);
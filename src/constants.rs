@@ -1,13 +1,52 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
 
-use chrono::prelude::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
+#[cfg(feature = "bill_manager")]
+use crate::datetime::{format_date, UtcDateTime};
+#[cfg(feature = "bill_manager")]
+use crate::validator::validate_local_phone_number;
 use crate::MpesaError;
+#[cfg(feature = "bill_manager")]
+use crate::MpesaResult;
+
+/// Placeholder printed in place of secrets in `Debug` output
+#[cfg(feature = "client")]
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// Rendered by `to_curl` in place of the security credential of a request
+#[cfg(feature = "openssl")]
+pub(crate) const SECURITY_CREDENTIAL_PLACEHOLDER: &str = "<SECURITY_CREDENTIAL>";
+
+/// Rendered by `to_curl` in place of the password of an M-Pesa Express request, which is derived
+/// from the passkey
+#[cfg(feature = "express_request")]
+pub(crate) const PASSWORD_PLACEHOLDER: &str = "<PASSWORD>";
+
+/// Offset of East Africa Time, the timezone Daraja validates timestamps in and sends the
+/// transaction times of callbacks in. Nairobi does not observe daylight saving time so the offset
+/// is fixed.
+pub(crate) const NAIROBI_UTC_OFFSET_SECS: i32 = 3 * 60 * 60;
+
+/// Test MSISDN documented in the Safaricom sandbox [test credentials](https://developer.safaricom.co.ke/test_credentials)
+pub const SANDBOX_TEST_MSISDN: &str = "254708374149";
+/// Lipa Na M-Pesa Online (M-Pesa Express) shortcode of the Safaricom sandbox
+pub const SANDBOX_EXPRESS_SHORTCODE: &str = "174379";
+/// Test shortcodes used throughout the Safaricom sandbox documentation and API examples. Other
+/// shortcodes in the `600000` range may belong to production organizations and are not listed.
+pub const SANDBOX_SHORTCODES: &[u32] = &[600_000, 600_111, 600_496, 600_497, 600_584, 600_638];
+/// Initiator password of the Safaricom sandbox [test credentials](https://developer.safaricom.co.ke/test_credentials)
+pub const SANDBOX_INITIATOR_PASSWORD: &str = "Safaricom999!*!";
+/// M-Pesa Express passkey of the Safaricom sandbox [test credentials](https://developer.safaricom.co.ke/test_credentials)
+pub const SANDBOX_PASSKEY: &str =
+    "bfb279f9aa9bdbcf158e97dd71a467cd2e0c893059b10f78e6b72ada1ed2c919";
 
 /// Mpesa command ids
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub enum CommandId {
     TransactionReversal,
     SalaryPayment,
@@ -15,6 +54,7 @@ pub enum CommandId {
     PromotionPayment,
     AccountBalance,
     CustomerPayBillOnline,
+    CustomerBuyGoodsOnline,
     TransactionStatusQuery,
     CheckIdentity,
     BusinessPayBill,
@@ -30,11 +70,46 @@ impl Display for CommandId {
     }
 }
 
+impl TryFrom<&str> for CommandId {
+    type Error = MpesaError;
+
+    /// Parses a command id from its Daraja spelling, ignoring case
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "transactionreversal" => Ok(CommandId::TransactionReversal),
+            "salarypayment" => Ok(CommandId::SalaryPayment),
+            "businesspayment" => Ok(CommandId::BusinessPayment),
+            "promotionpayment" => Ok(CommandId::PromotionPayment),
+            "accountbalance" => Ok(CommandId::AccountBalance),
+            "customerpaybillonline" => Ok(CommandId::CustomerPayBillOnline),
+            "customerbuygoodsonline" => Ok(CommandId::CustomerBuyGoodsOnline),
+            "transactionstatusquery" => Ok(CommandId::TransactionStatusQuery),
+            "checkidentity" => Ok(CommandId::CheckIdentity),
+            "businesspaybill" => Ok(CommandId::BusinessPayBill),
+            "businessbuygoods" => Ok(CommandId::BusinessBuyGoods),
+            "disbursefundstobusiness" => Ok(CommandId::DisburseFundsToBusiness),
+            "businesstobusinesstransfer" => Ok(CommandId::BusinessToBusinessTransfer),
+            "businesstransferfrommmftoutility" => Ok(CommandId::BusinessTransferFromMMFToUtility),
+            _ => Err(MpesaError::Message("Invalid command id")),
+        }
+    }
+}
+
+impl FromStr for CommandId {
+    type Err = MpesaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
+    }
+}
+
 /// Identifier types - both sender and receiver - identify an M-Pesa transaction’s sending and receiving party as
 /// either a shortcode, a till number or a MSISDN (phone number).
 /// There are three identifier types that can be used with M-Pesa APIs.
-#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u16)]
+#[non_exhaustive]
 pub enum IdentifierTypes {
     MSISDN = 1,
     TillNumber = 2,
@@ -48,11 +123,55 @@ impl Display for IdentifierTypes {
     }
 }
 
+impl TryFrom<u16> for IdentifierTypes {
+    type Error = MpesaError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(IdentifierTypes::MSISDN),
+            2 => Ok(IdentifierTypes::TillNumber),
+            4 => Ok(IdentifierTypes::ShortCode),
+            11 => Ok(IdentifierTypes::Reversal),
+            _ => Err(MpesaError::Message("Invalid identifier type")),
+        }
+    }
+}
+
+impl TryFrom<&str> for IdentifierTypes {
+    type Error = MpesaError;
+
+    /// Parses an identifier type from either its numeric code, as sent to Daraja, or its name,
+    /// ignoring case
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if let Ok(code) = value.parse::<u16>() {
+            return Self::try_from(code);
+        }
+
+        match value.to_lowercase().as_str() {
+            "msisdn" => Ok(IdentifierTypes::MSISDN),
+            "tillnumber" => Ok(IdentifierTypes::TillNumber),
+            "shortcode" => Ok(IdentifierTypes::ShortCode),
+            "reversal" => Ok(IdentifierTypes::Reversal),
+            _ => Err(MpesaError::Message("Invalid identifier type")),
+        }
+    }
+}
+
+impl FromStr for IdentifierTypes {
+    type Err = MpesaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
+    }
+}
+
 /// TODO: Enable deserializing of json numbers/ strings to `MpesaResponseCode`
 /// M-pesa result and response codes
 #[derive(Debug, Copy, Clone, Deserialize_repr)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u16)]
 #[allow(unused)]
+#[non_exhaustive]
 pub enum MpesaResponseCode {
     Success = 0,
     InsufficientFunds = 1,
@@ -79,8 +198,90 @@ impl Display for MpesaResponseCode {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Result codes of M-Pesa Express (STK push) payments, as reported in the `ResultCode` of their
+/// callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ExpressResultCode {
+    /// The customer completed the payment
+    Success,
+    /// The customer's balance is insufficient for the payment
+    InsufficientBalance,
+    /// Another transaction is in progress for the customer
+    TransactionInProgress,
+    /// The payment request expired before the customer entered their PIN
+    TransactionExpired,
+    /// The payment request could not be sent to the customer's phone
+    PushRequestFailed,
+    /// The customer cancelled the payment request
+    CancelledByUser,
+    /// The customer's phone could not be reached before the request timed out
+    UserUnreachable,
+    /// The customer entered a wrong PIN
+    InvalidPin,
+    Other(i32),
+}
+
+impl ExpressResultCode {
+    pub fn code(&self) -> i32 {
+        match self {
+            ExpressResultCode::Success => 0,
+            ExpressResultCode::InsufficientBalance => 1,
+            ExpressResultCode::TransactionInProgress => 1001,
+            ExpressResultCode::TransactionExpired => 1019,
+            ExpressResultCode::PushRequestFailed => 1025,
+            ExpressResultCode::CancelledByUser => 1032,
+            ExpressResultCode::UserUnreachable => 1037,
+            ExpressResultCode::InvalidPin => 2001,
+            ExpressResultCode::Other(code) => *code,
+        }
+    }
+
+    /// Describes why a payment failed, `None` for `Success` and unknown codes
+    pub fn description(&self) -> Option<&'static str> {
+        match self {
+            ExpressResultCode::Success | ExpressResultCode::Other(_) => None,
+            ExpressResultCode::InsufficientBalance => {
+                Some("The balance is insufficient for the transaction")
+            }
+            ExpressResultCode::TransactionInProgress => {
+                Some("A transaction is already in process for the subscriber")
+            }
+            ExpressResultCode::TransactionExpired => Some("The transaction has expired"),
+            ExpressResultCode::PushRequestFailed => Some("The push request could not be sent"),
+            ExpressResultCode::CancelledByUser => Some("The request was cancelled by the user"),
+            ExpressResultCode::UserUnreachable => Some("The user could not be reached"),
+            ExpressResultCode::InvalidPin => Some("The initiator information is invalid"),
+        }
+    }
+}
+
+impl From<i32> for ExpressResultCode {
+    fn from(code: i32) -> Self {
+        match code {
+            0 => ExpressResultCode::Success,
+            1 => ExpressResultCode::InsufficientBalance,
+            1001 => ExpressResultCode::TransactionInProgress,
+            1019 => ExpressResultCode::TransactionExpired,
+            1025 | 9999 => ExpressResultCode::PushRequestFailed,
+            1032 => ExpressResultCode::CancelledByUser,
+            1037 => ExpressResultCode::UserUnreachable,
+            2001 => ExpressResultCode::InvalidPin,
+            code => ExpressResultCode::Other(code),
+        }
+    }
+}
+
+impl Display for ExpressResultCode {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 /// C2B Register Response types
+#[non_exhaustive]
 pub enum ResponseType {
     Completed,
     Cancelled,
@@ -93,7 +294,9 @@ impl Display for ResponseType {
 }
 
 #[derive(Debug, Deserialize_repr, Serialize_repr, Copy, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u16)]
+#[non_exhaustive]
 pub enum SendRemindersTypes {
     Disable = 0,
     Enable = 1,
@@ -105,21 +308,76 @@ impl Display for SendRemindersTypes {
     }
 }
 
+#[cfg(feature = "bill_manager")]
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Invoice<'i> {
+    #[serde(serialize_with = "crate::json::serialize_amount")]
+    #[cfg_attr(feature = "schema", schemars(with = "f64"))]
     pub amount: f64,
     pub account_reference: &'i str,
     pub billed_full_name: &'i str,
     pub billed_period: &'i str,
     pub billed_phone_number: &'i str,
-    pub due_date: DateTime<Utc>,
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub due_date: UtcDateTime,
     pub external_reference: &'i str,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub invoice_items: Option<Vec<InvoiceItem<'i>>>,
     pub invoice_name: &'i str,
 }
 
+#[cfg(feature = "bill_manager")]
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+#[cfg(feature = "bill_manager")]
+impl<'i> Invoice<'i> {
+    /// Sets `due_date` from a `time::OffsetDateTime`
+    #[cfg(feature = "time")]
+    pub fn with_due_date_time(mut self, due_date: time::OffsetDateTime) -> Invoice<'i> {
+        self.due_date = crate::datetime::from_time(due_date);
+        self
+    }
+
+    /// Validates the fields Bill Manager silently drops invoices over:
+    /// `billed_period` must be in the format `"Month Year"` and `billed_phone_number` in the format `07XXXXXXXX`
+    pub(crate) fn validate(&self) -> MpesaResult<()> {
+        let is_valid_period = match self.billed_period.split_once(' ') {
+            Some((month, year)) => {
+                MONTHS.contains(&month)
+                    && year.len() == 4
+                    && year.bytes().all(|b| b.is_ascii_digit())
+            }
+            None => false,
+        };
+        if !is_valid_period {
+            return Err(MpesaError::Message(
+                "Invalid billed_period, must be in the format \"Month Year\" e.g. \"March 2023\"",
+            ));
+        }
+
+        validate_local_phone_number(
+            self.billed_phone_number,
+            "Invalid billed_phone_number, must be in the format 07XXXXXXXX",
+        )
+    }
+}
+
+#[cfg(feature = "bill_manager")]
 impl<'i> Display for Invoice<'i> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(
@@ -127,18 +385,23 @@ impl<'i> Display for Invoice<'i> {
             "amount: {}, account_reference: {}, due_date: {}, invoice_name: {}",
             self.amount,
             self.account_reference,
-            self.due_date.format("%Y-%m-%d"),
+            format_date(&self.due_date),
             self.invoice_name,
         )
     }
 }
 
-#[derive(Debug, Serialize)]
+#[cfg(feature = "bill_manager")]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct InvoiceItem<'i> {
+    #[serde(serialize_with = "crate::json::serialize_amount")]
+    #[cfg_attr(feature = "schema", schemars(with = "f64"))]
     pub amount: f64,
     pub item_name: &'i str,
 }
 
+#[cfg(feature = "bill_manager")]
 impl<'i> Display for InvoiceItem<'i> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(f, "amount: {}, item_name: {}", self.amount, self.item_name)
@@ -146,6 +409,8 @@ impl<'i> Display for InvoiceItem<'i> {
 }
 
 #[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub enum TransactionType {
     /// Send Money(Mobile number).
     SendMoney,
@@ -179,3 +444,163 @@ impl TryFrom<&str> for TransactionType {
         }
     }
 }
+
+/// The Daraja APIs, used to select the version of an API the client calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Service {
+    AccountBalance,
+    B2b,
+    B2c,
+    BillManager,
+    C2bRegister,
+    C2bSimulate,
+    DynamicQr,
+    ExpressRequest,
+    TransactionReversal,
+    TransactionStatus,
+}
+
+impl Service {
+    /// The version of the API called unless overridden with `MpesaBuilder::api_version`
+    pub fn default_api_version(&self) -> u8 {
+        1
+    }
+
+    /// Whether the API only exists in the sandbox, such as C2B simulate which stands in for
+    /// customers paying from their phone
+    pub fn is_sandbox_only(&self) -> bool {
+        matches!(self, Service::C2bSimulate)
+    }
+
+    /// Whether requests to the API move money or reach customers, which decides the retry policy
+    /// they are sent with
+    pub fn category(&self) -> ServiceCategory {
+        match self {
+            Service::B2b
+            | Service::B2c
+            | Service::C2bSimulate
+            | Service::ExpressRequest
+            | Service::TransactionReversal => ServiceCategory::Payment,
+            Service::BillManager => ServiceCategory::Notification,
+            Service::AccountBalance
+            | Service::C2bRegister
+            | Service::DynamicQr
+            | Service::TransactionStatus => ServiceCategory::Query,
+        }
+    }
+}
+
+/// Groups the Daraja APIs by whether their requests move money, see `MpesaBuilder::retry_policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ServiceCategory {
+    /// Requests that do not move money, such as balance and status queries
+    Query,
+    /// Requests that move money, which could be paid twice if sent again
+    Payment,
+    /// Requests that reach customers without moving money, such as Bill Manager invoices and
+    /// e-receipts sent by SMS, which could be sent twice if sent again
+    Notification,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_id_is_parsed_from_daraja_spelling() {
+        let command_ids = [
+            CommandId::TransactionReversal,
+            CommandId::SalaryPayment,
+            CommandId::BusinessPayment,
+            CommandId::PromotionPayment,
+            CommandId::AccountBalance,
+            CommandId::CustomerPayBillOnline,
+            CommandId::CustomerBuyGoodsOnline,
+            CommandId::TransactionStatusQuery,
+            CommandId::CheckIdentity,
+            CommandId::BusinessPayBill,
+            CommandId::BusinessBuyGoods,
+            CommandId::DisburseFundsToBusiness,
+            CommandId::BusinessToBusinessTransfer,
+            CommandId::BusinessTransferFromMMFToUtility,
+        ];
+        for command_id in command_ids {
+            assert_eq!(
+                command_id.to_string().parse::<CommandId>().unwrap(),
+                command_id
+            );
+        }
+        assert_eq!(
+            CommandId::try_from("customerPayBillOnline").unwrap(),
+            CommandId::CustomerPayBillOnline
+        );
+        assert!(CommandId::from_str("PayBill").is_err());
+    }
+
+    #[test]
+    fn test_express_result_code_round_trips() {
+        for code in [0, 1, 1001, 1019, 1025, 1032, 1037, 2001, 17] {
+            assert_eq!(ExpressResultCode::from(code).code(), code);
+        }
+        assert_eq!(
+            ExpressResultCode::from(9999),
+            ExpressResultCode::PushRequestFailed
+        );
+        assert_eq!(ExpressResultCode::from(17), ExpressResultCode::Other(17));
+        assert_eq!(ExpressResultCode::Success.description(), None);
+    }
+
+    #[test]
+    fn test_identifier_type_is_parsed_from_code_or_name() {
+        let identifier_types = [
+            IdentifierTypes::MSISDN,
+            IdentifierTypes::TillNumber,
+            IdentifierTypes::ShortCode,
+            IdentifierTypes::Reversal,
+        ];
+        for identifier_type in identifier_types {
+            assert_eq!(
+                IdentifierTypes::try_from(identifier_type as u16).unwrap(),
+                identifier_type
+            );
+            assert_eq!(
+                identifier_type
+                    .to_string()
+                    .parse::<IdentifierTypes>()
+                    .unwrap(),
+                identifier_type
+            );
+            assert_eq!(
+                format!("{identifier_type:?}")
+                    .parse::<IdentifierTypes>()
+                    .unwrap(),
+                identifier_type
+            );
+        }
+        assert_eq!(
+            IdentifierTypes::try_from("shortcode").unwrap(),
+            IdentifierTypes::ShortCode
+        );
+        assert!(IdentifierTypes::try_from(3).is_err());
+        assert!(IdentifierTypes::from_str("Paybill").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "schema")]
+    fn test_schema_matches_wire_format() {
+        let schema = schemars::schema_for!(IdentifierTypes);
+        assert_eq!(
+            schema.get("enum").unwrap(),
+            &serde_json::json!([1, 2, 4, 11])
+        );
+
+        let schema = schemars::schema_for!(CommandId);
+        assert!(schema
+            .get("enum")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("BusinessPayBill")));
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "__request")]
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Mutex,
+    time::Instant,
+};
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::constants::REDACTED;
+
+/// Strategy used to pick a consumer key/secret pair when a client is configured with several
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CredentialSelection {
+    /// Cycle through the credentials in the order they were registered
+    #[default]
+    RoundRobin,
+    /// Pick the credentials that have gone unused the longest
+    LeastRecentlyUsed,
+}
+
+/// A consumer key/secret pair
+#[derive(Clone)]
+pub(crate) struct Credentials {
+    consumer_key: Secret<String>,
+    consumer_secret: Secret<String>,
+    /// Hash of the consumer key, which the access tokens are cached under so that the global
+    /// cache holds no copy of the key
+    cache_key: u64,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("consumer_key", &REDACTED)
+            .field("consumer_secret", &REDACTED)
+            .finish()
+    }
+}
+
+impl Credentials {
+    pub(crate) fn new(consumer_key: String, consumer_secret: String) -> Self {
+        let mut hasher = DefaultHasher::new();
+        consumer_key.hash(&mut hasher);
+        Credentials {
+            consumer_key: Secret::new(consumer_key),
+            consumer_secret: Secret::new(consumer_secret),
+            cache_key: hasher.finish(),
+        }
+    }
+
+    /// Get the consumer key
+    pub(crate) fn consumer_key(&self) -> &str {
+        self.consumer_key.expose_secret()
+    }
+
+    /// Get the consumer secret
+    pub(crate) fn consumer_secret(&self) -> &str {
+        self.consumer_secret.expose_secret()
+    }
+
+    /// Get the key of the access token of the credentials in the `auth::AUTH` cache
+    pub(crate) fn cache_key(&self) -> u64 {
+        self.cache_key
+    }
+}
+
+/// The credentials registered on a client along with the state needed to select between them.
+/// Shared between clones of a client so that they rotate through the same credentials.
+pub(crate) struct CredentialPool {
+    credentials: Vec<Credentials>,
+    selection: CredentialSelection,
+    #[cfg(feature = "__request")]
+    next: AtomicUsize,
+    #[cfg(feature = "__request")]
+    last_used: Mutex<Vec<Option<Instant>>>,
+}
+
+impl CredentialPool {
+    /// Creates a pool from a non-empty list of credentials
+    pub(crate) fn new(credentials: Vec<Credentials>, selection: CredentialSelection) -> Self {
+        assert!(
+            !credentials.is_empty(),
+            "at least one credential is required"
+        );
+        CredentialPool {
+            #[cfg(feature = "__request")]
+            last_used: Mutex::new(vec![None; credentials.len()]),
+            credentials,
+            selection,
+            #[cfg(feature = "__request")]
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The credentials the client was created with
+    pub(crate) fn primary(&self) -> &Credentials {
+        &self.credentials[0]
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Credentials> {
+        self.credentials.iter()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.credentials.len()
+    }
+
+    pub(crate) fn selection(&self) -> CredentialSelection {
+        self.selection
+    }
+
+    /// Picks the credentials to use for the next request
+    #[cfg(feature = "__request")]
+    pub(crate) fn select(&self) -> &Credentials {
+        if self.credentials.len() == 1 {
+            return self.primary();
+        }
+
+        let index = match self.selection {
+            CredentialSelection::RoundRobin => {
+                self.next.fetch_add(1, Ordering::Relaxed) % self.credentials.len()
+            }
+            CredentialSelection::LeastRecentlyUsed => {
+                let mut last_used = self.last_used.lock().unwrap_or_else(|e| e.into_inner());
+                // `None` orders before any `Some`, so unused credentials are picked first
+                let index = last_used
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, used_at)| **used_at)
+                    .map(|(index, _)| index)
+                    .unwrap_or_default();
+                last_used[index] = Some(Instant::now());
+                index
+            }
+        };
+
+        &self.credentials[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(selection: CredentialSelection) -> CredentialPool {
+        CredentialPool::new(
+            vec![
+                Credentials::new("key_a".to_owned(), "secret_a".to_owned()),
+                Credentials::new("key_b".to_owned(), "secret_b".to_owned()),
+                Credentials::new("key_c".to_owned(), "secret_c".to_owned()),
+            ],
+            selection,
+        )
+    }
+
+    #[test]
+    fn test_round_robin_cycles_through_credentials() {
+        let pool = pool(CredentialSelection::RoundRobin);
+        let keys: Vec<_> = (0..4).map(|_| pool.select().consumer_key()).collect();
+        assert_eq!(keys, ["key_a", "key_b", "key_c", "key_a"]);
+    }
+
+    #[test]
+    fn test_least_recently_used_picks_the_stalest_credentials() {
+        let pool = pool(CredentialSelection::LeastRecentlyUsed);
+        let keys: Vec<_> = (0..3).map(|_| pool.select().consumer_key()).collect();
+        assert_eq!(keys, ["key_a", "key_b", "key_c"]);
+        assert_eq!(pool.select().consumer_key(), "key_a");
+    }
+}
@@ -0,0 +1,166 @@
+//! Date and time types used by the request payloads.
+//!
+//! These are `chrono` types. Projects standardizing on the `time` crate can enable the `time`
+//! feature, which adds [`from_time`] and [`timestamp_from_time`] along with setters such as
+//! `SingleInvoiceBuilder::due_date_time` and `Invoice::with_due_date_time` taking a
+//! `time::OffsetDateTime`. Either way the fields are serialized identically.
+
+use crate::constants::NAIROBI_UTC_OFFSET_SECS;
+
+/// Point in time used for invoice due dates and payment dates
+pub type UtcDateTime = chrono::DateTime<chrono::Utc>;
+
+/// Point in time used for request timestamps, such as the M-Pesa Express `Timestamp`
+pub type Timestamp = chrono::DateTime<chrono::Local>;
+
+/// Returns the current time as a `Timestamp`
+#[cfg(feature = "express_request")]
+pub(crate) fn now() -> Timestamp {
+    chrono::Local::now()
+}
+
+/// Converts a `time::OffsetDateTime` to a `UtcDateTime`
+#[cfg(feature = "time")]
+pub fn from_time(date: time::OffsetDateTime) -> UtcDateTime {
+    chrono::DateTime::from_timestamp(date.unix_timestamp(), date.nanosecond())
+        .expect("the range of chrono covers the range of time")
+}
+
+/// Converts a `time::OffsetDateTime` to a `Timestamp`
+#[cfg(all(feature = "time", feature = "express_request"))]
+pub fn timestamp_from_time(date: time::OffsetDateTime) -> Timestamp {
+    from_time(date).with_timezone(&chrono::Local)
+}
+
+/// Formats `date` as a `YYYYMMDDHHmmss` timestamp in Nairobi time, whatever the host timezone
+#[cfg(feature = "express_request")]
+pub(crate) fn format_timestamp<Tz: chrono::TimeZone>(date: &chrono::DateTime<Tz>) -> String {
+    let nairobi =
+        chrono::FixedOffset::east_opt(NAIROBI_UTC_OFFSET_SECS).expect("offset is in range");
+    date.with_timezone(&nairobi)
+        .format("%Y%m%d%H%M%S")
+        .to_string()
+}
+
+/// Parses a `YYYYMMDDHHmmss` timestamp in Nairobi time, such as the `TransTime` of a C2B payment
+#[cfg(feature = "bill_manager")]
+pub(crate) fn parse_timestamp(timestamp: &str) -> Option<UtcDateTime> {
+    let nairobi = chrono::FixedOffset::east_opt(NAIROBI_UTC_OFFSET_SECS)?;
+    chrono::NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S")
+        .ok()?
+        .and_local_timezone(nairobi)
+        .single()
+        .map(|date| date.with_timezone(&chrono::Utc))
+}
+
+/// Builds a `UtcDateTime` from a unix timestamp in seconds
+#[cfg(feature = "bill_manager")]
+pub(crate) fn from_unix_timestamp(secs: i64) -> Option<UtcDateTime> {
+    chrono::DateTime::from_timestamp(secs, 0)
+}
+
+/// Formats the date of `date` as `YYYY-MM-DD`
+#[cfg(feature = "bill_manager")]
+pub(crate) fn format_date(date: &UtcDateTime) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a `UtcDateTime` from a unix timestamp with nanosecond precision
+    #[cfg(any(feature = "bill_manager", feature = "time"))]
+    fn utc(secs: i64, nanos: u32) -> UtcDateTime {
+        chrono::DateTime::from_timestamp(secs, nanos).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "bill_manager")]
+    fn test_utc_date_time_is_serialized_as_rfc3339() {
+        // 2023-08-01T09:30:00Z
+        let secs = 1_690_882_200;
+        for (nanos, expected) in [
+            (0, "2023-08-01T09:30:00Z"),
+            (250_000_000, "2023-08-01T09:30:00.250Z"),
+            (250_500_000, "2023-08-01T09:30:00.250500Z"),
+        ] {
+            assert_eq!(serde_json::to_value(utc(secs, nanos)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "time")]
+    fn test_time_date_times_are_converted() {
+        // 2023-08-01T12:30:00.25+03:00
+        let date = time::OffsetDateTime::from_unix_timestamp(1_690_882_200).unwrap()
+            + time::Duration::milliseconds(250);
+        let date = date.to_offset(time::UtcOffset::from_hms(3, 0, 0).unwrap());
+        assert_eq!(from_time(date), utc(1_690_882_200, 250_000_000));
+    }
+
+    #[test]
+    #[cfg(all(feature = "time", feature = "bill_manager"))]
+    fn test_invoices_due_at_a_time_date_time_are_serialized_as_with_chrono() {
+        use crate::Invoice;
+
+        let invoice = |due_date| Invoice {
+            amount: 1000.0,
+            account_reference: "John Doe",
+            billed_full_name: "John Doe",
+            billed_period: "August 2021",
+            billed_phone_number: "0712345678",
+            due_date,
+            external_reference: "INV2345",
+            invoice_items: None,
+            invoice_name: "Invoice 001",
+        };
+        // 2023-08-01T12:30:00.2505+03:00
+        let date = time::OffsetDateTime::from_unix_timestamp(1_690_882_200).unwrap()
+            + time::Duration::microseconds(250_500);
+        let date = date.to_offset(time::UtcOffset::from_hms(3, 0, 0).unwrap());
+
+        let chrono = serde_json::to_vec(&invoice(utc(1_690_882_200, 250_500_000))).unwrap();
+        let time = serde_json::to_vec(&invoice(utc(0, 0)).with_due_date_time(date)).unwrap();
+        assert_eq!(time, chrono);
+    }
+
+    #[test]
+    #[cfg(all(feature = "time", feature = "express_request"))]
+    fn test_time_timestamps_are_formatted_as_with_chrono() {
+        // 2023-12-31T22:30:15Z
+        let chrono = chrono::DateTime::from_timestamp(1_704_061_815, 0)
+            .unwrap()
+            .with_timezone(&chrono::Local);
+        let time = time::OffsetDateTime::from_unix_timestamp(1_704_061_815).unwrap();
+
+        assert_eq!(timestamp_from_time(time), chrono);
+        assert_eq!(
+            format_timestamp(&timestamp_from_time(time)),
+            format_timestamp(&chrono)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "bill_manager")]
+    fn test_timestamp_is_parsed_in_nairobi_time() {
+        // 2024-01-01T01:30:15+03:00
+        assert_eq!(
+            parse_timestamp("20240101013015"),
+            Some(utc(1_704_061_815, 0))
+        );
+        assert_eq!(parse_timestamp("20241301013015"), None);
+        assert_eq!(parse_timestamp("2024-01-01"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "express_request")]
+    fn test_timestamp_is_formatted_in_nairobi_time() {
+        // 2023-12-31T22:30:15Z
+        let date = chrono::DateTime::from_timestamp(1_704_061_815, 0)
+            .unwrap()
+            .with_timezone(&chrono::Local);
+
+        assert_eq!(format_timestamp(&date), "20240101013015");
+    }
+}
@@ -0,0 +1,286 @@
+//! End-to-end walkthrough of the Safaricom sandbox
+//!
+//! [`SandboxDemo`] runs the flows a new integration usually starts with — registering C2B urls,
+//! simulating a C2B payment, sending an M-Pesa Express (STK push) request and querying its status —
+//! and returns a [`Transcript`] of every step, so that new users and CI smoke tests can check
+//! their credentials end-to-end with one call.
+//!
+//! ```rust,ignore
+//! use mpesa::demo::SandboxDemo;
+//! use mpesa::{Environment, Mpesa};
+//!
+//! let client = Mpesa::new(consumer_key, consumer_secret, Environment::Sandbox);
+//! let transcript = SandboxDemo::new(&client, "https://pay.example.com/mpesa")
+//!     .run()
+//!     .await;
+//! for step in &transcript.steps {
+//!     println!("{:?} in {:?}: {}", step.kind(), step.elapsed, step.summary());
+//! }
+//! assert!(transcript.is_success());
+//! ```
+//!
+//! Steps run in order and do not stop at the first failure, except for the status query which
+//! needs the `CheckoutRequestID` of the STK push.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::deserialize_string_from_number;
+
+use crate::client::Mpesa;
+use crate::constants::{CommandId, Service, SANDBOX_EXPRESS_SHORTCODE, SANDBOX_TEST_MSISDN};
+use crate::datetime::{self, format_timestamp};
+use crate::paths;
+use crate::services::{
+    C2bRegisterResponse, C2bSimulateResponse, MpesaExpress, MpesaExpressResponse,
+};
+use crate::MpesaResult;
+
+/// Response of an M-Pesa Express status query
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
+pub struct StkQueryResponse {
+    #[serde(rename = "MerchantRequestID")]
+    pub merchant_request_id: String,
+    #[serde(rename = "CheckoutRequestID")]
+    pub checkout_request_id: String,
+    pub response_code: String,
+    pub response_description: String,
+    /// `0` once the customer has paid, see `ExpressResultCode` for the others
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub result_code: String,
+    pub result_desc: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct StkQueryPayload<'a> {
+    business_short_code: &'a str,
+    password: String,
+    timestamp: String,
+    #[serde(rename = "CheckoutRequestID")]
+    checkout_request_id: &'a str,
+}
+
+/// Outcome of a step of a `SandboxDemo`
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DemoStep {
+    RegisterUrls(MpesaResult<C2bRegisterResponse>),
+    SimulateC2b(MpesaResult<C2bSimulateResponse>),
+    StkPush(MpesaResult<MpesaExpressResponse>),
+    StkQuery(MpesaResult<StkQueryResponse>),
+}
+
+/// Names of the steps of a `SandboxDemo`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DemoStepKind {
+    RegisterUrls,
+    SimulateC2b,
+    StkPush,
+    StkQuery,
+}
+
+/// A step of a `SandboxDemo` and how long it took
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct StepRecord {
+    pub step: DemoStep,
+    pub elapsed: Duration,
+}
+
+impl StepRecord {
+    pub fn kind(&self) -> DemoStepKind {
+        match self.step {
+            DemoStep::RegisterUrls(_) => DemoStepKind::RegisterUrls,
+            DemoStep::SimulateC2b(_) => DemoStepKind::SimulateC2b,
+            DemoStep::StkPush(_) => DemoStepKind::StkPush,
+            DemoStep::StkQuery(_) => DemoStepKind::StkQuery,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        match &self.step {
+            DemoStep::RegisterUrls(res) => res.is_ok(),
+            DemoStep::SimulateC2b(res) => res.is_ok(),
+            DemoStep::StkPush(res) => res.is_ok(),
+            DemoStep::StkQuery(res) => res.is_ok(),
+        }
+    }
+
+    /// Describes the outcome of the step in a line, the response description or the error
+    pub fn summary(&self) -> String {
+        match &self.step {
+            DemoStep::RegisterUrls(Ok(res)) => res.response_description.clone(),
+            DemoStep::SimulateC2b(Ok(res)) => res.response_description.clone(),
+            DemoStep::StkPush(Ok(res)) => res.response_description.clone(),
+            DemoStep::StkQuery(Ok(res)) => res.result_desc.clone(),
+            DemoStep::RegisterUrls(Err(e))
+            | DemoStep::SimulateC2b(Err(e))
+            | DemoStep::StkPush(Err(e))
+            | DemoStep::StkQuery(Err(e)) => e.to_string(),
+        }
+    }
+}
+
+/// The steps run by a `SandboxDemo`, in order
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct Transcript {
+    pub steps: Vec<StepRecord>,
+}
+
+impl Transcript {
+    /// Returns `true` if every step succeeded
+    pub fn is_success(&self) -> bool {
+        self.steps.iter().all(StepRecord::is_success)
+    }
+
+    /// Returns the steps that failed
+    pub fn failures(&self) -> impl Iterator<Item = &StepRecord> {
+        self.steps.iter().filter(|step| !step.is_success())
+    }
+
+    fn record<T>(&mut self, started: Instant, res: T, step: impl FnOnce(T) -> DemoStep) {
+        self.steps.push(StepRecord {
+            step: step(res),
+            elapsed: started.elapsed(),
+        });
+    }
+}
+
+/// Runs the C2B and M-Pesa Express flows against the Safaricom sandbox
+#[derive(Debug)]
+pub struct SandboxDemo<'mpesa> {
+    client: &'mpesa Mpesa,
+    base_url: &'mpesa str,
+    short_code: &'mpesa str,
+    phone_number: &'mpesa str,
+    amount: u32,
+    query_delay: Duration,
+}
+
+impl<'mpesa> SandboxDemo<'mpesa> {
+    /// Creates a demo whose callbacks are sent under `base_url`, e.g.
+    /// `https://pay.example.com/mpesa` for `https://pay.example.com/mpesa/c2b/validation`
+    pub fn new(client: &'mpesa Mpesa, base_url: &'mpesa str) -> Self {
+        SandboxDemo {
+            client,
+            base_url,
+            short_code: "600496",
+            phone_number: SANDBOX_TEST_MSISDN,
+            amount: 1,
+            query_delay: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets the C2B shortcode of the sandbox app. Defaults to `600496`
+    pub fn short_code(mut self, short_code: &'mpesa str) -> Self {
+        self.short_code = short_code;
+        self
+    }
+
+    /// Sets the phone number paying. Defaults to the sandbox test MSISDN
+    pub fn phone_number(mut self, phone_number: &'mpesa str) -> Self {
+        self.phone_number = phone_number;
+        self
+    }
+
+    /// Sets the amount paid, in shillings. Defaults to `1`
+    pub fn amount(mut self, amount: u32) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    /// Sets how long to wait after the STK push before querying its status, since it is only
+    /// known once the customer has answered the prompt. Defaults to `10` seconds
+    pub fn query_delay(mut self, query_delay: Duration) -> Self {
+        self.query_delay = query_delay;
+        self
+    }
+
+    /// Runs every step, returning their outcomes
+    pub async fn run(self) -> Transcript {
+        let mut transcript = Transcript::default();
+        let base_url = self.base_url.trim_end_matches('/');
+
+        let started = Instant::now();
+        let validation_url = format!("{base_url}/c2b/validation");
+        let confirmation_url = format!("{base_url}/c2b/confirmation");
+        let res = self
+            .client
+            .c2b_register()
+            .short_code(self.short_code)
+            .validation_url(&validation_url)
+            .confirmation_url(&confirmation_url)
+            .send()
+            .await;
+        transcript.record(started, res, DemoStep::RegisterUrls);
+
+        let started = Instant::now();
+        let res = self
+            .client
+            .c2b_simulate()
+            .command_id(CommandId::CustomerPayBillOnline)
+            .short_code(self.short_code)
+            .msisdn(self.phone_number)
+            .amount_kes(self.amount)
+            .bill_ref_number("demo")
+            .send()
+            .await;
+        transcript.record(started, res, DemoStep::SimulateC2b);
+
+        let started = Instant::now();
+        let res = self.stk_push(&format!("{base_url}/stk")).await;
+        let checkout_request_id = res.as_ref().ok().map(|res| res.checkout_request_id.clone());
+        transcript.record(started, res, DemoStep::StkPush);
+
+        if let Some(checkout_request_id) = checkout_request_id {
+            tokio::time::sleep(self.query_delay).await;
+            let started = Instant::now();
+            let res = self.stk_query(&checkout_request_id).await;
+            transcript.record(started, res, DemoStep::StkQuery);
+        }
+
+        transcript
+    }
+
+    async fn stk_push(&self, callback_url: &str) -> MpesaResult<MpesaExpressResponse> {
+        self.client
+            .express_request()
+            .business_short_code(SANDBOX_EXPRESS_SHORTCODE)
+            .transaction_type(CommandId::CustomerPayBillOnline)
+            .amount_kes(self.amount)
+            .party_a(self.phone_number)
+            .party_b(SANDBOX_EXPRESS_SHORTCODE)
+            .phone_number(self.phone_number)
+            .try_callback_url(callback_url)?
+            .account_ref("demo")
+            .build()?
+            .send()
+            .await
+    }
+
+    async fn stk_query(&self, checkout_request_id: &str) -> MpesaResult<StkQueryResponse> {
+        let timestamp = datetime::now();
+        let payload = StkQueryPayload {
+            business_short_code: SANDBOX_EXPRESS_SHORTCODE,
+            password: MpesaExpress::encode_password_at(SANDBOX_EXPRESS_SHORTCODE, None, &timestamp),
+            timestamp: format_timestamp(&timestamp),
+            checkout_request_id,
+        };
+        self.client
+            .send_with_meta(crate::client::Request {
+                method: reqwest::Method::POST,
+                service: Service::ExpressRequest,
+                path: self
+                    .client
+                    .api_path(Service::ExpressRequest, paths::EXPRESS_QUERY),
+                body: payload,
+            })
+            .await
+            .map(|res| res.response)
+    }
+}
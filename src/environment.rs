@@ -19,6 +19,7 @@ use crate::MpesaError;
 /// Enum to map to desired environment so as to access certificate
 /// and the base url
 /// Required to construct a new `Mpesa` struct
+#[non_exhaustive]
 pub enum Environment {
     /// Production environment
     Production,
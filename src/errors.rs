@@ -6,34 +6,111 @@ use thiserror::Error;
 
 /// Mpesa error stack
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum MpesaError {
     #[error("Service error: {0}")]
     Service(ResponseError),
+    #[cfg(feature = "client")]
     #[error("An error has occurred while performing the http request")]
     NetworkError(#[from] reqwest::Error),
     #[error("An error has occurred while serializing/ deserializing")]
     ParseError(#[from] serde_json::Error),
     #[error("An error has occurred while retrieving an environmental variable")]
     EnvironmentalVariableError(#[from] VarError),
+    #[cfg(feature = "openssl")]
     #[error("An error has occurred while generating security credentials")]
     EncryptionError(#[from] openssl::error::ErrorStack),
+    #[cfg(feature = "server")]
+    #[error("An error has occurred while running the webhook server")]
+    ServerError(#[from] hyper::Error),
+    #[cfg(feature = "sqlx")]
+    #[error("An error has occurred while accessing the database")]
+    DatabaseError(#[from] sqlx::Error),
+    #[cfg(any(feature = "kafka", feature = "nats", feature = "rabbitmq"))]
+    #[error("An error has occurred while publishing a callback: {0}")]
+    PublishError(Box<dyn std::error::Error + Send + Sync>),
+    #[error("The request quota has been exceeded, retry after {0:?}")]
+    QuotaExceeded(std::time::Duration),
+    #[cfg(feature = "client")]
+    #[error("The Safaricom API is down for planned maintenance")]
+    Maintenance(crate::status::MaintenanceWindow),
+    #[error("The deadline of the request has passed before it completed")]
+    DeadlineExceeded,
+    #[error("The client has been shut down")]
+    ShutDown,
+    #[error("The request was cancelled")]
+    Cancelled,
+    #[error("The {0:?} API is only available in the sandbox")]
+    SandboxOnly(crate::Service),
+    #[error("A request with the idempotency key {0} is already in progress")]
+    IdempotencyConflict(String),
+    #[error("The request is a duplicate of a transaction already processed")]
+    Duplicate {
+        /// `ConversationID` of the original transaction, when the API reports it
+        original_conversation_id: Option<String>,
+    },
+    #[error("An identical STK push was sent to the phone number within the guard window")]
+    DuplicateStkPush {
+        /// `CheckoutRequestID` of the original push, `None` while it is in flight
+        checkout_request_id: Option<String>,
+    },
+    #[error("{command_id} requires {party} to be {expected}")]
+    InvalidParty {
+        command_id: crate::CommandId,
+        /// `PartyA` or `PartyB`
+        party: &'static str,
+        expected: crate::validator::PartyType,
+    },
+    #[error("The body is over the limit of {0} bytes")]
+    BodyTooLarge(usize),
+    #[error("An error has occurred while reading or writing data")]
+    IoError(#[from] std::io::Error),
+    #[error("{0} is not a known beneficiary")]
+    UnknownBeneficiary(String),
+    #[error("The payout of {amount} to {msisdn} is over its limit of {max_amount}")]
+    BeneficiaryLimitExceeded {
+        msisdn: String,
+        amount: f64,
+        max_amount: f64,
+    },
+    #[error("{field} is {len} characters long, over the limit of {max_len}")]
+    TextTooLong {
+        field: crate::text_template::TextField,
+        len: usize,
+        max_len: usize,
+    },
     #[error("{0}")]
     Message(&'static str),
     #[error("An error has occurred while building the request: {0}")]
     BuilderError(BuilderError),
+    #[error("Validation failed: {0}")]
+    Validation(#[from] ValidationErrors),
 }
 
 /// `Result` enum type alias
 pub type MpesaResult<T> = Result<T, MpesaError>;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all(deserialize = "camelCase"))]
+#[non_exhaustive]
 pub struct ResponseError {
     pub request_id: String,
     pub error_code: String,
     pub error_message: String,
 }
 
+impl ResponseError {
+    /// Creates a `ResponseError`, for example to stand in for an error returned by the Safaricom API in tests
+    pub fn new<S: Into<String>>(request_id: S, error_code: S, error_message: S) -> Self {
+        ResponseError {
+            request_id: request_id.into(),
+            error_code: error_code.into(),
+            error_message: error_message.into(),
+        }
+    }
+}
+
 impl fmt::Display for ResponseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -44,7 +121,96 @@ impl fmt::Display for ResponseError {
     }
 }
 
+/// Every problem found while validating a request, as returned by the builders' `validate_all` methods.
+///
+/// Unlike `send`, which fails on the first missing or invalid field, `validate_all` checks all of
+/// them so that everything wrong with, for example, a payment form can be reported at once.
+#[derive(Debug, Default)]
+pub struct ValidationErrors {
+    errors: Vec<MpesaError>,
+}
+
+impl ValidationErrors {
+    /// Returns the number of problems found
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// Returns `true` if no problems were found
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns an iterator over the problems found, in field order
+    pub fn iter(&self) -> std::slice::Iter<'_, MpesaError> {
+        self.errors.iter()
+    }
+}
+
+#[cfg(any(
+    feature = "b2b",
+    feature = "b2c",
+    feature = "c2b_simulate",
+    feature = "express_request"
+))]
+impl ValidationErrors {
+    /// Records `error` if `value` is `None`
+    pub(crate) fn require<T>(&mut self, value: Option<T>, error: MpesaError) {
+        if value.is_none() {
+            self.errors.push(error);
+        }
+    }
+
+    /// Records the error of a failed check
+    pub(crate) fn check(&mut self, result: MpesaResult<()>) {
+        if let Err(e) = result {
+            self.errors.push(e);
+        }
+    }
+
+    pub(crate) fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl IntoIterator for ValidationErrors {
+    type Item = MpesaError;
+    type IntoIter = std::vec::IntoIter<MpesaError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ValidationErrors {
+    type Item = &'a MpesaError;
+    type IntoIter = std::slice::Iter<'a, MpesaError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.iter()
+    }
+}
+
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum BuilderError {
     #[error("Field [{0}] is required")]
     UninitializedField(&'static str),
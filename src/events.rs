@@ -0,0 +1,299 @@
+//! Domain events built from callbacks
+//!
+//! Business logic reacting to payments rarely cares about the shape of Daraja callbacks: it needs
+//! to know that order 1042 was paid 500 shillings. Implementing [`FromCallback`] for an event
+//! such as `OrderPaid { order_id, amount }` keeps the callback types at the edge of an
+//! application. An [`EventMapper`] reads the fields of a callback, along with the values captured
+//! from its reference by the `TextTemplate` the reference was rendered with, and hands them to
+//! `from_callback`.
+//!
+//! The reference of a C2B payment is its `BillRefNumber`. Results carry the `Metadata` of the
+//! `Occasion` or `Remarks` of their request rather than a reference. STK callbacks carry neither,
+//! so the account reference a push was sent with is given to `map_with_reference`, for instance
+//! after looking it up by `CheckoutRequestID`.
+//!
+//! ```rust
+//! use mpesa::callbacks::{Callback, C2bTransaction};
+//! use mpesa::events::{CallbackFields, EventMapper, FromCallback};
+//! use mpesa::text_template::{TextField, TextTemplate};
+//!
+//! struct OrderPaid {
+//!     order_id: u64,
+//!     amount: f64,
+//! }
+//!
+//! impl FromCallback for OrderPaid {
+//!     fn from_callback(fields: &CallbackFields<'_>) -> Option<Self> {
+//!         if !fields.is_success() {
+//!             return None;
+//!         }
+//!         Some(OrderPaid {
+//!             order_id: fields.parse("order_id")?,
+//!             amount: fields.amount()?,
+//!         })
+//!     }
+//! }
+//!
+//! let mapper = EventMapper::new()
+//!     .template(TextTemplate::parse(TextField::AccountReference, "Order {order_id}").unwrap());
+//! let transaction: C2bTransaction = serde_json::from_str(r#"{
+//!     "TransactionType": "Pay Bill",
+//!     "TransID": "RKTQDM7W6S",
+//!     "TransTime": "20191122063845",
+//!     "TransAmount": "500",
+//!     "BusinessShortCode": "600638",
+//!     "BillRefNumber": "Order 1042",
+//!     "MSISDN": "254708374149"
+//! }"#).unwrap();
+//! let event: OrderPaid = mapper.map(&Callback::C2b(transaction)).unwrap();
+//! assert_eq!((event.order_id, event.amount), (1042, 500.0));
+//! ```
+
+use std::str::FromStr;
+
+use crate::callbacks::{value_to_string, Callback};
+use crate::metadata::Metadata;
+use crate::text_template::TextTemplate;
+
+/// An event of an application built from a callback
+pub trait FromCallback: Sized {
+    /// Builds the event from the fields of a callback, `None` if the callback is not this event
+    fn from_callback(fields: &CallbackFields<'_>) -> Option<Self>;
+}
+
+/// The fields of a callback an event is built from
+#[derive(Debug)]
+pub struct CallbackFields<'a> {
+    callback: &'a Callback,
+    reference: Option<&'a str>,
+    values: Metadata,
+}
+
+impl<'a> CallbackFields<'a> {
+    /// The callback itself, for the fields not read below
+    pub fn callback(&self) -> &'a Callback {
+        self.callback
+    }
+
+    /// Returns `true` if the transaction succeeded. C2B payments posted to the `ConfirmationURL`
+    /// always have.
+    pub fn is_success(&self) -> bool {
+        match self.callback {
+            Callback::Stk(callback) => callback.is_success(),
+            Callback::C2b(_) => true,
+            Callback::Result(result) => result.is_success(),
+        }
+    }
+
+    /// Amount of the transaction, `None` if the callback does not report it
+    pub fn amount(&self) -> Option<f64> {
+        match self.callback {
+            Callback::Stk(callback) => callback.amount(),
+            Callback::C2b(transaction) => transaction.trans_amount.parse().ok(),
+            Callback::Result(result) => ["TransactionAmount", "Amount"]
+                .into_iter()
+                .find_map(|key| value_to_string(result.parameter(key)?).parse().ok()),
+        }
+    }
+
+    /// M-Pesa receipt number of the transaction, e.g. `NLJ7RT61SV`
+    pub fn receipt_number(&self) -> Option<String> {
+        match self.callback {
+            Callback::Stk(callback) => callback.mpesa_receipt_number().map(str::to_owned),
+            Callback::C2b(transaction) => Some(transaction.trans_id.clone()),
+            Callback::Result(result) => result
+                .parameter("TransactionReceipt")
+                .map(value_to_string)
+                .or_else(|| result.transaction_id.clone()),
+        }
+    }
+
+    /// Phone number of the customer who paid, for STK pushes and C2B payments
+    pub fn phone_number(&self) -> Option<String> {
+        match self.callback {
+            Callback::Stk(callback) => callback.phone_number(),
+            Callback::C2b(transaction) => Some(transaction.msisdn.clone()),
+            Callback::Result(_) => None,
+        }
+    }
+
+    /// The reference of the transaction, such as the `BillRefNumber` of a C2B payment
+    pub fn reference(&self) -> Option<&'a str> {
+        self.reference
+    }
+
+    /// Value captured from the reference by a template of the mapper, or read from the metadata
+    /// of a result
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name)
+    }
+
+    /// Parses the value `name`, `None` if it is missing or cannot be parsed as a `T`
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+}
+
+/// Builds events from callbacks, capturing values from their reference with templates
+#[derive(Debug, Clone, Default)]
+pub struct EventMapper {
+    templates: Vec<TextTemplate>,
+}
+
+impl EventMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a template the references of callbacks may have been rendered with. Templates are
+    /// tried in the order they were added and the values of the first that matches are kept.
+    pub fn template(mut self, template: TextTemplate) -> Self {
+        self.templates.push(template);
+        self
+    }
+
+    /// Builds the event `E` from `callback`
+    pub fn map<E: FromCallback>(&self, callback: &Callback) -> Option<E> {
+        E::from_callback(&self.fields(callback))
+    }
+
+    /// Builds the event `E` from `callback`, with `reference` as its reference, such as the
+    /// account reference an STK push was sent with
+    pub fn map_with_reference<E: FromCallback>(
+        &self,
+        callback: &Callback,
+        reference: &str,
+    ) -> Option<E> {
+        E::from_callback(&self.fields_with_reference(callback, reference))
+    }
+
+    /// Reads the fields of `callback`
+    pub fn fields<'a>(&self, callback: &'a Callback) -> CallbackFields<'a> {
+        let reference = match callback {
+            Callback::C2b(transaction) if !transaction.bill_ref_number.is_empty() => {
+                Some(transaction.bill_ref_number.as_str())
+            }
+            _ => None,
+        };
+        let values = match (callback, reference) {
+            (Callback::Result(result), _) => result.metadata().unwrap_or_default(),
+            (_, Some(reference)) => self.capture(reference),
+            (_, None) => Metadata::default(),
+        };
+        CallbackFields {
+            callback,
+            reference,
+            values,
+        }
+    }
+
+    /// Reads the fields of `callback`, with `reference` as its reference
+    pub fn fields_with_reference<'a>(
+        &self,
+        callback: &'a Callback,
+        reference: &'a str,
+    ) -> CallbackFields<'a> {
+        CallbackFields {
+            callback,
+            reference: Some(reference),
+            values: self.capture(reference),
+        }
+    }
+
+    /// Captures the values of `reference` with the first template it matches, or parses it as
+    /// metadata if it matches none
+    fn capture(&self, reference: &str) -> Metadata {
+        self.templates
+            .iter()
+            .find_map(|template| template.capture(reference))
+            .or_else(|| Metadata::parse(reference))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::callbacks::{ResultCallback, StkCallback};
+    use crate::text_template::TextField;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        OrderPaid { order_id: String, amount: f64 },
+        RefundSent { refund_id: u32 },
+    }
+
+    impl FromCallback for Event {
+        fn from_callback(fields: &CallbackFields<'_>) -> Option<Self> {
+            if !fields.is_success() {
+                return None;
+            }
+            match fields.callback() {
+                Callback::Result(_) => Some(Event::RefundSent {
+                    refund_id: fields.parse("refund")?,
+                }),
+                _ => Some(Event::OrderPaid {
+                    order_id: fields.get("order_id")?.to_owned(),
+                    amount: fields.amount()?,
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_events_are_built_from_every_kind_of_callback() {
+        let mapper = EventMapper::new()
+            .template(TextTemplate::parse(TextField::AccountReference, "Order {order_id}").unwrap())
+            .template(TextTemplate::parse(TextField::AccountReference, "ORD{order_id}").unwrap());
+
+        let stk = StkCallback::from_json(
+            json!({"Body": {"stkCallback": {
+                "MerchantRequestID": "29115-34620561-1",
+                "CheckoutRequestID": "ws_CO_191220191020363925",
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "CallbackMetadata": {"Item": [
+                    {"Name": "Amount", "Value": 1250.00},
+                    {"Name": "MpesaReceiptNumber", "Value": "NLJ7RT61SV"},
+                    {"Name": "PhoneNumber", "Value": 254708374149u64}
+                ]}
+            }}})
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap();
+        let stk = Callback::Stk(stk);
+        assert_eq!(mapper.map::<Event>(&stk), None);
+        assert_eq!(
+            mapper.map_with_reference(&stk, "ORDA-1042"),
+            Some(Event::OrderPaid {
+                order_id: "A-1042".to_owned(),
+                amount: 1250.0
+            })
+        );
+        let fields = mapper.fields_with_reference(&stk, "ORDA-1042");
+        assert_eq!(fields.receipt_number().as_deref(), Some("NLJ7RT61SV"));
+        assert_eq!(fields.phone_number().as_deref(), Some("254708374149"));
+
+        let result = ResultCallback::from_json(
+            json!({"Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "TransactionID": "NLJ41HAY6Q",
+                "ReferenceData": {"ReferenceItem": {"Key": "Occasion", "Value": "refund=77"}}
+            }})
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            mapper.map(&Callback::Result(result)),
+            Some(Event::RefundSent { refund_id: 77 })
+        );
+    }
+}
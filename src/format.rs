@@ -0,0 +1,122 @@
+//! Formatting of amounts for customer-facing strings
+//!
+//! Amounts are rendered the way M-Pesa confirmation messages show them, with comma separated
+//! thousands and two decimals, e.g. `KES 1,250.00`, so that invoice names, transaction
+//! descriptions and customer messages read consistently with the SMS the customer receives.
+//!
+//! Phone numbers written to logs or shown to support staff can be masked with
+//! [`mask_phone_number`], as they are in the `Display` summaries of requests.
+//!
+//! ```rust
+//! use mpesa::format::{format_amount, kes, mask_phone_number};
+//!
+//! assert_eq!(kes(1250), "KES 1,250.00");
+//! assert_eq!(format_amount(1_000_000.5), "1,000,000.50");
+//! assert_eq!(mask_phone_number("0708374149"), "2547****149");
+//! assert_eq!(mask_phone_number("600496"), "600496");
+//! ```
+
+use std::borrow::Cow;
+
+use crate::validator::{normalize_msisdn, PartyType};
+
+/// Currency code prefixed by `kes`
+pub const CURRENCY: &str = "KES";
+
+/// Renders `amount` with comma separated thousands and two decimals, e.g. `1,250.00`.
+/// Amounts are rounded to the nearest cent.
+pub fn format_amount(amount: impl Into<f64>) -> String {
+    let amount = amount.into();
+    if !amount.is_finite() {
+        return amount.to_string();
+    }
+
+    let cents = (amount.abs() * 100.0).round() as u128;
+    let units = (cents / 100).to_string();
+    let mut formatted = String::with_capacity(units.len() * 4 / 3 + 4);
+    if amount < 0.0 && cents > 0 {
+        formatted.push('-');
+    }
+    for (i, digit) in units.chars().enumerate() {
+        if i > 0 && (units.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted.push_str(&format!(".{:02}", cents % 100));
+    formatted
+}
+
+/// Renders `amount` in Kenyan shillings, e.g. `KES 1,250.00`
+pub fn kes(amount: impl Into<f64>) -> String {
+    format!("{CURRENCY} {}", format_amount(amount))
+}
+
+/// Masks the middle digits of a phone number, e.g. `2547****149`, in any of the formats accepted
+/// by `normalize_msisdn`. Shortcodes and other identifiers are returned as they are.
+pub fn mask_phone_number(party: &str) -> Cow<'_, str> {
+    if PartyType::of(party) != Some(PartyType::Msisdn) {
+        return Cow::Borrowed(party);
+    }
+    let msisdn = normalize_msisdn(party);
+    Cow::Owned(format!(
+        "{}****{}",
+        &msisdn[..4],
+        &msisdn[msisdn.len() - 3..]
+    ))
+}
+
+/// Summary of a request moving `amount` shillings, e.g. `1,000 KES`, for the `Display` of
+/// request payloads. Cents are left out of whole amounts.
+#[cfg(any(
+    feature = "b2b",
+    feature = "b2c",
+    feature = "c2b_simulate",
+    feature = "dynamic_qr",
+    feature = "express_request",
+    feature = "transaction_reversal"
+))]
+pub(crate) fn summary_amount(amount: impl Into<f64>) -> String {
+    let amount = format_amount(amount);
+    let amount = amount.strip_suffix(".00").unwrap_or(&amount);
+    format!("{amount} {CURRENCY}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amounts_are_grouped_by_thousands() {
+        assert_eq!(format_amount(0), "0.00");
+        assert_eq!(format_amount(999), "999.00");
+        assert_eq!(format_amount(1000), "1,000.00");
+        assert_eq!(format_amount(1250.5), "1,250.50");
+        assert_eq!(format_amount(123_456_789.994), "123,456,789.99");
+        assert_eq!(format_amount(-1500.5), "-1,500.50");
+        assert_eq!(format_amount(-0.001), "0.00");
+        assert_eq!(kes(1250), "KES 1,250.00");
+    }
+
+    #[test]
+    fn test_phone_numbers_are_masked() {
+        assert_eq!(mask_phone_number("254708374149"), "2547****149");
+        assert_eq!(mask_phone_number("+254708374149"), "2547****149");
+        assert_eq!(mask_phone_number("174379"), "174379");
+        assert_eq!(mask_phone_number("N/A"), "N/A");
+    }
+
+    #[cfg(any(
+        feature = "b2b",
+        feature = "b2c",
+        feature = "c2b_simulate",
+        feature = "dynamic_qr",
+        feature = "express_request",
+        feature = "transaction_reversal"
+    ))]
+    #[test]
+    fn test_summary_amounts_leave_out_whole_cents() {
+        assert_eq!(summary_amount(1000), "1,000 KES");
+        assert_eq!(summary_amount(99.5), "99.50 KES");
+    }
+}
@@ -0,0 +1,401 @@
+//! Forwarding of callbacks to a message broker
+//!
+//! [`Forwarder`] is a [`CallbackHandler`] publishing every callback the webhook server receives to
+//! a [`Publisher`], so that payment events can be consumed by other services. Publishers are
+//! provided for Kafka (`kafka` feature), NATS (`nats` feature) and RabbitMQ (`rabbitmq` feature).
+//!
+//! | Callback          | Topic                    | Key                  |
+//! |-------------------|--------------------------|----------------------|
+//! | M-Pesa Express    | `mpesa.stk`              | `CheckoutRequestID`  |
+//! | C2B confirmation  | `mpesa.c2b.confirmation` | `TransID`            |
+//! | Result            | `mpesa.result`           | `ConversationID`     |
+//! | Queue timeout     | `mpesa.timeout`          | `ConversationID`     |
+//!
+//! Messages are the callbacks serialized in the shape Safaricom posts them, so consumers can parse
+//! them with `StkCallback::from_json` and `ResultCallback::from_json`, or as a `C2bTransaction`.
+//! C2B payments are accepted without being forwarded at validation.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use mpesa::forward::{Forwarder, NatsPublisher};
+//! use mpesa::server;
+//!
+//! let client = async_nats::connect("localhost:4222").await?;
+//! let forwarder = Forwarder::new(NatsPublisher::new(client)).retries(5);
+//! server::serve(([0, 0, 0, 0], 8080).into(), forwarder).await?;
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::callbacks::{C2bTransaction, ResultCallback, StkCallback};
+use crate::server::CallbackHandler;
+use crate::{MpesaError, MpesaResult};
+
+/// A message broker callbacks are published to
+pub trait Publisher: Send + Sync + 'static {
+    /// Publishes `payload` to `topic`. `key` identifies the request the callback belongs to and
+    /// is used as the message key or id where the broker supports one.
+    fn publish(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &[u8],
+    ) -> impl Future<Output = MpesaResult<()>> + Send;
+}
+
+type FailureHook = Box<dyn Fn(&str, &[u8], &MpesaError) + Send + Sync>;
+
+/// Publishes the callbacks it handles, retrying failed deliveries
+pub struct Forwarder<P> {
+    publisher: P,
+    prefix: String,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    on_failure: Option<FailureHook>,
+}
+
+impl<P: Publisher> Forwarder<P> {
+    /// Creates a forwarder publishing to topics prefixed with `mpesa`, retrying failed deliveries
+    /// 3 times
+    pub fn new(publisher: P) -> Self {
+        Forwarder {
+            publisher,
+            prefix: "mpesa".to_owned(),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            on_failure: None,
+        }
+    }
+
+    /// Replaces the `mpesa` prefix of the topics
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Number of times a failed delivery is retried
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled after each attempt
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Longest delay between two retries, 30 seconds by default
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Called with the topic, the message and the last error of a delivery that failed every
+    /// retry, for example to log the callback or store it for later
+    pub fn on_failure(
+        mut self,
+        hook: impl Fn(&str, &[u8], &MpesaError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_failure = Some(Box::new(hook));
+        self
+    }
+
+    /// Publishes `payload` to `topic`, retrying failed deliveries
+    pub async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> MpesaResult<()> {
+        let mut attempt = 0;
+        loop {
+            match self.publisher.publish(topic, key, payload).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.retries => return Err(e),
+                Err(_) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Delay before retrying a delivery that failed `attempt + 1` times
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+
+    async fn forward(&self, topic: &str, key: &str, payload: MpesaResult<Vec<u8>>) {
+        let topic = format!("{}.{topic}", self.prefix);
+        let result = match payload {
+            Ok(payload) => match self.publish(&topic, key, &payload).await {
+                Ok(()) => return,
+                Err(e) => (payload, e),
+            },
+            Err(e) => (Vec::new(), e),
+        };
+        if let Some(hook) = &self.on_failure {
+            hook(&topic, &result.0, &result.1);
+        }
+    }
+}
+
+impl<P> fmt::Debug for Forwarder<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Forwarder")
+            .field("prefix", &self.prefix)
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("max_backoff", &self.max_backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P: Publisher> CallbackHandler for Forwarder<P> {
+    async fn on_stk_callback(&self, callback: StkCallback) {
+        self.forward("stk", &callback.checkout_request_id, callback.to_json())
+            .await;
+    }
+
+    async fn on_c2b_confirmation(&self, transaction: C2bTransaction) {
+        self.forward(
+            "c2b.confirmation",
+            &transaction.trans_id,
+            to_vec(&transaction),
+        )
+        .await;
+    }
+
+    async fn on_result(&self, result: ResultCallback) {
+        self.forward("result", &result.conversation_id, result.to_json())
+            .await;
+    }
+
+    async fn on_timeout(&self, result: ResultCallback) {
+        self.forward("timeout", &result.conversation_id, result.to_json())
+            .await;
+    }
+}
+
+fn to_vec<T: Serialize>(value: &T) -> MpesaResult<Vec<u8>> {
+    Ok(serde_json::to_vec(value)?)
+}
+
+/// Publishes to Kafka topics, with the key of the callback as the message key
+#[cfg(feature = "kafka")]
+pub struct KafkaPublisher {
+    producer: rdkafka::producer::FutureProducer,
+    queue_timeout: Duration,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaPublisher {
+    pub fn new(producer: rdkafka::producer::FutureProducer) -> Self {
+        KafkaPublisher {
+            producer,
+            queue_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// How long to wait for room in the producer queue before failing, 5 seconds by default
+    pub fn queue_timeout(mut self, queue_timeout: Duration) -> Self {
+        self.queue_timeout = queue_timeout;
+        self
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl Publisher for KafkaPublisher {
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> MpesaResult<()> {
+        let record = rdkafka::producer::FutureRecord::to(topic)
+            .key(key)
+            .payload(payload);
+        self.producer
+            .send(record, self.queue_timeout)
+            .await
+            .map_err(|(e, _)| MpesaError::PublishError(Box::new(e)))?;
+        Ok(())
+    }
+}
+
+/// Publishes to NATS subjects, waiting for the messages to be flushed to the server
+#[cfg(feature = "nats")]
+pub struct NatsPublisher {
+    client: async_nats::Client,
+}
+
+#[cfg(feature = "nats")]
+impl NatsPublisher {
+    pub fn new(client: async_nats::Client) -> Self {
+        NatsPublisher { client }
+    }
+}
+
+#[cfg(feature = "nats")]
+impl Publisher for NatsPublisher {
+    async fn publish(&self, topic: &str, _key: &str, payload: &[u8]) -> MpesaResult<()> {
+        self.client
+            .publish(topic.to_owned(), payload.to_vec().into())
+            .await
+            .map_err(|e| MpesaError::PublishError(Box::new(e)))?;
+        self.client
+            .flush()
+            .await
+            .map_err(|e| MpesaError::PublishError(Box::new(e)))
+    }
+}
+
+/// Publishes to a RabbitMQ exchange with the topic as the routing key and the key of the callback
+/// as the message id. A delivery fails if the broker negatively acknowledges it when publisher
+/// confirms are enabled on the channel.
+#[cfg(feature = "rabbitmq")]
+pub struct RabbitMqPublisher {
+    channel: lapin::Channel,
+    exchange: String,
+}
+
+#[cfg(feature = "rabbitmq")]
+impl RabbitMqPublisher {
+    pub fn new(channel: lapin::Channel, exchange: impl Into<String>) -> Self {
+        RabbitMqPublisher {
+            channel,
+            exchange: exchange.into(),
+        }
+    }
+}
+
+#[cfg(feature = "rabbitmq")]
+impl Publisher for RabbitMqPublisher {
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> MpesaResult<()> {
+        let properties = lapin::BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_message_id(key.into())
+            .with_delivery_mode(2);
+        let confirmation = self
+            .channel
+            .basic_publish(
+                &self.exchange,
+                topic,
+                lapin::options::BasicPublishOptions::default(),
+                payload,
+                properties,
+            )
+            .await
+            .map_err(|e| MpesaError::PublishError(Box::new(e)))?
+            .await
+            .map_err(|e| MpesaError::PublishError(Box::new(e)))?;
+        if confirmation.is_nack() {
+            return Err(MpesaError::Message(
+                "The message was not acknowledged by the broker",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Fails the first `failures` deliveries and records the others
+    #[derive(Default)]
+    struct TestPublisher {
+        failures: AtomicU32,
+        published: Mutex<Vec<(String, String, Vec<u8>)>>,
+    }
+
+    impl Publisher for Arc<TestPublisher> {
+        async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> MpesaResult<()> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(MpesaError::Message("unavailable"));
+            }
+            self.published.lock().unwrap().push((
+                topic.to_owned(),
+                key.to_owned(),
+                payload.to_vec(),
+            ));
+            Ok(())
+        }
+    }
+
+    fn result() -> ResultCallback {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581"
+            }
+        });
+        ResultCallback::from_json(body.to_string().as_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_callbacks_are_published_after_retries() {
+        let publisher = Arc::new(TestPublisher {
+            failures: AtomicU32::new(2),
+            ..Default::default()
+        });
+        let forwarder = Forwarder::new(publisher.clone())
+            .prefix("payments")
+            .backoff(Duration::ZERO);
+
+        forwarder.on_result(result()).await;
+
+        let published = publisher.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        let (topic, key, payload) = &published[0];
+        assert_eq!(topic, "payments.result");
+        assert_eq!(key, "AG_20191219_00004e48cf7e3533f581");
+        let callback = ResultCallback::from_json(payload).unwrap();
+        assert!(callback.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_failed_deliveries_are_reported() {
+        let publisher = Arc::new(TestPublisher {
+            failures: AtomicU32::new(3),
+            ..Default::default()
+        });
+        let failed = Arc::new(Mutex::new(Vec::new()));
+        let forwarder = Forwarder::new(publisher.clone())
+            .retries(2)
+            .backoff(Duration::ZERO)
+            .on_failure({
+                let failed = failed.clone();
+                move |topic, _, _| failed.lock().unwrap().push(topic.to_owned())
+            });
+
+        forwarder.on_timeout(result()).await;
+
+        assert!(publisher.published.lock().unwrap().is_empty());
+        assert_eq!(*failed.lock().unwrap(), vec!["mpesa.timeout".to_owned()]);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let forwarder = Forwarder::new(Arc::new(TestPublisher::default()))
+            .backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(60));
+
+        assert_eq!(forwarder.delay(0), Duration::from_secs(1));
+        assert_eq!(forwarder.delay(3), Duration::from_secs(8));
+        assert_eq!(forwarder.delay(6), Duration::from_secs(60));
+        assert_eq!(forwarder.delay(u32::MAX), Duration::from_secs(60));
+
+        let forwarder = forwarder.backoff(Duration::MAX).max_backoff(Duration::MAX);
+        assert_eq!(forwarder.delay(40), Duration::MAX);
+    }
+}
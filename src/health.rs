@@ -0,0 +1,162 @@
+use std::fmt;
+use std::time::Duration;
+#[cfg(feature = "openssl")]
+use std::time::SystemTime;
+
+#[cfg(feature = "openssl")]
+use openssl::{asn1::Asn1Time, x509::X509};
+
+use crate::{MpesaResult, TokenInfo};
+
+/// Diagnostics returned by `Mpesa::health_check`, suitable for readiness probes
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct HealthCheck {
+    /// Round-trip latency of a fresh authentication request, or the error it failed with
+    pub auth: MpesaResult<Duration>,
+    /// Expiry of the access token cached for the client's primary credentials
+    pub token: TokenInfo,
+    /// Base url of the environment the client talks to
+    pub base_url: String,
+    /// Validity window of the environment's certificate, `None` if it could not be parsed
+    #[cfg(feature = "openssl")]
+    pub certificate_validity: Option<CertificateValidity>,
+}
+
+impl HealthCheck {
+    /// Returns `true` if the client could authenticate against the Safaricom API.
+    ///
+    /// The certificate validity window is informational only: Safaricom keeps accepting
+    /// credentials encrypted with certificates past their `not_after` date.
+    pub fn is_healthy(&self) -> bool {
+        self.auth.is_ok()
+    }
+}
+
+/// Report of `Mpesa::preflight`, listing the outcome of every check
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Preflight {
+    pub results: Vec<PreflightResult>,
+}
+
+impl Preflight {
+    /// Returns `true` if every check passed
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|result| result.result.is_ok())
+    }
+
+    /// The checks that failed
+    pub fn failures(&self) -> impl Iterator<Item = &PreflightResult> {
+        self.results.iter().filter(|result| result.result.is_err())
+    }
+}
+
+/// One line per check, e.g. `FAIL authentication with credentials #1: Service error: ..`
+impl fmt::Display for Preflight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.result {
+                Ok(()) => writeln!(f, "ok   {}", result.check)?,
+                Err(e) => writeln!(f, "FAIL {}: {e}", result.check)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a check of `Mpesa::preflight`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct PreflightResult {
+    pub check: PreflightCheck,
+    pub result: MpesaResult<()>,
+}
+
+/// A check made by `Mpesa::preflight`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PreflightCheck {
+    /// The certificate of the environment parses and holds a RSA public key
+    Certificate,
+    /// The initiator password encrypts into a security credential: `None` for the password set
+    /// with `MpesaBuilder::initiator_password`, the name of the initiator for those registered
+    /// with `MpesaBuilder::initiator`
+    InitiatorPassword(Option<String>),
+    /// An access token is issued for the consumer key/secret pair registered at this position,
+    /// `0` being the pair the client was created with
+    Authentication(usize),
+    /// The host of a base url, or of a default callback url, resolves
+    Dns(String),
+}
+
+impl fmt::Display for PreflightCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightCheck::Certificate => write!(f, "certificate"),
+            PreflightCheck::InitiatorPassword(None) => write!(f, "initiator password"),
+            PreflightCheck::InitiatorPassword(Some(name)) => {
+                write!(f, "password of initiator {name}")
+            }
+            PreflightCheck::Authentication(index) => {
+                write!(f, "authentication with credentials #{index}")
+            }
+            PreflightCheck::Dns(host) => write!(f, "dns of {host}"),
+        }
+    }
+}
+
+/// Validity window of a X509 certificate
+#[cfg(feature = "openssl")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CertificateValidity {
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+#[cfg(feature = "openssl")]
+impl CertificateValidity {
+    /// Parses the validity window of a pem encoded certificate
+    pub(crate) fn from_pem(pem: &str) -> Option<Self> {
+        let cert = X509::from_pem(pem.as_bytes()).ok()?;
+        let epoch = Asn1Time::from_unix(0).ok()?;
+        let to_system_time = |time: &openssl::asn1::Asn1TimeRef| {
+            let diff = epoch.diff(time).ok()?;
+            let secs = i64::from(diff.days) * 86_400 + i64::from(diff.secs);
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+        };
+
+        Some(CertificateValidity {
+            not_before: to_system_time(cert.not_before())?,
+            not_after: to_system_time(cert.not_after())?,
+        })
+    }
+
+    /// Returns `true` if the current time falls within the validity window
+    pub fn is_valid(&self) -> bool {
+        let now = SystemTime::now();
+        self.not_before <= now && now <= self.not_after
+    }
+}
+
+#[cfg(all(test, feature = "openssl"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_validity_is_parsed_from_pem() {
+        let validity = CertificateValidity::from_pem(include_str!("certificates/sandbox")).unwrap();
+        // notBefore=Aug 27 00:00:00 2018 GMT, notAfter=Apr 4 12:00:00 2019 GMT
+        assert_eq!(
+            validity.not_before,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_535_328_000)
+        );
+        assert_eq!(
+            validity.not_after,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_554_379_200)
+        );
+        assert!(!validity.is_valid());
+        assert!(CertificateValidity::from_pem("certificate").is_none());
+    }
+}
@@ -0,0 +1,63 @@
+/// Format of the correlation identifiers generated by the client, such as the ones returned by
+/// `Mpesa::generate_id`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IdStrategy {
+    /// Random UUID, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    #[default]
+    UuidV4,
+    /// Timestamp-prefixed UUID that sorts in the order the identifiers were generated
+    UuidV7,
+    /// 26 character [ULID](https://github.com/ulid/spec) that sorts by the millisecond the
+    /// identifiers were generated in. Requires the `ulid` feature.
+    #[cfg(feature = "ulid")]
+    Ulid,
+}
+
+impl IdStrategy {
+    /// Generates a new identifier
+    pub(crate) fn generate(self) -> String {
+        match self {
+            IdStrategy::UuidV4 => uuid::Uuid::new_v4().to_string(),
+            IdStrategy::UuidV7 => uuid::Uuid::now_v7().to_string(),
+            #[cfg(feature = "ulid")]
+            IdStrategy::Ulid => ulid::Ulid::new().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid_v4_ids_are_unique() {
+        let id = IdStrategy::UuidV4.generate();
+        assert_eq!(uuid::Uuid::parse_str(&id).unwrap().get_version_num(), 4);
+        assert_ne!(id, IdStrategy::UuidV4.generate());
+    }
+
+    #[test]
+    fn test_uuid_v7_ids_sort_chronologically() {
+        let ids: Vec<_> = (0..100).map(|_| IdStrategy::UuidV7.generate()).collect();
+        assert_eq!(uuid::Uuid::parse_str(&ids[0]).unwrap().get_version_num(), 7);
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+    }
+
+    #[test]
+    #[cfg(feature = "ulid")]
+    fn test_ulids_sort_chronologically() {
+        let ids: Vec<_> = (0..10)
+            .map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(2));
+                IdStrategy::Ulid.generate()
+            })
+            .collect();
+        assert_eq!(ids[0].len(), 26);
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+    }
+}
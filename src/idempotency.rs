@@ -0,0 +1,170 @@
+//! Idempotency keys for requests sent more than once
+//!
+//! A request sent through `Mpesa::idempotent` carries a key chosen by the caller, such as the id
+//! of the order being paid. The response of the first request sent with a key is kept by an
+//! [`IdempotencyStore`], set with `MpesaBuilder::idempotency_store`, and requests sent again with
+//! the same key return it without reaching the Safaricom API. This makes it safe to resend a
+//! payment when a mobile app retries a request it did not get an answer for.
+//!
+//! Only accepted requests are kept: a request that failed can be sent again with the same key.
+//! A request Safaricom reports as a duplicate of an accepted one, as when the response to the
+//! first send was lost, is accepted with the `ConversationID` of the original and kept.
+//! While a request is in flight, others sent with its key fail with
+//! `MpesaError::IdempotencyConflict`.
+//!
+//! Responses are kept by [`MemoryIdempotencyStore`], any `storage::Storage`, or
+//! `persistence::SqlxStore` with the `sqlx` feature.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use mpesa::idempotency::MemoryIdempotencyStore;
+//! use mpesa::Mpesa;
+//!
+//! let client = Mpesa::builder(consumer_key, consumer_secret, Environment::Sandbox)
+//!     .idempotency_store(MemoryIdempotencyStore::default())
+//!     .build()?;
+//!
+//! // in the handler of a payment request, sent again by the app until it gets an answer
+//! let response = client
+//!     .idempotent(format!("order-{}", order.id))
+//!     .express_request()
+//!     /* .. */
+//!     .send()
+//!     .await?;
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+#[cfg(feature = "__request")]
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::storage::{namespaces, Storage};
+use crate::MpesaResult;
+
+/// Keeps the responses of the requests sent with an idempotency key
+pub trait IdempotencyStore: fmt::Debug + Send + Sync {
+    /// Returns the response kept for `key`, `None` if no request was accepted with it
+    fn get(&self, key: &str) -> impl Future<Output = MpesaResult<Option<Value>>> + Send;
+
+    /// Marks `key` as in use by a request about to be sent. Returns `false` if it is already in
+    /// use or has a response.
+    fn reserve(&self, key: &str) -> impl Future<Output = MpesaResult<bool>> + Send;
+
+    /// Keeps `response` as the response of the request sent with `key`
+    fn save(&self, key: &str, response: &Value) -> impl Future<Output = MpesaResult<()>> + Send;
+
+    /// Frees `key` after its request failed, so that it can be sent again
+    fn release(&self, key: &str) -> impl Future<Output = MpesaResult<()>> + Send;
+}
+
+/// Keeps responses in memory, where they are lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryIdempotencyStore {
+    /// `None` while the request of a key is in flight
+    responses: Mutex<HashMap<String, Option<Value>>>,
+}
+
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn get(&self, key: &str) -> MpesaResult<Option<Value>> {
+        Ok(self
+            .responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned()
+            .flatten())
+    }
+
+    async fn reserve(&self, key: &str) -> MpesaResult<bool> {
+        let mut responses = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        if responses.contains_key(key) {
+            return Ok(false);
+        }
+        responses.insert(key.to_owned(), None);
+        Ok(true)
+    }
+
+    async fn save(&self, key: &str, response: &Value) -> MpesaResult<()> {
+        self.responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_owned(), Some(response.clone()));
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> MpesaResult<()> {
+        let mut responses = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(None) = responses.get(key) {
+            responses.remove(key);
+        }
+        Ok(())
+    }
+}
+
+/// Keeps responses in the `IDEMPOTENCY` namespace, with an empty value while a request is in
+/// flight
+impl<S: Storage> IdempotencyStore for S {
+    async fn get(&self, key: &str) -> MpesaResult<Option<Value>> {
+        match Storage::get(self, namespaces::IDEMPOTENCY, key).await? {
+            Some(response) if !response.is_empty() => Ok(Some(serde_json::from_slice(&response)?)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn reserve(&self, key: &str) -> MpesaResult<bool> {
+        self.compare_and_swap(namespaces::IDEMPOTENCY, key, None, Some(b""), None)
+            .await
+    }
+
+    async fn save(&self, key: &str, response: &Value) -> MpesaResult<()> {
+        self.set(
+            namespaces::IDEMPOTENCY,
+            key,
+            &serde_json::to_vec(response)?,
+            None,
+        )
+        .await
+    }
+
+    async fn release(&self, key: &str) -> MpesaResult<()> {
+        self.compare_and_swap(namespaces::IDEMPOTENCY, key, Some(b""), None, None)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "__request")]
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// `IdempotencyStore` with boxed futures, for the client to hold any store
+#[cfg(feature = "__request")]
+pub(crate) trait DynIdempotencyStore: fmt::Debug + Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, MpesaResult<Option<Value>>>;
+    fn reserve<'a>(&'a self, key: &'a str) -> BoxFuture<'a, MpesaResult<bool>>;
+    fn save<'a>(&'a self, key: &'a str, response: &'a Value) -> BoxFuture<'a, MpesaResult<()>>;
+    fn release<'a>(&'a self, key: &'a str) -> BoxFuture<'a, MpesaResult<()>>;
+}
+
+#[cfg(feature = "__request")]
+impl<S: IdempotencyStore> DynIdempotencyStore for S {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, MpesaResult<Option<Value>>> {
+        Box::pin(IdempotencyStore::get(self, key))
+    }
+
+    fn reserve<'a>(&'a self, key: &'a str) -> BoxFuture<'a, MpesaResult<bool>> {
+        Box::pin(IdempotencyStore::reserve(self, key))
+    }
+
+    fn save<'a>(&'a self, key: &'a str, response: &'a Value) -> BoxFuture<'a, MpesaResult<()>> {
+        Box::pin(IdempotencyStore::save(self, key, response))
+    }
+
+    fn release<'a>(&'a self, key: &'a str) -> BoxFuture<'a, MpesaResult<()>> {
+        Box::pin(IdempotencyStore::release(self, key))
+    }
+}
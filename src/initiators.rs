@@ -0,0 +1,152 @@
+use std::fmt;
+#[cfg(any(feature = "b2b", feature = "b2c"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use secrecy::Secret;
+
+use crate::client::Initiator;
+
+/// An initiator added with `MpesaBuilder::initiator`, with its own password and the security
+/// credential generated from it
+pub(crate) struct WeightedInitiator {
+    name: String,
+    weight: u32,
+    pub(crate) initiator: RwLock<Initiator>,
+}
+
+impl fmt::Debug for WeightedInitiator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeightedInitiator")
+            .field("name", &self.name)
+            .field("weight", &self.weight)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WeightedInitiator {
+    pub(crate) fn new(name: String, password: Secret<String>, weight: u32) -> Self {
+        WeightedInitiator {
+            name,
+            weight,
+            initiator: RwLock::new(Initiator::new(Some(password))),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The initiators registered on a client, picked at random in proportion to their weight.
+/// Shared between clones of a client.
+#[derive(Debug)]
+pub(crate) struct InitiatorPool {
+    initiators: Vec<WeightedInitiator>,
+    #[cfg(any(feature = "b2b", feature = "b2c"))]
+    state: AtomicU64,
+}
+
+impl InitiatorPool {
+    pub(crate) fn new(initiators: Vec<WeightedInitiator>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::with_seed(initiators, seed)
+    }
+
+    #[cfg_attr(not(any(feature = "b2b", feature = "b2c")), allow(unused_variables))]
+    fn with_seed(initiators: Vec<WeightedInitiator>, seed: u64) -> Self {
+        InitiatorPool {
+            initiators,
+            #[cfg(any(feature = "b2b", feature = "b2c"))]
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &WeightedInitiator> {
+        self.initiators.iter()
+    }
+
+    /// The initiator registered as `name`
+    pub(crate) fn get(&self, name: &str) -> Option<&WeightedInitiator> {
+        self.initiators
+            .iter()
+            .find(|initiator| initiator.name == name)
+    }
+
+    /// Picks the initiator of the next request, `None` if no initiator has a weight above `0`
+    #[cfg(any(feature = "b2b", feature = "b2c"))]
+    pub(crate) fn select(&self) -> Option<&WeightedInitiator> {
+        let total: u64 = self
+            .initiators
+            .iter()
+            .map(|initiator| u64::from(initiator.weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        let mut target = self.next_u64() % total;
+        self.initiators.iter().find(|initiator| {
+            let weight = u64::from(initiator.weight);
+            if target < weight {
+                return true;
+            }
+            target -= weight;
+            false
+        })
+    }
+
+    /// Returns the next number of the SplitMix64 sequence of the seed
+    #[cfg(any(feature = "b2b", feature = "b2c"))]
+    fn next_u64(&self) -> u64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(all(test, feature = "b2c"))]
+mod tests {
+    use super::*;
+
+    fn initiator(name: &str, weight: u32) -> WeightedInitiator {
+        WeightedInitiator::new(name.to_owned(), Secret::new(format!("{name}_pw")), weight)
+    }
+
+    #[test]
+    fn test_initiators_are_picked_in_proportion_to_their_weight() {
+        let pool = InitiatorPool::with_seed(
+            vec![
+                initiator("ops_1", 3),
+                initiator("ops_2", 1),
+                initiator("paused", 0),
+            ],
+            42,
+        );
+        let mut counts = [0; 3];
+        for _ in 0..4000 {
+            let name = pool.select().unwrap().name();
+            let index = ["ops_1", "ops_2", "paused"]
+                .iter()
+                .position(|n| *n == name)
+                .unwrap();
+            counts[index] += 1;
+        }
+        assert!((2800..3200).contains(&counts[0]), "{counts:?}");
+        assert!((800..1200).contains(&counts[1]), "{counts:?}");
+        assert_eq!(counts[2], 0);
+
+        assert_eq!(pool.get("ops_2").unwrap().name(), "ops_2");
+        assert!(pool.get("unknown").is_none());
+        assert!(InitiatorPool::new(vec![initiator("paused", 0)])
+            .select()
+            .is_none());
+    }
+}
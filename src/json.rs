@@ -0,0 +1,198 @@
+//! Serialization of request bodies
+//!
+//! Daraja rejects amounts written in scientific notation or with more decimals than it expects,
+//! and a `NaN` or infinite amount would otherwise go out as `null`. Amounts are checked to be
+//! finite when a payload is serialized, and written with two decimals, e.g. `1000.00`, by clients
+//! built with `MpesaBuilder::fixed_decimal_amounts`.
+//!
+//! A body is serialized once per request, into a buffer reused by the requests sent from the
+//! same thread, and its JSON value is only built for the steps of the client that read fields
+//! of the request, such as the shortcode quotas.
+
+use std::cell::RefCell;
+use std::io;
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use serde::Serialize;
+use serde_json::ser::{Formatter, Serializer};
+use serde_json::Value;
+
+use crate::MpesaResult;
+
+/// Capacity above which the serialization buffer of a thread is freed rather than kept, so that
+/// a large bulk invoicing request does not pin its memory
+const MAX_BUFFER_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Serializes an amount, failing if it is `NaN` or infinite
+#[cfg(any(
+    feature = "b2b",
+    feature = "b2c",
+    feature = "bill_manager",
+    feature = "c2b_simulate"
+))]
+pub(crate) fn serialize_amount<S>(amount: &f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if !amount.is_finite() {
+        return Err(serde::ser::Error::custom(format!(
+            "amounts must be finite numbers, got {amount}"
+        )));
+    }
+    serializer.serialize_f64(*amount)
+}
+
+/// Writes the numbers with a fractional part, which in request bodies are all amounts, with two
+/// decimals and never in scientific notation
+struct FixedDecimals;
+
+impl Formatter for FixedDecimals {
+    fn write_f32<W: ?Sized + io::Write>(&mut self, writer: &mut W, value: f32) -> io::Result<()> {
+        self.write_f64(writer, f64::from(value))
+    }
+
+    fn write_f64<W: ?Sized + io::Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        write!(writer, "{value:.2}")
+    }
+}
+
+/// Serializes a request body, with amounts in fixed two decimal notation if `fixed_decimals`
+pub(crate) fn to_bytes<T: Serialize + ?Sized>(
+    body: &T,
+    fixed_decimals: bool,
+) -> MpesaResult<Bytes> {
+    BUFFER.with(|buffer| {
+        // Only taken while a body is serialized, which does not serialize another body
+        let Ok(mut buffer) = buffer.try_borrow_mut() else {
+            let mut bytes = Vec::new();
+            write(&mut bytes, body, fixed_decimals)?;
+            return Ok(Bytes::from(bytes));
+        };
+        buffer.clear();
+        let written = write(&mut buffer, body, fixed_decimals);
+        let bytes = Bytes::copy_from_slice(&buffer);
+        if buffer.capacity() > MAX_BUFFER_CAPACITY {
+            *buffer = Vec::new();
+        }
+        written.map(|()| bytes)
+    })
+}
+
+fn write<T: Serialize + ?Sized>(
+    bytes: &mut Vec<u8>,
+    body: &T,
+    fixed_decimals: bool,
+) -> MpesaResult<()> {
+    if fixed_decimals {
+        body.serialize(&mut Serializer::with_formatter(bytes, FixedDecimals))?;
+    } else {
+        body.serialize(&mut Serializer::new(bytes))?;
+    }
+    Ok(())
+}
+
+/// A serialized request body, along with its JSON value once it has been read
+#[derive(Debug, Clone)]
+pub(crate) struct Body {
+    bytes: Bytes,
+    value: OnceLock<Value>,
+}
+
+impl Body {
+    pub(crate) fn new<T: Serialize + ?Sized>(body: &T, fixed_decimals: bool) -> MpesaResult<Self> {
+        Ok(Body {
+            bytes: to_bytes(body, fixed_decimals)?,
+            value: OnceLock::new(),
+        })
+    }
+
+    /// Serializes `value`, which is kept rather than read back from the serialized body
+    pub(crate) fn from_value(value: Value, fixed_decimals: bool) -> MpesaResult<Self> {
+        Ok(Body {
+            bytes: to_bytes(&value, fixed_decimals)?,
+            value: OnceLock::from(value),
+        })
+    }
+
+    /// The serialized body, which is cheap to clone for every attempt at sending the request
+    pub(crate) fn bytes(&self) -> Bytes {
+        self.bytes.clone()
+    }
+
+    /// The JSON value of the body, parsed from the serialized body the first time it is read
+    pub(crate) fn value(&self) -> &Value {
+        self.value.get_or_init(|| self.parse())
+    }
+
+    pub(crate) fn into_value(mut self) -> Value {
+        self.value.take().unwrap_or_else(|| self.parse())
+    }
+
+    fn parse(&self) -> Value {
+        serde_json::from_slice(&self.bytes).expect("request bodies are serialized as JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_amounts_are_written_with_two_decimals() {
+        let body = json!({
+            "Amount": 0.1 + 0.2,
+            "Large": 1e21,
+            "PartyA": 600496,
+            "Remarks": "1.5"
+        });
+        assert_eq!(
+            String::from_utf8(to_bytes(&body, true).unwrap().to_vec()).unwrap(),
+            r#"{"Amount":0.30,"Large":1000000000000000000000.00,"PartyA":600496,"Remarks":"1.5"}"#
+        );
+        assert_eq!(
+            String::from_utf8(to_bytes(&body, false).unwrap().to_vec()).unwrap(),
+            r#"{"Amount":0.30000000000000004,"Large":1e+21,"PartyA":600496,"Remarks":"1.5"}"#
+        );
+    }
+
+    #[cfg(feature = "c2b_simulate")]
+    #[test]
+    fn test_non_finite_amounts_are_rejected() {
+        #[derive(Serialize)]
+        struct Payload {
+            #[serde(serialize_with = "serialize_amount")]
+            amount: f64,
+        }
+
+        for amount in [f64::NAN, f64::INFINITY] {
+            assert!(to_bytes(&Payload { amount }, false).is_err());
+            assert!(serde_json::to_value(Payload { amount }).is_err());
+        }
+        assert_eq!(
+            to_bytes(&Payload { amount: 10.0 }, true).unwrap(),
+            &br#"{"amount":10.00}"#[..]
+        );
+    }
+
+    #[test]
+    fn test_bodies_are_read_back_as_json() {
+        let body = Body::new(&json!({ "Amount": 10.5, "ShortCode": "600496" }), true).unwrap();
+        assert_eq!(
+            &body.bytes()[..],
+            br#"{"Amount":10.50,"ShortCode":"600496"}"#
+        );
+        assert_eq!(body.value()["ShortCode"], "600496");
+        assert_eq!(body.into_value()["Amount"], 10.5);
+
+        let body = Body::from_value(json!({ "Amount": 0 }), false).unwrap();
+        assert_eq!(&body.bytes()[..], br#"{"Amount":0}"#);
+        assert_eq!(body.into_value(), json!({ "Amount": 0 }));
+    }
+}
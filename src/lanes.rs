@@ -0,0 +1,157 @@
+#[cfg(feature = "__request")]
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "__request")]
+use tokio::sync::oneshot;
+
+/// Priority of the requests of a client, deciding which waiting request is sent first once
+/// `MpesaBuilder::max_concurrent_requests` are in flight
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Latency sensitive requests, such as the STK push of a customer waiting at a checkout
+    High,
+    #[default]
+    Normal,
+    /// Requests nobody is waiting on, such as batch invoicing or reconciliation jobs
+    Low,
+}
+
+/// Limits the number of requests sent at once, handing the slots freed by completed requests
+/// to the waiting requests of the highest priority first, in the order they started waiting
+#[cfg(feature = "__request")]
+#[derive(Debug)]
+pub(crate) struct Lanes {
+    state: Mutex<State>,
+}
+
+#[cfg(feature = "__request")]
+#[derive(Debug)]
+struct State {
+    available: usize,
+    /// Requests waiting for a slot, by `Priority`
+    waiting: [VecDeque<oneshot::Sender<LanePermit>>; 3],
+}
+
+#[cfg(feature = "__request")]
+impl Lanes {
+    pub(crate) fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Lanes {
+            state: Mutex::new(State {
+                available: limit,
+                waiting: Default::default(),
+            }),
+        })
+    }
+
+    /// Waits for a slot, which is freed when the returned permit is dropped
+    pub(crate) async fn acquire(self: &Arc<Self>, priority: Priority) -> LanePermit {
+        let permit = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.available > 0 {
+                state.available -= 1;
+                return LanePermit {
+                    lanes: Some(Arc::clone(self)),
+                };
+            }
+            let (sender, permit) = oneshot::channel();
+            state.waiting[priority as usize].push_back(sender);
+            permit
+        };
+        // Senders are only dropped once their receiver is gone
+        permit.await.expect("waiting requests are handed a permit")
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(waiting) = state.waiting.iter_mut().find_map(VecDeque::pop_front) {
+            let permit = LanePermit {
+                lanes: Some(Arc::clone(self)),
+            };
+            match waiting.send(permit) {
+                Ok(()) => return,
+                // The request stopped waiting, e.g. because its deadline passed
+                Err(mut permit) => {
+                    permit.lanes = None;
+                }
+            }
+        }
+        state.available += 1;
+    }
+
+    #[cfg(test)]
+    fn waiting(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.waiting.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// A slot to send a request, handed to the next waiting request when dropped
+#[cfg(feature = "__request")]
+#[derive(Debug)]
+pub(crate) struct LanePermit {
+    lanes: Option<Arc<Lanes>>,
+}
+
+#[cfg(feature = "__request")]
+impl Drop for LanePermit {
+    fn drop(&mut self) {
+        if let Some(lanes) = self.lanes.take() {
+            lanes.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_waiting_requests_are_sent_by_priority() {
+        let lanes = Lanes::new(1);
+        let permit = lanes.acquire(Priority::Normal).await;
+        let order = Mutex::new(Vec::new());
+        let send = |priority| {
+            let (lanes, order) = (&lanes, &order);
+            async move {
+                let _permit = lanes.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+                tokio::task::yield_now().await;
+            }
+        };
+        let release = async {
+            while lanes.waiting() < 3 {
+                tokio::task::yield_now().await;
+            }
+            drop(permit);
+        };
+
+        tokio::join!(
+            send(Priority::Low),
+            send(Priority::Normal),
+            send(Priority::High),
+            release
+        );
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::High, Priority::Normal, Priority::Low]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_that_stop_waiting_do_not_hold_a_slot() {
+        let lanes = Lanes::new(1);
+        let permit = lanes.acquire(Priority::Normal).await;
+        let abandoned =
+            tokio::time::timeout(Duration::from_millis(10), lanes.acquire(Priority::High)).await;
+        assert!(abandoned.is_err());
+
+        drop(permit);
+        let _permit = lanes.acquire(Priority::Low).await;
+        assert_eq!(lanes.waiting(), 0);
+    }
+}
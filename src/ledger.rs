@@ -0,0 +1,256 @@
+//! Ledger entries built from the callbacks of payments
+//!
+//! Money comes in as C2B payments posted to the `ConfirmationURL` and goes out as B2C payments
+//! whose results are posted to the `ResultURL`, in two shapes with their own field names, amount
+//! types and time formats. A [`LedgerEntry`] gives both the same shape: the direction of the
+//! money, the customer on the other side, the amount, the M-Pesa receipt number and the time of
+//! the transaction as a unix timestamp. Entries are built with `From`, and are what
+//! `reports::ReportEntry::from_ledger` and the Bill Manager `ReconciliationBuilder::ledger_entry`
+//! take.
+//!
+//! ```rust
+//! use mpesa::callbacks::C2bConfirmation;
+//! use mpesa::ledger::{LedgerEntry, LedgerKind};
+//! use mpesa::receipt::ReceiptKind;
+//!
+//! let confirmation: C2bConfirmation = serde_json::from_str(r#"{
+//!     "TransactionType": "Pay Bill",
+//!     "TransID": "RKTQDM7W6S",
+//!     "TransTime": "20191122063845",
+//!     "TransAmount": "500",
+//!     "BusinessShortCode": "600638",
+//!     "BillRefNumber": "INV-1029",
+//!     "MSISDN": "254708374149",
+//!     "FirstName": "John"
+//! }"#).unwrap();
+//! let entry = LedgerEntry::from(confirmation);
+//! assert_eq!((entry.direction, entry.kind), (ReceiptKind::Payment, LedgerKind::C2b));
+//! assert_eq!(entry.signed_amount(), Some(500.0));
+//! // 2019-11-22 06:38:45 in Nairobi
+//! assert_eq!(entry.transacted_at, Some(1_574_393_925));
+//! ```
+
+use std::fmt;
+
+use crate::callbacks::{B2cResult, C2bConfirmation};
+use crate::constants::NAIROBI_UTC_OFFSET_SECS;
+use crate::receipt::{non_empty, transaction_time, ReceiptKind};
+
+/// Service a payment of a ledger entry was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LedgerKind {
+    /// A C2B payment, confirmed on the `ConfirmationURL`
+    C2b,
+    /// A B2C payment, whose result was posted to the `ResultURL`
+    B2c,
+}
+
+impl LedgerKind {
+    /// The name of the kind, `c2b` or `b2c`, which reports group entries by
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerKind::C2b => "c2b",
+            LedgerKind::B2c => "b2c",
+        }
+    }
+}
+
+impl fmt::Display for LedgerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A payment into or out of the accounts of the business
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct LedgerEntry {
+    /// Direction of the money, from the point of view of the business
+    pub direction: ReceiptKind,
+    pub kind: LedgerKind,
+    /// `TransID` of a C2B payment or `ConversationID` of a B2C payment
+    pub correlation_id: String,
+    /// `ResultCode` of the payment, always `0` for C2B payments since only completed payments
+    /// are confirmed
+    pub result_code: String,
+    /// M-Pesa receipt number, e.g. `NLJ7RT61SV`, `None` if the payment failed
+    pub receipt_number: Option<String>,
+    /// Amount of the payment, always positive. `None` if the callback does not report it, as B2C
+    /// results do not when the payment failed.
+    pub amount: Option<f64>,
+    /// Phone number of the customer, masked or hashed by Safaricom on some shortcodes
+    pub counterparty: Option<String>,
+    /// Name of the customer
+    pub counterparty_name: Option<String>,
+    /// Account number the customer paid to, the `BillRefNumber` of a C2B payment
+    pub reference: Option<String>,
+    /// Unix timestamp in seconds of the transaction, as recorded by M-Pesa
+    pub transacted_at: Option<i64>,
+}
+
+impl LedgerEntry {
+    /// Returns `true` if the payment went through
+    pub fn is_success(&self) -> bool {
+        self.result_code == "0"
+    }
+
+    /// The amount, negative for money paid out
+    pub fn signed_amount(&self) -> Option<f64> {
+        self.amount.map(|amount| match self.direction {
+            ReceiptKind::Payment => amount,
+            ReceiptKind::Disbursement => -amount,
+        })
+    }
+}
+
+impl From<C2bConfirmation> for LedgerEntry {
+    fn from(confirmation: C2bConfirmation) -> Self {
+        let name = confirmation.full_name();
+        LedgerEntry {
+            direction: ReceiptKind::Payment,
+            kind: LedgerKind::C2b,
+            result_code: "0".to_owned(),
+            amount: confirmation.trans_amount.parse().ok(),
+            transacted_at: unix_timestamp(&confirmation.trans_time),
+            counterparty: non_empty(confirmation.msisdn),
+            counterparty_name: non_empty(name),
+            reference: non_empty(confirmation.bill_ref_number),
+            receipt_number: Some(confirmation.trans_id.clone()),
+            correlation_id: confirmation.trans_id,
+        }
+    }
+}
+
+impl From<B2cResult> for LedgerEntry {
+    fn from(result: B2cResult) -> Self {
+        let (counterparty, counterparty_name) = result
+            .receiver_party_public_name
+            .as_deref()
+            .map_or((None, None), split_public_name);
+        let receipt_number = if result.is_success() {
+            result
+                .transaction_receipt
+                .or_else(|| result.result.transaction_id.clone())
+        } else {
+            None
+        };
+        LedgerEntry {
+            direction: ReceiptKind::Disbursement,
+            kind: LedgerKind::B2c,
+            correlation_id: result.result.conversation_id,
+            result_code: result.result.result_code,
+            receipt_number,
+            amount: result.amount,
+            counterparty,
+            counterparty_name,
+            reference: None,
+            transacted_at: result
+                .transaction_completed_date_time
+                .as_deref()
+                .and_then(unix_timestamp),
+        }
+    }
+}
+
+/// Splits a `ReceiverPartyPublicName` such as `254708374149 - John Doe` into the phone number
+/// and the name of the recipient
+fn split_public_name(public_name: &str) -> (Option<String>, Option<String>) {
+    match public_name.split_once(['-', '–']) {
+        Some((party, name)) => (
+            non_empty(party.trim().to_owned()),
+            non_empty(name.trim().to_owned()),
+        ),
+        None => (None, non_empty(public_name.trim().to_owned())),
+    }
+}
+
+/// Converts a transaction time in Nairobi time, `YYYYMMDDHHmmss` or `DD.MM.YYYY HH:mm:ss`, to a
+/// unix timestamp in seconds
+fn unix_timestamp(time: &str) -> Option<i64> {
+    let (year, month, day, hour, minute, second) = transaction_time(time)?;
+    let days = days_from_civil(i64::from(year), i64::from(month), i64::from(day));
+    let seconds = i64::from(hour * 3600 + minute * 60 + second);
+    Some(days * 24 * 3600 + seconds - i64::from(NAIROBI_UTC_OFFSET_SECS))
+}
+
+/// The number of days from 1970-01-01 to a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Howard Hinnant's `days_from_civil`, the inverse of the `civil_from_days` of `reports`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_b2c_results_are_outgoing_entries() {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "TransactionID": "NLJ41HAY6Q",
+                "ResultParameters": {
+                    "ResultParameter": [
+                        { "Key": "TransactionAmount", "Value": 10 },
+                        { "Key": "TransactionReceipt", "Value": "NLJ41HAY6Q" },
+                        { "Key": "B2CRecipientIsRegisteredCustomer", "Value": "Y" },
+                        { "Key": "ReceiverPartyPublicName", "Value": "254708374149 - John Doe" },
+                        { "Key": "TransactionCompletedDateTime", "Value": "19.12.2019 11:45:50" },
+                        { "Key": "B2CUtilityAccountAvailableFunds", "Value": 10116.00 }
+                    ]
+                }
+            }
+        });
+        let result = B2cResult::from_json(body.to_string().as_bytes()).unwrap();
+        assert_eq!(result.recipient_is_registered_customer, Some(true));
+        assert_eq!(result.utility_account_available_funds, Some(10116.0));
+
+        let entry = LedgerEntry::from(result);
+        assert!(entry.is_success());
+        assert_eq!(entry.direction, ReceiptKind::Disbursement);
+        assert_eq!(entry.kind, LedgerKind::B2c);
+        assert_eq!(entry.correlation_id, "AG_20191219_00004e48cf7e3533f581");
+        assert_eq!(entry.receipt_number.as_deref(), Some("NLJ41HAY6Q"));
+        assert_eq!(entry.signed_amount(), Some(-10.0));
+        assert_eq!(entry.counterparty.as_deref(), Some("254708374149"));
+        assert_eq!(entry.counterparty_name.as_deref(), Some("John Doe"));
+        // 2019-12-19 08:45:50 UTC
+        assert_eq!(entry.transacted_at, Some(1_576_745_150));
+
+        let failed = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 2001,
+                "ResultDesc": "The initiator information is invalid.",
+                "OriginatorConversationID": "29112-34801843-1",
+                "ConversationID": "AG_20191219_00006c6fddb15123addf",
+                "TransactionID": "NLJ0000000"
+            }
+        });
+        let entry = LedgerEntry::from(B2cResult::from_json(failed.to_string().as_bytes()).unwrap());
+        assert!(!entry.is_success());
+        assert_eq!((entry.receipt_number, entry.amount), (None, None));
+        assert_eq!(entry.transacted_at, None);
+    }
+
+    #[test]
+    fn test_days_from_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+}
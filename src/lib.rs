@@ -1,18 +1,117 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "client")]
 mod auth;
+#[cfg(feature = "account_balance")]
+pub mod balances;
+pub mod beneficiary;
+#[cfg(feature = "callback_tokens")]
+pub mod callback_token;
+pub mod callbacks;
+#[cfg(feature = "client")]
+mod cancellation;
+#[cfg(feature = "client")]
 mod client;
 mod constants;
+#[cfg(feature = "client")]
+mod credentials;
+#[cfg(any(feature = "bill_manager", feature = "express_request"))]
+pub mod datetime;
+#[cfg(feature = "demo")]
+pub mod demo;
 pub mod environment;
 mod errors;
+pub mod events;
+pub mod format;
+#[cfg(any(feature = "kafka", feature = "nats", feature = "rabbitmq"))]
+pub mod forward;
+#[cfg(feature = "client")]
+mod health;
+#[cfg(feature = "client")]
+mod id;
+#[cfg(feature = "client")]
+pub mod idempotency;
+#[cfg(feature = "openssl")]
+mod initiators;
+#[cfg(feature = "__request")]
+mod json;
+#[cfg(feature = "client")]
+mod lanes;
+pub mod ledger;
+pub mod messages;
+pub mod metadata;
+#[cfg(feature = "client")]
+pub mod metrics;
+pub mod organization;
+pub mod paths;
+#[cfg(feature = "b2c")]
+pub mod payout;
+#[cfg(feature = "sqlx")]
+pub mod persistence;
+pub mod prelude;
+#[cfg(any(feature = "bill_manager", feature = "c2b_register"))]
+pub mod queue;
+#[cfg(feature = "__request")]
+mod quota;
+pub mod receipt;
+pub mod reports;
+#[cfg(feature = "client")]
+mod retry;
+#[cfg(feature = "schedule")]
+mod schedule;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod services;
+#[cfg(feature = "__request")]
+pub mod shadow;
+#[cfg(feature = "client")]
+mod shutdown;
+#[cfg(feature = "client")]
+pub mod status;
+pub mod storage;
+pub mod tariff;
+#[cfg(all(feature = "tracing", feature = "__request"))]
+mod telemetry;
+#[cfg(all(feature = "client", any(test, feature = "test-utils")))]
+pub mod test_utils;
+pub mod text_template;
 pub mod validator;
 
-pub use client::Mpesa;
+#[cfg(feature = "client")]
+pub use auth::TokenInfo;
+#[cfg(feature = "client")]
+pub use cancellation::{CancellationToken, OnCancel};
+#[cfg(feature = "client")]
+pub use client::{with_deadline, Mpesa, MpesaBuilder, ResponseMeta, WithMeta};
 pub use constants::{
-    CommandId, IdentifierTypes, Invoice, InvoiceItem, ResponseType, SendRemindersTypes,
-    TransactionType,
+    CommandId, ExpressResultCode, IdentifierTypes, ResponseType, SendRemindersTypes, Service,
+    ServiceCategory, TransactionType, SANDBOX_EXPRESS_SHORTCODE, SANDBOX_INITIATOR_PASSWORD,
+    SANDBOX_PASSKEY, SANDBOX_SHORTCODES, SANDBOX_TEST_MSISDN,
 };
+#[cfg(feature = "bill_manager")]
+pub use constants::{Invoice, InvoiceItem};
+#[cfg(feature = "client")]
+pub use credentials::CredentialSelection;
 pub use environment::ApiEnvironment;
 pub use environment::Environment::{self, Production, Sandbox};
-pub use errors::{BuilderError, MpesaError, MpesaResult, ResponseError};
+pub use errors::{BuilderError, MpesaError, MpesaResult, ResponseError, ValidationErrors};
+#[cfg(all(feature = "client", feature = "openssl"))]
+pub use health::CertificateValidity;
+#[cfg(feature = "client")]
+pub use health::{HealthCheck, Preflight, PreflightCheck, PreflightResult};
+#[cfg(feature = "client")]
+pub use id::IdStrategy;
+#[cfg(feature = "client")]
+pub use lanes::Priority;
+#[cfg(feature = "__request")]
+pub use quota::Quota;
+#[cfg(feature = "__tls")]
+pub use reqwest::Certificate;
+#[cfg(feature = "native-tls")]
+pub use reqwest::Identity;
+#[cfg(feature = "client")]
+pub use reqwest::Proxy;
+#[cfg(feature = "client")]
+pub use retry::RetryPolicy;
+#[cfg(feature = "schedule")]
+pub use schedule::{send_after, send_at};
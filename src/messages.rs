@@ -0,0 +1,267 @@
+//! Localized messages to show customers for the outcome of their payments
+//!
+//! The `CustomerMessage` of responses and the `ResultDesc` of callbacks are in English and meant
+//! for developers as much as for customers. A [`MessageCatalog`] maps the `ResponseCode` of
+//! M-Pesa Express responses and the `ResultCode` of their callbacks to messages in the language
+//! of the customer. English and Swahili messages ship with the default catalog, and
+//! [`MessageCatalog::with_result`] and [`MessageCatalog::with_response`] replace or add messages,
+//! e.g. to change their tone or to support another language.
+//!
+//! ```rust
+//! use mpesa::messages::{Language, MessageCatalog};
+//! use mpesa::ExpressResultCode;
+//!
+//! let catalog = MessageCatalog::default();
+//! assert_eq!(
+//!     catalog.result_message(Language::Swahili, 1032),
+//!     Some("Umeghairi ombi la malipo")
+//! );
+//!
+//! let catalog = catalog.with_result(
+//!     Language::Swahili,
+//!     ExpressResultCode::InsufficientBalance.code(),
+//!     "Salio halitoshi. Ongeza salio kisha ujaribu tena",
+//! );
+//! assert_eq!(
+//!     catalog.result_message(Language::Swahili, 1),
+//!     Some("Salio halitoshi. Ongeza salio kisha ujaribu tena")
+//! );
+//! ```
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::callbacks::StkCallback;
+#[cfg(feature = "express_request")]
+use crate::services::MpesaExpressResponse;
+use crate::ExpressResultCode;
+
+/// Languages of the messages of a catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Language {
+    English,
+    Swahili,
+}
+
+/// Messages for the `ResultCode` of M-Pesa Express callbacks, in English and Swahili
+const RESULT_MESSAGES: [(ExpressResultCode, &str, &str); 8] = [
+    (
+        ExpressResultCode::Success,
+        "Your payment was received",
+        "Malipo yako yamepokelewa",
+    ),
+    (
+        ExpressResultCode::InsufficientBalance,
+        "Your M-PESA balance is insufficient for this payment",
+        "Salio lako la M-PESA halitoshi kwa malipo haya",
+    ),
+    (
+        ExpressResultCode::TransactionInProgress,
+        "Another M-PESA transaction is in progress, please try again shortly",
+        "Muamala mwingine wa M-PESA unaendelea, tafadhali jaribu tena baadaye kidogo",
+    ),
+    (
+        ExpressResultCode::TransactionExpired,
+        "The payment request has expired, please try again",
+        "Muda wa ombi la malipo umeisha, tafadhali jaribu tena",
+    ),
+    (
+        ExpressResultCode::PushRequestFailed,
+        "The payment request could not be sent to your phone, please try again",
+        "Ombi la malipo halikuweza kutumwa kwa simu yako, tafadhali jaribu tena",
+    ),
+    (
+        ExpressResultCode::CancelledByUser,
+        "You cancelled the payment request",
+        "Umeghairi ombi la malipo",
+    ),
+    (
+        ExpressResultCode::UserUnreachable,
+        "Your phone could not be reached, please check that it is on and try again",
+        "Simu yako haikupatikana, tafadhali hakikisha imewashwa kisha ujaribu tena",
+    ),
+    (
+        ExpressResultCode::InvalidPin,
+        "The M-PESA PIN entered is incorrect",
+        "PIN ya M-PESA uliyoweka si sahihi",
+    ),
+];
+
+/// Messages for the `ResponseCode` of M-Pesa Express responses, in English and Swahili
+const RESPONSE_MESSAGES: [(i32, &str, &str); 1] = [(
+    0,
+    "Please enter your M-PESA PIN on your phone to complete the payment",
+    "Tafadhali weka PIN yako ya M-PESA kwenye simu yako ili kukamilisha malipo",
+)];
+
+/// Customer messages by language and code, the shipped ones unless replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCatalog {
+    results: HashMap<(Language, i32), String>,
+    responses: HashMap<(Language, i32), String>,
+}
+
+impl Default for MessageCatalog {
+    fn default() -> Self {
+        fn by_language<'a>(
+            messages: impl Iterator<Item = (i32, &'a str, &'a str)>,
+        ) -> HashMap<(Language, i32), String> {
+            messages
+                .flat_map(|(code, english, swahili)| {
+                    [
+                        ((Language::English, code), english.to_owned()),
+                        ((Language::Swahili, code), swahili.to_owned()),
+                    ]
+                })
+                .collect()
+        }
+
+        MessageCatalog {
+            results: by_language(
+                RESULT_MESSAGES
+                    .into_iter()
+                    .map(|(code, english, swahili)| (code.code(), english, swahili)),
+            ),
+            responses: by_language(RESPONSE_MESSAGES.into_iter()),
+        }
+    }
+}
+
+impl MessageCatalog {
+    /// A catalog without any message, to be filled with `with_result` and `with_response`
+    pub fn empty() -> Self {
+        MessageCatalog {
+            results: HashMap::new(),
+            responses: HashMap::new(),
+        }
+    }
+
+    /// Replaces or adds the message in `language` of the callbacks with `result_code`
+    pub fn with_result(
+        mut self,
+        language: Language,
+        result_code: i32,
+        message: impl Into<String>,
+    ) -> Self {
+        self.results.insert((language, result_code), message.into());
+        self
+    }
+
+    /// Replaces or adds the message in `language` of the responses with `response_code`
+    pub fn with_response(
+        mut self,
+        language: Language,
+        response_code: i32,
+        message: impl Into<String>,
+    ) -> Self {
+        self.responses
+            .insert((language, response_code), message.into());
+        self
+    }
+
+    /// Returns the message in `language` of the callbacks with `result_code`
+    pub fn result_message(&self, language: Language, result_code: i32) -> Option<&str> {
+        self.results
+            .get(&(language, result_code))
+            .map(String::as_str)
+    }
+
+    /// Returns the message in `language` of the responses with `response_code`
+    pub fn response_message(&self, language: Language, response_code: i32) -> Option<&str> {
+        self.responses
+            .get(&(language, response_code))
+            .map(String::as_str)
+    }
+
+    /// Returns the message in `language` for the outcome of a payment. Falls back to the English
+    /// message, then to the `ResultDesc` of the callback, for result codes without one
+    pub fn callback_message<'a>(
+        &'a self,
+        language: Language,
+        callback: &'a StkCallback,
+    ) -> &'a str {
+        self.result_message(language, callback.result_code)
+            .or_else(|| self.result_message(Language::English, callback.result_code))
+            .unwrap_or(&callback.result_desc)
+    }
+
+    /// Returns the message in `language` acknowledging a payment request. Falls back to the
+    /// English message, then to the `CustomerMessage` of the response, for response codes
+    /// without one
+    #[cfg(feature = "express_request")]
+    pub fn customer_message<'a>(
+        &'a self,
+        language: Language,
+        response: &'a MpesaExpressResponse,
+    ) -> &'a str {
+        response
+            .response_code
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|code| {
+                self.response_message(language, code)
+                    .or_else(|| self.response_message(Language::English, code))
+            })
+            .unwrap_or(&response.customer_message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_result_code_has_a_message_in_every_language() {
+        let catalog = MessageCatalog::default();
+        for (code, ..) in RESULT_MESSAGES {
+            for language in [Language::English, Language::Swahili] {
+                assert!(
+                    catalog.result_message(language, code.code()).is_some(),
+                    "{code} {language:?}"
+                );
+            }
+        }
+        assert_eq!(catalog.result_message(Language::English, 17), None);
+    }
+
+    #[test]
+    fn test_callback_message_falls_back_to_english_then_result_desc() {
+        let callback = |code: i32| {
+            StkCallback::from_json(
+                serde_json::json!({
+                    "Body": {
+                        "stkCallback": {
+                            "MerchantRequestID": "29115-34620561-1",
+                            "CheckoutRequestID": "ws_CO_191220191020363925",
+                            "ResultCode": code,
+                            "ResultDesc": "Request cancelled by user"
+                        }
+                    }
+                })
+                .to_string()
+                .as_bytes(),
+            )
+            .unwrap()
+        };
+        let catalog = MessageCatalog::default().with_result(Language::English, 17, "Try again");
+
+        let cancelled = callback(1032);
+        assert_eq!(
+            catalog.callback_message(Language::Swahili, &cancelled),
+            "Umeghairi ombi la malipo"
+        );
+        let unknown = callback(17);
+        assert_eq!(
+            catalog.callback_message(Language::Swahili, &unknown),
+            "Try again"
+        );
+        let unknown = callback(18);
+        assert_eq!(
+            MessageCatalog::empty().callback_message(Language::Swahili, &unknown),
+            "Request cancelled by user"
+        );
+    }
+}
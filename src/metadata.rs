@@ -0,0 +1,163 @@
+//! Key-value metadata carried in the `Remarks` and `Occasion` of requests
+//!
+//! Integrations often correlate M-Pesa transactions with their own records by writing an order
+//! id or a reference into the free text `Remarks` and `Occasion` fields. [`Metadata`] formalizes
+//! this: its entries are encoded as `key=value` pairs separated by `;`, within the 100 characters
+//! Safaricom accepts, and parsed back from the result callbacks echoing them.
+//!
+//! ```rust
+//! use mpesa::metadata::Metadata;
+//!
+//! let metadata = Metadata::new().with("order", "A-1029").with("tenant", "acme");
+//! let encoded = metadata.encode().unwrap();
+//! assert_eq!(encoded, "order=A-1029;tenant=acme");
+//! assert_eq!(Metadata::parse(&encoded), Some(metadata));
+//! ```
+//!
+//! The B2C and transaction reversal builders write metadata into the `Occasion` of the request,
+//! the B2B builder into its `Remarks`, since B2B requests have no `Occasion`.
+
+use std::collections::BTreeMap;
+
+use crate::{MpesaError, MpesaResult};
+
+/// Maximum length of the `Remarks` and `Occasion` of a request
+pub const MAX_LEN: usize = 100;
+
+/// Ordered key-value pairs, encoded into the `Remarks` or `Occasion` of a request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    entries: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the entry `key`, replacing its previous value
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Adds the entry `key`, returning its previous value
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.entries.insert(key.into(), value.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Iterates over the entries, ordered by key
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Encodes the entries as `key=value` pairs separated by `;`, escaping `%`, `;` and `=` in
+    /// keys and values with their percent encoding
+    ///
+    /// # Errors
+    /// If the encoded metadata is longer than the 100 characters accepted by Safaricom
+    pub fn encode(&self) -> MpesaResult<String> {
+        let encoded = self
+            .entries
+            .iter()
+            .map(|(k, v)| format!("{}={}", escape(k), escape(v)))
+            .collect::<Vec<_>>()
+            .join(";");
+        if encoded.chars().count() > MAX_LEN {
+            return Err(MpesaError::Message(
+                "metadata is longer than the 100 characters allowed in remarks and occasion",
+            ));
+        }
+        Ok(encoded)
+    }
+
+    /// Parses metadata encoded by `encode`. Returns `None` if `encoded` is empty or is free text
+    /// that was not encoded as metadata.
+    pub fn parse(encoded: &str) -> Option<Self> {
+        if encoded.is_empty() {
+            return None;
+        }
+        encoded
+            .split(';')
+            .map(|pair| {
+                let (k, v) = pair.split_once('=')?;
+                Some((unescape(k)?, unescape(v)?))
+            })
+            .collect::<Option<BTreeMap<_, _>>>()
+            .map(|entries| Metadata { entries })
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Metadata {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Metadata {
+            entries: iter
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace(';', "%3B")
+        .replace('=', "%3D")
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('%') {
+        unescaped.push_str(&rest[..i]);
+        let c = match rest.get(i + 1..i + 3)? {
+            "25" => '%',
+            "3B" => ';',
+            "3D" => '=',
+            _ => return None,
+        };
+        unescaped.push(c);
+        rest = &rest[i + 3..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_is_encoded_and_parsed_back() {
+        let metadata = Metadata::new()
+            .with("order", "A-1029")
+            .with("note", "50%; paid=yes");
+        let encoded = metadata.encode().unwrap();
+        assert_eq!(encoded, "note=50%25%3B paid%3Dyes;order=A-1029");
+        assert_eq!(Metadata::parse(&encoded), Some(metadata));
+
+        assert_eq!(Metadata::parse("None"), None);
+        assert_eq!(Metadata::parse("Salary payment"), None);
+        assert_eq!(Metadata::parse("order=100%"), None);
+        assert_eq!(Metadata::parse(""), None);
+    }
+
+    #[test]
+    fn test_metadata_longer_than_remarks_is_rejected() {
+        let metadata = Metadata::new().with("order", "A".repeat(94));
+        assert_eq!(metadata.encode().unwrap().len(), MAX_LEN);
+        assert!(metadata.with("tenant", "acme").encode().is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Counters reported by the client
+//!
+//! Access tokens are cached per consumer key, so that a busy application authenticates about once
+//! an hour rather than on every request. A client built with `MpesaBuilder::metrics_sink` reports
+//! how the cache is doing to a [`MetricsSink`], which can forward the [`Counter`]s to Prometheus,
+//! StatsD or OpenTelemetry. A steady stream of misses, or misses in bursts, points at an OAuth
+//! storm: tokens being revoked, or instances authenticating at once after a deploy.
+//!
+//! [`MemoryMetrics`] keeps the counters in memory, for tests or to be read by a metrics endpoint.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use std::sync::Arc;
+//!
+//! use mpesa::metrics::{Counter, MemoryMetrics};
+//! use mpesa::Mpesa;
+//!
+//! let metrics = Arc::new(MemoryMetrics::default());
+//! let client = Mpesa::builder(consumer_key, consumer_secret, Environment::Sandbox)
+//!     .metrics_sink(Arc::clone(&metrics))
+//!     .build()?;
+//!
+//! // in the handler of the metrics endpoint
+//! let hits = metrics.get(Counter::AuthCacheHits);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A counter reported by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Counter {
+    /// A request was sent with the access token cached for its consumer key
+    AuthCacheHits,
+    /// No token was cached for the consumer key of a request, which authenticated first
+    AuthCacheMisses,
+    /// A cached token was dropped before being used again: it expired, was revoked by Safaricom,
+    /// or made room for the token of another consumer key
+    AuthCacheEvictions,
+}
+
+impl Counter {
+    /// The name of the counter, following the OpenTelemetry naming conventions, e.g.
+    /// `mpesa.auth.cache.hits`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::AuthCacheHits => "mpesa.auth.cache.hits",
+            Counter::AuthCacheMisses => "mpesa.auth.cache.misses",
+            Counter::AuthCacheEvictions => "mpesa.auth.cache.evictions",
+        }
+    }
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Receives the counters of the client. Called on the path of requests, so implementations
+/// should not block.
+pub trait MetricsSink: fmt::Debug + Send + Sync {
+    /// Adds `1` to `counter`
+    fn increment(&self, counter: Counter);
+}
+
+impl<S: MetricsSink + ?Sized> MetricsSink for Arc<S> {
+    fn increment(&self, counter: Counter) {
+        (**self).increment(counter);
+    }
+}
+
+/// Keeps counters in memory
+#[derive(Debug, Default)]
+pub struct MemoryMetrics {
+    counters: Mutex<HashMap<Counter, u64>>,
+}
+
+impl MemoryMetrics {
+    /// The value of `counter`, `0` if it was never incremented
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&counter)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl MetricsSink for MemoryMetrics {
+    fn increment(&self, counter: Counter) {
+        *self
+            .counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(counter)
+            .or_default() += 1;
+    }
+}
@@ -0,0 +1,159 @@
+//! Display names of the organizations behind shortcodes
+//!
+//! Callbacks and results identify businesses by their shortcode, which means little to the
+//! people reading a reconciliation report or a receipt. An [`OrganizationRegistry`] maps
+//! shortcodes to display names, filled in by the application and optionally backed by an
+//! [`OrganizationSource`], such as a directory service, for the shortcodes it does not know.
+//!
+//! ```rust
+//! use mpesa::organization::OrganizationRegistry;
+//!
+//! let organizations = OrganizationRegistry::default().with("600496", "ACME LTD");
+//! assert_eq!(organizations.display_name("600496"), "ACME LTD");
+//! assert_eq!(organizations.display_name("600000"), "600000");
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+
+use crate::MpesaResult;
+
+/// Looks up the organizations missing from an `OrganizationRegistry`
+pub trait OrganizationSource: fmt::Debug + Send + Sync {
+    /// Returns the display name of the organization owning `shortcode`, `None` if it is unknown
+    fn name(&self, shortcode: &str) -> impl Future<Output = MpesaResult<Option<String>>> + Send;
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// `OrganizationSource` with boxed futures, for the registry to hold any source
+trait DynOrganizationSource: fmt::Debug + Send + Sync {
+    fn name<'a>(&'a self, shortcode: &'a str) -> BoxFuture<'a, MpesaResult<Option<String>>>;
+}
+
+impl<S: OrganizationSource> DynOrganizationSource for S {
+    fn name<'a>(&'a self, shortcode: &'a str) -> BoxFuture<'a, MpesaResult<Option<String>>> {
+        Box::pin(OrganizationSource::name(self, shortcode))
+    }
+}
+
+/// Display names of organizations by shortcode
+#[derive(Debug, Default)]
+pub struct OrganizationRegistry {
+    names: RwLock<HashMap<String, String>>,
+    source: Option<Box<dyn DynOrganizationSource>>,
+}
+
+impl OrganizationRegistry {
+    /// Adds the display `name` of the organization owning `shortcode`
+    pub fn with(self, shortcode: impl Into<String>, name: impl Into<String>) -> Self {
+        self.insert(shortcode, name);
+        self
+    }
+
+    /// Looks up the shortcodes missing from the registry with `source`. The names it finds are
+    /// kept by the registry.
+    pub fn with_source(mut self, source: impl OrganizationSource + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Adds or replaces the display `name` of the organization owning `shortcode`
+    pub fn insert(&self, shortcode: impl Into<String>, name: impl Into<String>) {
+        self.names
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(shortcode.into().trim().to_owned(), name.into());
+    }
+
+    /// Returns the display name of the organization owning `shortcode` if it is in the registry,
+    /// without querying the source
+    pub fn get(&self, shortcode: &str) -> Option<String> {
+        self.names
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(shortcode.trim())
+            .cloned()
+    }
+
+    /// Returns the display name of the organization owning `shortcode`, or the shortcode itself
+    /// if it is not in the registry, for rendering
+    pub fn display_name(&self, shortcode: &str) -> String {
+        self.get(shortcode)
+            .unwrap_or_else(|| shortcode.trim().to_owned())
+    }
+
+    /// Returns the display name of the organization owning `shortcode`, querying the source if it
+    /// is not in the registry. `None` if neither knows it.
+    ///
+    /// # Errors
+    /// Returns the error of the source if it fails
+    pub async fn lookup(&self, shortcode: &str) -> MpesaResult<Option<String>> {
+        if let Some(name) = self.get(shortcode) {
+            return Ok(Some(name));
+        }
+        let Some(source) = &self.source else {
+            return Ok(None);
+        };
+        let shortcode = shortcode.trim();
+        let name = source.name(shortcode).await?;
+        if let Some(name) = &name {
+            self.insert(shortcode, name.clone());
+        }
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::MpesaError;
+
+    #[derive(Debug, Default)]
+    struct Directory {
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl OrganizationSource for Directory {
+        async fn name(&self, shortcode: &str) -> MpesaResult<Option<String>> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            match shortcode {
+                "600000" => Ok(Some("Safaricom Sandbox".to_owned())),
+                "000000" => Err(MpesaError::Message("directory unavailable")),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_falls_back_to_the_source_and_keeps_its_names() {
+        let directory = Directory::default();
+        let queries = Arc::clone(&directory.queries);
+        let organizations = OrganizationRegistry::default()
+            .with("600496", "ACME LTD")
+            .with_source(directory);
+
+        assert_eq!(
+            organizations.lookup("600496").await.unwrap().as_deref(),
+            Some("ACME LTD")
+        );
+        assert_eq!(queries.load(Ordering::SeqCst), 0);
+
+        assert_eq!(
+            organizations.lookup(" 600000").await.unwrap().as_deref(),
+            Some("Safaricom Sandbox")
+        );
+        assert_eq!(organizations.display_name("600000"), "Safaricom Sandbox");
+        organizations.lookup("600000").await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+        assert_eq!(organizations.lookup("600999").await.unwrap(), None);
+        assert!(organizations.lookup("000000").await.is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Paths of the Daraja endpoints called by the client
+//!
+//! The paths are relative to the base url of an environment and carry the version of their API
+//! the client calls by default, e.g. `v1` in `mpesa/b2c/v1/paymentrequest`. Mocks, gateway
+//! allow-lists and dashboards can refer to them instead of copies that could drift from the
+//! crate. `Mpesa::endpoint_path` returns the path a client calls, with the version set with
+//! `MpesaBuilder::api_version`.
+//!
+//! ```rust
+//! use mpesa::paths::{self, Endpoint};
+//!
+//! assert_eq!(paths::B2C, "mpesa/b2c/v1/paymentrequest");
+//! assert_eq!(Endpoint::B2c.path(), paths::B2C);
+//! assert_eq!(Endpoint::B2c.version(), 1);
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::Service;
+
+pub const AUTH: &str = "oauth/v1/generate";
+pub const ACCOUNT_BALANCE: &str = "mpesa/accountbalance/v1/query";
+pub const B2B: &str = "mpesa/b2b/v1/paymentrequest";
+pub const B2C: &str = "mpesa/b2c/v1/paymentrequest";
+pub const C2B_REGISTER: &str = "mpesa/c2b/v1/registerurl";
+pub const C2B_SIMULATE: &str = "mpesa/c2b/v1/simulate";
+pub const DYNAMIC_QR: &str = "mpesa/qrcode/v1/generate";
+pub const EXPRESS_REQUEST: &str = "mpesa/stkpush/v1/processrequest";
+pub const EXPRESS_QUERY: &str = "mpesa/stkpushquery/v1/query";
+pub const TRANSACTION_REVERSAL: &str = "mpesa/reversal/v1/request";
+pub const TRANSACTION_STATUS: &str = "mpesa/transactionstatus/v1/query";
+pub const BILL_MANAGER_ONBOARD: &str = "v1/billmanager-invoice/optin";
+pub const BILL_MANAGER_ONBOARD_MODIFY: &str = "v1/billmanager-invoice/change-optin-details";
+pub const BILL_MANAGER_SINGLE_INVOICE: &str = "v1/billmanager-invoice/single-invoicing";
+pub const BILL_MANAGER_BULK_INVOICE: &str = "v1/billmanager-invoice/bulk-invoicing";
+pub const BILL_MANAGER_CANCEL_INVOICE: &str = "v1/billmanager-invoice/cancel-single-invoice";
+pub const BILL_MANAGER_RECONCILIATION: &str = "v1/billmanager-invoice/reconciliation";
+
+/// The Daraja endpoints called by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Endpoint {
+    /// Generates the access tokens of the other requests
+    Auth,
+    AccountBalance,
+    B2b,
+    B2c,
+    C2bRegister,
+    C2bSimulate,
+    DynamicQr,
+    ExpressRequest,
+    /// Queries the status of an M-Pesa Express request
+    ExpressQuery,
+    TransactionReversal,
+    TransactionStatus,
+    BillManagerOnboard,
+    BillManagerOnboardModify,
+    BillManagerSingleInvoice,
+    BillManagerBulkInvoice,
+    BillManagerCancelInvoice,
+    BillManagerReconciliation,
+}
+
+impl Endpoint {
+    /// Every endpoint called by the client
+    pub const ALL: [Endpoint; 17] = [
+        Endpoint::Auth,
+        Endpoint::AccountBalance,
+        Endpoint::B2b,
+        Endpoint::B2c,
+        Endpoint::C2bRegister,
+        Endpoint::C2bSimulate,
+        Endpoint::DynamicQr,
+        Endpoint::ExpressRequest,
+        Endpoint::ExpressQuery,
+        Endpoint::TransactionReversal,
+        Endpoint::TransactionStatus,
+        Endpoint::BillManagerOnboard,
+        Endpoint::BillManagerOnboardModify,
+        Endpoint::BillManagerSingleInvoice,
+        Endpoint::BillManagerBulkInvoice,
+        Endpoint::BillManagerCancelInvoice,
+        Endpoint::BillManagerReconciliation,
+    ];
+
+    /// The path of the endpoint, at the version of its API called by default
+    pub fn path(&self) -> &'static str {
+        match self {
+            Endpoint::Auth => AUTH,
+            Endpoint::AccountBalance => ACCOUNT_BALANCE,
+            Endpoint::B2b => B2B,
+            Endpoint::B2c => B2C,
+            Endpoint::C2bRegister => C2B_REGISTER,
+            Endpoint::C2bSimulate => C2B_SIMULATE,
+            Endpoint::DynamicQr => DYNAMIC_QR,
+            Endpoint::ExpressRequest => EXPRESS_REQUEST,
+            Endpoint::ExpressQuery => EXPRESS_QUERY,
+            Endpoint::TransactionReversal => TRANSACTION_REVERSAL,
+            Endpoint::TransactionStatus => TRANSACTION_STATUS,
+            Endpoint::BillManagerOnboard => BILL_MANAGER_ONBOARD,
+            Endpoint::BillManagerOnboardModify => BILL_MANAGER_ONBOARD_MODIFY,
+            Endpoint::BillManagerSingleInvoice => BILL_MANAGER_SINGLE_INVOICE,
+            Endpoint::BillManagerBulkInvoice => BILL_MANAGER_BULK_INVOICE,
+            Endpoint::BillManagerCancelInvoice => BILL_MANAGER_CANCEL_INVOICE,
+            Endpoint::BillManagerReconciliation => BILL_MANAGER_RECONCILIATION,
+        }
+    }
+
+    /// The API of the endpoint, whose version is set with `MpesaBuilder::api_version`.
+    /// `None` for `Auth`, which is not versioned per API.
+    pub fn service(&self) -> Option<Service> {
+        match self {
+            Endpoint::Auth => None,
+            Endpoint::AccountBalance => Some(Service::AccountBalance),
+            Endpoint::B2b => Some(Service::B2b),
+            Endpoint::B2c => Some(Service::B2c),
+            Endpoint::C2bRegister => Some(Service::C2bRegister),
+            Endpoint::C2bSimulate => Some(Service::C2bSimulate),
+            Endpoint::DynamicQr => Some(Service::DynamicQr),
+            Endpoint::ExpressRequest | Endpoint::ExpressQuery => Some(Service::ExpressRequest),
+            Endpoint::TransactionReversal => Some(Service::TransactionReversal),
+            Endpoint::TransactionStatus => Some(Service::TransactionStatus),
+            Endpoint::BillManagerOnboard
+            | Endpoint::BillManagerOnboardModify
+            | Endpoint::BillManagerSingleInvoice
+            | Endpoint::BillManagerBulkInvoice
+            | Endpoint::BillManagerCancelInvoice
+            | Endpoint::BillManagerReconciliation => Some(Service::BillManager),
+        }
+    }
+
+    /// The version of the API in `path`, e.g. `1` for `v1`
+    pub fn version(&self) -> u8 {
+        self.path()
+            .split('/')
+            .find_map(|segment| segment.strip_prefix('v')?.parse().ok())
+            .expect("every path has a version segment")
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints_are_called_at_the_default_version_of_their_api() {
+        for endpoint in Endpoint::ALL {
+            let version = endpoint.version();
+            if let Some(service) = endpoint.service() {
+                assert_eq!(version, service.default_api_version(), "{endpoint}");
+            }
+            assert!(!endpoint.path().starts_with('/'), "{endpoint}");
+        }
+    }
+}
@@ -0,0 +1,438 @@
+//! Supervision of B2C payouts whose result reports a failure
+//!
+//! [`PayoutSupervisor`] sends B2C payments and keeps them until their result is received. When
+//! a result reports a failure, such as a phone number that is not registered for M-Pesa, a
+//! [`PayoutPolicy`] decides whether the payout is sent again, rerouted to another phone number or
+//! parked for manual review. Payouts are kept by their `OriginatorConversationID`, generated by
+//! the client and saved before the payment is sent, so that a result posted before the response
+//! is read still finds its payout. Pending payouts and decisions are kept by a [`PayoutStore`]:
+//! [`MemoryPayoutStore`], any `storage::Storage`, or `persistence::SqlxStore` with the `sqlx`
+//! feature.
+//!
+//! Results received on the `QueueTimeOutURL` are not supervised, since a payment that timed out
+//! may still have been made.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use mpesa::callbacks::ResultCallback;
+//! use mpesa::payout::{MemoryPayoutStore, Payout, PayoutAction, PayoutSupervisor};
+//! use mpesa::{Environment, Mpesa};
+//!
+//! #[tokio::main]
+//! async fn main() -> mpesa::MpesaResult<()> {
+//!     let client = Mpesa::new("consumer_key", "consumer_secret", Environment::Sandbox);
+//!     let policy = |payout: &Payout, result: &ResultCallback| match result.result_code.as_str() {
+//!         "2040" if payout.attempt == 0 => PayoutAction::Reroute("254708374150".to_owned()),
+//!         "17" if payout.attempt < 3 => PayoutAction::Retry,
+//!         _ => PayoutAction::Park(result.result_desc.clone()),
+//!     };
+//!     let supervisor = PayoutSupervisor::new(client, MemoryPayoutStore::default(), policy);
+//!
+//!     let payout = Payout::new("testapi496", "600496", "254708374149", 1000)
+//!         .result_url("https://example.com/b2c/result")
+//!         .timeout_url("https://example.com/b2c/timeout");
+//!     supervisor.send(payout).await?;
+//!
+//!     // later, in the handler of the `ResultURL`
+//!     # let body = b"";
+//!     let result = ResultCallback::from_json(body)?;
+//!     if let Some(decision) = supervisor.on_result(&result).await? {
+//!         println!("{:?} payout {}", decision.action, decision.conversation_id);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::callbacks::ResultCallback;
+use crate::services::B2cResponse;
+use crate::storage::{namespaces, Storage};
+use crate::{CommandId, Mpesa, MpesaResult};
+
+/// A B2C payment, as sent by a `PayoutSupervisor`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Payout {
+    pub initiator_name: String,
+    pub party_a: String,
+    /// Phone number the payment is sent to
+    pub party_b: String,
+    pub amount: f64,
+    pub command_id: CommandId,
+    pub remarks: Option<String>,
+    pub occasion: Option<String>,
+    pub result_url: Option<String>,
+    pub timeout_url: Option<String>,
+    /// Number of times the payout was sent before, `0` for the first attempt
+    pub attempt: u32,
+}
+
+impl Payout {
+    /// Creates a `BusinessPayment` of `amount` from the shortcode `party_a` to the phone number
+    /// `party_b`
+    pub fn new<S: Into<String>>(
+        initiator_name: S,
+        party_a: S,
+        party_b: S,
+        amount: impl Into<f64>,
+    ) -> Self {
+        Payout {
+            initiator_name: initiator_name.into(),
+            party_a: party_a.into(),
+            party_b: party_b.into(),
+            amount: amount.into(),
+            command_id: CommandId::BusinessPayment,
+            remarks: None,
+            occasion: None,
+            result_url: None,
+            timeout_url: None,
+            attempt: 0,
+        }
+    }
+
+    pub fn command_id(mut self, command_id: CommandId) -> Self {
+        self.command_id = command_id;
+        self
+    }
+
+    pub fn remarks(mut self, remarks: impl Into<String>) -> Self {
+        self.remarks = Some(remarks.into());
+        self
+    }
+
+    pub fn occasion(mut self, occasion: impl Into<String>) -> Self {
+        self.occasion = Some(occasion.into());
+        self
+    }
+
+    pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+        self.result_url = Some(result_url.into());
+        self
+    }
+
+    pub fn timeout_url(mut self, timeout_url: impl Into<String>) -> Self {
+        self.timeout_url = Some(timeout_url.into());
+        self
+    }
+
+    fn next_attempt(&self) -> Self {
+        Payout {
+            attempt: self.attempt + 1,
+            ..self.clone()
+        }
+    }
+}
+
+/// What to do with a payout whose result reports a failure
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum PayoutAction {
+    /// Sends the payout again to the same phone number
+    Retry,
+    /// Sends the payout again to another phone number
+    Reroute(String),
+    /// Leaves the payout for manual review, with the reason why
+    Park(String),
+}
+
+impl PayoutAction {
+    /// `retry`, `reroute` or `park`
+    pub fn name(&self) -> &'static str {
+        match self {
+            PayoutAction::Retry => "retry",
+            PayoutAction::Reroute(_) => "reroute",
+            PayoutAction::Park(_) => "park",
+        }
+    }
+}
+
+/// Decides what to do with failed payouts
+pub trait PayoutPolicy: Send + Sync {
+    fn decide(&self, payout: &Payout, result: &ResultCallback) -> PayoutAction;
+}
+
+impl<F> PayoutPolicy for F
+where
+    F: Fn(&Payout, &ResultCallback) -> PayoutAction + Send + Sync,
+{
+    fn decide(&self, payout: &Payout, result: &ResultCallback) -> PayoutAction {
+        self(payout, result)
+    }
+}
+
+/// The action applied to a failed payout
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct PayoutDecision {
+    /// `ConversationID` of the failed payout
+    pub conversation_id: String,
+    pub payout: Payout,
+    pub result_code: String,
+    pub result_desc: String,
+    /// The action of the policy, or `Park` if the payout could not be sent again
+    pub action: PayoutAction,
+    /// `ConversationID` of the payout sent again when retried or rerouted
+    pub next_conversation_id: Option<String>,
+    /// Unix timestamp in seconds
+    pub decided_at: i64,
+}
+
+/// Keeps the payouts waiting for their result, by `OriginatorConversationID`, and the decisions
+/// made on failed payouts
+pub trait PayoutStore: Send + Sync {
+    /// Keeps `payout` until its result is handled
+    fn save_pending(
+        &self,
+        originator_conversation_id: &str,
+        payout: &Payout,
+    ) -> impl Future<Output = MpesaResult<()>> + Send;
+
+    /// Returns the payout sent with `originator_conversation_id`, `None` if it is unknown or its
+    /// result was already handled
+    fn pending(
+        &self,
+        originator_conversation_id: &str,
+    ) -> impl Future<Output = MpesaResult<Option<Payout>>> + Send;
+
+    /// Forgets the payout sent with `originator_conversation_id`, once its result is handled
+    fn remove_pending(
+        &self,
+        originator_conversation_id: &str,
+    ) -> impl Future<Output = MpesaResult<()>> + Send;
+
+    fn save_decision(
+        &self,
+        decision: &PayoutDecision,
+    ) -> impl Future<Output = MpesaResult<()>> + Send;
+}
+
+/// Keeps pending payouts and decisions in memory, where they are lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryPayoutStore {
+    pending: Mutex<HashMap<String, Payout>>,
+    decisions: Mutex<Vec<PayoutDecision>>,
+}
+
+impl MemoryPayoutStore {
+    /// Returns the decisions made so far, oldest first
+    pub fn decisions(&self) -> Vec<PayoutDecision> {
+        self.decisions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl PayoutStore for MemoryPayoutStore {
+    async fn save_pending(
+        &self,
+        originator_conversation_id: &str,
+        payout: &Payout,
+    ) -> MpesaResult<()> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(originator_conversation_id.to_owned(), payout.clone());
+        Ok(())
+    }
+
+    async fn pending(&self, originator_conversation_id: &str) -> MpesaResult<Option<Payout>> {
+        Ok(self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(originator_conversation_id)
+            .cloned())
+    }
+
+    async fn remove_pending(&self, originator_conversation_id: &str) -> MpesaResult<()> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(originator_conversation_id);
+        Ok(())
+    }
+
+    async fn save_decision(&self, decision: &PayoutDecision) -> MpesaResult<()> {
+        self.decisions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(decision.clone());
+        Ok(())
+    }
+}
+
+/// Keeps pending payouts in the `PAYOUTS` namespace and decisions in the `PAYOUT_DECISIONS`
+/// namespace, as JSON
+impl<S: Storage> PayoutStore for S {
+    async fn save_pending(
+        &self,
+        originator_conversation_id: &str,
+        payout: &Payout,
+    ) -> MpesaResult<()> {
+        self.set(
+            namespaces::PAYOUTS,
+            originator_conversation_id,
+            &serde_json::to_vec(payout)?,
+            None,
+        )
+        .await
+    }
+
+    async fn pending(&self, originator_conversation_id: &str) -> MpesaResult<Option<Payout>> {
+        self.get(namespaces::PAYOUTS, originator_conversation_id)
+            .await?
+            .map(|payout| Ok(serde_json::from_slice(&payout)?))
+            .transpose()
+    }
+
+    async fn remove_pending(&self, originator_conversation_id: &str) -> MpesaResult<()> {
+        self.delete(namespaces::PAYOUTS, originator_conversation_id)
+            .await
+    }
+
+    async fn save_decision(&self, decision: &PayoutDecision) -> MpesaResult<()> {
+        self.set(
+            namespaces::PAYOUT_DECISIONS,
+            &decision.conversation_id,
+            &serde_json::to_vec(decision)?,
+            None,
+        )
+        .await
+    }
+}
+
+/// Sends B2C payouts and applies a `PayoutPolicy` to those whose result reports a failure
+#[derive(Debug)]
+pub struct PayoutSupervisor<S, P> {
+    client: Mpesa,
+    store: S,
+    policy: P,
+}
+
+impl<S: PayoutStore, P: PayoutPolicy> PayoutSupervisor<S, P> {
+    pub fn new(client: Mpesa, store: S, policy: P) -> Self {
+        PayoutSupervisor {
+            client,
+            store,
+            policy,
+        }
+    }
+
+    /// Returns the store of the supervisor, e.g. to read the decisions made
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Sends `payout` and keeps it until its result is handled by `on_result`. The payout is
+    /// saved before it is sent, and forgotten if it cannot be sent.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the payment cannot be sent or the payout cannot be stored
+    pub async fn send(&self, payout: Payout) -> MpesaResult<B2cResponse> {
+        let originator_conversation_id = self.client.generate_id();
+        self.store
+            .save_pending(&originator_conversation_id, &payout)
+            .await?;
+
+        let mut request = self
+            .client
+            .b2c(&payout.initiator_name)
+            .originator_conversation_id(&originator_conversation_id)
+            .command_id(payout.command_id)
+            .party_a(&payout.party_a)
+            .party_b(&payout.party_b)
+            .amount(payout.amount);
+        if let Some(remarks) = &payout.remarks {
+            request = request.remarks(remarks);
+        }
+        if let Some(occasion) = &payout.occasion {
+            request = request.occasion(occasion);
+        }
+        if let Some(result_url) = &payout.result_url {
+            request = request.result_url(result_url);
+        }
+        if let Some(timeout_url) = &payout.timeout_url {
+            request = request.timeout_url(timeout_url);
+        }
+
+        match request.send().await {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                self.store
+                    .remove_pending(&originator_conversation_id)
+                    .await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Handles the result of a payout sent by the supervisor, returning the decision made if it
+    /// failed. Returns `None` for successful payouts and for results of unknown payouts.
+    ///
+    /// A payout that is retried or rerouted but cannot be sent again is parked. The payout is only
+    /// forgotten once the decision is stored, so that a result delivered again after a failure of
+    /// the store is handled again.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the store fails
+    pub async fn on_result(&self, result: &ResultCallback) -> MpesaResult<Option<PayoutDecision>> {
+        let originator_conversation_id = &result.originator_conversation_id;
+        let Some(payout) = self.store.pending(originator_conversation_id).await? else {
+            return Ok(None);
+        };
+        if result.is_success() {
+            self.store
+                .remove_pending(originator_conversation_id)
+                .await?;
+            return Ok(None);
+        }
+
+        let action = self.policy.decide(&payout, result);
+        let next = match &action {
+            PayoutAction::Retry => Some(payout.next_attempt()),
+            PayoutAction::Reroute(party_b) => Some(Payout {
+                party_b: party_b.clone(),
+                ..payout.next_attempt()
+            }),
+            PayoutAction::Park(_) => None,
+        };
+        let mut decision = PayoutDecision {
+            conversation_id: result.conversation_id.clone(),
+            payout,
+            result_code: result.result_code.clone(),
+            result_desc: result.result_desc.clone(),
+            action,
+            next_conversation_id: None,
+            decided_at: now(),
+        };
+        if let Some(next) = next {
+            match self.send(next).await {
+                Ok(response) => decision.next_conversation_id = Some(response.conversation_id),
+                Err(e) => {
+                    decision.action =
+                        PayoutAction::Park(format!("The payout could not be sent again: {e}"))
+                }
+            }
+        }
+
+        self.store.save_decision(&decision).await?;
+        self.store
+            .remove_pending(originator_conversation_id)
+            .await?;
+        Ok(Some(decision))
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
@@ -0,0 +1,637 @@
+//! Durable storage of requests and callbacks in Postgres, MySQL or SQLite through `sqlx`
+//!
+//! `SqlxStore` works with an `AnyPool`, so the drivers for the databases in use have to be enabled
+//! on your own `sqlx` dependency and installed with `sqlx::any::install_default_drivers` before the
+//! pool is created.
+//!
+//! Requests are stored under the id Safaricom correlates them with, the `CheckoutRequestID` of
+//! M-Pesa Express requests and the `ConversationID` of the others, which is also the id of the
+//! callbacks received for them.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use mpesa::persistence::{CallbackRecord, RequestRecord, SqlxStore};
+//!
+//! sqlx::any::install_default_drivers();
+//! let pool = sqlx::AnyPool::connect("postgres://localhost/payments").await?;
+//! let store = SqlxStore::new(pool);
+//! store.migrate().await?;
+//!
+//! let response = client.express_request()/* .. */.send().await?;
+//! store.insert_request(&RequestRecord::from(&response)).await?;
+//!
+//! // later, in the callback handler
+//! store.insert_callback(&CallbackRecord::stk(&callback)).await?;
+//! let callbacks = store.find_callbacks(&response.checkout_request_id).await?;
+//! ```
+//!
+//! With the `b2c` feature, `SqlxStore` is also a `payout::PayoutStore`, keeping the payouts of a
+//! `PayoutSupervisor` in `mpesa_payouts` and its decisions in `mpesa_payout_decisions`.
+//!
+//! With the `client` feature, `SqlxStore` is also an `idempotency::IdempotencyStore`, keeping the
+//! responses of requests sent with an idempotency key in `mpesa_idempotency_keys`.
+
+use std::borrow::Cow;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlx::any::AnyRow;
+use sqlx::{AnyPool, Row};
+
+use crate::callbacks::{C2bTransaction, ResultCallback, StkCallback};
+use crate::{MpesaError, MpesaResult};
+
+/// Statements creating the tables used by `SqlxStore`, run by `SqlxStore::migrate`.
+/// They are portable across Postgres, MySQL and SQLite and safe to run repeatedly.
+pub const MIGRATIONS: [&str; 5] = [
+    "CREATE TABLE IF NOT EXISTS mpesa_requests (
+        correlation_id VARCHAR(255) NOT NULL PRIMARY KEY,
+        kind VARCHAR(64) NOT NULL,
+        originator_id VARCHAR(255),
+        response_code VARCHAR(64),
+        response_description TEXT NOT NULL,
+        created_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS mpesa_callbacks (
+        kind VARCHAR(64) NOT NULL,
+        correlation_id VARCHAR(255) NOT NULL,
+        result_code VARCHAR(64) NOT NULL,
+        result_desc TEXT NOT NULL,
+        payload TEXT,
+        received_at BIGINT NOT NULL,
+        PRIMARY KEY (kind, correlation_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS mpesa_payouts (
+        originator_conversation_id VARCHAR(255) NOT NULL PRIMARY KEY,
+        payout TEXT NOT NULL,
+        created_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS mpesa_payout_decisions (
+        conversation_id VARCHAR(255) NOT NULL PRIMARY KEY,
+        action VARCHAR(64) NOT NULL,
+        detail TEXT,
+        result_code VARCHAR(64) NOT NULL,
+        result_desc TEXT NOT NULL,
+        next_conversation_id VARCHAR(255),
+        payout TEXT NOT NULL,
+        decided_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS mpesa_idempotency_keys (
+        idempotency_key VARCHAR(255) NOT NULL PRIMARY KEY,
+        response TEXT,
+        created_at BIGINT NOT NULL
+    )",
+];
+
+/// A request accepted by Safaricom, as stored in `mpesa_requests`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RequestRecord {
+    /// Service the request was made to, e.g. `express_request` or `b2c`
+    pub kind: String,
+    /// `CheckoutRequestID` or `ConversationID` of the request
+    pub correlation_id: String,
+    /// `MerchantRequestID` or `OriginatorConversationID` of the request
+    pub originator_id: Option<String>,
+    pub response_code: Option<String>,
+    pub response_description: String,
+    /// Unix timestamp in seconds
+    pub created_at: i64,
+}
+
+impl RequestRecord {
+    /// Creates a record of a request made to the service `kind`
+    pub fn new(
+        kind: impl Into<String>,
+        correlation_id: impl Into<String>,
+        originator_id: Option<String>,
+        response_code: Option<String>,
+        response_description: impl Into<String>,
+    ) -> Self {
+        RequestRecord {
+            kind: kind.into(),
+            correlation_id: correlation_id.into(),
+            originator_id,
+            response_code,
+            response_description: response_description.into(),
+            created_at: now(),
+        }
+    }
+
+    fn from_row(row: &AnyRow) -> Result<Self, sqlx::Error> {
+        Ok(RequestRecord {
+            kind: row.try_get("kind")?,
+            correlation_id: row.try_get("correlation_id")?,
+            originator_id: row.try_get("originator_id")?,
+            response_code: row.try_get("response_code")?,
+            response_description: row.try_get("response_description")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+/// Implements `From<&$response>` for `RequestRecord` for responses carrying a `ConversationID`
+macro_rules! conversation_record {
+    ($feature:literal, $response:ident, $kind:literal) => {
+        #[cfg(feature = $feature)]
+        impl From<&crate::services::$response> for RequestRecord {
+            fn from(response: &crate::services::$response) -> Self {
+                RequestRecord::new(
+                    $kind,
+                    response.conversation_id.clone(),
+                    Some(response.originator_conversation_id.clone()),
+                    Some(response.response_code.clone()),
+                    response.response_description.clone(),
+                )
+            }
+        }
+    };
+}
+
+conversation_record!("account_balance", AccountBalanceResponse, "account_balance");
+conversation_record!("b2b", B2bResponse, "b2b");
+conversation_record!("b2c", B2cResponse, "b2c");
+conversation_record!(
+    "transaction_reversal",
+    TransactionReversalResponse,
+    "transaction_reversal"
+);
+
+#[cfg(feature = "transaction_status")]
+impl From<&crate::services::TransactionStatusResponse> for RequestRecord {
+    fn from(response: &crate::services::TransactionStatusResponse) -> Self {
+        RequestRecord::new(
+            "transaction_status",
+            response.conversation_id.clone(),
+            Some(response.originator_conversation_id.clone()),
+            None,
+            response.response_description.clone(),
+        )
+    }
+}
+
+#[cfg(feature = "express_request")]
+impl From<&crate::services::MpesaExpressResponse> for RequestRecord {
+    fn from(response: &crate::services::MpesaExpressResponse) -> Self {
+        RequestRecord::new(
+            "express_request",
+            response.checkout_request_id.clone(),
+            Some(response.merchant_request_id.clone()),
+            Some(response.response_code.clone()),
+            response.response_description.clone(),
+        )
+    }
+}
+
+/// A callback received from Safaricom, as stored in `mpesa_callbacks`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CallbackRecord {
+    /// `stk`, `c2b`, `result` or `timeout`
+    pub kind: String,
+    /// `CheckoutRequestID`, `TransID` or `ConversationID` of the callback
+    pub correlation_id: String,
+    pub result_code: String,
+    pub result_desc: String,
+    /// Raw body of the callback, if provided with `with_payload`
+    pub payload: Option<String>,
+    /// Unix timestamp in seconds
+    pub received_at: i64,
+}
+
+impl CallbackRecord {
+    fn new(kind: &str, correlation_id: &str, result_code: String, result_desc: &str) -> Self {
+        CallbackRecord {
+            kind: kind.to_owned(),
+            correlation_id: correlation_id.to_owned(),
+            result_code,
+            result_desc: result_desc.to_owned(),
+            payload: None,
+            received_at: now(),
+        }
+    }
+
+    /// Record of the result of an M-Pesa Express request
+    pub fn stk(callback: &StkCallback) -> Self {
+        Self::new(
+            "stk",
+            &callback.checkout_request_id,
+            callback.result_code.to_string(),
+            &callback.result_desc,
+        )
+    }
+
+    /// Record of a confirmed C2B payment
+    pub fn c2b(transaction: &C2bTransaction) -> Self {
+        Self::new("c2b", &transaction.trans_id, "0".to_owned(), "Confirmed")
+    }
+
+    /// Record of a callback received on the `ResultURL`
+    pub fn result(result: &ResultCallback) -> Self {
+        Self::new(
+            "result",
+            &result.conversation_id,
+            result.result_code.clone(),
+            &result.result_desc,
+        )
+    }
+
+    /// Record of a callback received on the `QueueTimeOutURL`
+    pub fn timeout(result: &ResultCallback) -> Self {
+        Self {
+            kind: "timeout".to_owned(),
+            ..Self::result(result)
+        }
+    }
+
+    /// Stores the raw body of the callback along with the record
+    pub fn with_payload(mut self, payload: impl Into<String>) -> Self {
+        self.payload = Some(payload.into());
+        self
+    }
+
+    fn from_row(row: &AnyRow) -> Result<Self, sqlx::Error> {
+        Ok(CallbackRecord {
+            kind: row.try_get("kind")?,
+            correlation_id: row.try_get("correlation_id")?,
+            result_code: row.try_get("result_code")?,
+            result_desc: row.try_get("result_desc")?,
+            payload: row.try_get("payload")?,
+            received_at: row.try_get("received_at")?,
+        })
+    }
+}
+
+/// Stores requests and callbacks in the tables created by `MIGRATIONS`
+#[derive(Debug, Clone)]
+pub struct SqlxStore {
+    pool: AnyPool,
+    numbered_placeholders: bool,
+}
+
+impl SqlxStore {
+    pub fn new(pool: AnyPool) -> Self {
+        let scheme = pool.connect_options().database_url.scheme().to_owned();
+        SqlxStore {
+            pool,
+            numbered_placeholders: scheme.starts_with("postgres"),
+        }
+    }
+
+    /// Creates the tables if they do not exist yet
+    pub async fn migrate(&self) -> MpesaResult<()> {
+        for migration in MIGRATIONS {
+            sqlx::query(migration).execute(&self.pool).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores `record`, returns `false` if a request with the same correlation id was already stored
+    pub async fn insert_request(&self, record: &RequestRecord) -> MpesaResult<bool> {
+        let sql = self.sql(
+            "INSERT INTO mpesa_requests (correlation_id, kind, originator_id, response_code, \
+             response_description, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+        );
+        let result = sqlx::query(&sql)
+            .bind(&record.correlation_id)
+            .bind(&record.kind)
+            .bind(record.originator_id.clone())
+            .bind(record.response_code.clone())
+            .bind(&record.response_description)
+            .bind(record.created_at)
+            .execute(&self.pool)
+            .await;
+        inserted(result)
+    }
+
+    /// Stores `record`, returns `false` if the callback was already stored, such as when
+    /// Safaricom retries it
+    pub async fn insert_callback(&self, record: &CallbackRecord) -> MpesaResult<bool> {
+        let sql = self.sql(
+            "INSERT INTO mpesa_callbacks (kind, correlation_id, result_code, result_desc, \
+             payload, received_at) VALUES (?, ?, ?, ?, ?, ?)",
+        );
+        let result = sqlx::query(&sql)
+            .bind(&record.kind)
+            .bind(&record.correlation_id)
+            .bind(&record.result_code)
+            .bind(&record.result_desc)
+            .bind(record.payload.clone())
+            .bind(record.received_at)
+            .execute(&self.pool)
+            .await;
+        inserted(result)
+    }
+
+    /// Looks up a request by its `CheckoutRequestID` or `ConversationID`
+    pub async fn find_request(&self, correlation_id: &str) -> MpesaResult<Option<RequestRecord>> {
+        let sql = self.sql("SELECT * FROM mpesa_requests WHERE correlation_id = ?");
+        let row = sqlx::query(&sql)
+            .bind(correlation_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.as_ref().map(RequestRecord::from_row).transpose()?)
+    }
+
+    /// Returns the callbacks received for a request, oldest first
+    pub async fn find_callbacks(&self, correlation_id: &str) -> MpesaResult<Vec<CallbackRecord>> {
+        let sql = self.sql(
+            "SELECT * FROM mpesa_callbacks WHERE correlation_id = ? ORDER BY received_at, kind",
+        );
+        let rows = sqlx::query(&sql)
+            .bind(correlation_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(CallbackRecord::from_row)
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Rewrites `?` placeholders to `$1, $2, ..` for Postgres
+    fn sql<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        if !self.numbered_placeholders {
+            return Cow::Borrowed(query);
+        }
+
+        let mut sql = String::with_capacity(query.len() + 8);
+        let mut index = 0;
+        for c in query.chars() {
+            if c == '?' {
+                index += 1;
+                sql.push('$');
+                sql.push_str(&index.to_string());
+            } else {
+                sql.push(c);
+            }
+        }
+        Cow::Owned(sql)
+    }
+}
+
+#[cfg(feature = "b2c")]
+impl crate::payout::PayoutStore for SqlxStore {
+    async fn save_pending(
+        &self,
+        originator_conversation_id: &str,
+        payout: &crate::payout::Payout,
+    ) -> MpesaResult<()> {
+        let sql = self.sql(
+            "INSERT INTO mpesa_payouts (originator_conversation_id, payout, created_at) \
+             VALUES (?, ?, ?)",
+        );
+        sqlx::query(&sql)
+            .bind(originator_conversation_id)
+            .bind(serde_json::to_string(payout)?)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn pending(
+        &self,
+        originator_conversation_id: &str,
+    ) -> MpesaResult<Option<crate::payout::Payout>> {
+        let sql = self.sql("SELECT payout FROM mpesa_payouts WHERE originator_conversation_id = ?");
+        let Some(row) = sqlx::query(&sql)
+            .bind(originator_conversation_id)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let payout: String = row.try_get("payout")?;
+        Ok(Some(serde_json::from_str(&payout)?))
+    }
+
+    async fn remove_pending(&self, originator_conversation_id: &str) -> MpesaResult<()> {
+        let sql = self.sql("DELETE FROM mpesa_payouts WHERE originator_conversation_id = ?");
+        sqlx::query(&sql)
+            .bind(originator_conversation_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn save_decision(&self, decision: &crate::payout::PayoutDecision) -> MpesaResult<()> {
+        use crate::payout::PayoutAction;
+
+        let detail = match &decision.action {
+            PayoutAction::Reroute(detail) | PayoutAction::Park(detail) => Some(detail.clone()),
+            _ => None,
+        };
+        let sql = self.sql(
+            "INSERT INTO mpesa_payout_decisions (conversation_id, action, detail, result_code, \
+             result_desc, next_conversation_id, payout, decided_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        );
+        sqlx::query(&sql)
+            .bind(&decision.conversation_id)
+            .bind(decision.action.name())
+            .bind(detail)
+            .bind(&decision.result_code)
+            .bind(&decision.result_desc)
+            .bind(decision.next_conversation_id.clone())
+            .bind(serde_json::to_string(&decision.payout)?)
+            .bind(decision.decided_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "client")]
+impl crate::idempotency::IdempotencyStore for SqlxStore {
+    async fn get(&self, key: &str) -> MpesaResult<Option<serde_json::Value>> {
+        let sql = self.sql(
+            "SELECT response FROM mpesa_idempotency_keys \
+             WHERE idempotency_key = ? AND response IS NOT NULL",
+        );
+        let Some(row) = sqlx::query(&sql)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let response: String = row.try_get("response")?;
+        Ok(Some(serde_json::from_str(&response)?))
+    }
+
+    async fn reserve(&self, key: &str) -> MpesaResult<bool> {
+        let sql = self
+            .sql("INSERT INTO mpesa_idempotency_keys (idempotency_key, created_at) VALUES (?, ?)");
+        inserted(
+            sqlx::query(&sql)
+                .bind(key)
+                .bind(now())
+                .execute(&self.pool)
+                .await,
+        )
+    }
+
+    async fn save(&self, key: &str, response: &serde_json::Value) -> MpesaResult<()> {
+        let sql =
+            self.sql("UPDATE mpesa_idempotency_keys SET response = ? WHERE idempotency_key = ?");
+        sqlx::query(&sql)
+            .bind(response.to_string())
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> MpesaResult<()> {
+        let sql = self.sql(
+            "DELETE FROM mpesa_idempotency_keys WHERE idempotency_key = ? AND response IS NULL",
+        );
+        sqlx::query(&sql).bind(key).execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+/// Maps unique constraint violations to `Ok(false)`
+fn inserted(result: Result<sqlx::any::AnyQueryResult, sqlx::Error>) -> MpesaResult<bool> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(false),
+        Err(e) => Err(MpesaError::DatabaseError(e)),
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sqlx::any::AnyPoolOptions;
+
+    use super::*;
+
+    async fn store() -> SqlxStore {
+        sqlx::any::install_default_drivers();
+        // Every connection to `sqlite::memory:` opens a new database
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let store = SqlxStore::new(pool);
+        store.migrate().await.unwrap();
+        store.migrate().await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_stored_once() {
+        let store = store().await;
+        let record = RequestRecord::new(
+            "b2c",
+            "AG_20191219_00004e48cf7e3533f581",
+            Some("10571-7910404-1".to_owned()),
+            Some("0".to_owned()),
+            "Accept the service request successfully.",
+        );
+
+        assert!(store.insert_request(&record).await.unwrap());
+        assert!(!store.insert_request(&record).await.unwrap());
+        assert_eq!(
+            store
+                .find_request("AG_20191219_00004e48cf7e3533f581")
+                .await
+                .unwrap(),
+            Some(record)
+        );
+        assert_eq!(store.find_request("unknown").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_callbacks_are_stored_once() {
+        let store = store().await;
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581"
+            }
+        })
+        .to_string();
+        let result = ResultCallback::from_json(body.as_bytes()).unwrap();
+        let record = CallbackRecord::result(&result).with_payload(body);
+
+        assert!(store.insert_callback(&record).await.unwrap());
+        assert!(!store.insert_callback(&record).await.unwrap());
+        assert!(store
+            .insert_callback(&CallbackRecord::timeout(&result))
+            .await
+            .unwrap());
+
+        let callbacks = store
+            .find_callbacks("AG_20191219_00004e48cf7e3533f581")
+            .await
+            .unwrap();
+        assert_eq!(callbacks.len(), 2);
+        assert_eq!(callbacks[0], record);
+        assert_eq!(callbacks[1].kind, "timeout");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "b2c")]
+    async fn test_payouts_are_kept_until_removed() {
+        use crate::payout::{Payout, PayoutStore};
+
+        let store = store().await;
+        let payout = Payout::new("testapi496", "600496", "254708374149", 1000).remarks("Salary");
+
+        store
+            .save_pending("29464-48063588-1", &payout)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.pending("29464-48063588-1").await.unwrap(),
+            Some(payout)
+        );
+        store.remove_pending("29464-48063588-1").await.unwrap();
+        assert_eq!(store.pending("29464-48063588-1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "client")]
+    async fn test_idempotency_keys_are_reserved_once() {
+        use crate::idempotency::IdempotencyStore;
+
+        let store = store().await;
+        let response = json!({ "ConversationID": "AG_20191219_00004e48cf7e3533f581" });
+
+        assert!(store.reserve("order-1029").await.unwrap());
+        assert!(!store.reserve("order-1029").await.unwrap());
+        assert_eq!(store.get("order-1029").await.unwrap(), None);
+
+        store.release("order-1029").await.unwrap();
+        assert!(store.reserve("order-1029").await.unwrap());
+        store.save("order-1029", &response).await.unwrap();
+        store.release("order-1029").await.unwrap();
+        assert_eq!(store.get("order-1029").await.unwrap(), Some(response));
+        assert!(!store.reserve("order-1029").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_placeholders_are_numbered_for_postgres() {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .connect_lazy("postgres://localhost/mpesa")
+            .unwrap();
+        let store = SqlxStore::new(pool);
+        assert_eq!(
+            store.sql("SELECT * FROM t WHERE a = ? AND b = ?"),
+            "SELECT * FROM t WHERE a = $1 AND b = $2"
+        );
+    }
+}
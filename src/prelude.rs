@@ -0,0 +1,24 @@
+//! Convenience re-exports for typical integrations
+//!
+//! ```rust
+//! use mpesa::prelude::*;
+//! ```
+//!
+//! Brings the client, environments, builders and response types of the enabled services,
+//! the callback payloads, the transaction enums and the error types into scope.
+
+pub use crate::callbacks::{
+    B2bResult, C2bRejection, C2bTransaction, C2bValidationResponse, Callback, LenientCallback,
+    MmfTransferResult, ResultCallback, StkCallback,
+};
+#[cfg(feature = "bill_manager")]
+pub use crate::constants::{Invoice, InvoiceItem};
+#[allow(unused_imports)]
+pub use crate::services::*;
+pub use crate::{
+    ApiEnvironment, BuilderError, CommandId, Environment, ExpressResultCode, IdentifierTypes,
+    MpesaError, MpesaResult, ResponseError, ResponseType, SendRemindersTypes, TransactionType,
+    ValidationErrors,
+};
+#[cfg(feature = "client")]
+pub use crate::{CredentialSelection, IdStrategy, Mpesa, MpesaBuilder};
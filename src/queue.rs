@@ -0,0 +1,194 @@
+//! Requests queued while the Safaricom API is down
+//!
+//! Registering C2B urls or sending Bill Manager invoices moves no money, so when Daraja cannot be
+//! reached these requests can wait for it to come back rather than fail. With a queue set with
+//! `MpesaBuilder::degradation_queue`, the `send_or_queue` method of their builders returns
+//! [`Delivery::Queued`] instead of an error when the request fails during a maintenance window, or
+//! with a connection error once the retries of the client are exhausted. Callers can then tell a
+//! request accepted locally from one rejected by the API.
+//!
+//! Only requests that never reached Daraja are queued. A request that timed out may have been
+//! processed with only its response lost, so it fails rather than risk being sent twice.
+//!
+//! Queued requests are kept in a `storage::Storage`, so that they survive restarts with a
+//! persistent storage, and are sent again in the order they were queued by `Mpesa::flush_queue`.
+//! Payments are never queued: a payment sent hours after the customer asked for it is worse than
+//! one that failed.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{namespaces, DynStorage};
+use crate::{MpesaError, MpesaResult, Service};
+
+/// Key of the queue in the `namespaces::QUEUE` namespace
+const KEY: &str = "requests";
+
+/// Attempts to update the queue before giving up, when other clients keep updating it
+const MAX_ATTEMPTS: usize = 16;
+
+/// Outcome of a request sent with `send_or_queue`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery<T> {
+    /// The request was sent and accepted by the Safaricom API
+    Sent(T),
+    /// The Safaricom API could not be reached and the request was queued, to be sent by
+    /// `Mpesa::flush_queue`
+    Queued(QueuedRequest),
+}
+
+impl<T> Delivery<T> {
+    pub fn is_queued(&self) -> bool {
+        matches!(self, Delivery::Queued(_))
+    }
+
+    /// The response of the Safaricom API, `None` if the request was queued
+    pub fn sent(self) -> Option<T> {
+        match self {
+            Delivery::Sent(response) => Some(response),
+            Delivery::Queued(_) => None,
+        }
+    }
+}
+
+/// A `POST` request waiting for the Safaricom API to come back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct QueuedRequest {
+    /// Identifier generated by the client for the request, in the format of the
+    /// `MpesaBuilder::id_strategy`
+    pub id: String,
+    pub service: Service,
+    /// Path of the endpoint, at the API version the client was built with
+    pub path: String,
+    pub body: serde_json::Value,
+    /// When the request was queued, in Unix seconds
+    pub queued_at: i64,
+}
+
+impl QueuedRequest {
+    pub(crate) fn new(id: String, service: Service, path: String, body: serde_json::Value) -> Self {
+        QueuedRequest {
+            id,
+            service,
+            path,
+            body,
+            queued_at: now(),
+        }
+    }
+}
+
+/// A queued request sent by `Mpesa::flush_queue`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Replayed {
+    pub request: QueuedRequest,
+    /// The response of the Safaricom API, or the error it rejected the request with
+    pub result: MpesaResult<serde_json::Value>,
+}
+
+/// The queue of a client, kept in a storage as a JSON array of `QueuedRequest`
+#[derive(Debug)]
+pub(crate) struct Queue {
+    storage: Arc<dyn DynStorage>,
+}
+
+impl Queue {
+    pub(crate) fn new(storage: Arc<dyn DynStorage>) -> Self {
+        Queue { storage }
+    }
+
+    /// The queued requests, oldest first
+    pub(crate) async fn requests(&self) -> MpesaResult<Vec<QueuedRequest>> {
+        Ok(self.load().await?.1)
+    }
+
+    /// Adds `request` at the end of the queue
+    pub(crate) async fn push(&self, request: &QueuedRequest) -> MpesaResult<()> {
+        self.update(|requests| requests.push(request.clone())).await
+    }
+
+    /// Removes the request with `id` from the queue
+    pub(crate) async fn remove(&self, id: &str) -> MpesaResult<()> {
+        self.update(|requests| requests.retain(|request| request.id != id))
+            .await
+    }
+
+    async fn load(&self) -> MpesaResult<(Option<Vec<u8>>, Vec<QueuedRequest>)> {
+        let value = self.storage.get(namespaces::QUEUE, KEY).await?;
+        let requests = match &value {
+            Some(value) => serde_json::from_slice(value)?,
+            None => vec![],
+        };
+        Ok((value, requests))
+    }
+
+    /// Applies `change` to the queue, starting over if another client changed it meanwhile
+    async fn update(&self, change: impl Fn(&mut Vec<QueuedRequest>)) -> MpesaResult<()> {
+        for _ in 0..MAX_ATTEMPTS {
+            let (current, mut requests) = self.load().await?;
+            change(&mut requests);
+            let new = serde_json::to_vec(&requests)?;
+            let swapped = self
+                .storage
+                .compare_and_swap(namespaces::QUEUE, KEY, current.as_deref(), Some(&new), None)
+                .await?;
+            if swapped {
+                return Ok(());
+            }
+        }
+        Err(MpesaError::Message(
+            "The queue was changed by other clients too many times to be updated",
+        ))
+    }
+}
+
+/// Returns `true` if a request failed with `error` without reaching the Safaricom API, so that
+/// sending it again cannot process it twice
+pub(crate) fn never_reached(error: &MpesaError) -> bool {
+    match error {
+        MpesaError::Maintenance(_) => true,
+        MpesaError::NetworkError(error) => error.is_connect(),
+        _ => false,
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_queue_keeps_requests_in_order() {
+        let queue = Queue::new(Arc::new(MemoryStorage::default()));
+        assert!(queue.requests().await.unwrap().is_empty());
+
+        for id in ["1", "2", "3"] {
+            let request = QueuedRequest::new(
+                id.to_owned(),
+                Service::C2bRegister,
+                "mpesa/c2b/v1/registerurl".to_owned(),
+                serde_json::json!({ "ShortCode": "600496" }),
+            );
+            queue.push(&request).await.unwrap();
+        }
+        queue.remove("2").await.unwrap();
+
+        let ids: Vec<_> = queue
+            .requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|request| request.id)
+            .collect();
+        assert_eq!(ids, ["1", "3"]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// Fields holding the shortcode a request is made for, in order of precedence.
+/// `PartyA` is the customer's phone number in M-Pesa Express requests, which also carry a
+/// `BusinessShortCode`, and the organization's shortcode in the other requests.
+const SHORTCODE_FIELDS: [&str; 5] = [
+    "BusinessShortCode",
+    "ShortCode",
+    "shortCode",
+    "PartyA",
+    "ReceiverParty",
+];
+
+/// Number of request times a window allocates room for up front. The quota is configured by the
+/// user, so larger windows grow as requests are actually sent.
+const MAX_PREALLOCATED_REQUESTS: u32 = 64;
+
+/// Maximum number of requests allowed within a sliding period of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    requests: u32,
+    period: Duration,
+}
+
+impl Quota {
+    /// Allows `requests` requests within any window of `period`
+    pub fn new(requests: u32, period: Duration) -> Self {
+        Quota { requests, period }
+    }
+
+    /// Allows `requests` requests within any window of one second
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Allows `requests` requests within any window of one minute
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+}
+
+/// Times of the requests sent within the period of a quota
+#[derive(Debug)]
+struct Window {
+    quota: Quota,
+    sent: VecDeque<Instant>,
+}
+
+impl Window {
+    fn new(quota: Quota) -> Self {
+        Window {
+            quota,
+            sent: VecDeque::with_capacity(quota.requests.min(MAX_PREALLOCATED_REQUESTS) as usize),
+        }
+    }
+
+    /// Returns how long to wait before a request is allowed, `None` if it is allowed now
+    fn retry_after(&mut self, now: Instant) -> Option<Duration> {
+        while let Some(&sent) = self.sent.front() {
+            if now.duration_since(sent) < self.quota.period {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        if self.sent.len() < self.quota.requests as usize {
+            return None;
+        }
+        let oldest = self.sent.front().copied().unwrap_or(now);
+        Some(self.quota.period.saturating_sub(now.duration_since(oldest)))
+    }
+}
+
+/// Enforces the global quota and the quotas of each shortcode
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    global: Option<Mutex<Window>>,
+    shortcodes: HashMap<String, Mutex<Window>>,
+}
+
+impl Quotas {
+    pub(crate) fn new(global: Option<Quota>, shortcodes: HashMap<String, Quota>) -> Self {
+        Quotas {
+            global: global.map(|quota| Mutex::new(Window::new(quota))),
+            shortcodes: shortcodes
+                .into_iter()
+                .map(|(shortcode, quota)| (shortcode, Mutex::new(Window::new(quota))))
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if the shortcode of requests has to be looked up
+    pub(crate) fn by_shortcode(&self) -> bool {
+        !self.shortcodes.is_empty()
+    }
+
+    /// Records a request made for `shortcode` if every quota it is subject to allows it,
+    /// otherwise returns how long to wait before retrying
+    pub(crate) fn acquire(&self, shortcode: Option<&str>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut windows = self.global.iter().collect::<Vec<_>>();
+        windows.extend(shortcode.and_then(|shortcode| self.shortcodes.get(shortcode)));
+
+        // Locked in the same order by every request: the global window, then the shortcode's
+        let mut windows = windows
+            .into_iter()
+            .map(|window| window.lock().unwrap_or_else(|e| e.into_inner()))
+            .collect::<Vec<_>>();
+        if let Some(retry_after) = windows
+            .iter_mut()
+            .filter_map(|window| window.retry_after(now))
+            .max()
+        {
+            return Err(retry_after);
+        }
+        for window in &mut windows {
+            window.sent.push_back(now);
+        }
+        Ok(())
+    }
+}
+
+/// Looks up the shortcode a request body is made for
+pub(crate) fn shortcode(body: &Value) -> Option<String> {
+    SHORTCODE_FIELDS
+        .iter()
+        .find_map(|field| body.get(*field))
+        .and_then(|value| match value {
+            Value::String(value) => Some(value.clone()),
+            Value::Number(value) => Some(value.to_string()),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_window_allows_requests_within_the_quota() {
+        let mut window = Window::new(Quota::per_second(2));
+        let now = Instant::now();
+        assert_eq!(window.retry_after(now), None);
+        window.sent.push_back(now);
+        assert_eq!(window.retry_after(now), None);
+        window.sent.push_back(now);
+
+        let later = now + Duration::from_millis(400);
+        assert_eq!(window.retry_after(later), Some(Duration::from_millis(600)));
+        assert_eq!(window.retry_after(now + Duration::from_secs(1)), None);
+        assert!(window.sent.is_empty());
+    }
+
+    #[test]
+    fn test_large_quotas_are_not_allocated_up_front() {
+        let mut window = Window::new(Quota::per_minute(u32::MAX));
+        assert!(window.sent.capacity() < 1024);
+        assert_eq!(window.retry_after(Instant::now()), None);
+    }
+
+    #[test]
+    fn test_shortcode_quotas_apply_on_top_of_the_global_quota() {
+        let quotas = Quotas::new(
+            Some(Quota::per_minute(3)),
+            HashMap::from([("174379".to_owned(), Quota::per_minute(1))]),
+        );
+
+        assert!(quotas.acquire(Some("174379")).is_ok());
+        assert!(quotas.acquire(Some("174379")).is_err());
+        assert!(quotas.acquire(Some("600000")).is_ok());
+        assert!(quotas.acquire(None).is_ok());
+        assert!(quotas.acquire(None).is_err());
+    }
+
+    #[test]
+    fn test_shortcode_is_read_from_the_request_body() {
+        let express = json!({ "BusinessShortCode": "174379", "PartyA": "254708374149" });
+        assert_eq!(shortcode(&express).as_deref(), Some("174379"));
+        assert_eq!(
+            shortcode(&json!({ "PartyA": 600496 })).as_deref(),
+            Some("600496")
+        );
+        assert_eq!(shortcode(&json!([])), None);
+    }
+}
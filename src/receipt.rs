@@ -0,0 +1,281 @@
+//! Customer receipts built from the callbacks of successful transactions
+//!
+//! A [`Receipt`] holds what a customer is told about a transaction: its receipt number, amount
+//! and date. [`Receipt::text`] words it the way M-Pesa confirmation SMS do, for apps notifying
+//! customers through their own channels, such as email or push notifications.
+//!
+//! ```rust
+//! use mpesa::callbacks::StkCallback;
+//! use mpesa::receipt::Receipt;
+//!
+//! let body = br#"{"Body": {"stkCallback": {
+//!     "MerchantRequestID": "29115-34620561-1",
+//!     "CheckoutRequestID": "ws_CO_191220191020363925",
+//!     "ResultCode": 0,
+//!     "ResultDesc": "The service request is processed successfully.",
+//!     "CallbackMetadata": {"Item": [
+//!         {"Name": "Amount", "Value": 1250.00},
+//!         {"Name": "MpesaReceiptNumber", "Value": "NLJ7RT61SV"},
+//!         {"Name": "TransactionDate", "Value": 20191219142115},
+//!         {"Name": "PhoneNumber", "Value": 254708374149}
+//!     ]}
+//! }}}"#;
+//! let callback = StkCallback::from_json(body).unwrap();
+//! let receipt = Receipt::from_stk(&callback).unwrap();
+//! assert_eq!(
+//!     receipt.text("ACME LTD"),
+//!     "NLJ7RT61SV Confirmed. Ksh1,250.00 paid to ACME LTD on 19/12/19 at 2:21 PM."
+//! );
+//! ```
+
+use crate::callbacks::{value_to_string, C2bTransaction, ResultCallback, StkCallback};
+use crate::format::format_amount;
+
+/// Direction of the money a receipt is given for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ReceiptKind {
+    /// The customer paid the business, with M-Pesa Express or C2B
+    Payment,
+    /// The business paid the customer, with B2C
+    Disbursement,
+}
+
+/// Details of a successful transaction to tell the customer about
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Receipt {
+    pub kind: ReceiptKind,
+    /// M-Pesa receipt number, e.g. `NLJ7RT61SV`
+    pub receipt_number: String,
+    pub amount: f64,
+    /// Time of the transaction as worded in M-Pesa messages, e.g. `19/12/19 at 2:21 PM`
+    pub date: Option<String>,
+    /// Phone number or name of the customer
+    pub customer: Option<String>,
+    /// Account number the customer paid to, for C2B payments to a paybill
+    pub account: Option<String>,
+    /// Balance of the account of the business after the transaction, for C2B payments and B2C.
+    /// It is not part of the `text` sent to the customer.
+    pub balance: Option<f64>,
+}
+
+impl Receipt {
+    /// Builds the receipt of a completed M-Pesa Express payment, `None` if the payment failed
+    pub fn from_stk(callback: &StkCallback) -> Option<Self> {
+        if !callback.is_paid() {
+            return None;
+        }
+        Some(Receipt {
+            kind: ReceiptKind::Payment,
+            receipt_number: callback.mpesa_receipt_number()?.to_owned(),
+            amount: callback.amount()?,
+            date: callback.transaction_date().as_deref().and_then(sms_date),
+            customer: callback.phone_number(),
+            account: None,
+            balance: None,
+        })
+    }
+
+    /// Builds the receipt of a C2B payment, as posted to the `ConfirmationURL`. Returns `None` if
+    /// its amount cannot be read.
+    pub fn from_c2b(transaction: &C2bTransaction) -> Option<Self> {
+        let name = transaction.full_name();
+        Some(Receipt {
+            kind: ReceiptKind::Payment,
+            receipt_number: transaction.trans_id.clone(),
+            amount: transaction.trans_amount.parse().ok()?,
+            date: sms_date(&transaction.trans_time),
+            customer: non_empty(name).or_else(|| non_empty(transaction.msisdn.clone())),
+            account: non_empty(transaction.bill_ref_number.clone()),
+            balance: transaction.org_account_balance.parse().ok(),
+        })
+    }
+
+    /// Builds the receipt of a B2C payment from its result, `None` if the payment failed
+    pub fn from_b2c(result: &ResultCallback) -> Option<Self> {
+        if !result.is_success() {
+            return None;
+        }
+        let string = |key| result.parameter(key).map(value_to_string);
+        let number = |key| string(key)?.parse().ok();
+        Some(Receipt {
+            kind: ReceiptKind::Disbursement,
+            receipt_number: string("TransactionReceipt")
+                .or_else(|| result.transaction_id.clone())?,
+            amount: number("TransactionAmount")?,
+            date: string("TransactionCompletedDateTime")
+                .as_deref()
+                .and_then(sms_date),
+            customer: string("ReceiverPartyPublicName"),
+            account: None,
+            balance: number("B2CUtilityAccountAvailableFunds"),
+        })
+    }
+
+    /// Words the receipt like an M-Pesa confirmation SMS, e.g.
+    /// `NLJ7RT61SV Confirmed. Ksh1,250.00 paid to ACME LTD on 19/12/19 at 2:21 PM.`
+    /// for a payment, with `business_name` as the party the customer dealt with
+    pub fn text(&self, business_name: &str) -> String {
+        let amount = format!("Ksh{}", format_amount(self.amount));
+        let mut text = match (self.kind, &self.account) {
+            (ReceiptKind::Payment, Some(account)) => format!(
+                "{} Confirmed. {amount} sent to {business_name} for account {account}",
+                self.receipt_number
+            ),
+            (ReceiptKind::Payment, None) => format!(
+                "{} Confirmed. {amount} paid to {business_name}",
+                self.receipt_number
+            ),
+            (ReceiptKind::Disbursement, _) => format!(
+                "{} Confirmed. You have received {amount} from {business_name}",
+                self.receipt_number
+            ),
+        };
+        if let Some(date) = &self.date {
+            text.push_str(" on ");
+            text.push_str(date);
+        }
+        text.push('.');
+        text
+    }
+}
+
+/// `s`, or `None` if it is empty
+pub(crate) fn non_empty(s: String) -> Option<String> {
+    (!s.is_empty()).then_some(s)
+}
+
+/// Rewords a transaction time, either `YYYYMMDDHHmmss` or `DD.MM.YYYY HH:mm:ss` as in B2C
+/// results, the way M-Pesa messages show it, e.g. `19/12/19 at 2:21 PM`
+fn sms_date(time: &str) -> Option<String> {
+    let (year, month, day, hour, minute, _) = transaction_time(time)?;
+    let meridiem = if hour < 12 { "AM" } else { "PM" };
+    let hour = match hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    Some(format!(
+        "{day}/{month}/{:02} at {hour}:{minute:02} {meridiem}",
+        year % 100
+    ))
+}
+
+/// Reads the year, month, day, hour, minute and second of a transaction time, either
+/// `YYYYMMDDHHmmss` or `DD.MM.YYYY HH:mm:ss` as in B2C results
+pub(crate) fn transaction_time(time: &str) -> Option<(u32, u32, u32, u32, u32, u32)> {
+    let number = |s: &str| s.parse::<u32>().ok();
+    let (year, month, day, hour, minute, second) = match time.split_once(' ') {
+        Some((date, time)) => {
+            let mut date = date.split('.');
+            let mut time = time.split(':');
+            let day = number(date.next()?)?;
+            let month = number(date.next()?)?;
+            let year = number(date.next()?)?;
+            (
+                year,
+                month,
+                day,
+                number(time.next()?)?,
+                number(time.next()?)?,
+                time.next().map_or(Some(0), number)?,
+            )
+        }
+        None if time.len() == 14 && time.bytes().all(|b| b.is_ascii_digit()) => (
+            number(&time[..4])?,
+            number(&time[4..6])?,
+            number(&time[6..8])?,
+            number(&time[8..10])?,
+            number(&time[10..12])?,
+            number(&time[12..14])?,
+        ),
+        None => return None,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+    Some((year, month, day, hour, minute, second))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_transaction_times_are_worded_like_sms() {
+        assert_eq!(
+            sms_date("20191219142115").as_deref(),
+            Some("19/12/19 at 2:21 PM")
+        );
+        assert_eq!(
+            sms_date("19.12.2019 00:05:50").as_deref(),
+            Some("19/12/19 at 12:05 AM")
+        );
+        assert_eq!(sms_date("2019121914"), None);
+        assert_eq!(sms_date("20191319142115"), None);
+    }
+
+    #[test]
+    fn test_b2c_result_receipt() {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "TransactionID": "NLJ41HAY6Q",
+                "ResultParameters": {
+                    "ResultParameter": [
+                        { "Key": "TransactionAmount", "Value": 10 },
+                        { "Key": "TransactionReceipt", "Value": "NLJ41HAY6Q" },
+                        { "Key": "ReceiverPartyPublicName", "Value": "254708374149 - John Doe" },
+                        { "Key": "TransactionCompletedDateTime", "Value": "19.12.2019 11:45:50" },
+                        { "Key": "B2CUtilityAccountAvailableFunds", "Value": 10116.00 }
+                    ]
+                }
+            }
+        });
+        let result = ResultCallback::from_json(body.to_string().as_bytes()).unwrap();
+        let receipt = Receipt::from_b2c(&result).unwrap();
+
+        assert_eq!(receipt.kind, ReceiptKind::Disbursement);
+        assert_eq!(receipt.customer.as_deref(), Some("254708374149 - John Doe"));
+        assert_eq!(receipt.balance, Some(10116.0));
+        assert_eq!(
+            receipt.text("ACME LTD"),
+            "NLJ41HAY6Q Confirmed. You have received Ksh10.00 from ACME LTD on 19/12/19 at 11:45 AM."
+        );
+    }
+
+    #[test]
+    fn test_c2b_transaction_receipt() {
+        let transaction: C2bTransaction = serde_json::from_value(json!({
+            "TransactionType": "Pay Bill",
+            "TransID": "RKTQDM7W6S",
+            "TransTime": "20191122063845",
+            "TransAmount": "1500",
+            "BusinessShortCode": "600638",
+            "BillRefNumber": "INV-1029",
+            "OrgAccountBalance": "49197.00",
+            "MSISDN": "254708374149",
+            "FirstName": "John"
+        }))
+        .unwrap();
+        let receipt = Receipt::from_c2b(&transaction).unwrap();
+
+        assert_eq!(receipt.customer.as_deref(), Some("John"));
+        assert_eq!(receipt.balance, Some(49197.0));
+        assert_eq!(
+            receipt.text("ACME LTD"),
+            "RKTQDM7W6S Confirmed. Ksh1,500.00 sent to ACME LTD for account INV-1029 on 22/11/19 at 6:38 AM."
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Daily and weekly summaries of transactions, for operations dashboards
+//!
+//! A [`ReportEntry`] is a transaction as known to the application: the time it was made, its
+//! result code once its callback arrived, and its amount. Entries are built from callbacks, or
+//! from the records of `persistence::SqlxStore` with the `sqlx` feature. A [`Reporter`] groups
+//! them by day or week and by kind into [`Summary`] rows with counts, sums, failures by
+//! `ResultCode` and the average time callbacks took to arrive, which [`to_csv`] renders for a
+//! spreadsheet or a dashboard import.
+//!
+//! Days start at midnight UTC unless the reporter is given the offset of the local time, such as
+//! `Reporter::daily().utc_offset(3 * 3600)` for East Africa Time.
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use mpesa::reports::{to_csv, ReportEntry, Reporter};
+//!
+//! // 2024-03-01 09:00 UTC
+//! let at = 1_709_283_600;
+//! let entries = [
+//!     ReportEntry::new("express_request", "ws_CO_1", at)
+//!         .completed("0", Some(Duration::from_secs(12)))
+//!         .amount(1500),
+//!     ReportEntry::new("express_request", "ws_CO_2", at + 60).completed("1032", None),
+//!     ReportEntry::new("express_request", "ws_CO_3", at + 120),
+//! ];
+//! let summaries = Reporter::daily().summarize(&entries);
+//! assert_eq!(summaries[0].period, "2024-03-01");
+//! assert_eq!((summaries[0].succeeded, summaries[0].failed, summaries[0].pending), (1, 1, 1));
+//! assert_eq!(summaries[0].amount, 1500.0);
+//! assert!(to_csv(&summaries).lines().nth(1).unwrap().ends_with(",12000,1032:1"));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::callbacks::{C2bTransaction, StkCallback};
+use crate::ledger::LedgerEntry;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A transaction, as summarized by a `Reporter`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct ReportEntry {
+    /// Service the transaction was made with, e.g. `express_request`, `b2c` or `c2b`
+    pub kind: String,
+    /// `CheckoutRequestID`, `ConversationID` or `TransID` of the transaction
+    pub correlation_id: String,
+    /// Unix timestamp in seconds of the request, or of the payment for C2B transactions
+    pub at: i64,
+    /// `ResultCode` of the callback, `None` while it has not been received
+    pub result_code: Option<String>,
+    /// Time between the request and its callback
+    pub latency: Option<Duration>,
+    pub amount: Option<f64>,
+}
+
+impl ReportEntry {
+    /// Creates an entry for a transaction whose callback has not been received
+    pub fn new(kind: impl Into<String>, correlation_id: impl Into<String>, at: i64) -> Self {
+        ReportEntry {
+            kind: kind.into(),
+            correlation_id: correlation_id.into(),
+            at,
+            result_code: None,
+            latency: None,
+            amount: None,
+        }
+    }
+
+    /// Sets the `ResultCode` of the callback of the transaction and the time it took to arrive
+    pub fn completed(mut self, result_code: impl Into<String>, latency: Option<Duration>) -> Self {
+        self.result_code = Some(result_code.into());
+        self.latency = latency;
+        self
+    }
+
+    pub fn amount(mut self, amount: impl Into<f64>) -> Self {
+        self.amount = Some(amount.into());
+        self
+    }
+
+    /// Entry of an M-Pesa Express request made at `requested_at` whose callback was received at
+    /// `received_at`, both unix timestamps in seconds
+    pub fn from_stk(callback: &StkCallback, requested_at: i64, received_at: i64) -> Self {
+        let entry = ReportEntry::new(
+            "express_request",
+            &callback.checkout_request_id,
+            requested_at,
+        )
+        .completed(
+            callback.result_code.to_string(),
+            latency(requested_at, received_at),
+        );
+        match callback.amount() {
+            Some(amount) => entry.amount(amount),
+            None => entry,
+        }
+    }
+
+    /// Entry of a confirmed C2B payment received at `received_at`, a unix timestamp in seconds
+    pub fn from_c2b(transaction: &C2bTransaction, received_at: i64) -> Self {
+        let entry =
+            ReportEntry::new("c2b", &transaction.trans_id, received_at).completed("0", None);
+        match transaction.trans_amount.parse::<f64>() {
+            Ok(amount) => entry.amount(amount),
+            Err(_) => entry,
+        }
+    }
+
+    /// Entry of a ledger entry received at `received_at`, a unix timestamp in seconds, dated at
+    /// the time of its transaction when it has one
+    pub fn from_ledger(entry: &LedgerEntry, received_at: i64) -> Self {
+        let report = ReportEntry::new(
+            entry.kind.as_str(),
+            &entry.correlation_id,
+            entry.transacted_at.unwrap_or(received_at),
+        )
+        .completed(&entry.result_code, None);
+        match entry.amount {
+            Some(amount) => report.amount(amount),
+            None => report,
+        }
+    }
+
+    /// Entry of a stored request and, once received, its stored callback
+    #[cfg(feature = "sqlx")]
+    pub fn from_records(
+        request: &crate::persistence::RequestRecord,
+        callback: Option<&crate::persistence::CallbackRecord>,
+    ) -> Self {
+        let entry = ReportEntry::new(&request.kind, &request.correlation_id, request.created_at);
+        match callback {
+            Some(callback) => entry.completed(
+                &callback.result_code,
+                latency(request.created_at, callback.received_at),
+            ),
+            None => entry,
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        self.result_code.as_deref() == Some("0")
+    }
+}
+
+fn latency(requested_at: i64, received_at: i64) -> Option<Duration> {
+    u64::try_from(received_at - requested_at)
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Length of the periods of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Window {
+    Daily,
+    /// Weeks starting on Monday
+    Weekly,
+}
+
+/// Summary of the transactions of a kind over a period
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Summary {
+    /// First day of the period, `YYYY-MM-DD`
+    pub period: String,
+    /// Unix timestamp in seconds of the start of the period
+    pub start: i64,
+    pub kind: String,
+    pub count: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Transactions whose callback has not been received
+    pub pending: usize,
+    /// Sum of the amounts of the successful transactions
+    pub amount: f64,
+    /// Number of failed transactions by `ResultCode`
+    pub failures: BTreeMap<String, usize>,
+    /// Average time callbacks took to arrive, over the transactions whose latency is known
+    pub average_latency: Option<Duration>,
+}
+
+/// Groups transactions into summaries by period and kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reporter {
+    window: Window,
+    utc_offset: i64,
+}
+
+impl Reporter {
+    pub fn new(window: Window) -> Self {
+        Reporter {
+            window,
+            utc_offset: 0,
+        }
+    }
+
+    pub fn daily() -> Self {
+        Reporter::new(Window::Daily)
+    }
+
+    pub fn weekly() -> Self {
+        Reporter::new(Window::Weekly)
+    }
+
+    /// Starts days at local midnight, `utc_offset` seconds ahead of UTC
+    pub fn utc_offset(mut self, utc_offset: i32) -> Self {
+        self.utc_offset = utc_offset.into();
+        self
+    }
+
+    /// Returns the unix timestamp of the start of the period containing `at`
+    fn period_start(&self, at: i64) -> i64 {
+        let day = (at + self.utc_offset).div_euclid(SECS_PER_DAY);
+        let first_day = match self.window {
+            Window::Daily => day,
+            // 1970-01-01 was a Thursday, 3 days after the Monday starting its week
+            Window::Weekly => day - (day + 3).rem_euclid(7),
+        };
+        first_day * SECS_PER_DAY - self.utc_offset
+    }
+
+    /// Summarizes `entries` by period and kind, ordered by period then kind
+    pub fn summarize<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a ReportEntry>,
+    ) -> Vec<Summary> {
+        let mut summaries = BTreeMap::new();
+        let mut latencies: BTreeMap<(i64, String), (Duration, u32)> = BTreeMap::new();
+        for entry in entries {
+            let start = self.period_start(entry.at);
+            let key = (start, entry.kind.clone());
+            let summary = summaries.entry(key.clone()).or_insert_with(|| Summary {
+                period: date((start + self.utc_offset).div_euclid(SECS_PER_DAY)),
+                start,
+                kind: entry.kind.clone(),
+                count: 0,
+                succeeded: 0,
+                failed: 0,
+                pending: 0,
+                amount: 0.0,
+                failures: BTreeMap::new(),
+                average_latency: None,
+            });
+            summary.count += 1;
+            match &entry.result_code {
+                None => summary.pending += 1,
+                Some(_) if entry.is_success() => {
+                    summary.succeeded += 1;
+                    summary.amount += entry.amount.unwrap_or_default();
+                }
+                Some(result_code) => {
+                    summary.failed += 1;
+                    *summary.failures.entry(result_code.clone()).or_default() += 1;
+                }
+            }
+            if let Some(latency) = entry.latency {
+                let (total, count) = latencies.entry(key).or_default();
+                *total += latency;
+                *count += 1;
+            }
+        }
+
+        for (key, (total, count)) in latencies {
+            if let Some(summary) = summaries.get_mut(&key) {
+                summary.average_latency = Some(total / count);
+            }
+        }
+        summaries.into_values().collect()
+    }
+}
+
+/// Renders `summaries` as CSV with a header row. Failures are listed as `code:count` pairs
+/// separated by `;`, and latencies in milliseconds.
+pub fn to_csv(summaries: &[Summary]) -> String {
+    let mut csv = String::from(
+        "period,kind,count,succeeded,failed,pending,amount,average_latency_ms,failures\n",
+    );
+    for summary in summaries {
+        let failures = summary
+            .failures
+            .iter()
+            .map(|(result_code, count)| format!("{result_code}:{count}"))
+            .collect::<Vec<_>>()
+            .join(";");
+        let average_latency = summary
+            .average_latency
+            .map(|latency| latency.as_millis().to_string())
+            .unwrap_or_default();
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{:.2},{},{}",
+            summary.period,
+            csv_field(&summary.kind),
+            summary.count,
+            summary.succeeded,
+            summary.failed,
+            summary.pending,
+            summary.amount,
+            average_latency,
+            csv_field(&failures),
+        )
+        .expect("writing to a String does not fail");
+    }
+    csv
+}
+
+/// Quotes `field` if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Formats the day `days` after 1970-01-01 as `YYYY-MM-DD`, in the proleptic Gregorian calendar
+fn date(days: i64) -> String {
+    // Howard Hinnant's `civil_from_days`, counting in 400 year eras starting on March 1st
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(19_782), "2024-02-29");
+        assert_eq!(date(-1), "1969-12-31");
+    }
+
+    #[test]
+    fn test_entries_are_grouped_by_local_period_and_kind() {
+        // Friday 2024-03-01 22:30 UTC, Saturday 01:30 in Nairobi
+        let at = 1_709_332_200;
+        let entries = [
+            ReportEntry::new("b2c", "AG_1", at)
+                .completed("0", Some(Duration::from_secs(4)))
+                .amount(100),
+            ReportEntry::new("b2c", "AG_2", at)
+                .completed("0", Some(Duration::from_secs(8)))
+                .amount(250.5),
+            ReportEntry::new("b2c", "AG_3", at).completed("2001", None),
+            ReportEntry::new("b2c", "AG_4", at).completed("2001", None),
+            ReportEntry::new("express_request", "ws_CO_1", at - 3 * SECS_PER_DAY),
+        ];
+
+        let summaries = Reporter::daily().summarize(&entries);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].period, "2024-02-27");
+        assert_eq!(summaries[1].period, "2024-03-01");
+        assert_eq!(summaries[1].count, 4);
+        assert_eq!(summaries[1].amount, 350.5);
+        assert_eq!(summaries[1].failures["2001"], 2);
+        assert_eq!(summaries[1].average_latency, Some(Duration::from_secs(6)));
+
+        let summaries = Reporter::daily().utc_offset(3 * 3600).summarize(&entries);
+        assert_eq!(summaries[1].period, "2024-03-02");
+        assert_eq!(summaries[1].start, at - 90 * 60);
+
+        // Both fall in the week starting on Monday 2024-02-26
+        let summaries = Reporter::weekly().summarize(&entries);
+        assert_eq!(summaries.len(), 2);
+        assert!(summaries
+            .iter()
+            .all(|summary| summary.period == "2024-02-26"));
+
+        let csv = to_csv(&summaries);
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "period,kind,count,succeeded,failed,pending,amount,average_latency_ms,failures",
+                "2024-02-26,b2c,4,2,2,0,350.50,6000,2001:2",
+                "2024-02-26,express_request,1,0,0,1,0.00,,",
+            ]
+        );
+        assert_eq!(csv_field("Pay, \"now\""), "\"Pay, \"\"now\"\"\"");
+    }
+}
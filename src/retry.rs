@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::constants::ServiceCategory;
+
+/// How many times a failed request is sent again, and how long to wait in between.
+///
+/// Requests are retried when the connection fails or times out, and when the Safaricom API
+/// responds with `429 Too Many Requests` or a `5xx` gateway error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Retries a failed request up to `max_retries` times, waiting `backoff` before the first
+    /// retry and doubling the delay after each attempt
+    pub fn new(max_retries: u32, backoff: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            backoff,
+        }
+    }
+
+    /// Never retries a failed request
+    pub fn none() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+
+    /// Returns how long to wait before sending the request again after `attempt` failed
+    /// attempts, `None` if it should not be retried
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        (attempt < self.max_retries).then(|| self.backoff.saturating_mul(1 << attempt.min(16)))
+    }
+}
+
+/// The retry policies of a client: token fetches are cheap and safe to retry, requests moving
+/// money or reaching customers are not retried unless configured otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicies {
+    pub(crate) auth: RetryPolicy,
+    pub(crate) query: RetryPolicy,
+    pub(crate) payment: RetryPolicy,
+    pub(crate) notification: RetryPolicy,
+}
+
+impl RetryPolicies {
+    #[cfg(feature = "__request")]
+    pub(crate) fn get(&self, category: ServiceCategory) -> RetryPolicy {
+        match category {
+            ServiceCategory::Query => self.query,
+            ServiceCategory::Payment => self.payment,
+            ServiceCategory::Notification => self.notification,
+        }
+    }
+
+    pub(crate) fn set(&mut self, category: ServiceCategory, policy: RetryPolicy) {
+        match category {
+            ServiceCategory::Query => self.query = policy,
+            ServiceCategory::Payment => self.payment = policy,
+            ServiceCategory::Notification => self.notification = policy,
+        }
+    }
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        RetryPolicies {
+            auth: RetryPolicy::new(3, Duration::from_millis(200)),
+            query: RetryPolicy::new(2, Duration::from_millis(500)),
+            payment: RetryPolicy::none(),
+            notification: RetryPolicy::none(),
+        }
+    }
+}
+
+/// Returns `true` if a request that failed with `error` may succeed when sent again
+pub(crate) fn is_transient_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+/// Returns `true` if a request answered with `status` may succeed when sent again
+pub(crate) fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Service;
+
+    #[test]
+    fn test_backoff_doubles_until_retries_run_out() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100));
+        assert_eq!(policy.delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay(1), Some(Duration::from_millis(200)));
+        assert_eq!(policy.delay(2), Some(Duration::from_millis(400)));
+        assert_eq!(policy.delay(3), None);
+        assert_eq!(RetryPolicy::none().delay(0), None);
+    }
+
+    #[test]
+    fn test_payments_are_not_retried_by_default() {
+        let policies = RetryPolicies::default();
+        assert_eq!(policies.get(ServiceCategory::Payment), RetryPolicy::none());
+        assert_eq!(
+            policies.get(Service::BillManager.category()),
+            RetryPolicy::none()
+        );
+        assert_ne!(policies.get(ServiceCategory::Query), RetryPolicy::none());
+        assert_ne!(policies.auth, RetryPolicy::none());
+    }
+}
@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+/// Sends a request at `at`, for instance a `chrono::DateTime<Local>`, by awaiting the `send`
+/// future of its builder once `at` has come. Sent right away if `at` is in the past.
+///
+/// The request is only built once it is sent, so a missing required field is reported when `at`
+/// has come. Calling `to_curl` on the builder beforehand reports it right away.
+///
+/// Scheduled requests live in memory and are lost if the process exits before they are sent.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::{Duration, SystemTime};
+///
+/// use mpesa::{Environment, Mpesa};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = Mpesa::new("consumer_key", "consumer_secret", Environment::Sandbox);
+///
+///     let request = client
+///         .c2b_register()
+///         .short_code("600496")
+///         .confirmation_url("https://example.com/confirmation")
+///         .validation_url("https://example.com/validation");
+///     let response = mpesa::send_at(SystemTime::now() + Duration::from_secs(60), request.send())
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub async fn send_at<F: Future>(at: impl Into<SystemTime>, send: F) -> F::Output {
+    send_after(delay_until(at.into()), send).await
+}
+
+/// Sends a request once `delay` has elapsed, by awaiting the `send` future of its builder then.
+/// See `send_at`.
+pub async fn send_after<F: Future>(delay: Duration, send: F) -> F::Output {
+    tokio::time::sleep(delay).await;
+    send.await
+}
+
+/// Time left until `at`, zero if it is in the past
+fn delay_until(at: SystemTime) -> Duration {
+    at.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_until() {
+        let past = SystemTime::now() - Duration::from_secs(60);
+        assert_eq!(delay_until(past), Duration::ZERO);
+        let delay = delay_until(SystemTime::now() + Duration::from_secs(60));
+        assert!(delay > Duration::from_secs(59) && delay <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_sent_once_the_delay_has_elapsed() {
+        let started = std::time::Instant::now();
+
+        let sent_after = send_after(Duration::from_millis(50), async { started.elapsed() }).await;
+
+        assert!(sent_after >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,845 @@
+//! Embedded webhook server receiving the callbacks Safaricom posts for the requests made by the client
+//!
+//! The server routes each callback to a method of a [`CallbackHandler`]:
+//!
+//! | Path                | Register as                                       | Handler method        |
+//! |---------------------|---------------------------------------------------|-----------------------|
+//! | `/stk`              | `CallBackURL` of M-Pesa Express requests          | `on_stk_callback`     |
+//! | `/c2b/validation`   | `ValidationURL` of C2B Register                   | `on_c2b_validation`   |
+//! | `/c2b/confirmation` | `ConfirmationURL` of C2B Register                 | `on_c2b_confirmation` |
+//! | `/result`           | `ResultURL` of B2C, B2B, reversal, status, balance | `on_result`          |
+//! | `/timeout`          | `QueueTimeOutURL` of the same requests            | `on_timeout`          |
+//!
+//! and acknowledges it the way Daraja expects. Requests from addresses outside of the allowlist,
+//! by default the addresses Safaricom sends callbacks from, are refused, and callbacks Safaricom
+//! retries are acknowledged without invoking the handler again, remembering the callbacks seen in
+//! memory or in a `storage::Storage` shared by every instance of the server. Bodies over the size or not
+//! received within the time of the server's `BodyLimits` are refused. With the `callback_tokens`
+//! feature, [`Server::callback_signer`] also refuses callbacks whose url does not carry a valid
+//! token.
+
+use std::collections::{HashSet, VecDeque};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+
+#[cfg(feature = "callback_tokens")]
+use crate::callback_token::CallbackSigner;
+use crate::callbacks::{
+    BodyLimits, C2bTransaction, C2bValidationResponse, ResultCallback, StkCallback,
+};
+use crate::storage::{namespaces, DynStorage, Storage};
+use crate::MpesaResult;
+
+/// Addresses Safaricom sends callbacks from, as published on the Daraja portal
+pub const SAFARICOM_CALLBACK_IPS: [IpAddr; 12] = [
+    IpAddr::V4(Ipv4Addr::new(196, 201, 214, 200)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 214, 206)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 213, 114)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 214, 207)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 214, 208)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 213, 44)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 212, 127)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 212, 138)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 212, 129)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 212, 136)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 212, 74)),
+    IpAddr::V4(Ipv4Addr::new(196, 201, 212, 69)),
+];
+
+/// Number of callback ids remembered to detect retries by default
+const DEFAULT_DEDUP_CAPACITY: usize = 10_000;
+
+/// Receives the callbacks routed by the server. Every method has a default implementation that
+/// ignores the callback, so implementors only override the ones they registered urls for.
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::callbacks::StkCallback;
+/// use mpesa::server::CallbackHandler;
+///
+/// struct Payments;
+///
+/// impl CallbackHandler for Payments {
+///     async fn on_stk_callback(&self, callback: StkCallback) {
+///         if callback.is_success() {
+///             println!("{:?} paid", callback.amount());
+///         }
+///     }
+/// }
+/// ```
+pub trait CallbackHandler: Send + Sync + 'static {
+    /// Called with the result of an M-Pesa Express request
+    fn on_stk_callback(&self, callback: StkCallback) -> impl Future<Output = ()> + Send {
+        let _ = callback;
+        async {}
+    }
+
+    /// Called before a C2B payment is completed, the payment is cancelled if it is rejected.
+    /// Accepts every payment by default.
+    fn on_c2b_validation(
+        &self,
+        transaction: C2bTransaction,
+    ) -> impl Future<Output = C2bValidationResponse> + Send {
+        let _ = transaction;
+        async { C2bValidationResponse::accept() }
+    }
+
+    /// Called once a C2B payment has been completed
+    fn on_c2b_confirmation(&self, transaction: C2bTransaction) -> impl Future<Output = ()> + Send {
+        let _ = transaction;
+        async {}
+    }
+
+    /// Called with the result of a B2C, B2B, transaction reversal, transaction status or
+    /// account balance request
+    fn on_result(&self, result: ResultCallback) -> impl Future<Output = ()> + Send {
+        let _ = result;
+        async {}
+    }
+
+    /// Called when one of those requests timed out in the Safaricom queue
+    fn on_timeout(&self, result: ResultCallback) -> impl Future<Output = ()> + Send {
+        let _ = result;
+        async {}
+    }
+}
+
+/// Runs a webhook server on `addr` with the default configuration, see [`Server`]
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use mpesa::server::{self, CallbackHandler};
+///
+/// struct Payments;
+///
+/// impl CallbackHandler for Payments {}
+///
+/// #[tokio::main]
+/// async fn main() {
+///     server::serve(([0, 0, 0, 0], 8080).into(), Payments)
+///         .await
+///         .unwrap();
+/// }
+/// ```
+pub async fn serve<H: CallbackHandler>(addr: SocketAddr, handler: H) -> MpesaResult<()> {
+    Server::new(handler).serve(addr).await
+}
+
+/// Configurable webhook server
+#[derive(Debug)]
+pub struct Server<H> {
+    handler: H,
+    allowlist: Option<Vec<IpAddr>>,
+    trusted_proxy_hops: usize,
+    dedup_capacity: usize,
+    dedup_storage: Option<(Arc<dyn DynStorage>, Duration)>,
+    body_limits: BodyLimits,
+    #[cfg(feature = "callback_tokens")]
+    callback_signer: Option<CallbackSigner>,
+}
+
+impl<H: CallbackHandler> Server<H> {
+    /// Creates a server that only accepts callbacks from `SAFARICOM_CALLBACK_IPS`, within the
+    /// default `BodyLimits`, and remembers the ids of the last 10 000 callbacks to detect retries
+    pub fn new(handler: H) -> Self {
+        Server {
+            handler,
+            allowlist: Some(SAFARICOM_CALLBACK_IPS.to_vec()),
+            trusted_proxy_hops: 0,
+            dedup_capacity: DEFAULT_DEDUP_CAPACITY,
+            dedup_storage: None,
+            body_limits: BodyLimits::default(),
+            #[cfg(feature = "callback_tokens")]
+            callback_signer: None,
+        }
+    }
+
+    /// Replaces the addresses callbacks are accepted from
+    pub fn allowlist(mut self, ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.allowlist = Some(ips.into_iter().collect());
+        self
+    }
+
+    /// Accepts callbacks from any address, for example during local development
+    pub fn allow_any_ip(mut self) -> Self {
+        self.allowlist = None;
+        self
+    }
+
+    /// Checks the allowlist against the last address of the `X-Forwarded-For` header, the one
+    /// appended by the proxy in front of the server, instead of the address of the connection.
+    /// Only enable this behind a reverse proxy or tunnel that sets the header. Same as
+    /// `trusted_proxy_hops(1)`.
+    pub fn trust_forwarded_for(self, trust_forwarded_for: bool) -> Self {
+        self.trusted_proxy_hops(usize::from(trust_forwarded_for))
+    }
+
+    /// Checks the allowlist against the address `hops` entries from the end of the
+    /// `X-Forwarded-For` header, for servers behind `hops` proxies that each append the address
+    /// they received the request from. The entries before it are set by the client and are not
+    /// trusted. Requests with fewer entries are checked against the address of the connection.
+    /// `0`, the default, ignores the header.
+    pub fn trusted_proxy_hops(mut self, hops: usize) -> Self {
+        self.trusted_proxy_hops = hops;
+        self
+    }
+
+    /// Sets how many callback ids are remembered to detect retries, `0` disables deduplication
+    pub fn dedup_capacity(mut self, capacity: usize) -> Self {
+        self.dedup_capacity = capacity;
+        self
+    }
+
+    /// Remembers the callbacks seen in `storage` for `ttl` instead of in memory, so that retries
+    /// are detected across restarts and by every instance of the server sharing the storage.
+    /// A callback is handled if the storage fails, since dropping it could lose a payment.
+    /// A callback whose handler panicked is forgotten, so that its retry is handled.
+    pub fn dedup_storage(mut self, storage: impl Storage + 'static, ttl: Duration) -> Self {
+        self.dedup_storage = Some((Arc::new(storage), ttl));
+        self
+    }
+
+    /// Sets the largest body of a callback and the time allowed to receive it. Larger bodies are
+    /// refused with `413 Payload Too Large` and slower ones with `408 Request Timeout`.
+    pub fn body_limits(mut self, body_limits: BodyLimits) -> Self {
+        self.body_limits = body_limits;
+        self
+    }
+
+    /// Refuses callbacks whose url has no token generated by `signer` for its path, or an expired
+    /// one, and C2B callbacks for another shortcode than the one of the token. Callback urls are
+    /// signed with `CallbackSigner::sign_url` before they are sent to Safaricom.
+    #[cfg(feature = "callback_tokens")]
+    pub fn callback_signer(mut self, signer: CallbackSigner) -> Self {
+        self.callback_signer = Some(signer);
+        self
+    }
+
+    /// Runs the server on `addr` until it fails
+    pub async fn serve(self, addr: SocketAddr) -> MpesaResult<()> {
+        let state = Arc::new(State {
+            dedup: Dedup::new(self.dedup_capacity),
+            server: self,
+        });
+        let make_service = make_service_fn(move |conn: &AddrStream| {
+            let state = Arc::clone(&state);
+            let remote_addr = conn.remote_addr().ip();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = Arc::clone(&state);
+                    async move { Ok::<_, Infallible>(state.handle(remote_addr, request).await) }
+                }))
+            }
+        });
+
+        hyper::Server::try_bind(&addr)?.serve(make_service).await?;
+        Ok(())
+    }
+}
+
+struct State<H> {
+    server: Server<H>,
+    dedup: Dedup,
+}
+
+impl<H: CallbackHandler> State<H> {
+    async fn handle(&self, remote_addr: IpAddr, request: Request<Body>) -> Response<Body> {
+        if !self.is_allowed(remote_addr, &request) {
+            return status(StatusCode::FORBIDDEN);
+        }
+        // The shortcode the token of the url was generated for
+        #[cfg(feature = "callback_tokens")]
+        let signed_short_code = match &self.server.callback_signer {
+            Some(signer) => {
+                let query = request.uri().query().unwrap_or_default();
+                match signer.verify_query(request.uri().path(), query) {
+                    Ok(short_code) => Some(short_code),
+                    Err(_) => return status(StatusCode::FORBIDDEN),
+                }
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "callback_tokens"))]
+        let signed_short_code: Option<String> = None;
+        let for_another_short_code = |transaction: &C2bTransaction| {
+            signed_short_code
+                .as_ref()
+                .is_some_and(|short_code| *short_code != transaction.business_short_code)
+        };
+        if request.method() != Method::POST {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let path = request.uri().path().trim_end_matches('/').to_owned();
+        let body = match read_body(request.into_body(), self.server.body_limits).await {
+            Ok(body) => body,
+            Err(status_code) => return status(status_code),
+        };
+        let handler = &self.server.handler;
+
+        match path.as_str() {
+            "/stk" => match StkCallback::from_json(&body) {
+                Ok(callback) => {
+                    if let Some(seen) = self.mark_seen("stk", &callback.checkout_request_id).await {
+                        handler.on_stk_callback(callback).await;
+                        seen.handled();
+                    }
+                    acknowledgement()
+                }
+                Err(_) => status(StatusCode::BAD_REQUEST),
+            },
+            "/c2b/validation" => match serde_json::from_slice::<C2bTransaction>(&body) {
+                Ok(transaction) if for_another_short_code(&transaction) => {
+                    status(StatusCode::FORBIDDEN)
+                }
+                Ok(transaction) => json_response(&handler.on_c2b_validation(transaction).await),
+                Err(_) => status(StatusCode::BAD_REQUEST),
+            },
+            "/c2b/confirmation" => match serde_json::from_slice::<C2bTransaction>(&body) {
+                Ok(transaction) if for_another_short_code(&transaction) => {
+                    status(StatusCode::FORBIDDEN)
+                }
+                Ok(transaction) => {
+                    if let Some(seen) = self.mark_seen("c2b", &transaction.trans_id).await {
+                        handler.on_c2b_confirmation(transaction).await;
+                        seen.handled();
+                    }
+                    acknowledgement()
+                }
+                Err(_) => status(StatusCode::BAD_REQUEST),
+            },
+            "/result" => match ResultCallback::from_json(&body) {
+                Ok(result) => {
+                    if let Some(seen) = self.mark_seen("result", &result.conversation_id).await {
+                        handler.on_result(result).await;
+                        seen.handled();
+                    }
+                    acknowledgement()
+                }
+                Err(_) => status(StatusCode::BAD_REQUEST),
+            },
+            "/timeout" => match ResultCallback::from_json(&body) {
+                Ok(result) => {
+                    if let Some(seen) = self.mark_seen("timeout", &result.conversation_id).await {
+                        handler.on_timeout(result).await;
+                        seen.handled();
+                    }
+                    acknowledgement()
+                }
+                Err(_) => status(StatusCode::BAD_REQUEST),
+            },
+            _ => status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Records the callback `id` of `kind`, returning `None` if it was seen before. The record
+    /// is removed if the returned `Seen` is dropped before the callback is handled.
+    async fn mark_seen(&self, kind: &str, id: &str) -> Option<Seen<'_>> {
+        let mut seen = Seen {
+            dedup: &self.dedup,
+            storage: None,
+            key: None,
+        };
+        if id.is_empty() {
+            return Some(seen);
+        }
+        let key = format!("{kind}:{id}");
+        let Some((storage, ttl)) = &self.server.dedup_storage else {
+            if self.dedup.capacity > 0 {
+                if !self.dedup.insert(&key) {
+                    return None;
+                }
+                seen.key = Some(key);
+            }
+            return Some(seen);
+        };
+        match storage
+            .compare_and_swap(namespaces::CALLBACKS, &key, None, Some(b""), Some(*ttl))
+            .await
+        {
+            Ok(false) => None,
+            Ok(true) => {
+                seen.storage = Some(storage);
+                seen.key = Some(key);
+                Some(seen)
+            }
+            Err(_) => Some(seen),
+        }
+    }
+
+    fn is_allowed(&self, remote_addr: IpAddr, request: &Request<Body>) -> bool {
+        let Some(allowlist) = &self.server.allowlist else {
+            return true;
+        };
+
+        let hops = self.server.trusted_proxy_hops;
+        let addr = if hops > 0 {
+            request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').nth(hops - 1))
+                .and_then(|addr| addr.trim().parse().ok())
+                .unwrap_or(remote_addr)
+        } else {
+            remote_addr
+        };
+        allowlist.contains(&addr)
+    }
+}
+
+/// Reads `body` within `limits`, failing with the status the request is refused with
+async fn read_body(mut body: Body, limits: BodyLimits) -> Result<Vec<u8>, StatusCode> {
+    if body.size_hint().lower() > limits.max_len as u64 {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let read = async {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+            if bytes.len() + chunk.len() > limits.max_len {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    };
+    tokio::time::timeout(limits.timeout, read)
+        .await
+        .unwrap_or(Err(StatusCode::REQUEST_TIMEOUT))
+}
+
+/// Bounded set of the ids of the callbacks seen last
+struct Dedup {
+    capacity: usize,
+    seen: Mutex<(HashSet<String>, VecDeque<String>)>,
+}
+
+impl Dedup {
+    fn new(capacity: usize) -> Self {
+        Dedup {
+            capacity,
+            seen: Mutex::new((HashSet::new(), VecDeque::new())),
+        }
+    }
+
+    /// Records `key` and returns `true` if it was not seen before
+    fn insert(&self, key: &str) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let (ids, order) = &mut *seen;
+        if !ids.insert(key.to_owned()) {
+            return false;
+        }
+        order.push_back(key.to_owned());
+        if order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Forgets `key`, so that it is not seen before the next time it is inserted
+    fn remove(&self, key: &str) {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let (ids, order) = &mut *seen;
+        if ids.remove(key) {
+            if let Some(index) = order.iter().rposition(|id| id == key) {
+                order.remove(index);
+            }
+        }
+    }
+}
+
+/// A callback recorded as seen while its handler runs. The record is removed when this is
+/// dropped before `handled` is called, i.e. when the handler panicked or the request was
+/// dropped, so that the retry of the callback by Safaricom is handled rather than acknowledged
+/// as a duplicate.
+struct Seen<'a> {
+    dedup: &'a Dedup,
+    storage: Option<&'a Arc<dyn DynStorage>>,
+    key: Option<String>,
+}
+
+impl Seen<'_> {
+    /// Keeps the record, the callback was handled
+    fn handled(mut self) {
+        self.key = None;
+    }
+}
+
+impl Drop for Seen<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        match self.storage {
+            Some(storage) => {
+                let storage = Arc::clone(storage);
+                tokio::spawn(async move {
+                    let _ = storage.delete(namespaces::CALLBACKS, &key).await;
+                });
+            }
+            None => self.dedup.remove(&key),
+        }
+    }
+}
+
+/// The response Daraja expects once a callback has been received
+fn acknowledgement() -> Response<Body> {
+    json_response(&json!({ "ResultCode": 0, "ResultDesc": "Accepted" }))
+}
+
+fn json_response<T: Serialize>(body: &T) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(_) => status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::callbacks::C2bRejection;
+
+    #[derive(Default)]
+    struct Counter {
+        stk: AtomicUsize,
+        results: AtomicUsize,
+        panic_on_next_stk: AtomicBool,
+    }
+
+    impl CallbackHandler for Arc<Counter> {
+        async fn on_stk_callback(&self, _callback: StkCallback) {
+            if self.panic_on_next_stk.swap(false, Ordering::SeqCst) {
+                panic!("the database is down");
+            }
+            self.stk.fetch_add(1, Ordering::SeqCst);
+        }
+
+        async fn on_c2b_validation(&self, transaction: C2bTransaction) -> C2bValidationResponse {
+            if transaction.bill_ref_number.is_empty() {
+                C2bValidationResponse::reject(C2bRejection::InvalidAccountNumber)
+            } else {
+                C2bValidationResponse::accept()
+            }
+        }
+
+        async fn on_result(&self, _result: ResultCallback) {
+            self.results.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn state(server: Server<Arc<Counter>>) -> State<Arc<Counter>> {
+        State {
+            dedup: Dedup::new(server.dedup_capacity),
+            server,
+        }
+    }
+
+    fn post(path: &str, body: serde_json::Value) -> Request<Body> {
+        Request::post(path)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn json_body(response: Response<Body>) -> serde_json::Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn stk_callback() -> serde_json::Value {
+        json!({
+            "Body": {
+                "stkCallback": {
+                    "MerchantRequestID": "29115-34620561-1",
+                    "CheckoutRequestID": "ws_CO_191220191020363925",
+                    "ResultCode": 1032,
+                    "ResultDesc": "Request cancelled by user."
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_callbacks_are_acknowledged_once() {
+        let counter = Arc::new(Counter::default());
+        let state = state(Server::new(Arc::clone(&counter)).allow_any_ip());
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        for _ in 0..2 {
+            let response = state.handle(localhost, post("/stk", stk_callback())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                json_body(response).await,
+                json!({ "ResultCode": 0, "ResultDesc": "Accepted" })
+            );
+        }
+        assert_eq!(counter.stk.load(Ordering::SeqCst), 1);
+
+        let result = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": 0,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "10571-7910404-1",
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581"
+            }
+        });
+        let response = state.handle(localhost, post("/result/", result)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(counter.results.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_callbacks_are_acknowledged_once_across_servers_sharing_a_storage() {
+        use crate::storage::MemoryStorage;
+
+        let counter = Arc::new(Counter::default());
+        let storage = Arc::new(MemoryStorage::default());
+        let ttl = Duration::from_secs(60);
+        let states = [(); 2].map(|_| {
+            state(
+                Server::new(Arc::clone(&counter))
+                    .allow_any_ip()
+                    .dedup_storage(Arc::clone(&storage), ttl),
+            )
+        });
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        for state in &states {
+            let response = state.handle(localhost, post("/stk", stk_callback())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(counter.stk.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_callbacks_whose_handler_panicked_are_handled_when_retried() {
+        use crate::storage::MemoryStorage;
+
+        let counter = Arc::new(Counter::default());
+        let servers = [
+            Server::new(Arc::clone(&counter)).allow_any_ip(),
+            Server::new(Arc::clone(&counter))
+                .allow_any_ip()
+                .dedup_storage(MemoryStorage::default(), Duration::from_secs(60)),
+        ];
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        for (handled, server) in (1..).zip(servers) {
+            let state = Arc::new(state(server));
+            counter.panic_on_next_stk.store(true, Ordering::SeqCst);
+            let first = tokio::spawn({
+                let state = Arc::clone(&state);
+                async move { state.handle(localhost, post("/stk", stk_callback())).await }
+            });
+            assert!(first.await.unwrap_err().is_panic());
+            tokio::task::yield_now().await;
+
+            for _ in 0..2 {
+                let response = state.handle(localhost, post("/stk", stk_callback())).await;
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+            assert_eq!(counter.stk.load(Ordering::SeqCst), handled);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_c2b_validation_returns_the_handler_decision() {
+        let state = state(Server::new(Arc::new(Counter::default())).allow_any_ip());
+        let response = state
+            .handle(
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                post(
+                    "/c2b/validation",
+                    json!({
+                        "TransactionType": "Pay Bill",
+                        "TransID": "RKTQDM7W6S",
+                        "TransTime": "20191122063845",
+                        "TransAmount": "10",
+                        "BusinessShortCode": "600638",
+                        "MSISDN": "25470****149"
+                    }),
+                ),
+            )
+            .await;
+
+        assert_eq!(
+            json_body(response).await,
+            json!({ "ResultCode": "C2B00012", "ResultDesc": "Rejected" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_outside_the_allowlist_are_refused() {
+        let counter = Arc::new(Counter::default());
+        let two_proxies = state(Server::new(Arc::clone(&counter)).trusted_proxy_hops(2));
+        let state = state(Server::new(Arc::clone(&counter)).trust_forwarded_for(true));
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let response = state.handle(localhost, post("/stk", stk_callback())).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // the client sets the first address, the proxy appends the last one
+        let mut request = post("/stk", stk_callback());
+        request.headers_mut().insert(
+            "x-forwarded-for",
+            "196.201.214.200, 10.0.0.1".parse().unwrap(),
+        );
+        let response = state.handle(localhost, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut request = post("/stk", stk_callback());
+        request.headers_mut().insert(
+            "x-forwarded-for",
+            "10.0.0.1, 196.201.214.200".parse().unwrap(),
+        );
+        let response = state.handle(localhost, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(counter.stk.load(Ordering::SeqCst), 1);
+
+        let mut request = post("/stk", stk_callback());
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", "196.201.214.200".parse().unwrap());
+        let response = two_proxies.handle(localhost, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[cfg(feature = "callback_tokens")]
+    #[tokio::test]
+    async fn test_callbacks_without_a_valid_token_are_refused() {
+        use std::time::Duration;
+
+        let counter = Arc::new(Counter::default());
+        let signer = CallbackSigner::new("secret");
+        let state = state(
+            Server::new(Arc::clone(&counter))
+                .allow_any_ip()
+                .callback_signer(signer.clone()),
+        );
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let response = state.handle(localhost, post("/stk", stk_callback())).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let forged = CallbackSigner::new("guess")
+            .sign_url("https://x/stk", "174379", Duration::from_secs(60))
+            .unwrap();
+        let path = forged.trim_start_matches("https://x");
+        let response = state.handle(localhost, post(path, stk_callback())).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let signed = signer
+            .sign_url("https://x/stk", "174379", Duration::from_secs(60))
+            .unwrap();
+        let path = signed.trim_start_matches("https://x");
+        let response = state.handle(localhost, post(path, stk_callback())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(counter.stk.load(Ordering::SeqCst), 1);
+
+        // a token is only valid on the path and for the shortcode it was generated for
+        let response = state
+            .handle(
+                localhost,
+                post(&path.replace("/stk", "/result"), stk_callback()),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let transaction = |short_code: &str| {
+            json!({
+                "TransactionType": "Pay Bill",
+                "TransID": "RKTQDM7W6S",
+                "TransTime": "20191122063845",
+                "TransAmount": "10",
+                "BusinessShortCode": short_code,
+                "BillRefNumber": "A-1029",
+                "MSISDN": "254708374149"
+            })
+        };
+        let signed = signer
+            .sign_url(
+                "https://x/c2b/validation",
+                "600638",
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        let path = signed.trim_start_matches("https://x");
+        let response = state
+            .handle(localhost, post(path, transaction("600638")))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = state
+            .handle(localhost, post(path, transaction("600000")))
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_and_unknown_requests_are_rejected() {
+        let state = state(Server::new(Arc::new(Counter::default())).allow_any_ip());
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let response = state.handle(localhost, post("/stk", json!({}))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = state.handle(localhost, post("/unknown", json!({}))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let request = Request::get("/stk").body(Body::empty()).unwrap();
+        let response = state.handle(localhost, request).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_bodies_over_the_limits_are_refused() {
+        let limits = BodyLimits {
+            max_len: 1024,
+            timeout: Duration::from_millis(50),
+        };
+        let state = state(
+            Server::new(Arc::new(Counter::default()))
+                .allow_any_ip()
+                .body_limits(limits),
+        );
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let padding = " ".repeat(1024);
+        let response = state
+            .handle(localhost, post("/result", json!({ "Result": padding })))
+            .await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // A body whose end never arrives
+        let (mut sender, body) = Body::channel();
+        sender.send_data("{".into()).await.unwrap();
+        let request = Request::post("/result").body(body).unwrap();
+        let response = state.handle(localhost, request).await;
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        drop(sender);
+    }
+}
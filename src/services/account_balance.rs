@@ -1,38 +1,70 @@
 #![doc = include_str!("../../docs/client/account_balance.md")]
 
+use std::borrow::Cow;
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
-use crate::constants::{CommandId, IdentifierTypes};
+use crate::client::{UrlKind, WithMeta};
+use crate::constants::{
+    CommandId, IdentifierTypes, Service, REDACTED, SECURITY_CREDENTIAL_PLACEHOLDER,
+};
+use crate::paths;
+use crate::services::CallbackUrls;
 use crate::{Mpesa, MpesaError, MpesaResult};
 
-const ACCOUNT_BALANCE_URL: &str = "mpesa/accountbalance/v1/query";
-
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
 /// Account Balance payload
+#[serde(rename_all = "PascalCase")]
 struct AccountBalancePayload<'mpesa> {
-    #[serde(rename(serialize = "Initiator"))]
     initiator: &'mpesa str,
-    #[serde(rename(serialize = "SecurityCredential"))]
     security_credential: &'mpesa str,
     #[serde(rename(serialize = "CommandID"))]
     command_id: CommandId,
-    #[serde(rename(serialize = "PartyA"))]
     party_a: &'mpesa str,
-    #[serde(rename(serialize = "IdentifierType"))]
-    identifier_type: &'mpesa str,
-    #[serde(rename(serialize = "Remarks"))]
+    identifier_type: String,
     remarks: &'mpesa str,
     #[serde(rename(serialize = "QueueTimeOutURL"))]
-    queue_time_out_url: &'mpesa str,
+    queue_time_out_url: Cow<'mpesa, str>,
     #[serde(rename(serialize = "ResultURL"))]
-    result_url: &'mpesa str,
+    result_url: Cow<'mpesa, str>,
+}
+
+impl fmt::Debug for AccountBalancePayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccountBalancePayload")
+            .field("initiator", &self.initiator)
+            .field("security_credential", &REDACTED)
+            .field("command_id", &self.command_id)
+            .field("party_a", &self.party_a)
+            .field("identifier_type", &self.identifier_type)
+            .field("remarks", &self.remarks)
+            .field("queue_time_out_url", &self.queue_time_out_url)
+            .field("result_url", &self.result_url)
+            .finish()
+    }
+}
+
+impl fmt::Display for AccountBalancePayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Account balance of {} via {}",
+            self.party_a, self.command_id
+        )
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct AccountBalanceResponse {
     #[serde(rename(deserialize = "ConversationID"))]
     pub conversation_id: String,
-    #[serde(rename(deserialize = "OriginatorConversationID"))]
+    #[serde(
+        rename(deserialize = "OriginatorConversationID"),
+        alias = "OriginatorCoversationID"
+    )]
     pub originator_conversation_id: String,
     #[serde(rename(deserialize = "ResponseCode"))]
     pub response_code: String,
@@ -128,11 +160,19 @@ impl<'mpesa> AccountBalanceBuilder<'mpesa> {
         self
     }
 
+    /// Adds `QueueTimeoutUrl` and `ResultUrl`, which are required fields
+    pub fn callback_urls(self, urls: CallbackUrls<'mpesa>) -> AccountBalanceBuilder<'mpesa> {
+        self.timeout_url(urls.timeout_url)
+            .result_url(urls.result_url)
+    }
+
     /// Adds `QueueTimeoutUrl` and `ResultUrl`. This is a required field
     ///
     /// # Error
     /// If either `QueueTimeoutUrl` and `ResultUrl` is invalid or not provided
-    #[deprecated]
+    #[deprecated(
+        note = "use `callback_urls(CallbackUrls::new(..))`, or `timeout_url` and `result_url`; `urls` will be removed in 2.0.0"
+    )]
     pub fn urls(
         mut self,
         timeout_url: &'mpesa str,
@@ -152,34 +192,76 @@ impl<'mpesa> AccountBalanceBuilder<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<AccountBalanceResponse> {
-        let credentials = self.client.gen_security_credentials()?;
+        Ok(self.send_with_meta().await?.response)
+    }
 
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<AccountBalanceResponse>> {
+        let credentials = self.client.security_credential_for(self.initiator_name)?;
+        self.client
+            .send_with_meta(self.request(&credentials)?)
+            .await
+    }
+
+    /// Renders the request as a curl command, with a placeholder in place of the security
+    /// credential, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client
+            .to_curl(&self.request(SECURITY_CREDENTIAL_PLACEHOLDER)?)
+    }
+
+    /// Renders a one-line summary of the request without its secrets, such as
+    /// `Account balance of 600496 via AccountBalance`, for logs and support tooling
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn summary(&self) -> MpesaResult<String> {
+        Ok(self
+            .request(SECURITY_CREDENTIAL_PLACEHOLDER)?
+            .body
+            .to_string())
+    }
+
+    fn request<'a>(
+        &'a self,
+        security_credential: &'a str,
+    ) -> MpesaResult<crate::client::Request<AccountBalancePayload<'a>>> {
         let payload = AccountBalancePayload {
             command_id: self.command_id.unwrap_or(CommandId::AccountBalance),
             party_a: self
                 .party_a
                 .ok_or(MpesaError::Message("party_a is required"))?,
-            identifier_type: &self
+            identifier_type: self
                 .identifier_type
                 .unwrap_or(IdentifierTypes::ShortCode)
                 .to_string(),
-            remarks: self.remarks.unwrap_or_else(|| stringify!(None)),
+            remarks: self.remarks.unwrap_or(stringify!(None)),
             initiator: self.initiator_name,
             queue_time_out_url: self
-                .queue_timeout_url
+                .client
+                .resolve_url(UrlKind::Timeout, self.queue_timeout_url)
                 .ok_or(MpesaError::Message("queue_timeout_url is required"))?,
             result_url: self
-                .result_url
+                .client
+                .resolve_url(UrlKind::Result, self.result_url)
                 .ok_or(MpesaError::Message("result_url is required"))?,
-            security_credential: &credentials,
+            security_credential,
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: ACCOUNT_BALANCE_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::AccountBalance,
+            path: self
+                .client
+                .api_path(Service::AccountBalance, paths::ACCOUNT_BALANCE),
+            body: payload,
+        })
     }
 }
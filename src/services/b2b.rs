@@ -1,55 +1,92 @@
 #![doc = include_str!("../../docs/client/b2b.md")]
 
-use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
 
-use crate::client::Mpesa;
-use crate::constants::{CommandId, IdentifierTypes};
-use crate::errors::{MpesaError, MpesaResult};
+use serde::{Deserialize, Serialize};
 
-const B2B_URL: &str = "mpesa/b2b/v1/paymentrequest";
+use crate::client::{Mpesa, UrlKind, WithMeta};
+use crate::constants::{
+    CommandId, IdentifierTypes, Service, REDACTED, SECURITY_CREDENTIAL_PLACEHOLDER,
+};
+use crate::errors::{MpesaError, MpesaResult, ValidationErrors};
+use crate::format::summary_amount;
+use crate::metadata::Metadata;
+use crate::paths;
+use crate::services::{CallbackUrls, PartyA, PartyB};
 
-#[derive(Debug, Serialize)]
-struct B2bPayload<'mpesa> {
-    #[serde(rename(serialize = "Initiator"))]
-    initiator: &'mpesa str,
-    #[serde(rename(serialize = "SecurityCredential"))]
-    security_credential: &'mpesa str,
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct B2bPayload<'mpesa> {
+    pub(super) initiator: &'mpesa str,
+    pub(super) security_credential: &'mpesa str,
     #[serde(rename(serialize = "CommandID"))]
-    command_id: CommandId,
-    #[serde(rename(serialize = "Amount"))]
-    amount: f64,
-    #[serde(rename(serialize = "PartyA"))]
-    party_a: &'mpesa str,
-    #[serde(rename(serialize = "SenderIdentifierType"))]
-    sender_identifier_type: &'mpesa str,
-    #[serde(rename(serialize = "PartyB"))]
-    party_b: &'mpesa str,
+    pub(super) command_id: CommandId,
+    #[serde(serialize_with = "crate::json::serialize_amount")]
+    pub(super) amount: f64,
+    pub(super) party_a: &'mpesa str,
+    pub(super) sender_identifier_type: String,
+    pub(super) party_b: &'mpesa str,
+    // Daraja spells the field name this way
     #[serde(rename(serialize = "RecieverIdentifierType"))]
-    reciever_identifier_type: &'mpesa str,
-    #[serde(rename(serialize = "Remarks"))]
-    remarks: &'mpesa str,
+    pub(super) receiver_identifier_type: String,
+    pub(super) remarks: &'mpesa str,
     #[serde(
         rename(serialize = "QueueTimeOutURL"),
         skip_serializing_if = "Option::is_none"
     )]
-    queue_time_out_url: Option<&'mpesa str>,
+    pub(super) queue_time_out_url: Option<Cow<'mpesa, str>>,
     #[serde(
         rename(serialize = "ResultURL"),
         skip_serializing_if = "Option::is_none"
     )]
-    result_url: Option<&'mpesa str>,
-    #[serde(
-        rename(serialize = "AccountReference"),
-        skip_serializing_if = "Option::is_none"
-    )]
-    account_reference: Option<&'mpesa str>,
+    pub(super) result_url: Option<Cow<'mpesa, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) account_reference: Option<&'mpesa str>,
+}
+
+impl fmt::Debug for B2bPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("B2bPayload")
+            .field("initiator", &self.initiator)
+            .field("security_credential", &REDACTED)
+            .field("command_id", &self.command_id)
+            .field("amount", &self.amount)
+            .field("party_a", &self.party_a)
+            .field("sender_identifier_type", &self.sender_identifier_type)
+            .field("party_b", &self.party_b)
+            .field("receiver_identifier_type", &self.receiver_identifier_type)
+            .field("remarks", &self.remarks)
+            .field("queue_time_out_url", &self.queue_time_out_url)
+            .field("result_url", &self.result_url)
+            .field("account_reference", &self.account_reference)
+            .finish()
+    }
+}
+
+impl fmt::Display for B2bPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "B2B {} {}→{} via {}",
+            summary_amount(self.amount),
+            self.party_a,
+            self.party_b,
+            self.command_id
+        )
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct B2bResponse {
     #[serde(rename(deserialize = "ConversationID"))]
     pub conversation_id: String,
-    #[serde(rename(deserialize = "OriginatorConversationID"))]
+    #[serde(
+        rename(deserialize = "OriginatorConversationID"),
+        alias = "OriginatorCoversationID"
+    )]
     pub originator_conversation_id: String,
     #[serde(rename(deserialize = "ResponseCode"))]
     pub response_code: String,
@@ -68,7 +105,7 @@ pub struct B2bBuilder<'mpesa> {
     sender_id: Option<IdentifierTypes>,
     party_b: Option<&'mpesa str>,
     receiver_id: Option<IdentifierTypes>,
-    remarks: Option<&'mpesa str>,
+    remarks: Option<Cow<'mpesa, str>>,
     queue_timeout_url: Option<&'mpesa str>,
     result_url: Option<&'mpesa str>,
     account_ref: Option<&'mpesa str>,
@@ -123,12 +160,23 @@ impl<'mpesa> B2bBuilder<'mpesa> {
         self
     }
 
+    /// Adds `Party A` and `Party B`, which are required fields
+    pub fn party_pair(
+        self,
+        party_a: PartyA<'mpesa>,
+        party_b: PartyB<'mpesa>,
+    ) -> B2bBuilder<'mpesa> {
+        self.party_a(party_a.0).party_b(party_b.0)
+    }
+
     /// Adds `Party A` and `Party B`. Both are required fields
     /// `Party A` should be a paybill number while `Party B` should be a mobile number.
     ///
     /// # Errors
     /// If either `Party A` or `Party B` is invalid or not provided
-    #[deprecated]
+    #[deprecated(
+        note = "use `party_pair(PartyA(..), PartyB(..))`, or `party_a` and `party_b`; `parties` will be removed in 2.0.0"
+    )]
     pub fn parties(mut self, party_a: &'mpesa str, party_b: &'mpesa str) -> B2bBuilder<'mpesa> {
         self.party_a = Some(party_a);
         self.party_b = Some(party_b);
@@ -153,11 +201,19 @@ impl<'mpesa> B2bBuilder<'mpesa> {
         self
     }
 
+    /// Adds `QueueTimeoutUrl` and `ResultUrl`, which are required fields
+    pub fn callback_urls(self, urls: CallbackUrls<'mpesa>) -> B2bBuilder<'mpesa> {
+        self.timeout_url(urls.timeout_url)
+            .result_url(urls.result_url)
+    }
+
     /// Adds `QueueTimeoutUrl` and `ResultUrl`. This is a required field
     ///
     /// # Error
     /// If either `QueueTimeoutUrl` and `ResultUrl` is invalid or not provided
-    #[deprecated]
+    #[deprecated(
+        note = "use `callback_urls(CallbackUrls::new(..))`, or `timeout_url` and `result_url`; `urls` will be removed in 2.0.0"
+    )]
     pub fn urls(mut self, timeout_url: &'mpesa str, result_url: &'mpesa str) -> B2bBuilder<'mpesa> {
         // TODO: validate urls
         self.queue_timeout_url = Some(timeout_url);
@@ -191,12 +247,52 @@ impl<'mpesa> B2bBuilder<'mpesa> {
         self
     }
 
+    /// Adds an `amount` in whole shillings
+    pub fn amount_kes(self, amount: u32) -> B2bBuilder<'mpesa> {
+        self.amount(amount)
+    }
+
+    /// Adds an `amount` in cents, e.g. `150_000` for KES 1,500
+    pub fn amount_cents(self, cents: u64) -> B2bBuilder<'mpesa> {
+        self.amount(cents as f64 / 100.0)
+    }
+
     /// Adds `remarks`. This field is optional, will default to "None" if not explicitly passed
     pub fn remarks(mut self, remarks: &'mpesa str) -> B2bBuilder<'mpesa> {
-        self.remarks = Some(remarks);
+        self.remarks = Some(Cow::Borrowed(remarks));
         self
     }
 
+    /// Encodes `metadata` into the `Remarks`, replacing any remarks added before, since B2B
+    /// requests have no `Occasion`. It can be read back from the result with
+    /// `ResultCallback::metadata`.
+    ///
+    /// # Errors
+    /// If the encoded metadata is longer than the 100 characters allowed in the `Remarks`
+    pub fn metadata(mut self, metadata: &Metadata) -> MpesaResult<B2bBuilder<'mpesa>> {
+        self.remarks = Some(Cow::Owned(metadata.encode()?));
+        Ok(self)
+    }
+
+    /// Checks every field of the request, returning all the missing fields at once instead of
+    /// failing on the first one like `send` does.
+    ///
+    /// # Errors
+    /// Returns `ValidationErrors` listing every problem found
+    pub fn validate_all(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.require(self.amount, MpesaError::Message("amount is required"));
+        errors.require(self.party_a, MpesaError::Message("party_a is required"));
+        errors.require(self.party_b, MpesaError::Message("party_b is required"));
+        if let (Some(party_a), Some(party_b)) = (self.party_a, self.party_b) {
+            let command_id = self
+                .command_id
+                .unwrap_or(CommandId::BusinessToBusinessTransfer);
+            errors.check(self.client.check_parties(command_id, party_a, party_b));
+        }
+        errors.into_result()
+    }
+
     /// # B2B API
     ///
     /// Sends b2b payment request.
@@ -211,11 +307,53 @@ impl<'mpesa> B2bBuilder<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<B2bResponse> {
-        let credentials = self.client.gen_security_credentials()?;
+        Ok(self.send_with_meta().await?.response)
+    }
 
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<B2bResponse>> {
+        let credentials = self.client.security_credential_for(self.initiator_name)?;
+        let mut response: WithMeta<B2bResponse> = self
+            .client
+            .send_with_meta(self.request(&credentials)?)
+            .await?;
+        response.initiator = Some(self.initiator_name.to_owned());
+        Ok(response)
+    }
+
+    /// Renders the request as a curl command, with a placeholder in place of the security
+    /// credential, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client
+            .to_curl(&self.request(SECURITY_CREDENTIAL_PLACEHOLDER)?)
+    }
+
+    /// Renders a one-line summary of the request without its secrets, such as
+    /// `B2B 25,000 KES 600496→600000 via BusinessPayBill`, for logs and support tooling
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn summary(&self) -> MpesaResult<String> {
+        Ok(self
+            .request(SECURITY_CREDENTIAL_PLACEHOLDER)?
+            .body
+            .to_string())
+    }
+
+    fn request<'a>(
+        &'a self,
+        security_credential: &'a str,
+    ) -> MpesaResult<crate::client::Request<B2bPayload<'a>>> {
         let payload = B2bPayload {
             initiator: self.initiator_name,
-            security_credential: &credentials,
+            security_credential,
             command_id: self
                 .command_id
                 .unwrap_or(CommandId::BusinessToBusinessTransfer),
@@ -225,29 +363,32 @@ impl<'mpesa> B2bBuilder<'mpesa> {
             party_a: self
                 .party_a
                 .ok_or(MpesaError::Message("party_a is required"))?,
-            sender_identifier_type: &self
+            sender_identifier_type: self
                 .sender_id
                 .unwrap_or(IdentifierTypes::ShortCode)
                 .to_string(),
             party_b: self
                 .party_b
                 .ok_or(MpesaError::Message("party_b is required"))?,
-            reciever_identifier_type: &self
+            receiver_identifier_type: self
                 .receiver_id
                 .unwrap_or(IdentifierTypes::ShortCode)
                 .to_string(),
-            remarks: self.remarks.unwrap_or_else(|| stringify!(None)),
-            queue_time_out_url: self.queue_timeout_url,
-            result_url: self.result_url,
+            remarks: self.remarks.as_deref().unwrap_or(stringify!(None)),
+            queue_time_out_url: self
+                .client
+                .resolve_url(UrlKind::Timeout, self.queue_timeout_url),
+            result_url: self.client.resolve_url(UrlKind::Result, self.result_url),
             account_reference: self.account_ref,
         };
-
         self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: B2B_URL,
-                body: payload,
-            })
-            .await
+            .check_parties(payload.command_id, payload.party_a, payload.party_b)?;
+
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::B2b,
+            path: self.client.api_path(Service::B2b, paths::B2B),
+            body: payload,
+        })
     }
 }
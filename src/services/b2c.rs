@@ -1,41 +1,88 @@
 #![doc = include_str!("../../docs/client/b2c.md")]
 
-use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
 
-use crate::{CommandId, Mpesa, MpesaError, MpesaResult};
+use serde::{Deserialize, Serialize};
 
-const B2C_URL: &str = "mpesa/b2c/v1/paymentrequest";
+use crate::beneficiary::BeneficiaryBook;
+use crate::client::{UrlKind, WithMeta};
+use crate::constants::{Service, REDACTED, SECURITY_CREDENTIAL_PLACEHOLDER};
+use crate::format::{mask_phone_number, summary_amount};
+use crate::metadata::Metadata;
+use crate::paths;
+use crate::services::{CallbackUrls, PartyA, PartyB};
+use crate::{CommandId, Mpesa, MpesaError, MpesaResult, ValidationErrors};
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
 /// Payload to allow for b2c transactions:
+#[serde(rename_all = "PascalCase")]
 struct B2cPayload<'mpesa> {
-    #[serde(rename(serialize = "InitiatorName"))]
+    #[serde(
+        rename(serialize = "OriginatorConversationID"),
+        skip_serializing_if = "Option::is_none"
+    )]
+    originator_conversation_id: Option<&'mpesa str>,
     initiator_name: &'mpesa str,
-    #[serde(rename(serialize = "SecurityCredential"))]
     security_credential: &'mpesa str,
     #[serde(rename(serialize = "CommandID"))]
     command_id: CommandId,
-    #[serde(rename(serialize = "Amount"))]
+    #[serde(serialize_with = "crate::json::serialize_amount")]
     amount: f64,
-    #[serde(rename(serialize = "PartyA"))]
     party_a: &'mpesa str,
-    #[serde(rename(serialize = "PartyB"))]
-    party_b: &'mpesa str,
-    #[serde(rename(serialize = "Remarks"))]
+    party_b: Cow<'mpesa, str>,
     remarks: &'mpesa str,
     #[serde(rename(serialize = "QueueTimeOutURL"))]
-    queue_time_out_url: &'mpesa str,
+    queue_time_out_url: Cow<'mpesa, str>,
     #[serde(rename(serialize = "ResultURL"))]
-    result_url: &'mpesa str,
-    #[serde(rename(serialize = "Occasion"))]
+    result_url: Cow<'mpesa, str>,
     occasion: &'mpesa str,
 }
 
+impl fmt::Debug for B2cPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("B2cPayload")
+            .field(
+                "originator_conversation_id",
+                &self.originator_conversation_id,
+            )
+            .field("initiator_name", &self.initiator_name)
+            .field("security_credential", &REDACTED)
+            .field("command_id", &self.command_id)
+            .field("amount", &self.amount)
+            .field("party_a", &self.party_a)
+            .field("party_b", &self.party_b)
+            .field("remarks", &self.remarks)
+            .field("queue_time_out_url", &self.queue_time_out_url)
+            .field("result_url", &self.result_url)
+            .field("occasion", &self.occasion)
+            .finish()
+    }
+}
+
+impl fmt::Display for B2cPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "B2C {} {}→{} via {}",
+            summary_amount(self.amount),
+            self.party_a,
+            mask_phone_number(&self.party_b),
+            self.command_id
+        )
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct B2cResponse {
     #[serde(rename(deserialize = "ConversationID"))]
     pub conversation_id: String,
-    #[serde(rename(deserialize = "OriginatorConversationID"))]
+    #[serde(
+        rename(deserialize = "OriginatorConversationID"),
+        alias = "OriginatorCoversationID"
+    )]
     pub originator_conversation_id: String,
     #[serde(rename(deserialize = "ResponseCode"))]
     pub response_code: String,
@@ -55,7 +102,9 @@ pub struct B2cBuilder<'mpesa> {
     remarks: Option<&'mpesa str>,
     queue_timeout_url: Option<&'mpesa str>,
     result_url: Option<&'mpesa str>,
-    occasion: Option<&'mpesa str>,
+    occasion: Option<Cow<'mpesa, str>>,
+    originator_conversation_id: Option<&'mpesa str>,
+    beneficiaries: Option<&'mpesa BeneficiaryBook>,
 }
 
 impl<'mpesa> B2cBuilder<'mpesa> {
@@ -72,7 +121,9 @@ impl<'mpesa> B2cBuilder<'mpesa> {
             queue_timeout_url: None,
             result_url: None,
             occasion: None,
+            originator_conversation_id: None,
             command_id: None,
+            beneficiaries: None,
         }
     }
 
@@ -102,12 +153,23 @@ impl<'mpesa> B2cBuilder<'mpesa> {
         self
     }
 
+    /// Adds `Party A` and `Party B`, which are required fields
+    pub fn party_pair(
+        self,
+        party_a: PartyA<'mpesa>,
+        party_b: PartyB<'mpesa>,
+    ) -> B2cBuilder<'mpesa> {
+        self.party_a(party_a.0).party_b(party_b.0)
+    }
+
     /// Adds `Party A` and `Party B`. Both are required fields
     /// `Party A` should be a paybill number while `Party B` should be a mobile number.
     ///
     /// # Errors
     /// If either `Party A` or `Party B` is invalid or not provided
-    #[deprecated]
+    #[deprecated(
+        note = "use `party_pair(PartyA(..), PartyB(..))`, or `party_a` and `party_b`; `parties` will be removed in 2.0.0"
+    )]
     pub fn parties(mut self, party_a: &'mpesa str, party_b: &'mpesa str) -> B2cBuilder<'mpesa> {
         // TODO: add validation
         self.party_a = Some(party_a);
@@ -123,10 +185,30 @@ impl<'mpesa> B2cBuilder<'mpesa> {
 
     /// Adds `Occasion`. This is an optional field, will default to an empty string
     pub fn occasion(mut self, occasion: &'mpesa str) -> B2cBuilder<'mpesa> {
-        self.occasion = Some(occasion);
+        self.occasion = Some(Cow::Borrowed(occasion));
+        self
+    }
+
+    /// Adds the `OriginatorConversationID`, the identifier of the request echoed back in its
+    /// response and result. Generated by Safaricom if not provided.
+    pub fn originator_conversation_id(
+        mut self,
+        originator_conversation_id: &'mpesa str,
+    ) -> B2cBuilder<'mpesa> {
+        self.originator_conversation_id = Some(originator_conversation_id);
         self
     }
 
+    /// Encodes `metadata` into the `Occasion`, replacing any occasion added before.
+    /// It can be read back from the result with `ResultCallback::metadata`.
+    ///
+    /// # Errors
+    /// If the encoded metadata is longer than the 100 characters allowed in the `Occasion`
+    pub fn metadata(mut self, metadata: &Metadata) -> MpesaResult<B2cBuilder<'mpesa>> {
+        self.occasion = Some(Cow::Owned(metadata.encode()?));
+        Ok(self)
+    }
+
     /// Adds an `amount` to the request
     /// This is a required field
     pub fn amount<Number: Into<f64>>(mut self, amount: Number) -> B2cBuilder<'mpesa> {
@@ -134,6 +216,16 @@ impl<'mpesa> B2cBuilder<'mpesa> {
         self
     }
 
+    /// Adds an `amount` in whole shillings
+    pub fn amount_kes(self, amount: u32) -> B2cBuilder<'mpesa> {
+        self.amount(amount)
+    }
+
+    /// Adds an `amount` in cents, e.g. `150_000` for KES 1,500
+    pub fn amount_cents(self, cents: u64) -> B2cBuilder<'mpesa> {
+        self.amount(cents as f64 / 100.0)
+    }
+
     // Adds `QueueTimeoutUrl` This is a required field
     ///
     /// # Error
@@ -152,11 +244,19 @@ impl<'mpesa> B2cBuilder<'mpesa> {
         self
     }
 
+    /// Adds `QueueTimeoutUrl` and `ResultUrl`, which are required fields
+    pub fn callback_urls(self, urls: CallbackUrls<'mpesa>) -> B2cBuilder<'mpesa> {
+        self.timeout_url(urls.timeout_url)
+            .result_url(urls.result_url)
+    }
+
     /// Adds `QueueTimeoutUrl` and `ResultUrl`. This is a required field
     ///
     /// # Error
     /// If either `QueueTimeoutUrl` and `ResultUrl` is invalid or not provided
-    #[deprecated]
+    #[deprecated(
+        note = "use `callback_urls(CallbackUrls::new(..))`, or `timeout_url` and `result_url`; `urls` will be removed in 2.0.0"
+    )]
     pub fn urls(mut self, timeout_url: &'mpesa str, result_url: &'mpesa str) -> B2cBuilder<'mpesa> {
         // TODO: validate urls; will probably return a `Result` from this
         self.queue_timeout_url = Some(timeout_url);
@@ -164,6 +264,47 @@ impl<'mpesa> B2cBuilder<'mpesa> {
         self
     }
 
+    /// Refuses to send the payment unless `PartyB` is a beneficiary in `beneficiaries` and the
+    /// amount is within its limit, to catch mistyped phone numbers before the money is sent
+    pub fn only_known_beneficiaries(
+        mut self,
+        beneficiaries: &'mpesa BeneficiaryBook,
+    ) -> B2cBuilder<'mpesa> {
+        self.beneficiaries = Some(beneficiaries);
+        self
+    }
+
+    /// Checks every field of the request, returning all the missing fields at once instead of
+    /// failing on the first one like `send` does.
+    ///
+    /// # Errors
+    /// Returns `ValidationErrors` listing every problem found
+    pub fn validate_all(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.require(self.amount, MpesaError::Message("amount is required"));
+        errors.require(self.party_a, MpesaError::Message("party_a is required"));
+        errors.require(self.party_b, MpesaError::Message("party_b is required"));
+        if let (Some(party_a), Some(party_b)) = (self.party_a, self.party_b) {
+            let command_id = self.command_id.unwrap_or(CommandId::BusinessPayment);
+            errors.check(self.client.check_parties(command_id, party_a, party_b));
+        }
+        if let (Some(beneficiaries), Some(party_b), Some(amount)) =
+            (self.beneficiaries, self.party_b, self.amount)
+        {
+            errors.check(beneficiaries.check(party_b, amount).map(|_| ()));
+        }
+        errors.require(
+            self.client
+                .resolve_url(UrlKind::Timeout, self.queue_timeout_url),
+            MpesaError::Message("queue_timeout_url is required"),
+        );
+        errors.require(
+            self.client.resolve_url(UrlKind::Result, self.result_url),
+            MpesaError::Message("result_url is required"),
+        );
+        errors.into_result()
+    }
+
     /// # B2C API
     ///
     /// Sends b2c payment request.
@@ -178,11 +319,54 @@ impl<'mpesa> B2cBuilder<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure.
     pub async fn send(self) -> MpesaResult<B2cResponse> {
-        let credentials = self.client.gen_security_credentials()?;
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<B2cResponse>> {
+        let credentials = self.client.security_credential_for(self.initiator_name)?;
+        let mut response: WithMeta<B2cResponse> = self
+            .client
+            .send_with_meta(self.request(&credentials)?)
+            .await?;
+        response.initiator = Some(self.initiator_name.to_owned());
+        Ok(response)
+    }
 
+    /// Renders the request as a curl command, with a placeholder in place of the security
+    /// credential, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client
+            .to_curl(&self.request(SECURITY_CREDENTIAL_PLACEHOLDER)?)
+    }
+
+    /// Renders a one-line summary of the request without its secrets, such as
+    /// `B2C 1,000 KES 600496→2547****149 via BusinessPayment`, for logs and support tooling
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn summary(&self) -> MpesaResult<String> {
+        Ok(self
+            .request(SECURITY_CREDENTIAL_PLACEHOLDER)?
+            .body
+            .to_string())
+    }
+
+    fn request<'a>(
+        &'a self,
+        security_credential: &'a str,
+    ) -> MpesaResult<crate::client::Request<B2cPayload<'a>>> {
         let payload = B2cPayload {
+            originator_conversation_id: self.originator_conversation_id,
             initiator_name: self.initiator_name,
-            security_credential: &credentials,
+            security_credential,
             command_id: self.command_id.unwrap_or(CommandId::BusinessPayment),
             amount: self
                 .amount
@@ -192,23 +376,30 @@ impl<'mpesa> B2cBuilder<'mpesa> {
                 .ok_or(MpesaError::Message("party_a is required"))?,
             party_b: self
                 .party_b
+                .map(|party_b| self.client.msisdn(party_b))
                 .ok_or(MpesaError::Message("party_b is required"))?,
-            remarks: self.remarks.unwrap_or_else(|| stringify!(None)),
+            remarks: self.remarks.unwrap_or(stringify!(None)),
             queue_time_out_url: self
-                .queue_timeout_url
+                .client
+                .resolve_url(UrlKind::Timeout, self.queue_timeout_url)
                 .ok_or(MpesaError::Message("queue_timeout_url is required"))?,
             result_url: self
-                .result_url
+                .client
+                .resolve_url(UrlKind::Result, self.result_url)
                 .ok_or(MpesaError::Message("result_url is required"))?,
-            occasion: self.occasion.unwrap_or_else(|| stringify!(None)),
+            occasion: self.occasion.as_deref().unwrap_or(stringify!(None)),
         };
-
         self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: B2C_URL,
-                body: payload,
-            })
-            .await
+            .check_parties(payload.command_id, payload.party_a, &payload.party_b)?;
+        if let Some(beneficiaries) = self.beneficiaries {
+            beneficiaries.check(&payload.party_b, payload.amount)?;
+        }
+
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::B2c,
+            path: self.client.api_path(Service::B2c, paths::B2C),
+            body: payload,
+        })
     }
 }
@@ -2,13 +2,15 @@
 
 use serde::Deserialize;
 
-use crate::client::Mpesa;
-use crate::constants::Invoice;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{Invoice, Service};
 use crate::errors::{MpesaError, MpesaResult};
-
-const BILL_MANAGER_BULK_INVOICE_API_URL: &str = "v1/billmanager-invoice/bulk-invoicing";
+use crate::paths;
+use crate::queue::Delivery;
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct BulkInvoiceResponse {
     #[serde(rename(deserialize = "rescode"))]
     pub response_code: String,
@@ -52,16 +54,53 @@ impl<'mpesa> BulkInvoiceBuilder<'mpesa> {
     /// # Errors
     /// Returns an `MpesaError` on failure.
     pub async fn send(self) -> MpesaResult<BulkInvoiceResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<BulkInvoiceResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Sends the request, queuing it to be sent by `Mpesa::flush_queue` if the Safaricom API
+    /// cannot be reached, see the `queue` module
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request is rejected, or if the client has no
+    /// `MpesaBuilder::degradation_queue`
+    pub async fn send_or_queue(self) -> MpesaResult<Delivery<BulkInvoiceResponse>> {
+        self.client.send_or_queue(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<&[Invoice<'_>]>> {
         if self.invoices.is_empty() {
             return Err(MpesaError::Message("invoices cannot be empty"));
         }
+        if self.client.validation {
+            for invoice in &self.invoices {
+                invoice.validate()?;
+            }
+        }
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: BILL_MANAGER_BULK_INVOICE_API_URL,
-                body: self.invoices,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::BillManager,
+            path: self
+                .client
+                .api_path(Service::BillManager, paths::BILL_MANAGER_BULK_INVOICE),
+            body: &self.invoices,
+        })
     }
 }
@@ -2,10 +2,11 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::client::Mpesa;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::Service;
 use crate::errors::MpesaResult;
-
-const BILL_MANAGER_CANCEL_INVOICE_API_URL: &str = "v1/billmanager-invoice/cancel-single-invoice";
+use crate::paths;
+use crate::queue::Delivery;
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -14,6 +15,8 @@ struct CancelInvoicePayload<'mpesa> {
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct CancelInvoiceResponse {
     #[serde(rename(deserialize = "rescode"))]
     pub response_code: String,
@@ -71,12 +74,44 @@ impl<'mpesa> CancelInvoiceBuilder<'mpesa> {
     /// # Errors
     /// Returns an `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<CancelInvoiceResponse> {
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: BILL_MANAGER_CANCEL_INVOICE_API_URL,
-                body: self.external_references,
-            })
-            .await
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<CancelInvoiceResponse>> {
+        self.client.send_with_meta(self.request()).await
+    }
+
+    /// Sends the request, queuing it to be sent by `Mpesa::flush_queue` if the Safaricom API
+    /// cannot be reached, see the `queue` module
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request is rejected, or if the client has no
+    /// `MpesaBuilder::degradation_queue`
+    pub async fn send_or_queue(self) -> MpesaResult<Delivery<CancelInvoiceResponse>> {
+        self.client.send_or_queue(self.request()).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request cannot be serialized
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request())
+    }
+
+    fn request(&self) -> crate::client::Request<&[CancelInvoicePayload<'_>]> {
+        crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::BillManager,
+            path: self
+                .client
+                .api_path(Service::BillManager, paths::BILL_MANAGER_CANCEL_INVOICE),
+            body: &self.external_references,
+        }
     }
 }
@@ -2,28 +2,28 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::client::Mpesa;
-use crate::constants::SendRemindersTypes;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{SendRemindersTypes, Service};
 use crate::errors::{MpesaError, MpesaResult};
-
-const BILL_MANAGER_ONBOARD_API_URL: &str = "v1/billmanager-invoice/optin";
+use crate::paths;
+use crate::validator::{validate_email, validate_local_phone_number};
 
 #[derive(Debug, Serialize)]
 /// Payload to opt you in as a biller to the bill manager features.
+#[serde(rename_all = "camelCase")]
 struct OnboardPayload<'mpesa> {
-    #[serde(rename(serialize = "callbackUrl"))]
     callback_url: &'mpesa str,
     email: &'mpesa str,
     logo: &'mpesa str,
-    #[serde(rename(serialize = "officialContact"))]
     official_contact: &'mpesa str,
-    #[serde(rename(serialize = "sendReminders"))]
     send_reminders: SendRemindersTypes,
     #[serde(rename(serialize = "shortcode"))]
     short_code: &'mpesa str,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct OnboardResponse {
     #[serde(rename(deserialize = "app_key"))]
     pub app_key: String,
@@ -70,7 +70,7 @@ impl<'mpesa> OnboardBuilder<'mpesa> {
     /// Adds an `email` address to the request.
     ///
     /// # Errors
-    /// If `email` is not provided.
+    /// If `email` is invalid or not provided.
     pub fn email(mut self, email: &'mpesa str) -> OnboardBuilder<'mpesa> {
         self.email = Some(email);
         self
@@ -121,6 +121,39 @@ impl<'mpesa> OnboardBuilder<'mpesa> {
     /// # Errors
     /// Returns an `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<OnboardResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<OnboardResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<OnboardPayload<'_>>> {
+        if self.client.validation {
+            if let Some(email) = self.email {
+                validate_email(email)?;
+            }
+            if let Some(official_contact) = self.official_contact {
+                validate_local_phone_number(
+                    official_contact,
+                    "Invalid official_contact, must be in the format 07XXXXXXXX",
+                )?;
+            }
+        }
+
         let payload = OnboardPayload {
             callback_url: self
                 .callback_url
@@ -136,12 +169,13 @@ impl<'mpesa> OnboardBuilder<'mpesa> {
                 .ok_or(MpesaError::Message("short_code is required"))?,
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: BILL_MANAGER_ONBOARD_API_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::BillManager,
+            path: self
+                .client
+                .api_path(Service::BillManager, paths::BILL_MANAGER_ONBOARD),
+            body: payload,
+        })
     }
 }
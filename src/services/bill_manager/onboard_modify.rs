@@ -2,33 +2,25 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::client::Mpesa;
-use crate::constants::SendRemindersTypes;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{SendRemindersTypes, Service};
 use crate::errors::MpesaResult;
-
-const BILL_MANAGER_ONBOARD_MODIFY_API_URL: &str = "v1/billmanager-invoice/change-optin-details";
+use crate::paths;
+use crate::validator::{validate_email, validate_local_phone_number};
 
 #[derive(Debug, Serialize)]
 /// Payload to modify opt-in details to the bill manager api.
+#[serde(rename_all = "camelCase")]
 struct OnboardModifyPayload<'mpesa> {
-    #[serde(
-        rename(serialize = "callbackUrl"),
-        skip_serializing_if = "Option::is_none"
-    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
     callback_url: Option<&'mpesa str>,
-    #[serde(rename(serialize = "email"), skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     email: Option<&'mpesa str>,
-    #[serde(rename(serialize = "logo"), skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     logo: Option<&'mpesa str>,
-    #[serde(
-        rename(serialize = "officialContact"),
-        skip_serializing_if = "Option::is_none"
-    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
     official_contact: Option<&'mpesa str>,
-    #[serde(
-        rename(serialize = "sendReminders"),
-        skip_serializing_if = "Option::is_none"
-    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
     send_reminders: Option<SendRemindersTypes>,
     #[serde(
         rename(serialize = "shortcode"),
@@ -38,6 +30,8 @@ struct OnboardModifyPayload<'mpesa> {
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct OnboardModifyResponse {
     #[serde(rename(deserialize = "rescode"))]
     pub response_code: String,
@@ -121,6 +115,39 @@ impl<'mpesa> OnboardModifyBuilder<'mpesa> {
     /// # Errors
     /// Returns an `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<OnboardModifyResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<OnboardModifyResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<OnboardModifyPayload<'_>>> {
+        if self.client.validation {
+            if let Some(email) = self.email {
+                validate_email(email)?;
+            }
+            if let Some(official_contact) = self.official_contact {
+                validate_local_phone_number(
+                    official_contact,
+                    "Invalid official_contact, must be in the format 07XXXXXXXX",
+                )?;
+            }
+        }
+
         let payload = OnboardModifyPayload {
             callback_url: self.callback_url,
             email: self.email,
@@ -130,12 +157,13 @@ impl<'mpesa> OnboardModifyBuilder<'mpesa> {
             short_code: self.short_code,
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: BILL_MANAGER_ONBOARD_MODIFY_API_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::BillManager,
+            path: self
+                .client
+                .api_path(Service::BillManager, paths::BILL_MANAGER_ONBOARD_MODIFY),
+            body: payload,
+        })
     }
 }
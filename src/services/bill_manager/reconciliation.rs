@@ -1,12 +1,17 @@
 #![doc = include_str!("../../../docs/client/bill_manager/reconciliation.md")]
 
-use chrono::prelude::{DateTime, Utc};
+use std::borrow::Cow;
+
 use serde::{Deserialize, Serialize};
 
-use crate::client::Mpesa;
+use crate::callbacks::C2bTransaction;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::Service;
+use crate::datetime::{from_unix_timestamp, parse_timestamp, UtcDateTime};
 use crate::errors::{MpesaError, MpesaResult};
-
-const BILL_MANAGER_RECONCILIATION_API_URL: &str = "v1/billmanager-invoice/reconciliation";
+use crate::ledger::LedgerEntry;
+use crate::paths;
+use crate::receipt::ReceiptKind;
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -15,13 +20,16 @@ struct ReconciliationPayload<'mpesa> {
     external_reference: &'mpesa str,
     full_name: &'mpesa str,
     invoice_name: &'mpesa str,
+    #[serde(serialize_with = "crate::json::serialize_amount")]
     paid_amount: f64,
-    payment_date: DateTime<Utc>,
+    payment_date: UtcDateTime,
     phone_number: &'mpesa str,
     transaction_id: &'mpesa str,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct ReconciliationResponse {
     #[serde(rename(deserialize = "rescode"))]
     pub response_code: String,
@@ -34,10 +42,10 @@ pub struct ReconciliationBuilder<'mpesa> {
     client: &'mpesa Mpesa,
     account_reference: Option<&'mpesa str>,
     external_reference: Option<&'mpesa str>,
-    full_name: Option<&'mpesa str>,
+    full_name: Option<Cow<'mpesa, str>>,
     invoice_name: Option<&'mpesa str>,
     paid_amount: Option<f64>,
-    payment_date: Option<DateTime<Utc>>,
+    payment_date: Option<UtcDateTime>,
     phone_number: Option<&'mpesa str>,
     transaction_id: Option<&'mpesa str>,
 }
@@ -78,7 +86,7 @@ impl<'mpesa> ReconciliationBuilder<'mpesa> {
 
     /// Adds `full_name`
     pub fn full_name(mut self, full_name: &'mpesa str) -> ReconciliationBuilder<'mpesa> {
-        self.full_name = Some(full_name);
+        self.full_name = Some(Cow::Borrowed(full_name));
         self
     }
 
@@ -98,11 +106,20 @@ impl<'mpesa> ReconciliationBuilder<'mpesa> {
     }
 
     /// Adds `payment_date`
-    pub fn payment_date(mut self, payment_date: DateTime<Utc>) -> ReconciliationBuilder<'mpesa> {
+    pub fn payment_date(mut self, payment_date: UtcDateTime) -> ReconciliationBuilder<'mpesa> {
         self.payment_date = Some(payment_date);
         self
     }
 
+    /// Adds `payment_date` as a `time::OffsetDateTime`
+    #[cfg(feature = "time")]
+    pub fn payment_date_time(
+        self,
+        payment_date: time::OffsetDateTime,
+    ) -> ReconciliationBuilder<'mpesa> {
+        self.payment_date(crate::datetime::from_time(payment_date))
+    }
+
     /// Adds `phone_number`
     pub fn phone_number(mut self, phone_number: &'mpesa str) -> ReconciliationBuilder<'mpesa> {
         self.phone_number = Some(phone_number);
@@ -115,6 +132,96 @@ impl<'mpesa> ReconciliationBuilder<'mpesa> {
         self
     }
 
+    /// Fills in every field from a C2B payment received at the `ConfirmationURL`, to send the
+    /// customer an e-receipt for it:
+    /// - `account_reference` and `invoice_name` from `BillRefNumber`
+    /// - `external_reference` from `InvoiceNumber`, or `BillRefNumber` if it is empty
+    /// - `full_name` from `FirstName`, `MiddleName` and `LastName`
+    /// - `paid_amount` from `TransAmount`, `payment_date` from `TransTime`
+    /// - `phone_number` from `MSISDN`, `transaction_id` from `TransID`
+    ///
+    /// Any field can be overridden by calling its method afterwards.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the `TransAmount` or `TransTime` of the payment is malformed
+    pub fn c2b_confirmation(
+        mut self,
+        transaction: &'mpesa C2bTransaction,
+    ) -> MpesaResult<ReconciliationBuilder<'mpesa>> {
+        let paid_amount = transaction
+            .trans_amount
+            .parse::<f64>()
+            .map_err(|_| MpesaError::Message("TransAmount of the C2B payment is not a number"))?;
+        let payment_date = parse_timestamp(&transaction.trans_time).ok_or(MpesaError::Message(
+            "TransTime of the C2B payment is not a YYYYMMDDHHmmss timestamp",
+        ))?;
+        let full_name = transaction.full_name();
+        let external_reference = if transaction.invoice_number.is_empty() {
+            &transaction.bill_ref_number
+        } else {
+            &transaction.invoice_number
+        };
+
+        self.account_reference = Some(&transaction.bill_ref_number);
+        self.external_reference = Some(external_reference);
+        self.full_name = Some(Cow::Owned(full_name));
+        self.invoice_name = Some(&transaction.bill_ref_number);
+        self.paid_amount = Some(paid_amount);
+        self.payment_date = Some(payment_date);
+        self.phone_number = Some(&transaction.msisdn);
+        self.transaction_id = Some(&transaction.trans_id);
+        Ok(self)
+    }
+
+    /// Fills in every field from the `LedgerEntry` of a payment made to the paybill, such as one
+    /// built from a C2B confirmation:
+    /// - `account_reference`, `external_reference` and `invoice_name` from `reference`
+    /// - `full_name` from `counterparty_name`, `phone_number` from `counterparty`
+    /// - `paid_amount` from `amount`, `payment_date` from `transacted_at`
+    /// - `transaction_id` from `receipt_number`
+    ///
+    /// Any field can be overridden by calling its method afterwards.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the entry is not an incoming payment, or lacks its amount, time,
+    /// reference or receipt number
+    pub fn ledger_entry(
+        mut self,
+        entry: &'mpesa LedgerEntry,
+    ) -> MpesaResult<ReconciliationBuilder<'mpesa>> {
+        if entry.direction != ReceiptKind::Payment {
+            return Err(MpesaError::Message(
+                "Only incoming payments can be reconciled with Bill Manager",
+            ));
+        }
+        let paid_amount = entry
+            .amount
+            .ok_or(MpesaError::Message("The ledger entry has no amount"))?;
+        let payment_date =
+            entry
+                .transacted_at
+                .and_then(from_unix_timestamp)
+                .ok_or(MpesaError::Message(
+                    "The ledger entry has no transaction time",
+                ))?;
+        let reference = entry
+            .reference
+            .as_deref()
+            .ok_or(MpesaError::Message("The ledger entry has no reference"))?;
+
+        self.account_reference = Some(reference);
+        self.external_reference = Some(reference);
+        self.full_name = entry.counterparty_name.as_deref().map(Cow::Borrowed);
+        self.invoice_name = Some(reference);
+        self.paid_amount = Some(paid_amount);
+        self.payment_date = Some(payment_date);
+        self.phone_number = entry.counterparty.as_deref();
+        self.transaction_id = Some(entry.receipt_number.as_deref().ok_or(MpesaError::Message(
+            "The ledger entry has no receipt number",
+        ))?);
+        Ok(self)
+    }
+
     /// Bill Manager Reconciliation API
     ///
     /// Enables your customers to receive e-receipts for payments made to your paybill account
@@ -124,6 +231,27 @@ impl<'mpesa> ReconciliationBuilder<'mpesa> {
     /// # Errors
     /// Returns an `MpesaError` on failure.
     pub async fn send(self) -> MpesaResult<ReconciliationResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<ReconciliationResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<ReconciliationPayload<'_>>> {
         let payload = ReconciliationPayload {
             account_reference: self
                 .account_reference
@@ -133,6 +261,7 @@ impl<'mpesa> ReconciliationBuilder<'mpesa> {
                 .ok_or(MpesaError::Message("external_reference is required"))?,
             full_name: self
                 .full_name
+                .as_deref()
                 .ok_or(MpesaError::Message("full_name is required"))?,
             invoice_name: self
                 .invoice_name
@@ -151,12 +280,13 @@ impl<'mpesa> ReconciliationBuilder<'mpesa> {
                 .ok_or(MpesaError::Message("transaction_id is required"))?,
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: BILL_MANAGER_RECONCILIATION_API_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::BillManager,
+            path: self
+                .client
+                .api_path(Service::BillManager, paths::BILL_MANAGER_RECONCILIATION),
+            body: payload,
+        })
     }
 }
@@ -1,15 +1,17 @@
 #![doc = include_str!("../../../docs/client/bill_manager/single_invoice.md")]
 
-use chrono::prelude::{DateTime, Utc};
 use serde::Deserialize;
 
-use crate::client::Mpesa;
-use crate::constants::{Invoice, InvoiceItem};
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{Invoice, InvoiceItem, Service};
+use crate::datetime::UtcDateTime;
 use crate::errors::{MpesaError, MpesaResult};
-
-const BILL_MANAGER_SINGLE_INVOICE_API_URL: &str = "v1/billmanager-invoice/single-invoicing";
+use crate::paths;
+use crate::queue::Delivery;
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct SingleInvoiceResponse {
     #[serde(rename(deserialize = "rescode"))]
     pub response_code: String,
@@ -27,7 +29,7 @@ pub struct SingleInvoiceBuilder<'mpesa> {
     billed_full_name: Option<&'mpesa str>,
     billed_period: Option<&'mpesa str>,
     billed_phone_number: Option<&'mpesa str>,
-    due_date: Option<DateTime<Utc>>,
+    due_date: Option<UtcDateTime>,
     external_reference: Option<&'mpesa str>,
     invoice_items: Option<Vec<InvoiceItem<'mpesa>>>,
     invoice_name: Option<&'mpesa str>,
@@ -90,11 +92,17 @@ impl<'mpesa> SingleInvoiceBuilder<'mpesa> {
     }
 
     /// Adds `due_date`
-    pub fn due_date(mut self, due_date: DateTime<Utc>) -> SingleInvoiceBuilder<'mpesa> {
+    pub fn due_date(mut self, due_date: UtcDateTime) -> SingleInvoiceBuilder<'mpesa> {
         self.due_date = Some(due_date);
         self
     }
 
+    /// Adds `due_date` as a `time::OffsetDateTime`
+    #[cfg(feature = "time")]
+    pub fn due_date_time(self, due_date: time::OffsetDateTime) -> SingleInvoiceBuilder<'mpesa> {
+        self.due_date(crate::datetime::from_time(due_date))
+    }
+
     /// Adds `external_reference`
     pub fn external_reference(
         mut self,
@@ -128,6 +136,37 @@ impl<'mpesa> SingleInvoiceBuilder<'mpesa> {
     /// # Errors
     /// Returns an `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<SingleInvoiceResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<SingleInvoiceResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Sends the request, queuing it to be sent by `Mpesa::flush_queue` if the Safaricom API
+    /// cannot be reached, see the `queue` module
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request is rejected, or if the client has no
+    /// `MpesaBuilder::degradation_queue`
+    pub async fn send_or_queue(self) -> MpesaResult<Delivery<SingleInvoiceResponse>> {
+        self.client.send_or_queue(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<Invoice<'_>>> {
         let payload = Invoice {
             amount: self
                 .amount
@@ -150,18 +189,22 @@ impl<'mpesa> SingleInvoiceBuilder<'mpesa> {
             external_reference: self
                 .external_reference
                 .ok_or(MpesaError::Message("external_reference is required"))?,
-            invoice_items: self.invoice_items,
+            invoice_items: self.invoice_items.clone(),
             invoice_name: self
                 .invoice_name
                 .ok_or(MpesaError::Message("invoice_name is required"))?,
         };
+        if self.client.validation {
+            payload.validate()?;
+        }
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: BILL_MANAGER_SINGLE_INVOICE_API_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::BillManager,
+            path: self
+                .client
+                .api_path(Service::BillManager, paths::BILL_MANAGER_SINGLE_INVOICE),
+            body: payload,
+        })
     }
 }
@@ -1,29 +1,34 @@
 #![doc = include_str!("../../docs/client/c2b_register.md")]
 
+use futures_util::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 
-use crate::client::Mpesa;
-use crate::constants::ResponseType;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{ResponseType, Service};
 use crate::errors::{MpesaError, MpesaResult};
-
-const C2B_REGISTER_URL: &str = "mpesa/c2b/v1/registerurl";
+use crate::paths;
+use crate::queue::Delivery;
 
 #[derive(Debug, Serialize)]
 /// Payload to register the 3rd party’s confirmation and validation URLs to M-Pesa
+#[serde(rename_all = "PascalCase")]
 struct C2bRegisterPayload<'mpesa> {
     #[serde(rename(serialize = "ValidationURL"))]
     validation_url: &'mpesa str,
     #[serde(rename(serialize = "ConfirmationURL"))]
     confirmation_url: &'mpesa str,
-    #[serde(rename(serialize = "ResponseType"))]
     response_type: ResponseType,
-    #[serde(rename(serialize = "ShortCode"))]
     short_code: &'mpesa str,
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct C2bRegisterResponse {
-    #[serde(rename(deserialize = "OriginatorCoversationID"))]
+    #[serde(
+        rename(deserialize = "OriginatorCoversationID"),
+        alias = "OriginatorConversationID"
+    )]
     pub originator_conversation_id: String,
     #[serde(rename(deserialize = "ResponseCode"))]
     pub response_code: String,
@@ -56,7 +61,7 @@ impl<'mpesa> C2bRegisterBuilder<'mpesa> {
     /// Adds `ValidationURL` for the client. This is a required field
     ///
     /// # Error
-    /// If `ValidationURL` is invalid or not provided
+    /// If `ValidationURL` is not provided, or is not an https url reachable by Safaricom
     pub fn validation_url(mut self, validation_url: &'mpesa str) -> C2bRegisterBuilder<'mpesa> {
         self.validation_url = Some(validation_url);
         self
@@ -65,7 +70,7 @@ impl<'mpesa> C2bRegisterBuilder<'mpesa> {
     /// Adds `ConfirmationUrl` for the client. This is a required field
     ///
     /// # Error
-    /// If `ConfirmationUrl` is invalid or not provided
+    /// If `ConfirmationUrl` is not provided, or is not an https url reachable by Safaricom
     pub fn confirmation_url(mut self, confirmation_url: &'mpesa str) -> C2bRegisterBuilder<'mpesa> {
         self.confirmation_url = Some(confirmation_url);
         self
@@ -102,27 +107,186 @@ impl<'mpesa> C2bRegisterBuilder<'mpesa> {
     ///
     /// # Errors
     /// Returns a `MpesaError` on failure
-
     pub async fn send(self) -> MpesaResult<C2bRegisterResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<C2bRegisterResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Sends the request, queuing it to be sent by `Mpesa::flush_queue` if the Safaricom API
+    /// cannot be reached, see the `queue` module
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request is rejected, or if the client has no
+    /// `MpesaBuilder::degradation_queue`
+    pub async fn send_or_queue(self) -> MpesaResult<Delivery<C2bRegisterResponse>> {
+        self.client.send_or_queue(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<C2bRegisterPayload<'_>>> {
+        let validation_url = self
+            .validation_url
+            .ok_or(MpesaError::Message("validation_url is required"))?;
+        let confirmation_url = self
+            .confirmation_url
+            .ok_or(MpesaError::Message("confirmation_url is required"))?;
+        self.client.check_callback_url(validation_url)?;
+        self.client.check_callback_url(confirmation_url)?;
+
         let payload = C2bRegisterPayload {
-            validation_url: self
-                .validation_url
-                .ok_or(MpesaError::Message("validation_url is required"))?,
-            confirmation_url: self
-                .confirmation_url
-                .ok_or(MpesaError::Message("confirmation_url is required"))?,
+            validation_url,
+            confirmation_url,
             response_type: self.response_type.unwrap_or(ResponseType::Completed),
             short_code: self
                 .short_code
                 .ok_or(MpesaError::Message("short_code is required"))?,
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: C2B_REGISTER_URL,
-                body: payload,
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::C2bRegister,
+            path: self
+                .client
+                .api_path(Service::C2bRegister, paths::C2B_REGISTER),
+            body: payload,
+        })
+    }
+}
+
+/// Placeholder replaced by the shortcode in the url templates of a `BulkC2bRegisterBuilder`
+const SHORT_CODE_PLACEHOLDER: &str = "{short_code}";
+
+/// Outcome of the registration of the urls of a shortcode by a `BulkC2bRegisterBuilder`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct C2bRegistration {
+    pub short_code: String,
+    pub validation_url: String,
+    pub confirmation_url: String,
+    pub result: MpesaResult<C2bRegisterResponse>,
+}
+
+#[derive(Debug)]
+/// Builder registering the C2B urls of several shortcodes, with a bounded number of requests in
+/// flight at a time
+pub struct BulkC2bRegisterBuilder<'mpesa> {
+    client: &'mpesa Mpesa,
+    short_codes: Vec<&'mpesa str>,
+    validation_url: Option<&'mpesa str>,
+    confirmation_url: Option<&'mpesa str>,
+    response_type: Option<ResponseType>,
+    concurrency: usize,
+}
+
+impl<'mpesa> BulkC2bRegisterBuilder<'mpesa> {
+    /// Creates a new bulk C2B Register builder sending up to 4 requests at a time
+    pub fn new(client: &'mpesa Mpesa) -> BulkC2bRegisterBuilder<'mpesa> {
+        BulkC2bRegisterBuilder {
+            client,
+            short_codes: vec![],
+            validation_url: None,
+            confirmation_url: None,
+            response_type: None,
+            concurrency: 4,
+        }
+    }
+
+    /// Adds a shortcode to register the urls of
+    pub fn short_code(mut self, short_code: &'mpesa str) -> BulkC2bRegisterBuilder<'mpesa> {
+        self.short_codes.push(short_code);
+        self
+    }
+
+    /// Adds shortcodes to register the urls of
+    pub fn short_codes<I>(mut self, short_codes: I) -> BulkC2bRegisterBuilder<'mpesa>
+    where
+        I: IntoIterator<Item = &'mpesa str>,
+    {
+        self.short_codes.extend(short_codes);
+        self
+    }
+
+    /// Adds the template of the `ValidationURL` of each shortcode, in which `{short_code}` is
+    /// replaced by the shortcode, e.g. `https://example.com/c2b/{short_code}/validation`.
+    /// This is a required field
+    pub fn validation_url(mut self, validation_url: &'mpesa str) -> BulkC2bRegisterBuilder<'mpesa> {
+        self.validation_url = Some(validation_url);
+        self
+    }
+
+    /// Adds the template of the `ConfirmationURL` of each shortcode, in which `{short_code}` is
+    /// replaced by the shortcode. This is a required field
+    pub fn confirmation_url(
+        mut self,
+        confirmation_url: &'mpesa str,
+    ) -> BulkC2bRegisterBuilder<'mpesa> {
+        self.confirmation_url = Some(confirmation_url);
+        self
+    }
+
+    /// Adds `ResponseType` for timeout. Will default to `ResponseType::Completed` if not explicitly provided
+    pub fn response_type(mut self, response_type: ResponseType) -> BulkC2bRegisterBuilder<'mpesa> {
+        self.response_type = Some(response_type);
+        self
+    }
+
+    /// Sets the maximum number of registration requests in flight at a time. Defaults to `4`
+    pub fn concurrency(mut self, concurrency: usize) -> BulkC2bRegisterBuilder<'mpesa> {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Registers the urls of every shortcode, returning the outcome of each registration in the
+    /// order the shortcodes were added. A failed registration does not stop the others.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a url template is missing
+    pub async fn send(self) -> MpesaResult<Vec<C2bRegistration>> {
+        let validation_url = self
+            .validation_url
+            .ok_or(MpesaError::Message("validation_url is required"))?;
+        let confirmation_url = self
+            .confirmation_url
+            .ok_or(MpesaError::Message("confirmation_url is required"))?;
+        let response_type = self.response_type.unwrap_or(ResponseType::Completed);
+        let client = self.client;
+
+        let registrations = stream::iter(self.short_codes)
+            .map(|short_code| async move {
+                let validation_url = validation_url.replace(SHORT_CODE_PLACEHOLDER, short_code);
+                let confirmation_url = confirmation_url.replace(SHORT_CODE_PLACEHOLDER, short_code);
+                let result = C2bRegisterBuilder::new(client)
+                    .short_code(short_code)
+                    .validation_url(&validation_url)
+                    .confirmation_url(&confirmation_url)
+                    .response_type(response_type)
+                    .send()
+                    .await;
+                C2bRegistration {
+                    short_code: short_code.to_owned(),
+                    validation_url,
+                    confirmation_url,
+                    result,
+                }
             })
-            .await
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        Ok(registrations)
     }
 }
@@ -1,37 +1,56 @@
 #![doc = include_str!("../../docs/client/c2b_simulate.md")]
 
-use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
 
-use crate::client::Mpesa;
-use crate::constants::CommandId;
-use crate::errors::{MpesaError, MpesaResult};
+use serde::{Deserialize, Serialize};
 
-const C2B_SIMULATE_URL: &str = "mpesa/c2b/v1/simulate";
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{CommandId, Service};
+use crate::errors::{MpesaError, MpesaResult, ValidationErrors};
+use crate::format::{mask_phone_number, summary_amount};
+use crate::paths;
 
 #[derive(Debug, Serialize)]
 /// Payload to make payment requests from C2B.
 /// See more: https://developer.safaricom.co.ke/docs#c2b-api
+#[serde(rename_all = "PascalCase")]
 struct C2bSimulatePayload<'mpesa> {
     #[serde(rename(serialize = "CommandID"))]
     command_id: CommandId,
-    #[serde(rename(serialize = "Amount"))]
+    #[serde(serialize_with = "crate::json::serialize_amount")]
     amount: f64,
-    #[serde(rename(serialize = "Msisdn"))]
-    msisdn: &'mpesa str,
-    #[serde(rename(serialize = "BillRefNumber"))]
+    msisdn: Cow<'mpesa, str>,
     bill_ref_number: &'mpesa str,
-    #[serde(rename(serialize = "ShortCode"))]
     short_code: &'mpesa str,
 }
 
+impl fmt::Display for C2bSimulatePayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "C2B {} {}→{} via {}",
+            summary_amount(self.amount),
+            mask_phone_number(&self.msisdn),
+            self.short_code,
+            self.command_id
+        )
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct C2bSimulateResponse {
     #[serde(
         rename(deserialize = "ConversationID"),
         skip_serializing_if = "Option::is_none"
     )]
     pub conversation_id: Option<String>,
-    #[serde(rename(deserialize = "OriginatorCoversationID"))]
+    #[serde(
+        rename(deserialize = "OriginatorCoversationID"),
+        alias = "OriginatorConversationID"
+    )]
     pub originator_conversation_id: String,
     #[serde(rename(deserialize = "ResponseCode"))]
     pub response_code: String,
@@ -62,15 +81,21 @@ impl<'mpesa> C2bSimulateBuilder<'mpesa> {
         }
     }
 
-    /// Adds `CommandId`. Defaults to `CommandId::CustomerPaybillOnline` if no value explicitly passed
+    /// Adds `CommandId`. Defaults to `CommandId::CustomerPayBillOnline` if no value explicitly passed
     ///
     /// # Errors
-    /// If `CommandId` is not valid
+    /// If `CommandId` is neither `CustomerPayBillOnline` nor `CustomerBuyGoodsOnline`
     pub fn command_id(mut self, command_id: CommandId) -> C2bSimulateBuilder<'mpesa> {
         self.command_id = Some(command_id);
         self
     }
 
+    /// Simulates a payment to a till number, with the `CustomerBuyGoodsOnline` command.
+    /// Till payments carry no `BillRefNumber`, which must be left out.
+    pub fn buy_goods(self) -> C2bSimulateBuilder<'mpesa> {
+        self.command_id(CommandId::CustomerBuyGoodsOnline)
+    }
+
     /// Adds an `amount` to the request
     ///
     /// # Errors
@@ -80,6 +105,16 @@ impl<'mpesa> C2bSimulateBuilder<'mpesa> {
         self
     }
 
+    /// Adds an `amount` in whole shillings
+    pub fn amount_kes(self, amount: u32) -> C2bSimulateBuilder<'mpesa> {
+        self.amount(amount)
+    }
+
+    /// Adds an `amount` in cents, e.g. `150_000` for KES 1,500
+    pub fn amount_cents(self, cents: u64) -> C2bSimulateBuilder<'mpesa> {
+        self.amount(cents as f64 / 100.0)
+    }
+
     /// Adds the MSISDN(phone number) sending the transaction, start by country code without the `+`.
     /// This is a required field
     ///
@@ -99,15 +134,34 @@ impl<'mpesa> C2bSimulateBuilder<'mpesa> {
         self
     }
 
-    /// Adds Bill reference number.
+    /// Adds Bill reference number, the account number entered by the customer when paying a
+    /// Pay Bill number.
     ///
     /// # Errors
-    /// If `BillRefNumber` is invalid or not provided
+    /// If `BillRefNumber` is not provided when paying a Pay Bill number, or is provided when paying
+    /// a till number
     pub fn bill_ref_number(mut self, bill_ref_number: &'mpesa str) -> C2bSimulateBuilder<'mpesa> {
         self.bill_ref_number = Some(bill_ref_number);
         self
     }
 
+    /// Checks every field of the request, returning all the missing fields at once instead of
+    /// failing on the first one like `send` does.
+    ///
+    /// # Errors
+    /// Returns `ValidationErrors` listing every problem found
+    pub fn validate_all(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.require(self.amount, MpesaError::Message("amount is required"));
+        errors.require(self.msisdn, MpesaError::Message("msisdn is required"));
+        errors.check(self.resolved_bill_ref_number().map(|_| ()));
+        errors.require(
+            self.short_code,
+            MpesaError::Message("short_code is required"),
+        );
+        errors.into_result()
+    }
+
     /// # C2B Simulate API
     ///
     /// Make payment requests from Client to Business
@@ -120,28 +174,81 @@ impl<'mpesa> C2bSimulateBuilder<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<C2bSimulateResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<C2bSimulateResponse>> {
+        self.client.send_with_meta(self.request()?).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request()?)
+    }
+
+    /// Renders a one-line summary of the request, such as
+    /// `C2B 1,000 KES 2547****149→600496 via CustomerPayBillOnline`, for logs and support tooling
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn summary(&self) -> MpesaResult<String> {
+        Ok(self.request()?.body.to_string())
+    }
+
+    /// Returns the `BillRefNumber`, required to pay a Pay Bill number and empty when paying a till
+    /// number
+    fn resolved_bill_ref_number(&self) -> MpesaResult<&'mpesa str> {
+        match self.resolved_command_id() {
+            CommandId::CustomerPayBillOnline => self
+                .bill_ref_number
+                .ok_or(MpesaError::Message("bill_ref_number is required")),
+            CommandId::CustomerBuyGoodsOnline => match self.bill_ref_number {
+                None | Some("") => Ok(""),
+                Some(_) => Err(MpesaError::Message(
+                    "bill_ref_number must be empty when paying a till number",
+                )),
+            },
+            _ => Err(MpesaError::Message(
+                "command_id must be CustomerPayBillOnline or CustomerBuyGoodsOnline",
+            )),
+        }
+    }
+
+    fn resolved_command_id(&self) -> CommandId {
+        self.command_id.unwrap_or(CommandId::CustomerPayBillOnline)
+    }
+
+    fn request(&self) -> MpesaResult<crate::client::Request<C2bSimulatePayload<'_>>> {
         let payload = C2bSimulatePayload {
-            command_id: self.command_id.unwrap_or(CommandId::CustomerPayBillOnline),
+            command_id: self.resolved_command_id(),
             amount: self
                 .amount
                 .ok_or(MpesaError::Message("amount is required"))?,
             msisdn: self
                 .msisdn
+                .map(|msisdn| self.client.msisdn(msisdn))
                 .ok_or(MpesaError::Message("msisdn is required"))?,
-            bill_ref_number: self
-                .bill_ref_number
-                .ok_or(MpesaError::Message("bill_ref_number is required"))?,
+            bill_ref_number: self.resolved_bill_ref_number()?,
             short_code: self
                 .short_code
                 .ok_or(MpesaError::Message("short_code is required"))?,
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: C2B_SIMULATE_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::C2bSimulate,
+            path: self
+                .client
+                .api_path(Service::C2bSimulate, paths::C2B_SIMULATE),
+            body: payload,
+        })
     }
 }
@@ -0,0 +1,25 @@
+/// `PartyA` of a request, the party the money is sent from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartyA<'a>(pub &'a str);
+
+/// `PartyB` of a request, the party the money is sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartyB<'a>(pub &'a str);
+
+/// The `QueueTimeOutURL` and `ResultURL` of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallbackUrls<'a> {
+    /// Where Safaricom posts the result of a request that timed out in its queue
+    pub timeout_url: &'a str,
+    /// Where Safaricom posts the result of the request
+    pub result_url: &'a str,
+}
+
+impl<'a> CallbackUrls<'a> {
+    pub fn new(timeout_url: &'a str, result_url: &'a str) -> Self {
+        CallbackUrls {
+            timeout_url,
+            result_url,
+        }
+    }
+}
@@ -1,15 +1,18 @@
 #![doc = include_str!("../../docs/client/dynamic_qr.md")]
 
+use std::fmt;
+
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
-use crate::client::Mpesa;
-use crate::constants::TransactionType;
+use crate::client::{Mpesa, WithMeta};
+use crate::constants::{Service, TransactionType};
 use crate::errors::{MpesaError, MpesaResult};
-
-const DYNAMIC_QR_URL: &str = "mpesa/qrcode/v1/generate";
+use crate::format::{mask_phone_number, summary_amount};
+use crate::paths;
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all(serialize = "PascalCase"))]
 pub struct DynamicQRRequest<'mpesa> {
     /// Name of the Company/M-Pesa Merchant Name
@@ -41,8 +44,22 @@ pub struct DynamicQRRequest<'mpesa> {
     pub size: &'mpesa str,
 }
 
+impl fmt::Display for DynamicQRRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Dynamic QR {} to {} via {}",
+            summary_amount(self.amount),
+            mask_phone_number(self.credit_party_identifier),
+            self.transaction_type
+        )
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all(deserialize = "PascalCase"))]
+#[non_exhaustive]
 pub struct DynamicQRResponse {
     #[serde(rename(deserialize = "QRCode"))]
     pub qr_code: String,
@@ -99,6 +116,12 @@ impl<'mpesa> From<DynamicQR<'mpesa>> for DynamicQRRequest<'mpesa> {
     }
 }
 
+impl fmt::Display for DynamicQR<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.request().body.fmt(f)
+    }
+}
+
 impl<'mpesa> DynamicQR<'mpesa> {
     pub(crate) fn builder(client: &'mpesa Mpesa) -> DynamicQRBuilder<'mpesa> {
         DynamicQRBuilder::default().client(client)
@@ -136,12 +159,32 @@ impl<'mpesa> DynamicQR<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<DynamicQRResponse> {
-        self.client
-            .send::<DynamicQRRequest, _>(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: DYNAMIC_QR_URL,
-                body: self.into(),
-            })
-            .await
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<DynamicQRResponse>> {
+        self.client.send_with_meta(self.request()).await
+    }
+
+    /// Renders the request as a curl command, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request cannot be serialized
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&self.request())
+    }
+
+    fn request(&self) -> crate::client::Request<DynamicQRRequest<'_>> {
+        crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::DynamicQr,
+            path: self.client.api_path(Service::DynamicQr, paths::DYNAMIC_QR),
+            body: self.clone().into(),
+        }
     }
 }
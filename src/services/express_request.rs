@@ -1,24 +1,33 @@
 #![doc = include_str!("../../docs/client/express_request.md")]
 
-use chrono::prelude::Local;
-use chrono::DateTime;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use derive_builder::Builder;
-use openssl::base64;
+use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 use url::Url;
+use zeroize::Zeroizing;
 
-use crate::client::Mpesa;
-use crate::constants::CommandId;
-use crate::errors::{MpesaError, MpesaResult};
-use crate::validator::PhoneNumberValidator;
+use crate::client::{self, Mpesa, UrlKind, WithMeta};
+use crate::constants::SANDBOX_PASSKEY;
+use crate::constants::{CommandId, Service, PASSWORD_PLACEHOLDER, REDACTED};
+use crate::datetime::{self, format_timestamp, Timestamp};
+use crate::errors::{BuilderError, MpesaError, MpesaResult, ValidationErrors};
+use crate::format::{mask_phone_number, summary_amount};
+use crate::paths;
+use crate::services::RequestTemplate;
+use crate::storage::{namespaces, DynStorage};
+use crate::validator::{validate_callback_url, PartyType, PhoneNumberValidator};
 
 /// Source: [test credentials](https://developer.safaricom.co.ke/test_credentials)
-pub static DEFAULT_PASSKEY: &str =
-    "bfb279f9aa9bdbcf158e97dd71a467cd2e0c893059b10f78e6b72ada1ed2c919";
-
-const EXPRESS_REQUEST_URL: &str = "mpesa/stkpush/v1/processrequest";
+pub static DEFAULT_PASSKEY: &str = SANDBOX_PASSKEY;
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "PascalCase")]
 pub struct MpesaExpressRequest<'mpesa> {
     /// This is the organization's shortcode (Paybill or Buygoods - A 5 to
@@ -30,7 +39,8 @@ pub struct MpesaExpressRequest<'mpesa> {
     /// This is the Timestamp of the transaction, normally in the format of
     /// (YYYYMMDDHHMMSS)
     #[serde(serialize_with = "serialize_utc_to_string")]
-    pub timestamp: DateTime<Local>,
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub timestamp: Timestamp,
     /// This is the transaction type that is used to identify the transaction
     /// when sending the request to M-PESA
     ///
@@ -54,6 +64,7 @@ pub struct MpesaExpressRequest<'mpesa> {
     /// notifications from M-Pesa API.
     /// It is the endpoint to which the results will be sent by M-Pesa API.
     #[serde(rename = "CallBackURL")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub call_back_url: Url,
     /// Account Reference: This is an Alpha-Numeric parameter that is defined
     /// by your system as an Identifier of the transaction for
@@ -64,17 +75,49 @@ pub struct MpesaExpressRequest<'mpesa> {
     pub transaction_desc: Option<&'mpesa str>,
 }
 
-fn serialize_utc_to_string<S>(date: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error>
+impl fmt::Debug for MpesaExpressRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MpesaExpressRequest")
+            .field("business_short_code", &self.business_short_code)
+            .field("password", &REDACTED)
+            .field("timestamp", &self.timestamp)
+            .field("transaction_type", &self.transaction_type)
+            .field("amount", &self.amount)
+            .field("party_a", &self.party_a)
+            .field("party_b", &self.party_b)
+            .field("phone_number", &self.phone_number)
+            .field("call_back_url", &self.call_back_url)
+            .field("account_reference", &self.account_reference)
+            .field("transaction_desc", &self.transaction_desc)
+            .finish()
+    }
+}
+
+impl fmt::Display for MpesaExpressRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "STK push {} {}→{} via {}",
+            summary_amount(self.amount),
+            mask_phone_number(self.phone_number),
+            self.business_short_code,
+            self.transaction_type
+        )
+    }
+}
+
+fn serialize_utc_to_string<S>(date: &Timestamp, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    let s = date.format("%Y%m%d%H%M%S").to_string();
-    serializer.serialize_str(&s)
+    serializer.serialize_str(&format_timestamp(date))
 }
 
 // TODO:: The success response has more fields than this
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
 pub struct MpesaExpressResponse {
     ///This is a global unique identifier of the processed checkout transaction
     /// request.
@@ -98,7 +141,7 @@ pub struct MpesaExpressResponse {
     pub response_description: String,
 }
 
-#[derive(Builder, Debug, Clone)]
+#[derive(Builder, Clone)]
 #[builder(build_fn(error = "MpesaError", validate = "Self::validate"))]
 pub struct MpesaExpress<'mpesa> {
     #[builder(pattern = "immutable")]
@@ -126,7 +169,12 @@ pub struct MpesaExpress<'mpesa> {
     /// A CallBack URL is a valid secure URL that is used to receive
     /// notifications from M-Pesa API.
     /// It is the endpoint to which the results will be sent by M-Pesa API.
-    #[builder(try_setter, setter(into))]
+    /// Defaults to the `MpesaBuilder::default_callback_url` of the client.
+    #[builder(
+        try_setter,
+        setter(into),
+        default = "client::default_url(self.client, UrlKind::Callback, \"callback_url\")?"
+    )]
     callback_url: Url,
     /// Account Reference: This is an Alpha-Numeric parameter that is defined
     /// by your system as an Identifier of the transaction for
@@ -141,16 +189,51 @@ pub struct MpesaExpress<'mpesa> {
     /// The password for encrypting the request is obtained by base64 encoding
     /// BusinessShortCode, Passkey and Timestamp.
     /// The timestamp format is YYYYMMDDHHmmss
-    #[builder(setter(into, strip_option), default = "Some(DEFAULT_PASSKEY)")]
-    pass_key: Option<&'mpesa str>,
+    #[builder(setter(custom), default)]
+    pass_key: Option<Secret<String>>,
+}
+
+impl fmt::Debug for MpesaExpress<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MpesaExpress")
+            .field("client", &self.client)
+            .field("business_short_code", &self.business_short_code)
+            .field("transaction_type", &self.transaction_type)
+            .field("amount", &self.amount)
+            .field("party_a", &self.party_a)
+            .field("party_b", &self.party_b)
+            .field("phone_number", &self.phone_number)
+            .field("callback_url", &self.callback_url)
+            .field("account_ref", &self.account_ref)
+            .field("transaction_desc", &self.transaction_desc)
+            .field("pass_key", &self.pass_key.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+impl fmt::Display for MpesaExpress<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "STK push {} {}→{} via {}",
+            summary_amount(self.amount),
+            mask_phone_number(self.phone_number),
+            self.business_short_code,
+            self.transaction_type
+        )
+    }
 }
 
 impl<'mpesa> From<MpesaExpress<'mpesa>> for MpesaExpressRequest<'mpesa> {
     fn from(express: MpesaExpress<'mpesa>) -> MpesaExpressRequest<'mpesa> {
-        let timestamp = chrono::Local::now();
+        let timestamp = datetime::now();
 
-        let encoded_password =
-            MpesaExpress::encode_password(express.business_short_code, express.pass_key);
+        // The password must be encoded with the same timestamp that is sent with the request
+        let encoded_password = MpesaExpress::encode_password_at(
+            express.business_short_code,
+            express.pass_key.as_ref(),
+            &timestamp,
+        );
 
         MpesaExpressRequest {
             business_short_code: express.business_short_code,
@@ -169,11 +252,60 @@ impl<'mpesa> From<MpesaExpress<'mpesa>> for MpesaExpressRequest<'mpesa> {
 }
 
 impl MpesaExpressBuilder<'_> {
+    /// Sets the passkey the password of the request is encoded with, `DEFAULT_PASSKEY`, the
+    /// passkey of the sandbox shortcode, by default
+    pub fn pass_key(&mut self, pass_key: impl Into<String>) -> &mut Self {
+        self.pass_key = Some(Some(Secret::new(pass_key.into())));
+        self
+    }
+
     /// Validates the request, returning a `MpesaError` if validation fails
     ///
     /// Express requests can only be of type `BusinessBuyGoods` or
-    /// `CustomerPayBillOnline`
+    /// `CustomerPayBillOnline`, and their callback url must be reachable by Safaricom
     fn validate(&self) -> MpesaResult<()> {
+        self.validate_transaction_type()?;
+
+        if let Some(phone_number) = self.phone_number {
+            self.validate_phone_number(phone_number)?;
+        }
+        self.validate_parties()?;
+        self.validate_callback_url()?;
+
+        Ok(())
+    }
+
+    /// Sets the callback url to `path` joined onto the `MpesaBuilder::default_callback_url` of
+    /// the client, e.g. `stk/orders` for `https://pay.example.com/mpesa/callback/stk/orders`
+    ///
+    /// # Errors
+    /// If the joined url is invalid, such as when the client has no default callback url
+    pub fn callback_path(&mut self, path: &str) -> MpesaResult<&mut Self> {
+        let url = client::url_from_path(self.client, UrlKind::Callback, path)?;
+        Ok(self.callback_url(url))
+    }
+
+    /// Sets the `amount` in whole shillings
+    pub fn amount_kes(&mut self, amount: u32) -> &mut Self {
+        self.amount(amount)
+    }
+
+    /// Sets the `amount` in cents, e.g. `150_000` for KES 1,500
+    ///
+    /// # Errors
+    /// If `cents` is not a whole number of shillings, the only amounts M-Pesa Express accepts
+    pub fn amount_cents(&mut self, cents: u64) -> MpesaResult<&mut Self> {
+        if !cents.is_multiple_of(100) {
+            return Err(MpesaError::Message(
+                "M-Pesa Express amounts must be whole shillings",
+            ));
+        }
+        let amount =
+            u32::try_from(cents / 100).map_err(|_| MpesaError::Message("amount is too large"))?;
+        Ok(self.amount(amount))
+    }
+
+    fn validate_transaction_type(&self) -> MpesaResult<()> {
         if self.transaction_type != Some(CommandId::BusinessBuyGoods)
             && self.transaction_type != Some(CommandId::CustomerPayBillOnline)
         {
@@ -181,13 +313,83 @@ impl MpesaExpressBuilder<'_> {
                 "Invalid transaction type. Expected BusinessBuyGoods or CustomerPayBillOnline",
             ));
         }
+        Ok(())
+    }
 
-        if let Some(phone_number) = self.phone_number {
-            phone_number.validate()?;
+    fn validate_phone_number(&self, phone_number: &str) -> MpesaResult<()> {
+        match self.client {
+            Some(client) if !client.validation => Ok(()),
+            Some(client) => client.msisdn(phone_number).as_ref().validate(),
+            None => phone_number.validate(),
         }
+    }
 
+    /// Checks that the customer paying, `party_a`, is a phone number and `party_b` a shortcode
+    fn validate_parties(&self) -> MpesaResult<()> {
+        let Some(command_id) = self.transaction_type else {
+            return Ok(());
+        };
+        if matches!(self.client, Some(client) if !client.validation) {
+            return Ok(());
+        }
+        if let Some(party_a) = self.party_a {
+            PartyType::Msisdn.check(command_id, "PartyA", party_a)?;
+        }
+        if let Some(party_b) = self.party_b {
+            PartyType::ShortCode.check(command_id, "PartyB", party_b)?;
+        }
         Ok(())
     }
+
+    /// Checks that Safaricom can deliver the callback to the callback url, or to the default
+    /// callback url of the client if none is set
+    fn validate_callback_url(&self) -> MpesaResult<()> {
+        let url = match &self.callback_url {
+            Some(url) => url.clone(),
+            None => match client::default_url(self.client, UrlKind::Callback, "callback_url") {
+                Ok(url) => url,
+                // A missing callback url is reported by `build`
+                Err(_) => return Ok(()),
+            },
+        };
+        match self.client {
+            Some(client) => client.check_callback_url(url.as_str()),
+            None => validate_callback_url(url.as_str()),
+        }
+    }
+
+    /// Checks every field of the request, returning all the missing and invalid fields at once
+    /// instead of failing on the first one like `build` does.
+    ///
+    /// # Errors
+    /// Returns `ValidationErrors` listing every problem found
+    pub fn validate_all(&self) -> Result<(), ValidationErrors> {
+        let missing = |field| MpesaError::BuilderError(BuilderError::UninitializedField(field));
+        let mut errors = ValidationErrors::default();
+
+        errors.require(self.business_short_code, missing("business_short_code"));
+        match self.transaction_type {
+            Some(_) => errors.check(self.validate_transaction_type()),
+            None => errors.require(self.transaction_type, missing("transaction_type")),
+        }
+        errors.require(self.amount, missing("amount"));
+        errors.require(self.party_a, missing("party_a"));
+        errors.require(self.party_b, missing("party_b"));
+        errors.check(self.validate_parties());
+        match self.phone_number {
+            Some(phone_number) => errors.check(self.validate_phone_number(phone_number)),
+            None => errors.require(self.phone_number, missing("phone_number")),
+        }
+        if self.callback_url.is_none() {
+            errors.check(
+                client::default_url(self.client, UrlKind::Callback, "callback_url").map(|_| ()),
+            );
+        }
+        errors.check(self.validate_callback_url());
+        errors.require(self.account_ref, missing("account_ref"));
+
+        errors.into_result()
+    }
 }
 
 impl<'mpesa> MpesaExpress<'mpesa> {
@@ -199,25 +401,51 @@ impl<'mpesa> MpesaExpress<'mpesa> {
     /// Encodes the password for the request
     /// The password for encrypting the request is obtained by base64 encoding
     /// BusinessShortCode, Passkey and Timestamp.
-    /// The timestamp format is YYYYMMDDHHmmss
-    pub fn encode_password(business_short_code: &str, pass_key: Option<&'mpesa str>) -> String {
-        let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S").to_string();
-        base64::encode_block(
-            format!(
-                "{}{}{}",
-                business_short_code,
-                pass_key.unwrap_or(DEFAULT_PASSKEY),
-                timestamp
-            )
-            .as_bytes(),
+    /// The timestamp format is YYYYMMDDHHmmss, in Nairobi time
+    pub fn encode_password(business_short_code: &str, pass_key: Option<&Secret<String>>) -> String {
+        Self::encode_password_at(business_short_code, pass_key, &datetime::now())
+    }
+
+    /// Encodes the password for a request sent with the `Timestamp` converted from `timestamp`
+    /// by `datetime::timestamp_from_time`
+    #[cfg(feature = "time")]
+    pub fn encode_password_at_time(
+        business_short_code: &str,
+        pass_key: Option<&Secret<String>>,
+        timestamp: time::OffsetDateTime,
+    ) -> String {
+        Self::encode_password_at(
+            business_short_code,
+            pass_key,
+            &datetime::timestamp_from_time(timestamp),
         )
     }
 
+    pub(crate) fn encode_password_at(
+        business_short_code: &str,
+        pass_key: Option<&Secret<String>>,
+        timestamp: &Timestamp,
+    ) -> String {
+        let password = Zeroizing::new(format!(
+            "{}{}{}",
+            business_short_code,
+            pass_key.map_or(DEFAULT_PASSKEY, |pass_key| pass_key.expose_secret()),
+            format_timestamp(timestamp)
+        ));
+        STANDARD.encode(password.as_bytes())
+    }
+
+    /// Turns the request into a template for requests differing only by their customer, amount
+    /// and account reference
+    pub fn into_template(self) -> RequestTemplate<Self> {
+        RequestTemplate::new(self)
+    }
+
     /// Creates a new `MpesaExpress` from a `MpesaExpressRequest`
     pub fn from_request(
         client: &'mpesa Mpesa,
         request: MpesaExpressRequest<'mpesa>,
-        pass_key: Option<&'mpesa str>,
+        pass_key: Option<Secret<String>>,
     ) -> MpesaExpress<'mpesa> {
         MpesaExpress {
             client,
@@ -243,12 +471,162 @@ impl<'mpesa> MpesaExpress<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure
     pub async fn send(self) -> MpesaResult<MpesaExpressResponse> {
-        self.client
-            .send::<MpesaExpressRequest, _>(crate::client::Request {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<MpesaExpressResponse>> {
+        let client = self.client;
+        let party_a = client.msisdn(self.party_a);
+        let phone_number = client.msisdn(self.phone_number);
+
+        let guard = client.stk_push_guard.as_deref().map(|guard| {
+            let key = StkPushGuard::key(&phone_number, self.amount, self.account_ref);
+            (guard, key)
+        });
+        if let Some((guard, key)) = &guard {
+            guard.reserve(key).await?;
+        }
+
+        let mut request = MpesaExpressRequest::from(self);
+        request.party_a = &party_a;
+        request.phone_number = &phone_number;
+
+        let response = client
+            .send_with_meta::<MpesaExpressRequest, MpesaExpressResponse>(crate::client::Request {
                 method: reqwest::Method::POST,
-                path: EXPRESS_REQUEST_URL,
-                body: self.into(),
+                service: Service::ExpressRequest,
+                path: client.api_path(Service::ExpressRequest, paths::EXPRESS_REQUEST),
+                body: request,
             })
+            .await;
+        if let Some((guard, key)) = &guard {
+            match &response {
+                Ok(sent) => guard.save(key, &sent.response.checkout_request_id).await?,
+                Err(_) => guard.release(key).await?,
+            }
+        }
+        response
+    }
+
+    /// Renders the request as a curl command, with a placeholder in place of the password
+    /// derived from the passkey, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request cannot be serialized
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        let client = self.client;
+        let party_a = client.msisdn(self.party_a);
+        let phone_number = client.msisdn(self.phone_number);
+
+        let mut request = MpesaExpressRequest::from(self.clone());
+        request.password = PASSWORD_PLACEHOLDER.to_owned();
+        request.party_a = &party_a;
+        request.phone_number = &phone_number;
+
+        client.to_curl(&crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::ExpressRequest,
+            path: client.api_path(Service::ExpressRequest, paths::EXPRESS_REQUEST),
+            body: request,
+        })
+    }
+}
+
+/// Keeps the STK pushes sent within a window, see `MpesaBuilder::stk_push_guard`
+#[derive(Debug)]
+pub(crate) struct StkPushGuard {
+    storage: Arc<dyn DynStorage>,
+    window: Duration,
+}
+
+impl StkPushGuard {
+    pub(crate) fn new(storage: Arc<dyn DynStorage>, window: Duration) -> Self {
+        StkPushGuard { storage, window }
+    }
+
+    fn key(phone_number: &str, amount: u32, account_ref: &str) -> String {
+        format!("{phone_number}:{amount}:{account_ref}")
+    }
+
+    /// Reserves `key` for a push about to be sent
+    ///
+    /// # Errors
+    /// Returns `MpesaError::DuplicateStkPush` if an identical push was sent within the window
+    async fn reserve(&self, key: &str) -> MpesaResult<()> {
+        let reserved = self
+            .storage
+            .compare_and_swap(
+                namespaces::STK_PUSHES,
+                key,
+                None,
+                Some(b""),
+                Some(self.window),
+            )
+            .await?;
+        if reserved {
+            return Ok(());
+        }
+        let checkout_request_id = self
+            .storage
+            .get(namespaces::STK_PUSHES, key)
+            .await?
+            .filter(|id| !id.is_empty())
+            .map(|id| String::from_utf8_lossy(&id).into_owned());
+        Err(MpesaError::DuplicateStkPush {
+            checkout_request_id,
+        })
+    }
+
+    /// Keeps the `CheckoutRequestID` of the push sent for `key` until the end of the window
+    async fn save(&self, key: &str, checkout_request_id: &str) -> MpesaResult<()> {
+        self.storage
+            .set(
+                namespaces::STK_PUSHES,
+                key,
+                checkout_request_id.as_bytes(),
+                Some(self.window),
+            )
+            .await
+    }
+
+    /// Frees `key` after its push failed, for it to be sent again
+    async fn release(&self, key: &str) -> MpesaResult<()> {
+        self.storage
+            .compare_and_swap(namespaces::STK_PUSHES, key, Some(b""), None, None)
             .await
+            .map(|_| ())
+    }
+}
+
+impl<'mpesa> RequestTemplate<MpesaExpress<'mpesa>> {
+    /// Returns the request of the template for the customer with `phone_number`, who is both
+    /// prompted and charged, for `amount` shillings and with `account_ref` as account reference.
+    /// Only the phone number is validated, the rest of the request was when the template was built.
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the phone number is not valid
+    pub fn stamp<'a>(
+        &self,
+        phone_number: &'a str,
+        amount: u32,
+        account_ref: &'a str,
+    ) -> MpesaResult<MpesaExpress<'a>>
+    where
+        'mpesa: 'a,
+    {
+        let mut request: MpesaExpress<'a> = self.request.clone();
+        if request.client.validation {
+            request.client.msisdn(phone_number).as_ref().validate()?;
+        }
+        request.party_a = phone_number;
+        request.phone_number = phone_number;
+        request.amount = amount;
+        request.account_ref = account_ref;
+        Ok(request)
     }
 }
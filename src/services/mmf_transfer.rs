@@ -0,0 +1,212 @@
+#![doc = include_str!("../../docs/client/mmf_transfer.md")]
+
+use super::b2b::B2bPayload;
+use super::B2bResponse;
+use crate::client::{Mpesa, UrlKind, WithMeta};
+use crate::constants::{CommandId, IdentifierTypes, Service, SECURITY_CREDENTIAL_PLACEHOLDER};
+use crate::errors::{MpesaError, MpesaResult, ValidationErrors};
+use crate::paths;
+
+/// Response of a transfer from the MMF account to the utility account, which is a B2B request
+pub type MmfTransferResponse = B2bResponse;
+
+#[derive(Debug)]
+/// Builder of a transfer from the MMF/Working account of an organization to its utility account
+pub struct MmfTransferBuilder<'mpesa> {
+    initiator_name: &'mpesa str,
+    client: &'mpesa Mpesa,
+    amount: Option<f64>,
+    party_a: Option<&'mpesa str>,
+    party_b: Option<&'mpesa str>,
+    remarks: Option<&'mpesa str>,
+    queue_timeout_url: Option<&'mpesa str>,
+    result_url: Option<&'mpesa str>,
+    account_ref: Option<&'mpesa str>,
+}
+
+impl<'mpesa> MmfTransferBuilder<'mpesa> {
+    /// Creates a new MMF to utility transfer builder
+    /// Requires an `initiator_name`, the credential/ username used to authenticate the transaction request
+    pub fn new(client: &'mpesa Mpesa, initiator_name: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        MmfTransferBuilder {
+            client,
+            initiator_name,
+            amount: None,
+            party_a: None,
+            party_b: None,
+            remarks: None,
+            queue_timeout_url: None,
+            result_url: None,
+            account_ref: None,
+        }
+    }
+
+    /// Adds `Party A`, the shortcode of the organization whose MMF account is debited.
+    /// This is a required field
+    pub fn party_a(mut self, party_a: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        self.party_a = Some(party_a);
+        self
+    }
+
+    /// Adds `Party B`, the shortcode whose utility account is credited.
+    /// Defaults to `Party A` since both accounts belong to the same organization.
+    ///
+    /// # Errors
+    /// If `Party B` is not the shortcode of `Party A`
+    pub fn party_b(mut self, party_b: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        self.party_b = Some(party_b);
+        self
+    }
+
+    /// Adds `QueueTimeoutUrl`
+    pub fn timeout_url(mut self, timeout_url: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        self.queue_timeout_url = Some(timeout_url);
+        self
+    }
+
+    /// Adds `ResultUrl`, where the `MmfTransferResult` of the transfer is posted
+    pub fn result_url(mut self, result_url: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        self.result_url = Some(result_url);
+        self
+    }
+
+    /// Adds `account_ref`
+    pub fn account_ref(mut self, account_ref: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        self.account_ref = Some(account_ref);
+        self
+    }
+
+    /// Adds an `amount` to the request
+    /// This is a required field
+    pub fn amount<Number: Into<f64>>(mut self, amount: Number) -> MmfTransferBuilder<'mpesa> {
+        self.amount = Some(amount.into());
+        self
+    }
+
+    /// Adds an `amount` in whole shillings
+    pub fn amount_kes(self, amount: u32) -> MmfTransferBuilder<'mpesa> {
+        self.amount(amount)
+    }
+
+    /// Adds an `amount` in cents, e.g. `150_000` for KES 1,500
+    pub fn amount_cents(self, cents: u64) -> MmfTransferBuilder<'mpesa> {
+        self.amount(cents as f64 / 100.0)
+    }
+
+    /// Adds `remarks`. This field is optional, will default to "None" if not explicitly passed
+    pub fn remarks(mut self, remarks: &'mpesa str) -> MmfTransferBuilder<'mpesa> {
+        self.remarks = Some(remarks);
+        self
+    }
+
+    /// Checks every field of the request, returning all the problems at once instead of
+    /// failing on the first one like `send` does.
+    ///
+    /// # Errors
+    /// Returns `ValidationErrors` listing every problem found
+    pub fn validate_all(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.require(self.amount, MpesaError::Message("amount is required"));
+        errors.check(self.parties().map(|_| ()));
+        errors.into_result()
+    }
+
+    /// # MMF to utility transfer
+    ///
+    /// Sends a B2B request with the `BusinessTransferFromMMFToUtility` command, moving funds from
+    /// the MMF/Working account of an organization to its utility account. Both parties are
+    /// identified by their shortcode.
+    ///
+    /// A successful request returns a `MmfTransferResponse` type
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send(self) -> MpesaResult<MmfTransferResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<MmfTransferResponse>> {
+        let credentials = self.client.security_credential_for(self.initiator_name)?;
+        self.client
+            .send_with_meta(self.request(&credentials)?)
+            .await
+    }
+
+    /// Renders the request as a curl command, with a placeholder in place of the security
+    /// credential, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client
+            .to_curl(&self.request(SECURITY_CREDENTIAL_PLACEHOLDER)?)
+    }
+
+    /// Renders a one-line summary of the request without its secrets, such as
+    /// `B2B 5,000 KES 600496→600496 via BusinessTransferFromMMFToUtility`, for logs and support tooling
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing or invalid
+    pub fn summary(&self) -> MpesaResult<String> {
+        Ok(self
+            .request(SECURITY_CREDENTIAL_PLACEHOLDER)?
+            .body
+            .to_string())
+    }
+
+    /// Returns the shortcodes of `Party A` and `Party B`
+    fn parties(&self) -> MpesaResult<(&'mpesa str, &'mpesa str)> {
+        let party_a = self
+            .party_a
+            .ok_or(MpesaError::Message("party_a is required"))?;
+        let party_b = self.party_b.unwrap_or(party_a);
+        if party_a != party_b {
+            return Err(MpesaError::Message(
+                "party_a and party_b must be the shortcode of the same organization",
+            ));
+        }
+        self.client.check_parties(
+            CommandId::BusinessTransferFromMMFToUtility,
+            party_a,
+            party_b,
+        )?;
+        Ok((party_a, party_b))
+    }
+
+    fn request<'a>(
+        &'a self,
+        security_credential: &'a str,
+    ) -> MpesaResult<crate::client::Request<B2bPayload<'a>>> {
+        let (party_a, party_b) = self.parties()?;
+        let payload = B2bPayload {
+            initiator: self.initiator_name,
+            security_credential,
+            command_id: CommandId::BusinessTransferFromMMFToUtility,
+            amount: self
+                .amount
+                .ok_or(MpesaError::Message("amount is required"))?,
+            party_a,
+            sender_identifier_type: IdentifierTypes::ShortCode.to_string(),
+            party_b,
+            receiver_identifier_type: IdentifierTypes::ShortCode.to_string(),
+            remarks: self.remarks.unwrap_or(stringify!(None)),
+            queue_time_out_url: self
+                .client
+                .resolve_url(UrlKind::Timeout, self.queue_timeout_url),
+            result_url: self.client.resolve_url(UrlKind::Result, self.result_url),
+            account_reference: self.account_ref,
+        };
+
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::B2b,
+            path: self.client.api_path(Service::B2b, paths::B2B),
+            body: payload,
+        })
+    }
+}
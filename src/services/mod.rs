@@ -17,15 +17,30 @@
 //! 9. [Transaction Status](https://developer.safaricom.co.ke/APIs/TransactionStatus)
 //! 10. [Dynamic QR](https://developer.safaricom.co.ke/APIs/DynamicQRCode)
 
+#[cfg(feature = "account_balance")]
 mod account_balance;
+#[cfg(feature = "b2b")]
 mod b2b;
+#[cfg(feature = "b2c")]
 mod b2c;
+#[cfg(feature = "bill_manager")]
 mod bill_manager;
+#[cfg(feature = "c2b_register")]
 mod c2b_register;
+#[cfg(feature = "c2b_simulate")]
 mod c2b_simulate;
+mod common;
+#[cfg(feature = "dynamic_qr")]
 mod dynamic_qr;
+#[cfg(feature = "express_request")]
 mod express_request;
+#[cfg(feature = "b2b")]
+mod mmf_transfer;
+#[cfg(feature = "express_request")]
+mod template;
+#[cfg(feature = "transaction_reversal")]
 mod transaction_reversal;
+#[cfg(feature = "transaction_status")]
 mod transaction_status;
 
 #[cfg(feature = "account_balance")]
@@ -37,15 +52,24 @@ pub use b2c::{B2cBuilder, B2cResponse};
 #[cfg(feature = "bill_manager")]
 pub use bill_manager::*;
 #[cfg(feature = "c2b_register")]
-pub use c2b_register::{C2bRegisterBuilder, C2bRegisterResponse};
+pub use c2b_register::{
+    BulkC2bRegisterBuilder, C2bRegisterBuilder, C2bRegisterResponse, C2bRegistration,
+};
 #[cfg(feature = "c2b_simulate")]
 pub use c2b_simulate::{C2bSimulateBuilder, C2bSimulateResponse};
+pub use common::{CallbackUrls, PartyA, PartyB};
 #[cfg(feature = "dynamic_qr")]
 pub use dynamic_qr::{DynamicQR, DynamicQRBuilder, DynamicQRRequest, DynamicQRResponse};
 #[cfg(feature = "express_request")]
+pub(crate) use express_request::StkPushGuard;
+#[cfg(feature = "express_request")]
 pub use express_request::{
     MpesaExpress, MpesaExpressBuilder, MpesaExpressRequest, MpesaExpressResponse,
 };
+#[cfg(feature = "b2b")]
+pub use mmf_transfer::{MmfTransferBuilder, MmfTransferResponse};
+#[cfg(feature = "express_request")]
+pub use template::RequestTemplate;
 #[cfg(feature = "transaction_reversal")]
 pub use transaction_reversal::{
     TransactionReversal, TransactionReversalBuilder, TransactionReversalRequest,
@@ -53,3 +77,94 @@ pub use transaction_reversal::{
 };
 #[cfg(feature = "transaction_status")]
 pub use transaction_status::{TransactionStatusBuilder, TransactionStatusResponse};
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::callbacks::ResultCallback;
+    use crate::MpesaResult;
+
+    /// Parses a body, returning the `OriginatorConversationID` it carries
+    type Parse = fn(&[u8]) -> MpesaResult<String>;
+
+    /// Body of the responses acknowledging a request
+    fn response() -> Value {
+        json!({
+            "ConversationID": "AG_20191219_00005797af5d7d75f652",
+            "OriginatorConversationID": "16740-34861180-1",
+            "ResponseCode": "0",
+            "ResponseDescription": "Accept the service request successfully."
+        })
+    }
+
+    #[test]
+    fn test_both_spellings_of_originator_conversation_id_are_accepted() {
+        let mut cases: Vec<(&str, Value, Parse)> = vec![(
+            "ResultCallback",
+            json!({
+                "Result": {
+                    "ResultType": 0,
+                    "ResultCode": 0,
+                    "ResultDesc": "The service request is processed successfully.",
+                    "OriginatorConversationID": "16740-34861180-1",
+                    "ConversationID": "AG_20191219_00005797af5d7d75f652",
+                    "TransactionID": "NLJ41HAY6Q"
+                }
+            }),
+            |body| Ok(ResultCallback::from_json(body)?.originator_conversation_id),
+        )];
+        #[cfg(feature = "account_balance")]
+        cases.push(("AccountBalanceResponse", response(), |body| {
+            Ok(
+                serde_json::from_slice::<super::AccountBalanceResponse>(body)?
+                    .originator_conversation_id,
+            )
+        }));
+        #[cfg(feature = "b2b")]
+        cases.push(("B2bResponse", response(), |body| {
+            Ok(serde_json::from_slice::<super::B2bResponse>(body)?.originator_conversation_id)
+        }));
+        #[cfg(feature = "b2c")]
+        cases.push(("B2cResponse", response(), |body| {
+            Ok(serde_json::from_slice::<super::B2cResponse>(body)?.originator_conversation_id)
+        }));
+        #[cfg(feature = "c2b_register")]
+        cases.push(("C2bRegisterResponse", response(), |body| {
+            Ok(serde_json::from_slice::<super::C2bRegisterResponse>(body)?
+                .originator_conversation_id)
+        }));
+        #[cfg(feature = "c2b_simulate")]
+        cases.push(("C2bSimulateResponse", response(), |body| {
+            Ok(serde_json::from_slice::<super::C2bSimulateResponse>(body)?
+                .originator_conversation_id)
+        }));
+        #[cfg(feature = "transaction_reversal")]
+        cases.push(("TransactionReversalResponse", response(), |body| {
+            Ok(
+                serde_json::from_slice::<super::TransactionReversalResponse>(body)?
+                    .originator_conversation_id,
+            )
+        }));
+        #[cfg(feature = "transaction_status")]
+        cases.push(("TransactionStatusResponse", response(), |body| {
+            Ok(
+                serde_json::from_slice::<super::TransactionStatusResponse>(body)?
+                    .originator_conversation_id,
+            )
+        }));
+
+        for (name, fixture, parse) in cases {
+            for spelling in ["OriginatorConversationID", "OriginatorCoversationID"] {
+                let body = fixture
+                    .to_string()
+                    .replace("OriginatorConversationID", spelling);
+                assert_eq!(
+                    parse(body.as_bytes()).unwrap(),
+                    "16740-34861180-1",
+                    "{name} with {spelling}"
+                );
+            }
+        }
+    }
+}
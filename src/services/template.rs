@@ -0,0 +1,23 @@
+/// A request built and validated once, from which requests differing only in their customer
+/// details are stamped out
+///
+/// Request handlers sending the same kind of payment over and over, such as the STK push of a
+/// product, build the request once at startup instead of going through the builder and its
+/// validation for every customer. M-Pesa Express templates are stamped with
+/// `RequestTemplate::stamp`.
+#[derive(Debug, Clone)]
+pub struct RequestTemplate<R> {
+    pub(crate) request: R,
+}
+
+impl<R> RequestTemplate<R> {
+    /// Creates a template from a request returned by a builder, and so already validated
+    pub fn new(request: R) -> Self {
+        RequestTemplate { request }
+    }
+
+    /// The request stamped out by the template
+    pub fn request(&self) -> &R {
+        &self.request
+    }
+}
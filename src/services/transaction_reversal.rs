@@ -1,14 +1,21 @@
 #![doc = include_str!("../../docs/client/transaction_reversal.md")]
 
+use std::borrow::Cow;
+use std::fmt;
+
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::client::{self, UrlKind, WithMeta};
+use crate::constants::{Service, REDACTED, SECURITY_CREDENTIAL_PLACEHOLDER};
+use crate::format::{mask_phone_number, summary_amount};
+use crate::metadata::Metadata;
+use crate::paths;
 use crate::{CommandId, IdentifierTypes, Mpesa, MpesaError, MpesaResult};
 
-const TRANSACTION_REVERSAL_URL: &str = "mpesa/reversal/v1/request";
-
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "PascalCase")]
 pub struct TransactionReversalRequest<'mpesa> {
     /// The name of the initiator to initiate the request.
@@ -28,27 +35,62 @@ pub struct TransactionReversalRequest<'mpesa> {
     pub receiver_identifier_type: IdentifierTypes,
     /// The path that stores information about the transaction.
     #[serde(rename = "ResultURL")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub result_url: Url,
     /// The path that stores information about the time-out transaction.
     #[serde(rename = "QueueTimeOutURL")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub queue_timeout_url: Url,
     /// Comments that are sent along with the transaction.
     pub remarks: &'mpesa str,
     /// Comments that are sent along with the transaction.
-    pub occasion: Option<&'mpesa str>,
+    pub occasion: Option<Cow<'mpesa, str>>,
     /// The amount transacted in the transaction is to be reversed, down to the
     /// cent.
     pub amount: u32,
 }
 
+impl fmt::Debug for TransactionReversalRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransactionReversalRequest")
+            .field("initiator", &self.initiator)
+            .field("security_credential", &REDACTED)
+            .field("command_id", &self.command_id)
+            .field("transaction_id", &self.transaction_id)
+            .field("receiver_party", &self.receiver_party)
+            .field("receiver_identifier_type", &self.receiver_identifier_type)
+            .field("result_url", &self.result_url)
+            .field("queue_timeout_url", &self.queue_timeout_url)
+            .field("remarks", &self.remarks)
+            .field("occasion", &self.occasion)
+            .field("amount", &self.amount)
+            .finish()
+    }
+}
+
+impl fmt::Display for TransactionReversalRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Reversal of {} {} by {} via {}",
+            self.transaction_id,
+            summary_amount(self.amount),
+            mask_phone_number(self.receiver_party),
+            self.command_id
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "PascalCase")]
+#[non_exhaustive]
 pub struct TransactionReversalResponse {
     /// The unique request ID for tracking a transaction.
     #[serde(rename = "ConversationID")]
     pub conversation_id: String,
     /// The unique request ID is returned by mpesa for each request made.
-    #[serde(rename = "OriginatorConversationID")]
+    #[serde(rename = "OriginatorConversationID", alias = "OriginatorCoversationID")]
     pub originator_conversation_id: String,
     /// Response Description message
     pub response_description: String,
@@ -71,17 +113,27 @@ pub struct TransactionReversal<'mpesa> {
     #[builder(setter(into))]
     receiver_party: &'mpesa str,
     /// The path that stores information about the transaction.
-    #[builder(try_setter, setter(into))]
+    /// Defaults to the `MpesaBuilder::default_result_url` of the client.
+    #[builder(
+        try_setter,
+        setter(into),
+        default = "client::default_url(self.client, UrlKind::Result, \"result_url\")?"
+    )]
     result_url: Url,
     /// The path that stores information about the time-out transaction.
-    #[builder(try_setter, setter(into))]
+    /// Defaults to the `MpesaBuilder::default_timeout_url` of the client.
+    #[builder(
+        try_setter,
+        setter(into),
+        default = "client::default_url(self.client, UrlKind::Timeout, \"timeout_url\")?"
+    )]
     timeout_url: Url,
     /// Comments that are sent along with the transaction.
     #[builder(setter(into))]
     remarks: &'mpesa str,
     /// Comments that are sent along with the transaction.
     #[builder(setter(into, strip_option), default)]
-    occasion: Option<&'mpesa str>,
+    occasion: Option<Cow<'mpesa, str>>,
     /// Type of organization that receives the transaction.
     pub receiver_identifier_type: IdentifierTypes,
     /// The amount transacted in the transaction is to be reversed, down to the
@@ -89,27 +141,51 @@ pub struct TransactionReversal<'mpesa> {
     amount: u32,
 }
 
+impl TransactionReversalBuilder<'_> {
+    /// Sets the result url to `path` joined onto the `MpesaBuilder::default_result_url` of the
+    /// client
+    ///
+    /// # Errors
+    /// If the joined url is invalid, such as when the client has no default result url
+    pub fn result_path(&mut self, path: &str) -> MpesaResult<&mut Self> {
+        let url = client::url_from_path(self.client, UrlKind::Result, path)?;
+        Ok(self.result_url(url))
+    }
+
+    /// Sets the timeout url to `path` joined onto the `MpesaBuilder::default_timeout_url` of the
+    /// client
+    ///
+    /// # Errors
+    /// If the joined url is invalid, such as when the client has no default timeout url
+    pub fn timeout_path(&mut self, path: &str) -> MpesaResult<&mut Self> {
+        let url = client::url_from_path(self.client, UrlKind::Timeout, path)?;
+        Ok(self.timeout_url(url))
+    }
+
+    /// Encodes `metadata` into the occasion, replacing any occasion set before.
+    /// It can be read back from the result with `ResultCallback::metadata`.
+    ///
+    /// # Errors
+    /// If the encoded metadata is longer than the 100 characters allowed in the occasion
+    pub fn metadata(&mut self, metadata: &Metadata) -> MpesaResult<&mut Self> {
+        Ok(self.occasion(metadata.encode()?))
+    }
+}
+
 impl<'mpesa> TryFrom<TransactionReversal<'mpesa>> for TransactionReversalRequest<'mpesa> {
     type Error = MpesaError;
 
     fn try_from(
         value: TransactionReversal<'mpesa>,
     ) -> Result<TransactionReversalRequest<'mpesa>, Self::Error> {
-        let credentials = value.client.gen_security_credentials()?;
+        let credentials = value.client.security_credential_for(value.initiator)?;
+        Ok(value.request_body(credentials))
+    }
+}
 
-        Ok(TransactionReversalRequest {
-            initiator: value.initiator,
-            security_credential: credentials,
-            command_id: CommandId::TransactionReversal,
-            transaction_id: value.transaction_id,
-            receiver_party: value.receiver_party,
-            receiver_identifier_type: value.receiver_identifier_type,
-            result_url: value.result_url,
-            queue_timeout_url: value.timeout_url,
-            remarks: value.remarks,
-            occasion: value.occasion,
-            amount: value.amount,
-        })
+impl fmt::Display for TransactionReversal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.request_body(REDACTED.to_owned()).fmt(f)
     }
 }
 
@@ -119,6 +195,22 @@ impl<'mpesa> TransactionReversal<'mpesa> {
         TransactionReversalBuilder::default().client(client)
     }
 
+    fn request_body(&self, security_credential: String) -> TransactionReversalRequest<'mpesa> {
+        TransactionReversalRequest {
+            initiator: self.initiator,
+            security_credential,
+            command_id: CommandId::TransactionReversal,
+            transaction_id: self.transaction_id,
+            receiver_party: self.receiver_party,
+            receiver_identifier_type: self.receiver_identifier_type,
+            result_url: self.result_url.clone(),
+            queue_timeout_url: self.timeout_url.clone(),
+            remarks: self.remarks,
+            occasion: self.occasion.clone(),
+            amount: self.amount,
+        }
+    }
+
     /// Creates a new `TransactionReversal` from a `TransactionReversalRequest`
     pub fn from_request(
         client: &'mpesa Mpesa,
@@ -158,12 +250,40 @@ impl<'mpesa> TransactionReversal<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure.
     pub async fn send(self) -> MpesaResult<TransactionReversalResponse> {
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<TransactionReversalResponse>> {
         self.client
-            .send::<TransactionReversalRequest, _>(crate::client::Request {
+            .send_with_meta::<TransactionReversalRequest, _>(crate::client::Request {
                 method: reqwest::Method::POST,
-                path: TRANSACTION_REVERSAL_URL,
+                service: Service::TransactionReversal,
+                path: self
+                    .client
+                    .api_path(Service::TransactionReversal, paths::TRANSACTION_REVERSAL),
                 body: self.try_into()?,
             })
             .await
     }
+
+    /// Renders the request as a curl command, with a placeholder in place of the security
+    /// credential, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if the request cannot be serialized
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client.to_curl(&crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::TransactionReversal,
+            path: self
+                .client
+                .api_path(Service::TransactionReversal, paths::TRANSACTION_REVERSAL),
+            body: self.request_body(SECURITY_CREDENTIAL_PLACEHOLDER.to_owned()),
+        })
+    }
 }
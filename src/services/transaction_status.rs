@@ -1,14 +1,19 @@
 #![doc = include_str!("../../docs/client/transaction_status.md")]
 
+use std::borrow::Cow;
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
+use crate::client::{UrlKind, WithMeta};
+use crate::constants::{Service, REDACTED, SECURITY_CREDENTIAL_PLACEHOLDER};
+use crate::format::mask_phone_number;
+use crate::paths;
 use crate::{CommandId, IdentifierTypes, Mpesa, MpesaError, MpesaResult};
 
-const TRANSACTION_STATUS_URL: &str = "mpesa/transactionstatus/v1/query";
-
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct TransactionStatusPayload<'mpesa> {
-    #[serde(rename(serialize = "Initiator"))]
     initiator: &'mpesa str,
     #[serde(rename(serialize = "SecurityCredential"))]
     security_credentials: &'mpesa str,
@@ -16,25 +21,55 @@ pub struct TransactionStatusPayload<'mpesa> {
     command_id: CommandId,
     #[serde(rename(serialize = "TransactionID"))]
     transaction_id: &'mpesa str,
-    #[serde(rename = "PartyA")]
     party_a: &'mpesa str,
-    #[serde(rename(serialize = "IdentifierType"))]
     identifier_type: IdentifierTypes,
     #[serde(rename(serialize = "ResultURL"))]
-    result_url: &'mpesa str,
+    result_url: Cow<'mpesa, str>,
     #[serde(rename(serialize = "QueueTimeOutURL"))]
-    timeout_url: &'mpesa str,
-    #[serde(rename(serialize = "Remarks"))]
+    timeout_url: Cow<'mpesa, str>,
     remarks: &'mpesa str,
-    #[serde(rename(serialize = "Occasion"))]
     occasion: &'mpesa str,
 }
 
+impl fmt::Debug for TransactionStatusPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransactionStatusPayload")
+            .field("initiator", &self.initiator)
+            .field("security_credentials", &REDACTED)
+            .field("command_id", &self.command_id)
+            .field("transaction_id", &self.transaction_id)
+            .field("party_a", &self.party_a)
+            .field("identifier_type", &self.identifier_type)
+            .field("result_url", &self.result_url)
+            .field("timeout_url", &self.timeout_url)
+            .field("remarks", &self.remarks)
+            .field("occasion", &self.occasion)
+            .finish()
+    }
+}
+
+impl fmt::Display for TransactionStatusPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Transaction status of {} at {} via {}",
+            self.transaction_id,
+            mask_phone_number(self.party_a),
+            self.command_id
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[non_exhaustive]
 pub struct TransactionStatusResponse {
     #[serde(rename(deserialize = "ConversationID"))]
     pub conversation_id: String,
-    #[serde(rename(deserialize = "OriginatorConversationID"))]
+    #[serde(
+        rename(deserialize = "OriginatorConversationID"),
+        alias = "OriginatorCoversationID"
+    )]
     pub originator_conversation_id: String,
     #[serde(rename(deserialize = "ResponseDescription"))]
     pub response_description: String,
@@ -155,11 +190,50 @@ impl<'mpesa> TransactionStatusBuilder<'mpesa> {
     /// # Errors
     /// Returns a `MpesaError` on failure.
     pub async fn send(self) -> MpesaResult<TransactionStatusResponse> {
-        let credentials = self.client.gen_security_credentials()?;
+        Ok(self.send_with_meta().await?.response)
+    }
+
+    /// Sends the request, returning the HTTP status, headers and latency of the response along
+    /// with it
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` on failure
+    pub async fn send_with_meta(self) -> MpesaResult<WithMeta<TransactionStatusResponse>> {
+        let credentials = self.client.security_credential_for(self.initiator)?;
+        self.client
+            .send_with_meta(self.request(&credentials)?)
+            .await
+    }
+
+    /// Renders the request as a curl command, with a placeholder in place of the security
+    /// credential, to reproduce it outside of the client
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn to_curl(&self) -> MpesaResult<String> {
+        self.client
+            .to_curl(&self.request(SECURITY_CREDENTIAL_PLACEHOLDER)?)
+    }
 
+    /// Renders a one-line summary of the request without its secrets, such as
+    /// `Transaction status of OEI2AK4Q16 at 600496 via TransactionStatusQuery`, for logs and support tooling
+    ///
+    /// # Errors
+    /// Returns a `MpesaError` if a required field is missing
+    pub fn summary(&self) -> MpesaResult<String> {
+        Ok(self
+            .request(SECURITY_CREDENTIAL_PLACEHOLDER)?
+            .body
+            .to_string())
+    }
+
+    fn request<'a>(
+        &'a self,
+        security_credential: &'a str,
+    ) -> MpesaResult<crate::client::Request<TransactionStatusPayload<'a>>> {
         let payload = TransactionStatusPayload {
             initiator: self.initiator,
-            security_credentials: &credentials,
+            security_credentials: security_credential,
             command_id: self.command_id.unwrap_or(CommandId::TransactionStatusQuery),
             transaction_id: self
                 .transaction_id
@@ -169,21 +243,24 @@ impl<'mpesa> TransactionStatusBuilder<'mpesa> {
                 .ok_or(MpesaError::Message("party_a is required"))?,
             identifier_type: self.identifier_type.unwrap_or(IdentifierTypes::ShortCode),
             result_url: self
-                .result_url
+                .client
+                .resolve_url(UrlKind::Result, self.result_url)
                 .ok_or(MpesaError::Message("result_url is required"))?,
             timeout_url: self
-                .timeout_url
+                .client
+                .resolve_url(UrlKind::Timeout, self.timeout_url)
                 .ok_or(MpesaError::Message("timeout_url is required"))?,
             remarks: self.remarks.unwrap_or(stringify!(None)),
             occasion: self.occasion.unwrap_or(stringify!(None)),
         };
 
-        self.client
-            .send(crate::client::Request {
-                method: reqwest::Method::POST,
-                path: TRANSACTION_STATUS_URL,
-                body: payload,
-            })
-            .await
+        Ok(crate::client::Request {
+            method: reqwest::Method::POST,
+            service: Service::TransactionStatus,
+            path: self
+                .client
+                .api_path(Service::TransactionStatus, paths::TRANSACTION_STATUS),
+            body: payload,
+        })
     }
 }
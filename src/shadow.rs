@@ -0,0 +1,387 @@
+//! Mirroring requests to a second environment
+//!
+//! When moving an integration to production, or from one set of credentials or API version to
+//! another, it helps to know that the new environment answers the requests of the application the
+//! way the current one does before relying on it. A client built with `MpesaBuilder::shadow` sends
+//! each request to its own environment and, at the same time, a copy of it to the environment of
+//! the [`Shadow`] client, such as the sandbox. The response of its own environment is the one
+//! returned. The two responses are compared into a [`ShadowReport`] handed to the hook of the
+//! shadow.
+//!
+//! Copies have their amounts zeroed, so that a shadow environment with live credentials moves no
+//! money, unless `Shadow::keep_amounts` is set. The `SecurityCredential` of a copy is generated
+//! with the initiator password and certificate of the shadow client.
+//!
+//! The copy is sent by a task of its own, so the response of the environment of the client is
+//! returned without waiting for the shadow. The report is handed to the hook once both
+//! environments have answered.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use crate::client::{Request, ResponseMeta};
+use crate::json::Body;
+use crate::{Mpesa, MpesaError, MpesaResult, Service};
+
+type ReportHook = dyn Fn(&ShadowReport) + Send + Sync;
+
+/// The client of the environment requests are mirrored to
+#[derive(Clone)]
+pub struct Shadow {
+    client: Mpesa,
+    zero_amounts: bool,
+    on_report: Arc<ReportHook>,
+}
+
+impl fmt::Debug for Shadow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shadow")
+            .field("client", &self.client)
+            .field("zero_amounts", &self.zero_amounts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Shadow {
+    /// Mirrors requests with `client`, passing the comparison of each pair of responses to
+    /// `on_report`, for example to log the differences
+    pub fn new(client: Mpesa, on_report: impl Fn(&ShadowReport) + Send + Sync + 'static) -> Self {
+        Shadow {
+            client,
+            zero_amounts: true,
+            on_report: Arc::new(on_report),
+        }
+    }
+
+    /// Sets the `Amount` of the copies of the requests to `0`, the default
+    pub fn zero_amounts(mut self) -> Self {
+        self.zero_amounts = true;
+        self
+    }
+
+    /// Sends the copies of the requests with their amounts. Payments are then made twice when the
+    /// shadow environment moves money, so only use this with the sandbox.
+    pub fn keep_amounts(mut self) -> Self {
+        self.zero_amounts = false;
+        self
+    }
+
+    /// Sends `primary`, the request to the environment of the client, while a task sends a copy
+    /// of the request to the shadow environment and reports how their responses differ
+    pub(crate) async fn mirror(
+        self: Arc<Self>,
+        method: reqwest::Method,
+        service: Service,
+        path: String,
+        body: Body,
+        primary: impl std::future::Future<Output = MpesaResult<(Value, ResponseMeta)>>,
+    ) -> MpesaResult<(Value, ResponseMeta)> {
+        let (primary_tx, primary_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let shadow = async {
+                let request = Request {
+                    method,
+                    service,
+                    path: path.clone().into(),
+                    body: Body::from_value(
+                        self.copy(body.into_value())?,
+                        self.client.fixed_decimal_amounts,
+                    )?,
+                };
+                Ok(self.client.send_unshadowed(request).await?.0)
+            }
+            .await;
+            // the request is dropped before it is answered when it is cancelled
+            let Ok(primary) = primary_rx.await else {
+                return;
+            };
+            let report = ShadowReport::new(service, path, primary, Outcome::new(shadow.as_ref()));
+            (self.on_report)(&report);
+        });
+
+        let primary = primary.await;
+        let _ = primary_tx.send(Outcome::new(primary.as_ref().map(|(response, _)| response)));
+        primary
+    }
+
+    /// The copy of a request body sent to the shadow environment
+    fn copy(&self, mut body: Value) -> MpesaResult<Value> {
+        if self.zero_amounts {
+            zero_amounts(&mut body);
+        }
+        #[cfg(feature = "openssl")]
+        if body.get("SecurityCredential").is_some() {
+            let initiator = body.get("Initiator").or_else(|| body.get("InitiatorName"));
+            let initiator = initiator.and_then(Value::as_str).unwrap_or_default();
+            body["SecurityCredential"] =
+                Value::String(self.client.security_credential_for(initiator)?);
+        }
+        Ok(body)
+    }
+}
+
+/// Sets the `Amount` fields of `value`, at any depth, to `0`
+fn zero_amounts(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                if key.eq_ignore_ascii_case("amount") {
+                    *field = match field {
+                        Value::String(_) => Value::String("0".to_owned()),
+                        _ => Value::from(0),
+                    };
+                } else {
+                    zero_amounts(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(zero_amounts),
+        _ => {}
+    }
+}
+
+/// How the responses of the two environments to a request compare
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ShadowReport {
+    pub service: Service,
+    pub path: String,
+    /// The response of the environment of the client, or its error
+    pub primary: Result<Value, String>,
+    /// The response of the shadow environment, or its error
+    pub shadow: Result<Value, String>,
+    pub differences: Vec<Difference>,
+}
+
+impl ShadowReport {
+    fn new(service: Service, path: String, primary: Outcome, shadow: Outcome) -> Self {
+        let mut differences = vec![];
+        if primary.response.is_ok() != shadow.response.is_ok() {
+            differences.push(Difference::Outcome {
+                primary: primary.response.is_ok(),
+                shadow: shadow.response.is_ok(),
+            });
+        }
+        if let (Ok(primary), Ok(shadow)) = (&primary.response, &shadow.response) {
+            differences.extend(compare_fields(primary, shadow));
+        }
+        if let (Some(primary), Some(shadow)) = (primary.code, shadow.code) {
+            if primary != shadow {
+                differences.push(Difference::Code { primary, shadow });
+            }
+        }
+
+        ShadowReport {
+            service,
+            path,
+            primary: primary.response,
+            shadow: shadow.response,
+            differences,
+        }
+    }
+
+    /// Returns `true` if the two environments answered alike
+    pub fn matches(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+/// A way in which the responses of the two environments differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Difference {
+    /// One environment accepted the request and the other rejected it
+    Outcome { primary: bool, shadow: bool },
+    /// A field of the primary response is missing from the shadow response
+    MissingField(String),
+    /// A field of the shadow response is not in the primary response
+    ExtraField(String),
+    /// A field has a different JSON type in the two responses
+    FieldType {
+        field: String,
+        primary: &'static str,
+        shadow: &'static str,
+    },
+    /// The `ResponseCode` of the responses, or the `errorCode` of the errors, differ
+    Code { primary: String, shadow: String },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let outcome = |accepted: bool| if accepted { "accepted" } else { "rejected" };
+        match self {
+            Difference::Outcome { primary, shadow } => write!(
+                f,
+                "the request was {} by the primary environment and {} by the shadow",
+                outcome(*primary),
+                outcome(*shadow)
+            ),
+            Difference::MissingField(field) => write!(f, "{field} is missing from the shadow"),
+            Difference::ExtraField(field) => write!(f, "{field} is only in the shadow"),
+            Difference::FieldType {
+                field,
+                primary,
+                shadow,
+            } => write!(
+                f,
+                "{field} is a {primary} in the primary and a {shadow} in the shadow"
+            ),
+            Difference::Code { primary, shadow } => {
+                write!(
+                    f,
+                    "code {primary} in the primary and {shadow} in the shadow"
+                )
+            }
+        }
+    }
+}
+
+/// Compares the top level fields of two responses
+fn compare_fields(primary: &Value, shadow: &Value) -> Vec<Difference> {
+    let (Some(primary), Some(shadow)) = (primary.as_object(), shadow.as_object()) else {
+        return vec![];
+    };
+    let mut differences = vec![];
+    for (field, value) in primary {
+        match shadow.get(field) {
+            None => differences.push(Difference::MissingField(field.clone())),
+            Some(other) if type_name(value) != type_name(other) => {
+                differences.push(Difference::FieldType {
+                    field: field.clone(),
+                    primary: type_name(value),
+                    shadow: type_name(other),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    differences.extend(
+        shadow
+            .keys()
+            .filter(|field| !primary.contains_key(*field))
+            .map(|field| Difference::ExtraField(field.clone())),
+    );
+    differences
+}
+
+/// The response of an environment, or its error, along with its code
+struct Outcome {
+    response: Result<Value, String>,
+    code: Option<String>,
+}
+
+impl Outcome {
+    fn new(response: Result<&Value, &MpesaError>) -> Self {
+        Outcome {
+            response: response.cloned().map_err(|e| e.to_string()),
+            code: code(response),
+        }
+    }
+}
+
+/// The `ResponseCode` of a response or the `errorCode` of an error
+fn code(response: Result<&Value, &MpesaError>) -> Option<String> {
+    match response {
+        Ok(response) => response
+            .get("ResponseCode")
+            .map(crate::callbacks::value_to_string),
+        Err(MpesaError::Service(error)) => Some(error.error_code.clone()),
+        Err(_) => None,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::ResponseError;
+
+    #[test]
+    fn test_responses_are_compared_field_by_field() {
+        let primary = json!({
+            "ConversationID": "AG_20191219_00005797af5d7d75f652",
+            "ResponseCode": "0",
+            "ResponseDescription": "Accept the service request successfully."
+        });
+        let shadow = json!({
+            "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+            "ResponseCode": 0,
+            "OriginatorConversationID": "16740-34861180-1"
+        });
+        let report = ShadowReport::new(
+            Service::B2c,
+            "mpesa/b2c/v1/paymentrequest".to_owned(),
+            Outcome::new(Ok(&primary)),
+            Outcome::new(Ok(&shadow)),
+        );
+        assert_eq!(
+            report.differences,
+            [
+                Difference::FieldType {
+                    field: "ResponseCode".to_owned(),
+                    primary: "string",
+                    shadow: "number"
+                },
+                Difference::MissingField("ResponseDescription".to_owned()),
+                Difference::ExtraField("OriginatorConversationID".to_owned()),
+            ]
+        );
+
+        let error = MpesaError::Service(ResponseError::new(
+            "11728-2929992-1",
+            "400.002.02",
+            "Bad Request - Invalid Amount",
+        ));
+        let report = ShadowReport::new(
+            Service::B2c,
+            "mpesa/b2c/v1/paymentrequest".to_owned(),
+            Outcome::new(Ok(&primary)),
+            Outcome::new(Err(&error)),
+        );
+        assert!(!report.matches());
+        assert_eq!(
+            report.differences,
+            [
+                Difference::Outcome {
+                    primary: true,
+                    shadow: false
+                },
+                Difference::Code {
+                    primary: "0".to_owned(),
+                    shadow: "400.002.02".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_amounts_of_copies_are_zeroed() {
+        let mut body = json!({
+            "Amount": 1000,
+            "invoices": [{ "amount": "250", "billedPeriod": "August 2021" }]
+        });
+        zero_amounts(&mut body);
+        assert_eq!(
+            body,
+            json!({
+                "Amount": 0,
+                "invoices": [{ "amount": "0", "billedPeriod": "August 2021" }]
+            })
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::{MpesaError, MpesaResult};
+
+/// Requests in flight on a client and its clones, which stop being accepted once it is shut down
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    count: AtomicUsize,
+    closed: AtomicBool,
+    idle: Notify,
+}
+
+impl InFlight {
+    /// Counts a request as in flight until the returned guard is dropped
+    ///
+    /// # Errors
+    /// Returns `MpesaError::ShutDown` if the client has been shut down
+    #[cfg(feature = "__request")]
+    pub(crate) fn enter(&self) -> MpesaResult<InFlightGuard<'_>> {
+        // Counted before checking `closed`, so that `shutdown` either sees the request or the
+        // request sees the shutdown
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self);
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(MpesaError::ShutDown);
+        }
+        Ok(guard)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting requests and waits up to `timeout` for those in flight to complete
+    ///
+    /// # Errors
+    /// Returns `MpesaError::DeadlineExceeded` if requests are still in flight after `timeout`
+    pub(crate) async fn shutdown(&self, timeout: Duration) -> MpesaResult<()> {
+        self.closed.store(true, Ordering::SeqCst);
+        let drained = async {
+            loop {
+                // Created before checking the count so that the last request completing in
+                // between still wakes it
+                let idle = self.idle.notified();
+                if self.len() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        tokio::time::timeout(timeout, drained)
+            .await
+            .map_err(|_| MpesaError::DeadlineExceeded)
+    }
+}
+
+#[cfg(feature = "__request")]
+pub(crate) struct InFlightGuard<'a>(&'a InFlight);
+
+#[cfg(feature = "__request")]
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_requests_in_flight() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.enter().unwrap();
+        assert_eq!(in_flight.len(), 1);
+
+        let shutdown = in_flight.shutdown(Duration::from_secs(5));
+        let release = async {
+            tokio::task::yield_now().await;
+            assert!(matches!(in_flight.enter(), Err(MpesaError::ShutDown)));
+            drop(guard);
+        };
+        let (res, ()) = tokio::join!(shutdown, release);
+        assert!(res.is_ok());
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_times_out() {
+        let in_flight = InFlight::default();
+        let _guard = in_flight.enter().unwrap();
+        assert!(matches!(
+            in_flight.shutdown(Duration::from_millis(10)).await,
+            Err(MpesaError::DeadlineExceeded)
+        ));
+        assert!(in_flight.is_closed());
+    }
+}
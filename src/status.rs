@@ -0,0 +1,194 @@
+//! Planned downtime of the Safaricom API
+//!
+//! Safaricom announces maintenance of Daraja ahead of time, during which requests fail with
+//! connection errors or `5xx` gateway errors. A [`MaintenanceSchedule`] lists these windows, either
+//! built by the operator from the announcements or fetched from a status document with
+//! `Mpesa::fetch_maintenance_schedule`.
+//!
+//! During a window, the client does not retry failed requests, since they would fail again until
+//! it ends, and reports the failures as `MpesaError::Maintenance` carrying the window, so that
+//! callers can tell the customer when to try again.
+//!
+//! Status documents are JSON, with the start and end of each window in Unix seconds:
+//!
+//! ```rust
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! use mpesa::status::MaintenanceSchedule;
+//!
+//! let schedule = MaintenanceSchedule::from_json(br#"{"maintenance": [{
+//!     "start": 1735689600,
+//!     "end": 1735700400,
+//!     "description": "M-Pesa system upgrade"
+//! }]}"#)
+//! .unwrap();
+//! let window = schedule
+//!     .window_at(UNIX_EPOCH + Duration::from_secs(1735693200))
+//!     .unwrap();
+//! assert_eq!(window.description.as_deref(), Some("M-Pesa system upgrade"));
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::MpesaResult;
+
+/// A period during which the Safaricom API is down for planned maintenance
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MaintenanceWindow {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// What is being maintained, as announced by Safaricom
+    pub description: Option<String>,
+}
+
+impl MaintenanceWindow {
+    pub fn new(start: SystemTime, end: SystemTime) -> Self {
+        MaintenanceWindow {
+            start,
+            end,
+            description: None,
+        }
+    }
+
+    /// Adds the `description` of the maintenance
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Returns `true` if `at` is within the window, its end excluded
+    pub fn contains(&self, at: SystemTime) -> bool {
+        self.start <= at && at < self.end
+    }
+
+    /// Returns how long the window lasts after `at`, zero once it has ended
+    pub fn remaining(&self, at: SystemTime) -> Duration {
+        self.end.duration_since(at).unwrap_or_default()
+    }
+}
+
+/// The planned maintenance windows of the Safaricom API
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceSchedule {
+    windows: Vec<MaintenanceWindow>,
+}
+
+impl MaintenanceSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a maintenance `window` to the schedule
+    pub fn with(mut self, window: MaintenanceWindow) -> Self {
+        self.windows.push(window);
+        self
+    }
+
+    /// Parses a status document listing maintenance windows under `maintenance`, each with a
+    /// `start` and `end` in Unix seconds and an optional `description`
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if the document is not valid
+    pub fn from_json(bytes: &[u8]) -> MpesaResult<Self> {
+        let document: StatusDocument = serde_json::from_slice(bytes)?;
+        let windows = document
+            .maintenance
+            .into_iter()
+            .map(|window| MaintenanceWindow {
+                start: UNIX_EPOCH + Duration::from_secs(window.start),
+                end: UNIX_EPOCH + Duration::from_secs(window.end),
+                description: window.description,
+            })
+            .collect();
+        Ok(MaintenanceSchedule { windows })
+    }
+
+    /// Returns the window `at` is within, the one ending last if several overlap
+    pub fn window_at(&self, at: SystemTime) -> Option<&MaintenanceWindow> {
+        self.windows
+            .iter()
+            .filter(|window| window.contains(at))
+            .max_by_key(|window| window.end)
+    }
+
+    /// Returns `true` if the Safaricom API is down for maintenance at `at`
+    pub fn is_maintenance_window(&self, at: SystemTime) -> bool {
+        self.window_at(at).is_some()
+    }
+
+    /// Returns the next window starting after `at`
+    pub fn next_window(&self, at: SystemTime) -> Option<&MaintenanceWindow> {
+        self.windows
+            .iter()
+            .filter(|window| window.start > at)
+            .min_by_key(|window| window.start)
+    }
+
+    pub fn windows(&self) -> &[MaintenanceWindow] {
+        &self.windows
+    }
+}
+
+#[derive(Deserialize)]
+struct StatusDocument {
+    #[serde(default)]
+    maintenance: Vec<WindowDocument>,
+}
+
+#[derive(Deserialize)]
+struct WindowDocument {
+    start: u64,
+    end: u64,
+    description: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_windows_are_looked_up_by_time() {
+        let schedule = MaintenanceSchedule::new()
+            .with(MaintenanceWindow::new(at(100), at(200)).description("upgrade"))
+            .with(MaintenanceWindow::new(at(150), at(300)))
+            .with(MaintenanceWindow::new(at(500), at(600)));
+
+        assert!(!schedule.is_maintenance_window(at(99)));
+        assert_eq!(
+            schedule.window_at(at(120)).unwrap().description.as_deref(),
+            Some("upgrade")
+        );
+        assert_eq!(schedule.window_at(at(160)).unwrap().end, at(300));
+        assert_eq!(
+            schedule.window_at(at(160)).unwrap().remaining(at(160)),
+            Duration::from_secs(140)
+        );
+        assert!(!schedule.is_maintenance_window(at(300)));
+        assert_eq!(schedule.next_window(at(300)).unwrap().start, at(500));
+        assert_eq!(schedule.next_window(at(500)), None);
+    }
+
+    #[test]
+    fn test_status_document_is_parsed() {
+        let schedule = MaintenanceSchedule::from_json(
+            br#"{"maintenance": [{"start": 100, "end": 200}], "status": "operational"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule.windows(),
+            [MaintenanceWindow::new(at(100), at(200))]
+        );
+        assert_eq!(
+            MaintenanceSchedule::from_json(b"{}").unwrap(),
+            MaintenanceSchedule::new()
+        );
+        assert!(MaintenanceSchedule::from_json(br#"{"maintenance": [{"start": 100}]}"#).is_err());
+    }
+}
@@ -0,0 +1,573 @@
+//! Key-value storage shared by the stateful parts of the crate
+//!
+//! Idempotency keys, pending payouts and the ids of the callbacks seen by the webhook server all
+//! need to be kept somewhere, ideally somewhere shared by every instance of an application and
+//! surviving restarts. Rather than implementing a store for each of them, an application can
+//! implement [`Storage`] once over its database or cache: every `Storage` is an
+//! `idempotency::IdempotencyStore` and a `payout::PayoutStore`, and can hold the callback ids of
+//! `server::Server::dedup_storage`.
+//!
+//! Keys are grouped in namespaces, listed in [`namespaces`], so that subsystems sharing a
+//! storage do not overwrite each other's keys. Values are bytes, usually JSON, and may expire.
+//! `compare_and_swap` is what lets two instances reserve the same idempotency key without both
+//! succeeding, so implementations must make it atomic.
+//!
+//! The crate provides [`MemoryStorage`], lost when the process exits, and [`FileStorage`], which
+//! keeps one file per key in a directory.
+//!
+//! ```rust
+//! # #[tokio::main]
+//! # async fn main() {
+//! use std::time::Duration;
+//!
+//! use mpesa::storage::{MemoryStorage, Storage};
+//!
+//! let storage = MemoryStorage::default();
+//! let ttl = Some(Duration::from_secs(60));
+//! assert!(storage.compare_and_swap("locks", "order-1042", None, Some(b"worker-1"), ttl).await.unwrap());
+//! assert!(!storage.compare_and_swap("locks", "order-1042", None, Some(b"worker-2"), ttl).await.unwrap());
+//! assert_eq!(storage.get("locks", "order-1042").await.unwrap().as_deref(), Some(&b"worker-1"[..]));
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+#[cfg(any(
+    feature = "bill_manager",
+    feature = "c2b_register",
+    feature = "express_request",
+    feature = "server"
+))]
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::MpesaResult;
+
+/// Namespaces of the keys kept by the subsystems of the crate
+pub mod namespaces {
+    /// Responses of the requests sent with an idempotency key, by key. The value of a key whose
+    /// request is in flight is empty.
+    pub const IDEMPOTENCY: &str = "idempotency";
+    /// Payouts of `payout::PayoutSupervisor` waiting for their result, by
+    /// `OriginatorConversationID`
+    pub const PAYOUTS: &str = "payouts";
+    /// Decisions of `payout::PayoutSupervisor` on failed payouts, by `ConversationID`
+    pub const PAYOUT_DECISIONS: &str = "payout_decisions";
+    /// Callbacks already handled by the webhook server, by kind and id
+    pub const CALLBACKS: &str = "callbacks";
+    /// STK pushes sent within the window of `MpesaBuilder::stk_push_guard`, by phone number,
+    /// amount and account reference. The value is the `CheckoutRequestID` of the push, empty
+    /// while it is in flight.
+    pub const STK_PUSHES: &str = "stk_pushes";
+    /// Requests queued while the Safaricom API is down, see `queue`. The queue is a JSON array
+    /// under the `requests` key.
+    pub const QUEUE: &str = "queue";
+}
+
+/// Namespaced key-value storage with expiring keys and compare-and-swap
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Returns the value of `key`, `None` if it is missing or expired
+    fn get(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> impl Future<Output = MpesaResult<Option<Vec<u8>>>> + Send;
+
+    /// Sets the value of `key`, which expires after `ttl` if it is set
+    fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = MpesaResult<()>> + Send;
+
+    /// Replaces the value of `key` with `new` if it is `current`, atomically. `None` stands for
+    /// a missing or expired key, so `current: None` only sets a key that is not set and
+    /// `new: None` deletes it. Returns `false` if the value was not `current`.
+    fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        new: Option<&[u8]>,
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = MpesaResult<bool>> + Send;
+
+    /// Deletes `key`, if it is set
+    fn delete(&self, namespace: &str, key: &str) -> impl Future<Output = MpesaResult<()>> + Send;
+}
+
+/// A storage shared by several subsystems or servers
+impl<S: Storage> Storage for Arc<S> {
+    fn get(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> impl Future<Output = MpesaResult<Option<Vec<u8>>>> + Send {
+        S::get(self, namespace, key)
+    }
+
+    fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = MpesaResult<()>> + Send {
+        S::set(self, namespace, key, value, ttl)
+    }
+
+    fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        new: Option<&[u8]>,
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = MpesaResult<bool>> + Send {
+        S::compare_and_swap(self, namespace, key, current, new, ttl)
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> impl Future<Output = MpesaResult<()>> + Send {
+        S::delete(self, namespace, key)
+    }
+}
+
+#[cfg(any(
+    feature = "bill_manager",
+    feature = "c2b_register",
+    feature = "express_request",
+    feature = "server"
+))]
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The part of `Storage` used by the webhook server, the STK push guard and the degradation queue,
+/// with boxed futures for them to hold any storage
+#[cfg(any(
+    feature = "bill_manager",
+    feature = "c2b_register",
+    feature = "express_request",
+    feature = "server"
+))]
+pub(crate) trait DynStorage: fmt::Debug + Send + Sync {
+    #[cfg(any(
+        feature = "bill_manager",
+        feature = "c2b_register",
+        feature = "express_request"
+    ))]
+    fn get<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, MpesaResult<Option<Vec<u8>>>>;
+
+    #[cfg(feature = "express_request")]
+    fn set<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, MpesaResult<()>>;
+
+    fn compare_and_swap<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        current: Option<&'a [u8]>,
+        new: Option<&'a [u8]>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, MpesaResult<bool>>;
+
+    #[cfg(feature = "server")]
+    fn delete<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, MpesaResult<()>>;
+}
+
+#[cfg(any(
+    feature = "bill_manager",
+    feature = "c2b_register",
+    feature = "express_request",
+    feature = "server"
+))]
+impl<S: Storage> DynStorage for S {
+    #[cfg(any(
+        feature = "bill_manager",
+        feature = "c2b_register",
+        feature = "express_request"
+    ))]
+    fn get<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, MpesaResult<Option<Vec<u8>>>> {
+        Box::pin(Storage::get(self, namespace, key))
+    }
+
+    #[cfg(feature = "express_request")]
+    fn set<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, MpesaResult<()>> {
+        Box::pin(Storage::set(self, namespace, key, value, ttl))
+    }
+
+    fn compare_and_swap<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        current: Option<&'a [u8]>,
+        new: Option<&'a [u8]>,
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, MpesaResult<bool>> {
+        Box::pin(Storage::compare_and_swap(
+            self, namespace, key, current, new, ttl,
+        ))
+    }
+
+    #[cfg(feature = "server")]
+    fn delete<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, MpesaResult<()>> {
+        Box::pin(Storage::delete(self, namespace, key))
+    }
+}
+
+/// Number of writes between two removals of the expired keys of a `MemoryStorage`
+const MEMORY_PURGE_INTERVAL: usize = 1024;
+
+#[derive(Debug)]
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<SystemTime>,
+}
+
+impl Entry {
+    fn new(value: &[u8], ttl: Option<Duration>) -> Self {
+        Entry {
+            value: value.to_vec(),
+            expires_at: ttl.map(|ttl| SystemTime::now() + ttl),
+        }
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    entries: HashMap<(String, String), Entry>,
+    writes: usize,
+}
+
+impl Entries {
+    fn get(&self, namespace: &str, key: &str) -> Option<&[u8]> {
+        self.entries
+            .get(&(namespace.to_owned(), key.to_owned()))
+            .filter(|entry| !entry.is_expired(SystemTime::now()))
+            .map(|entry| entry.value.as_slice())
+    }
+
+    fn write(&mut self, namespace: &str, key: &str, value: Option<&[u8]>, ttl: Option<Duration>) {
+        let key = (namespace.to_owned(), key.to_owned());
+        match value {
+            Some(value) => self.entries.insert(key, Entry::new(value, ttl)),
+            None => self.entries.remove(&key),
+        };
+        self.writes += 1;
+        if self.writes.is_multiple_of(MEMORY_PURGE_INTERVAL) {
+            let now = SystemTime::now();
+            self.entries.retain(|_, entry| !entry.is_expired(now));
+        }
+    }
+}
+
+/// Keeps keys in memory, where they are lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryStorage {
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn get(&self, namespace: &str, key: &str) -> MpesaResult<Option<Vec<u8>>> {
+        Ok(self.entries().get(namespace, key).map(<[u8]>::to_vec))
+    }
+
+    async fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> MpesaResult<()> {
+        self.entries().write(namespace, key, Some(value), ttl);
+        Ok(())
+    }
+
+    async fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        new: Option<&[u8]>,
+        ttl: Option<Duration>,
+    ) -> MpesaResult<bool> {
+        let mut entries = self.entries();
+        if entries.get(namespace, key) != current {
+            return Ok(false);
+        }
+        entries.write(namespace, key, new, ttl);
+        Ok(true)
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> MpesaResult<()> {
+        self.entries().write(namespace, key, None, None);
+        Ok(())
+    }
+}
+
+/// Keeps each key in a file of a directory, named after the hex encoding of its namespace and
+/// key, so that keys survive restarts.
+///
+/// Files are written with blocking I/O and `compare_and_swap` is only atomic within a process,
+/// which suits a single instance with modest volumes. Keys are limited to about 120 bytes by the
+/// length of file names.
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+    /// Serializes the read-modify-write of `compare_and_swap` with the other writes
+    lock: Mutex<()>,
+}
+
+impl FileStorage {
+    /// Opens the storage kept in `dir`, creating the directory if needed
+    ///
+    /// # Errors
+    /// Returns `MpesaError::IoError` if the directory cannot be created
+    pub fn open(dir: impl Into<PathBuf>) -> MpesaResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStorage {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the directory of the storage
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, namespace: &str, key: &str) -> PathBuf {
+        self.dir.join(hex(namespace)).join(hex(key))
+    }
+
+    /// Reads the value of the file at `path`, removing it if it has expired. Files start with the
+    /// time they expire at in seconds since the epoch, `0` for never, in 8 big-endian bytes.
+    fn read(path: &Path) -> MpesaResult<Option<Vec<u8>>> {
+        let mut contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(expires_at) = contents.get(..8) else {
+            return Err(io::Error::new(ErrorKind::InvalidData, "truncated storage file").into());
+        };
+        let expires_at = u64::from_be_bytes(expires_at.try_into().expect("8 bytes"));
+        if expires_at != 0 && expires_at <= unix_time(SystemTime::now()) {
+            Self::remove(path)?;
+            return Ok(None);
+        }
+        Ok(Some(contents.split_off(8)))
+    }
+
+    /// Writes the file through a temporary file, so that readers never see half of it
+    fn write(path: &Path, value: &[u8], ttl: Option<Duration>) -> MpesaResult<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let expires_at = ttl.map_or(0, |ttl| unix_time(SystemTime::now() + ttl).max(1));
+        let mut contents = expires_at.to_be_bytes().to_vec();
+        contents.extend_from_slice(value);
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    fn remove(path: &Path) -> MpesaResult<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Storage for FileStorage {
+    async fn get(&self, namespace: &str, key: &str) -> MpesaResult<Option<Vec<u8>>> {
+        let _lock = self.lock();
+        Self::read(&self.path(namespace, key))
+    }
+
+    async fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> MpesaResult<()> {
+        let _lock = self.lock();
+        Self::write(&self.path(namespace, key), value, ttl)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &str,
+        current: Option<&[u8]>,
+        new: Option<&[u8]>,
+        ttl: Option<Duration>,
+    ) -> MpesaResult<bool> {
+        let _lock = self.lock();
+        let path = self.path(namespace, key);
+        if Self::read(&path)?.as_deref() != current {
+            return Ok(false);
+        }
+        match new {
+            Some(new) => Self::write(&path, new, ttl)?,
+            None => Self::remove(&path)?,
+        }
+        Ok(true)
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> MpesaResult<()> {
+        let _lock = self.lock();
+        Self::remove(&self.path(namespace, key))
+    }
+}
+
+fn hex(name: &str) -> String {
+    name.bytes().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").expect("writing to a String does not fail");
+        hex
+    })
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn check_storage(storage: impl Storage) {
+        assert_eq!(storage.get("a", "key").await.unwrap(), None);
+        storage.set("a", "key", b"1", None).await.unwrap();
+        storage.set("b", "key", b"2", None).await.unwrap();
+        assert_eq!(storage.get("a", "key").await.unwrap().unwrap(), b"1");
+        assert_eq!(storage.get("b", "key").await.unwrap().unwrap(), b"2");
+
+        assert!(!storage
+            .compare_and_swap("a", "key", None, Some(b"3"), None)
+            .await
+            .unwrap());
+        assert!(!storage
+            .compare_and_swap("a", "key", Some(b"2"), Some(b"3"), None)
+            .await
+            .unwrap());
+        assert!(storage
+            .compare_and_swap("a", "key", Some(b"1"), Some(b"3"), None)
+            .await
+            .unwrap());
+        assert!(storage
+            .compare_and_swap("a", "key", Some(b"3"), None, None)
+            .await
+            .unwrap());
+        assert_eq!(storage.get("a", "key").await.unwrap(), None);
+
+        storage.delete("b", "key").await.unwrap();
+        storage.delete("b", "key").await.unwrap();
+        assert_eq!(storage.get("b", "key").await.unwrap(), None);
+
+        // An expired key counts as missing
+        let ttl = Some(Duration::ZERO);
+        storage.set("a", "expired", b"1", ttl).await.unwrap();
+        assert_eq!(storage.get("a", "expired").await.unwrap(), None);
+        assert!(storage
+            .compare_and_swap("a", "expired", None, Some(b""), None)
+            .await
+            .unwrap());
+        assert_eq!(storage.get("a", "expired").await.unwrap().unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        check_storage(MemoryStorage::default()).await;
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_storage_keeps_idempotency_keys() {
+        use serde_json::json;
+
+        use crate::idempotency::IdempotencyStore;
+
+        let storage = MemoryStorage::default();
+        assert!(storage.reserve("order-1029").await.unwrap());
+        assert!(!storage.reserve("order-1029").await.unwrap());
+        assert_eq!(
+            IdempotencyStore::get(&storage, "order-1029").await.unwrap(),
+            None
+        );
+
+        storage.release("order-1029").await.unwrap();
+        assert!(storage.reserve("order-1029").await.unwrap());
+        let response = json!({ "ResponseCode": "0" });
+        storage.save("order-1029", &response).await.unwrap();
+        storage.release("order-1029").await.unwrap();
+        assert_eq!(
+            IdempotencyStore::get(&storage, "order-1029").await.unwrap(),
+            Some(response)
+        );
+        assert!(!storage.reserve("order-1029").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_file_storage() {
+        let dir = std::env::temp_dir().join(format!("mpesa-storage-{}", std::process::id()));
+        check_storage(FileStorage::open(&dir).unwrap()).await;
+
+        // Keys survive reopening the storage
+        let storage = FileStorage::open(&dir).unwrap();
+        Storage::set(&storage, "a", "order/1042?", b"kept", None)
+            .await
+            .unwrap();
+        let storage = FileStorage::open(&dir).unwrap();
+        assert_eq!(
+            Storage::get(&storage, "a", "order/1042?")
+                .await
+                .unwrap()
+                .unwrap(),
+            b"kept"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Estimation of the M-Pesa transaction fees of payments
+//!
+//! The tariffs published by Safaricom are shipped as [`Tariff`] tables, so that the total cost of
+//! a payout or a payment can be shown before it is initiated. Safaricom revises its tariffs from
+//! time to time: [`Tariffs::with`] replaces the table of a service, and tables can be loaded from
+//! JSON configuration since they are `Deserialize`.
+//!
+//! ```rust
+//! use mpesa::tariff::{estimate_fee, Tariff, TariffBand, TariffService, Tariffs};
+//!
+//! assert_eq!(estimate_fee(TariffService::SendMoney, 1200), Some(23));
+//! assert_eq!(estimate_fee(TariffService::SendMoney, 300_000), None);
+//!
+//! let tariffs = Tariffs::default().with(
+//!     TariffService::B2c,
+//!     Tariff::new(vec![TariffBand::new(10, 250_000, 15)]),
+//! );
+//! assert_eq!(tariffs.estimate_fee(TariffService::B2c, 1200), Some(15));
+//! assert_eq!(tariffs.total_cost(TariffService::B2c, 1200), Some(1215.0));
+//! ```
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Services with a published tariff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum TariffService {
+    /// Payments from a business to a registered customer, charged to the business
+    B2c,
+    /// Payments from a customer to a Pay Bill number, charged to the customer
+    PayBill,
+    /// Transfers between registered customers, charged to the sender
+    SendMoney,
+}
+
+/// The fee charged for amounts from `min` to `max` shillings, both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TariffBand {
+    pub min: u32,
+    pub max: u32,
+    pub fee: u32,
+}
+
+impl TariffBand {
+    pub const fn new(min: u32, max: u32, fee: u32) -> Self {
+        TariffBand { min, max, fee }
+    }
+}
+
+/// The fees of a service by amount
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Tariff {
+    bands: Vec<TariffBand>,
+}
+
+/// B2C tariff for registered customers
+const B2C: [TariffBand; 4] = [
+    TariffBand::new(10, 100, 0),
+    TariffBand::new(101, 1_500, 5),
+    TariffBand::new(1_501, 5_000, 9),
+    TariffBand::new(5_001, 250_000, 11),
+];
+
+/// Pay Bill tariff charged to customers
+const PAY_BILL: [TariffBand; 19] = [
+    TariffBand::new(1, 49, 0),
+    TariffBand::new(50, 100, 0),
+    TariffBand::new(101, 500, 5),
+    TariffBand::new(501, 1_000, 10),
+    TariffBand::new(1_001, 1_500, 15),
+    TariffBand::new(1_501, 2_500, 20),
+    TariffBand::new(2_501, 3_500, 25),
+    TariffBand::new(3_501, 5_000, 34),
+    TariffBand::new(5_001, 7_500, 42),
+    TariffBand::new(7_501, 10_000, 48),
+    TariffBand::new(10_001, 15_000, 57),
+    TariffBand::new(15_001, 20_000, 62),
+    TariffBand::new(20_001, 25_000, 67),
+    TariffBand::new(25_001, 30_000, 72),
+    TariffBand::new(30_001, 35_000, 83),
+    TariffBand::new(35_001, 45_000, 99),
+    TariffBand::new(45_001, 50_000, 103),
+    TariffBand::new(50_001, 70_000, 108),
+    TariffBand::new(70_001, 250_000, 108),
+];
+
+/// Send Money tariff for transfers to registered customers
+const SEND_MONEY: [TariffBand; 15] = [
+    TariffBand::new(1, 49, 0),
+    TariffBand::new(50, 100, 0),
+    TariffBand::new(101, 500, 7),
+    TariffBand::new(501, 1_000, 13),
+    TariffBand::new(1_001, 1_500, 23),
+    TariffBand::new(1_501, 2_500, 33),
+    TariffBand::new(2_501, 3_500, 53),
+    TariffBand::new(3_501, 5_000, 57),
+    TariffBand::new(5_001, 7_500, 78),
+    TariffBand::new(7_501, 10_000, 90),
+    TariffBand::new(10_001, 15_000, 100),
+    TariffBand::new(15_001, 20_000, 105),
+    TariffBand::new(20_001, 35_000, 108),
+    TariffBand::new(35_001, 50_000, 108),
+    TariffBand::new(50_001, 250_000, 108),
+];
+
+impl Tariff {
+    /// Creates a tariff from its bands, which should not overlap
+    pub fn new(bands: Vec<TariffBand>) -> Self {
+        Tariff { bands }
+    }
+
+    /// The tariff of `service` as published by Safaricom
+    pub fn published(service: TariffService) -> Self {
+        let bands: &[TariffBand] = match service {
+            TariffService::B2c => &B2C,
+            TariffService::PayBill => &PAY_BILL,
+            TariffService::SendMoney => &SEND_MONEY,
+        };
+        Tariff::new(bands.to_vec())
+    }
+
+    /// Returns the fee of a transaction of `amount` shillings, rounded up to the next shilling,
+    /// `None` if the amount is outside of the bands of the tariff
+    pub fn fee(&self, amount: impl Into<f64>) -> Option<u32> {
+        let amount = amount.into().ceil();
+        self.bands
+            .iter()
+            .find(|band| f64::from(band.min) <= amount && amount <= f64::from(band.max))
+            .map(|band| band.fee)
+    }
+
+    pub fn bands(&self) -> &[TariffBand] {
+        &self.bands
+    }
+}
+
+/// The tariffs of every service, the published ones unless replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tariffs {
+    tariffs: HashMap<TariffService, Tariff>,
+}
+
+impl Default for Tariffs {
+    fn default() -> Self {
+        Tariffs {
+            tariffs: [
+                TariffService::B2c,
+                TariffService::PayBill,
+                TariffService::SendMoney,
+            ]
+            .into_iter()
+            .map(|service| (service, Tariff::published(service)))
+            .collect(),
+        }
+    }
+}
+
+impl Tariffs {
+    /// Replaces the tariff of `service`, e.g. after Safaricom revised it
+    pub fn with(mut self, service: TariffService, tariff: Tariff) -> Self {
+        self.tariffs.insert(service, tariff);
+        self
+    }
+
+    pub fn get(&self, service: TariffService) -> Option<&Tariff> {
+        self.tariffs.get(&service)
+    }
+
+    /// Returns the fee of a transaction of `amount` shillings with `service`, `None` if the
+    /// amount is outside of its tariff
+    pub fn estimate_fee(&self, service: TariffService, amount: impl Into<f64>) -> Option<u32> {
+        self.get(service)?.fee(amount)
+    }
+
+    /// Returns the cost of a transaction of `amount` shillings with `service`, fee included
+    pub fn total_cost(&self, service: TariffService, amount: impl Into<f64>) -> Option<f64> {
+        let amount = amount.into();
+        Some(amount + f64::from(self.estimate_fee(service, amount)?))
+    }
+}
+
+/// Returns the fee of a transaction of `amount` shillings with `service` according to its
+/// published tariff, `None` if the amount is outside of it
+pub fn estimate_fee(service: TariffService, amount: impl Into<f64>) -> Option<u32> {
+    Tariff::published(service).fee(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fees_are_looked_up_by_band() {
+        assert_eq!(estimate_fee(TariffService::SendMoney, 100), Some(0));
+        assert_eq!(estimate_fee(TariffService::SendMoney, 101), Some(7));
+        assert_eq!(estimate_fee(TariffService::SendMoney, 100.5), Some(7));
+        assert_eq!(estimate_fee(TariffService::PayBill, 5_000), Some(34));
+        assert_eq!(estimate_fee(TariffService::B2c, 250_000), Some(11));
+        assert_eq!(estimate_fee(TariffService::B2c, 250_001), None);
+        assert_eq!(estimate_fee(TariffService::B2c, 5), None);
+    }
+
+    #[test]
+    fn test_published_tables_are_contiguous() {
+        for service in [
+            TariffService::B2c,
+            TariffService::PayBill,
+            TariffService::SendMoney,
+        ] {
+            let tariff = Tariff::published(service);
+            for bands in tariff.bands().windows(2) {
+                assert_eq!(bands[0].max + 1, bands[1].min, "{service:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_tariffs_are_loaded_from_json() {
+        let tariff: Tariff =
+            serde_json::from_str(r#"[{"min": 1, "max": 1000, "fee": 12}]"#).unwrap();
+        let tariffs = Tariffs::default().with(TariffService::PayBill, tariff);
+        assert_eq!(tariffs.estimate_fee(TariffService::PayBill, 800), Some(12));
+        assert_eq!(tariffs.estimate_fee(TariffService::PayBill, 2_000), None);
+        assert_eq!(
+            tariffs.estimate_fee(TariffService::SendMoney, 800),
+            Some(13)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Spans of the requests made to the Safaricom API, following the OpenTelemetry semantic
+//! conventions for HTTP clients so that they can be exported with `tracing-opentelemetry`
+
+use std::time::SystemTime;
+
+use serde_json::Value;
+use tracing::field::Empty;
+use tracing::Span;
+
+use crate::status::MaintenanceWindow;
+use crate::MpesaError;
+
+/// Creates the span of a request made to `path`, recording the `CommandID` of `body` if it has one
+pub(crate) fn request_span(
+    method: &reqwest::Method,
+    path: &str,
+    body: &Value,
+    client_request_id: &str,
+) -> Span {
+    let span = tracing::info_span!(
+        "mpesa.request",
+        otel.name = format!("{method} {path}"),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        http.request.method = method.as_str(),
+        http.response.status_code = Empty,
+        server.address = Empty,
+        server.port = Empty,
+        url.full = Empty,
+        error.type = Empty,
+        mpesa.command_id = Empty,
+        mpesa.initiator = Empty,
+        mpesa.conversation_id = Empty,
+        mpesa.client_request_id = client_request_id,
+    );
+    if let Some(command_id) = body.get("CommandID").and_then(Value::as_str) {
+        span.record("mpesa.command_id", command_id);
+    }
+    let initiator = body.get("Initiator").or_else(|| body.get("InitiatorName"));
+    if let Some(initiator) = initiator.and_then(Value::as_str) {
+        span.record("mpesa.initiator", initiator);
+    }
+    span
+}
+
+/// Records the url a request is sent to, which changes when failing over to another base url
+pub(crate) fn record_url(url: &str) {
+    let span = Span::current();
+    span.record("url.full", url);
+    if let Ok(url) = url::Url::parse(url) {
+        if let Some(host) = url.host_str() {
+            span.record("server.address", host);
+        }
+        if let Some(port) = url.port_or_known_default() {
+            span.record("server.port", port);
+        }
+    }
+}
+
+pub(crate) fn record_status(status: reqwest::StatusCode) {
+    Span::current().record("http.response.status_code", status.as_u16());
+}
+
+/// Records the `ConversationID` of a successful response
+pub(crate) fn record_response(body: &Value) {
+    if let Some(conversation_id) = body.get("ConversationID").and_then(Value::as_str) {
+        Span::current().record("mpesa.conversation_id", conversation_id);
+    }
+}
+
+/// Logs a request that failed during a maintenance window of the Safaricom API
+pub(crate) fn record_maintenance(window: &MaintenanceWindow) {
+    let now = SystemTime::now();
+    tracing::warn!(
+        mpesa.maintenance.remaining_secs = window.remaining(now).as_secs(),
+        mpesa.maintenance.description = window.description.as_deref(),
+        "request failed during a planned maintenance window of the Safaricom API"
+    );
+}
+
+/// Marks the span as failed, with the Daraja error code or the kind of error as `error.type`
+pub(crate) fn record_error(span: &Span, error: &MpesaError) {
+    let error_type = match error {
+        MpesaError::Service(e) => e.error_code.as_str(),
+        MpesaError::NetworkError(e) if e.is_timeout() => "timeout",
+        MpesaError::NetworkError(_) => "network",
+        MpesaError::ParseError(_) => "parse",
+        MpesaError::QuotaExceeded(_) => "quota_exceeded",
+        MpesaError::Duplicate { .. } => "duplicate",
+        MpesaError::Maintenance(_) => "maintenance",
+        MpesaError::ShutDown => "shutdown",
+        MpesaError::Cancelled => "cancelled",
+        MpesaError::SandboxOnly(_) => "sandbox_only",
+        _ => "_OTHER",
+    };
+    span.record("error.type", error_type);
+    span.record("otel.status_code", "ERROR");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    use super::*;
+    use crate::ResponseError;
+
+    /// Collects the fields recorded on the single span of a test
+    #[derive(Clone, Default)]
+    struct Recorder {
+        fields: Arc<Mutex<HashMap<String, String>>>,
+        span: Arc<Mutex<Option<&'static Metadata<'static>>>>,
+        entered: Arc<Mutex<bool>>,
+    }
+
+    impl Visit for Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.fields
+                .lock()
+                .unwrap()
+                .insert(field.name().to_owned(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields
+                .lock()
+                .unwrap()
+                .insert(field.name().to_owned(), value.to_owned());
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            *self.span.lock().unwrap() = Some(span.metadata());
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {
+            *self.entered.lock().unwrap() = true;
+        }
+
+        fn exit(&self, _: &Id) {
+            *self.entered.lock().unwrap() = false;
+        }
+
+        fn current_span(&self) -> Current {
+            match *self.span.lock().unwrap() {
+                Some(metadata) if *self.entered.lock().unwrap() => {
+                    Current::new(Id::from_u64(1), metadata)
+                }
+                _ => Current::none(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_request_span_follows_http_conventions() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let body = json!({ "CommandID": "BusinessPayment" });
+            let span = request_span(
+                &reqwest::Method::POST,
+                "mpesa/b2c/v1/paymentrequest",
+                &body,
+                "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            );
+            span.in_scope(|| {
+                record_url("https://sandbox.safaricom.co.ke/mpesa/b2c/v1/paymentrequest");
+                record_status(reqwest::StatusCode::BAD_REQUEST);
+            });
+            let error = MpesaError::Service(ResponseError::new("id", "400.002.02", "Bad Request"));
+            record_error(&span, &error);
+        });
+
+        let fields = recorder.fields.lock().unwrap();
+        assert_eq!(fields["otel.name"], "POST mpesa/b2c/v1/paymentrequest");
+        assert_eq!(fields["http.request.method"], "POST");
+        assert_eq!(fields["server.address"], "sandbox.safaricom.co.ke");
+        assert_eq!(fields["server.port"], "443");
+        assert_eq!(fields["http.response.status_code"], "400");
+        assert_eq!(fields["mpesa.command_id"], "BusinessPayment");
+        assert_eq!(
+            fields["mpesa.client_request_id"],
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+        assert_eq!(fields["error.type"], "400.002.02");
+        assert_eq!(fields["otel.status_code"], "ERROR");
+    }
+}
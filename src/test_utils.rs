@@ -0,0 +1,483 @@
+//! Helpers for testing code built on top of the client
+//!
+//! Enabled with the `test-utils` feature, which is not meant to be used in production.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use mpesa::test_utils::{assert_conversation_id, assert_service_error, assert_success};
+//!
+//! let response = client.b2c("testapi496").amount(1000).send().await.unwrap();
+//! assert_success(&response);
+//! assert_conversation_id(&response, "AG_20230206_201056794190723278ff");
+//!
+//! let error = client.b2c("testapi496").amount(0).send().await.unwrap_err();
+//! assert_service_error(&error, "400.002.02");
+//! ```
+//!
+//! [`Chaos`], set with `MpesaBuilder::chaos`, adds latency and failures to the requests of a
+//! client, and [`DelayedCallbacks`] delays the callbacks received by the webhook server, to test
+//! timeouts, retries and reconciliation against the sandbox before going live.
+
+use std::fmt;
+#[cfg(feature = "test-utils")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "test-utils")]
+use std::time::Duration;
+
+#[cfg(all(feature = "server", feature = "test-utils"))]
+use crate::callbacks::{C2bTransaction, C2bValidationResponse, ResultCallback, StkCallback};
+#[cfg(all(feature = "server", feature = "test-utils"))]
+use crate::server::CallbackHandler;
+use crate::{MpesaError, MpesaResult};
+
+/// Generates the `SecurityCredential` of requests from the initiator password.
+///
+/// The client encrypts the password with the certificate of the environment, which is randomized
+/// by the PKCS#1 padding. Set a deterministic signer with `MpesaBuilder::credential_signer` to
+/// compare request payloads against golden files.
+pub trait CredentialSigner: fmt::Debug + Send + Sync {
+    /// # Errors
+    /// Returns a `MpesaError` if the credential cannot be generated
+    fn sign(&self, initiator_password: &str) -> MpesaResult<String>;
+}
+
+/// Signs every initiator password to the same credential
+#[derive(Debug, Clone)]
+pub struct StubSigner {
+    security_credential: String,
+}
+
+impl StubSigner {
+    pub fn new<S: Into<String>>(security_credential: S) -> Self {
+        StubSigner {
+            security_credential: security_credential.into(),
+        }
+    }
+}
+
+impl Default for StubSigner {
+    /// Signs to `stub_security_credential`
+    fn default() -> Self {
+        Self::new("stub_security_credential")
+    }
+}
+
+impl CredentialSigner for StubSigner {
+    fn sign(&self, _initiator_password: &str) -> MpesaResult<String> {
+        Ok(self.security_credential.clone())
+    }
+}
+
+/// Misbehavior injected into every request of a client, access token requests included
+///
+/// Latency is added before a request is sent, so it counts toward the deadline of
+/// `with_deadline` but not toward the `MpesaBuilder::timeout` of the HTTP client. Failed
+/// requests are not sent: they get an error response, by default the `500.003.02` "System is
+/// busy" error Daraja returns under load, which the client retries like any transient error.
+///
+/// ```rust,ignore
+/// use mpesa::test_utils::Chaos;
+///
+/// let client = Mpesa::builder(consumer_key, consumer_secret, Environment::Sandbox)
+///     .chaos(
+///         Chaos::new()
+///             .latency(Duration::from_millis(800))
+///             .jitter(Duration::from_secs(2))
+///             .error_rate(0.2),
+///     )
+///     .build()?;
+/// ```
+#[cfg(feature = "test-utils")]
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    latency: Duration,
+    jitter: Duration,
+    error_rate: f64,
+    error_status: u16,
+    error_code: String,
+    error_message: String,
+    seed: u64,
+}
+
+#[cfg(feature = "test-utils")]
+impl Default for Chaos {
+    fn default() -> Self {
+        Chaos {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            error_rate: 0.0,
+            error_status: 500,
+            error_code: "500.003.02".to_owned(),
+            error_message: "System is busy. Please try again in few minutes.".to_owned(),
+            seed: 0,
+        }
+    }
+}
+
+#[cfg(feature = "test-utils")]
+impl Chaos {
+    /// Creates a `Chaos` that injects nothing until configured
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every request by `latency`
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Delays every request by a random duration up to `jitter`, on top of the `latency`
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Fails `rate` of the requests, from `0.0` for none to `1.0` for all of them
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Replaces the error response of failed requests
+    pub fn error<S: Into<String>>(mut self, status: u16, error_code: S, error_message: S) -> Self {
+        self.error_status = status;
+        self.error_code = error_code.into();
+        self.error_message = error_message.into();
+        self
+    }
+
+    /// Seeds the random jitter and failures, so that a test run can be reproduced. Defaults to `0`
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Applies a `Chaos` to the requests of a client and its clones
+#[cfg(feature = "test-utils")]
+#[derive(Debug)]
+pub(crate) struct ChaosInjector {
+    chaos: Chaos,
+    state: AtomicU64,
+}
+
+#[cfg(feature = "test-utils")]
+impl ChaosInjector {
+    pub(crate) fn new(chaos: Chaos) -> Self {
+        ChaosInjector {
+            state: AtomicU64::new(chaos.seed),
+            chaos,
+        }
+    }
+
+    /// Waits for the injected latency, returning the error response of the request if it fails
+    pub(crate) async fn inject(&self) -> Option<reqwest::Response> {
+        let jitter = self.chaos.jitter.mul_f64(self.next_f64());
+        let delay = self.chaos.latency + jitter;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if self.next_f64() >= self.chaos.error_rate {
+            return None;
+        }
+
+        let body = serde_json::json!({
+            "requestId": "",
+            "errorCode": self.chaos.error_code,
+            "errorMessage": self.chaos.error_message,
+        });
+        let response = http::Response::builder()
+            .status(self.chaos.error_status)
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .expect("the status of a Chaos error is valid");
+        Some(response.into())
+    }
+
+    /// Returns a random number in `[0, 1)`, from the SplitMix64 sequence of the seed
+    fn next_f64(&self) -> f64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Wraps a `CallbackHandler`, handing it callbacks `delay` after the server received them, as
+/// when Safaricom is slow to deliver results
+///
+/// The server acknowledges a callback once it has been handled, so delays longer than the time
+/// Safaricom waits for the acknowledgement also test how retried callbacks are handled.
+#[cfg(all(feature = "server", feature = "test-utils"))]
+#[derive(Debug)]
+pub struct DelayedCallbacks<H> {
+    handler: H,
+    delay: Duration,
+}
+
+#[cfg(all(feature = "server", feature = "test-utils"))]
+impl<H: CallbackHandler> DelayedCallbacks<H> {
+    pub fn new(handler: H, delay: Duration) -> Self {
+        DelayedCallbacks { handler, delay }
+    }
+}
+
+#[cfg(all(feature = "server", feature = "test-utils"))]
+impl<H: CallbackHandler> CallbackHandler for DelayedCallbacks<H> {
+    async fn on_stk_callback(&self, callback: StkCallback) {
+        tokio::time::sleep(self.delay).await;
+        self.handler.on_stk_callback(callback).await;
+    }
+
+    async fn on_c2b_validation(&self, transaction: C2bTransaction) -> C2bValidationResponse {
+        tokio::time::sleep(self.delay).await;
+        self.handler.on_c2b_validation(transaction).await
+    }
+
+    async fn on_c2b_confirmation(&self, transaction: C2bTransaction) {
+        tokio::time::sleep(self.delay).await;
+        self.handler.on_c2b_confirmation(transaction).await;
+    }
+
+    async fn on_result(&self, result: ResultCallback) {
+        tokio::time::sleep(self.delay).await;
+        self.handler.on_result(result).await;
+    }
+
+    async fn on_timeout(&self, result: ResultCallback) {
+        tokio::time::sleep(self.delay).await;
+        self.handler.on_timeout(result).await;
+    }
+}
+
+/// A response of the Safaricom API, as checked by the assertions of this module
+pub trait ApiResponse: fmt::Debug {
+    /// Returns `true` if the API accepted the request
+    fn is_accepted(&self) -> bool;
+
+    /// The `ConversationID` of the request, for APIs returning one
+    fn conversation_id(&self) -> Option<&str> {
+        None
+    }
+}
+
+macro_rules! api_response {
+    ($feature:literal, $response:ident, |$r:ident| $accepted:expr) => {
+        #[cfg(feature = $feature)]
+        impl ApiResponse for crate::services::$response {
+            fn is_accepted(&self) -> bool {
+                let $r = self;
+                $accepted
+            }
+        }
+    };
+    ($feature:literal, $response:ident, |$r:ident| $accepted:expr, $conversation_id:expr) => {
+        #[cfg(feature = $feature)]
+        impl ApiResponse for crate::services::$response {
+            fn is_accepted(&self) -> bool {
+                let $r = self;
+                $accepted
+            }
+
+            fn conversation_id(&self) -> Option<&str> {
+                let $r = self;
+                $conversation_id
+            }
+        }
+    };
+}
+
+api_response!(
+    "account_balance",
+    AccountBalanceResponse,
+    |r| r.response_code == "0",
+    Some(&r.conversation_id)
+);
+api_response!(
+    "b2b",
+    B2bResponse,
+    |r| r.response_code == "0",
+    Some(&r.conversation_id)
+);
+api_response!(
+    "b2c",
+    B2cResponse,
+    |r| r.response_code == "0",
+    Some(&r.conversation_id)
+);
+api_response!("c2b_register", C2bRegisterResponse, |r| r.response_code
+    == "0");
+api_response!(
+    "c2b_simulate",
+    C2bSimulateResponse,
+    |r| r.response_code == "0",
+    r.conversation_id.as_deref()
+);
+api_response!("dynamic_qr", DynamicQRResponse, |r| matches!(
+    r.response_code.as_str(),
+    "0" | "00"
+));
+api_response!("express_request", MpesaExpressResponse, |r| r.response_code
+    == "0");
+api_response!(
+    "transaction_reversal",
+    TransactionReversalResponse,
+    |r| r.response_code == "0",
+    Some(&r.conversation_id)
+);
+// Transaction status responses carry no response code, failures are returned as errors
+api_response!(
+    "transaction_status",
+    TransactionStatusResponse,
+    |_r| true,
+    Some(&_r.conversation_id)
+);
+api_response!("bill_manager", BulkInvoiceResponse, |r| r.response_code
+    == "200");
+api_response!("bill_manager", CancelInvoiceResponse, |r| r.response_code
+    == "200");
+api_response!("bill_manager", OnboardResponse, |r| r.response_code
+    == "200");
+api_response!("bill_manager", OnboardModifyResponse, |r| r.response_code
+    == "200");
+api_response!("bill_manager", ReconciliationResponse, |r| r.response_code
+    == "200");
+api_response!("bill_manager", SingleInvoiceResponse, |r| r.response_code
+    == "200");
+
+/// Asserts that the API accepted the request `response` was returned for
+///
+/// # Panics
+/// If the response code of `response` is not a success code
+#[track_caller]
+pub fn assert_success<R: ApiResponse>(response: &R) {
+    assert!(
+        response.is_accepted(),
+        "expected the request to be accepted, got {response:?}"
+    );
+}
+
+/// Asserts that `error` is an error returned by the Safaricom API with `error_code`, e.g. `500.001.1001`
+///
+/// # Panics
+/// If `error` is another kind of error or has another error code
+#[track_caller]
+pub fn assert_service_error(error: &MpesaError, error_code: &str) {
+    match error {
+        MpesaError::Service(e) => assert_eq!(
+            e.error_code, error_code,
+            "expected a service error with code {error_code}, got {e}"
+        ),
+        e => panic!("expected a service error with code {error_code}, got {e:?}"),
+    }
+}
+
+/// Asserts that `response` carries the `ConversationID` `expected`
+///
+/// # Panics
+/// If the conversation id of `response` is missing or different
+#[track_caller]
+pub fn assert_conversation_id<R: ApiResponse>(response: &R, expected: &str) {
+    assert_eq!(
+        response.conversation_id(),
+        Some(expected),
+        "unexpected conversation id in {response:?}"
+    );
+}
+
+/// Returns `true` if `id` has the format of the conversation ids generated by M-Pesa, such as
+/// `AG_20230206_201056794190723278ff`
+pub fn is_conversation_id(id: &str) -> bool {
+    let mut parts = id.splitn(3, '_');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some("AG"), Some(date), Some(suffix))
+            if date.len() == 8
+                && date.bytes().all(|b| b.is_ascii_digit())
+                && !suffix.is_empty()
+                && suffix.bytes().all(|b| b.is_ascii_hexdigit())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseError;
+
+    #[test]
+    fn test_conversation_id_format() {
+        assert!(is_conversation_id("AG_20230206_201056794190723278ff"));
+        assert!(is_conversation_id("AG_20191219_00004e48cf7e3533f581"));
+        assert!(!is_conversation_id("29464-48063588-1"));
+        assert!(!is_conversation_id("AG_2023_201056794190723278ff"));
+        assert!(!is_conversation_id("AG_20230206_"));
+    }
+
+    #[test]
+    fn test_service_error_assertion() {
+        let error = MpesaError::Service(ResponseError::new("id", "500.001.1001", "Error"));
+        assert_service_error(&error, "500.001.1001");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_service_error_assertion_fails_on_another_code() {
+        let error = MpesaError::Service(ResponseError::new("id", "500.001.1001", "Error"));
+        assert_service_error(&error, "400.002.02");
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_chaos_fails_the_configured_rate_of_requests() {
+        let injector = ChaosInjector::new(Chaos::new().error_rate(0.25).seed(7));
+        let mut failures = 0;
+        for _ in 0..1000 {
+            if let Some(response) = injector.inject().await {
+                assert_eq!(
+                    response.status(),
+                    reqwest::StatusCode::INTERNAL_SERVER_ERROR
+                );
+                failures += 1;
+            }
+        }
+        assert!((200..300).contains(&failures), "{failures} failures");
+
+        let injector = ChaosInjector::new(Chaos::new());
+        assert!(injector.inject().await.is_none());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_chaos_is_reproducible_with_a_seed() {
+        let values = |seed| {
+            let injector = ChaosInjector::new(Chaos::new().seed(seed));
+            (0..8).map(|_| injector.next_f64()).collect::<Vec<_>>()
+        };
+        assert_eq!(values(42), values(42));
+        assert_ne!(values(42), values(43));
+        assert!(values(42).iter().all(|value| (0.0..1.0).contains(value)));
+    }
+
+    #[test]
+    #[cfg(feature = "b2c")]
+    fn test_response_assertions() {
+        use serde_json::json;
+
+        let response: crate::services::B2cResponse = serde_json::from_value(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        }))
+        .unwrap();
+        assert_success(&response);
+        assert_conversation_id(&response, "AG_20230206_201056794190723278ff");
+    }
+}
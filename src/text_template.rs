@@ -0,0 +1,256 @@
+//! Templates of the free text fields of requests, such as `AccountReference`
+//!
+//! Requests built from domain events usually word their account reference and description the
+//! same way, e.g. `Order 1042`. A [`TextTemplate`] is parsed once from a pattern with named
+//! placeholders, and renders the text of each request, checking that it fits the length limit
+//! Daraja enforces on its field. Braces are written `{{` and `}}`.
+//!
+//! ```rust
+//! use mpesa::text_template::{TextField, TextTemplate};
+//!
+//! let account_ref = TextTemplate::parse(TextField::AccountReference, "Order {order_id}").unwrap();
+//! assert_eq!(account_ref.render(&[("order_id", &1042)]).unwrap(), "Order 1042");
+//! assert!(account_ref.render(&[("order_id", &"1042-2024-KE")]).is_err());
+//! ```
+
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::metadata::Metadata;
+use crate::{MpesaError, MpesaResult};
+
+/// Free text fields of requests, with the length limit Daraja enforces on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TextField {
+    /// `AccountReference` of M-Pesa Express requests, shown to the customer, up to 12 characters
+    AccountReference,
+    /// `TransactionDesc` of M-Pesa Express requests, up to 13 characters
+    TransactionDesc,
+    /// `Remarks` of B2C, B2B, reversal and query requests, up to 100 characters
+    Remarks,
+    /// `Occasion` of B2C and reversal requests, up to 100 characters
+    Occasion,
+}
+
+impl TextField {
+    /// The maximum number of characters of the field
+    pub fn max_len(&self) -> usize {
+        match self {
+            TextField::AccountReference => 12,
+            TextField::TransactionDesc => 13,
+            TextField::Remarks | TextField::Occasion => 100,
+        }
+    }
+
+    /// Checks that `text` fits the field
+    ///
+    /// # Errors
+    /// Returns `MpesaError::TextTooLong` if `text` is longer than `max_len`
+    pub fn check(&self, text: &str) -> MpesaResult<()> {
+        let len = text.chars().count();
+        if len > self.max_len() {
+            return Err(MpesaError::TextTooLong {
+                field: *self,
+                len,
+                max_len: self.max_len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Display for TextField {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A pattern of the text of a field, such as `"Order {order_id}"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextTemplate {
+    field: TextField,
+    parts: Vec<Part>,
+}
+
+impl TextTemplate {
+    /// Parses `pattern`, in which placeholders are names of letters, digits and underscores
+    /// between braces
+    ///
+    /// # Errors
+    /// Returns `MpesaError::Message` if a brace is unmatched or a placeholder has an invalid name
+    pub fn parse(field: TextField, pattern: &str) -> MpesaResult<Self> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => literal.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => literal.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                            _ => {
+                                return Err(MpesaError::Message(
+                                    "Template placeholders must be a name between braces",
+                                ))
+                            }
+                        }
+                    }
+                    if name.is_empty() {
+                        return Err(MpesaError::Message(
+                            "Template placeholders must be a name between braces",
+                        ));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(name));
+                }
+                '}' => return Err(MpesaError::Message("Unmatched `}` in template")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(TextTemplate { field, parts })
+    }
+
+    pub fn field(&self) -> TextField {
+        self.field
+    }
+
+    /// Returns the names of the placeholders of the template
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Placeholder(name) => Some(name.as_str()),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// Renders the template with the values of its placeholders
+    ///
+    /// # Errors
+    /// Returns `MpesaError::Message` if a placeholder has no value, and
+    /// `MpesaError::TextTooLong` if the text does not fit its field
+    pub fn render(&self, values: &[(&str, &dyn Display)]) -> MpesaResult<String> {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => text.push_str(literal),
+                Part::Placeholder(name) => {
+                    let (_, value) =
+                        values
+                            .iter()
+                            .find(|(key, _)| key == name)
+                            .ok_or(MpesaError::Message(
+                                "A placeholder of the template has no value",
+                            ))?;
+                    write!(text, "{value}").expect("writing to a String does not fail");
+                }
+            }
+        }
+        self.field.check(&text)?;
+        Ok(text)
+    }
+
+    /// Reads the values of the placeholders back from `text` rendered by the template, such as
+    /// the account reference echoed by a callback. A placeholder followed by a literal ends at
+    /// the first occurrence of that literal. Returns `None` if `text` does not match the template,
+    /// a placeholder would be empty, or two placeholders follow each other.
+    pub fn capture(&self, text: &str) -> Option<Metadata> {
+        let mut values = Metadata::new();
+        let mut rest = text;
+        let mut parts = self.parts.iter().peekable();
+        while let Some(part) = parts.next() {
+            match part {
+                Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+                Part::Placeholder(name) => {
+                    let end = match parts.peek() {
+                        None => rest.len(),
+                        Some(Part::Literal(literal)) => rest.find(literal.as_str())?,
+                        Some(Part::Placeholder(_)) => return None,
+                    };
+                    if end == 0 {
+                        return None;
+                    }
+                    values.insert(name.as_str(), &rest[..end]);
+                    rest = &rest[end..];
+                }
+            }
+        }
+        rest.is_empty().then_some(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_are_parsed_with_escaped_braces() {
+        let template =
+            TextTemplate::parse(TextField::Remarks, "{{{kind}}} for {order_id}").unwrap();
+        assert_eq!(
+            template.placeholders().collect::<Vec<_>>(),
+            ["kind", "order_id"]
+        );
+        assert_eq!(
+            template
+                .render(&[("order_id", &1042), ("kind", &"Refund")])
+                .unwrap(),
+            "{Refund} for 1042"
+        );
+
+        for pattern in ["Order {", "Order {}", "Order }", "Order {order id}"] {
+            assert!(
+                TextTemplate::parse(TextField::Remarks, pattern).is_err(),
+                "{pattern}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_values_are_captured_from_rendered_text() {
+        let template =
+            TextTemplate::parse(TextField::AccountReference, "INV-{year}-{number}").unwrap();
+        let values = template.capture("INV-2024-0042").unwrap();
+        assert_eq!(values.get("year"), Some("2024"));
+        assert_eq!(values.get("number"), Some("0042"));
+
+        for text in ["INV-2024", "INV--0042", "BILL-2024-0042", "INV-2024-"] {
+            assert_eq!(template.capture(text), None, "{text}");
+        }
+        let adjacent = TextTemplate::parse(TextField::AccountReference, "{a}{b}").unwrap();
+        assert_eq!(adjacent.capture("ab"), None);
+    }
+
+    #[test]
+    fn test_rendered_text_fits_its_field() {
+        let template = TextTemplate::parse(TextField::TransactionDesc, "Pay {item}").unwrap();
+        assert_eq!(
+            template.render(&[("item", &"Tickets 2")]).unwrap(),
+            "Pay Tickets 2"
+        );
+        assert!(matches!(
+            template.render(&[("item", &"Tickets x2")]),
+            Err(MpesaError::TextTooLong {
+                field: TextField::TransactionDesc,
+                len: 14,
+                max_len: 13
+            })
+        ));
+        assert!(matches!(template.render(&[]), Err(MpesaError::Message(_))));
+        assert!(TextField::AccountReference.check("Mwananchi Ltd").is_err());
+        assert!(TextField::AccountReference.check("Café Order 7").is_ok());
+    }
+}
@@ -1,6 +1,288 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
 use regex::Regex;
+use url::{Host, Url};
+
+use crate::constants::{SANDBOX_EXPRESS_SHORTCODE, SANDBOX_SHORTCODES, SANDBOX_TEST_MSISDN};
+use crate::{CommandId, MpesaError, MpesaResult};
+
+/// Compiled once on first use and shared across all validations
+static PHONE_REGEX: OnceLock<Regex> = OnceLock::new();
+
+fn phone_regex() -> &'static Regex {
+    PHONE_REGEX.get_or_init(|| {
+        Regex::new(r"^(254\d{9}|07\d{8}|011\d{7}|7\d{8}|1\d{8})$")
+            .expect("phone number regex is valid")
+    })
+}
+
+#[cfg(feature = "bill_manager")]
+/// Compiled once on first use and shared across all validations
+static EMAIL_REGEX: OnceLock<Regex> = OnceLock::new();
+
+#[cfg(feature = "bill_manager")]
+fn email_regex() -> &'static Regex {
+    EMAIL_REGEX.get_or_init(|| {
+        Regex::new(r"^[^@ \t\r\n]+@[^@ \t\r\n.]+(\.[^@ \t\r\n.]+)+$").expect("email regex is valid")
+    })
+}
+
+#[cfg(feature = "bill_manager")]
+/// Validates the syntax of an email address
+pub(crate) fn validate_email(email: &str) -> MpesaResult<()> {
+    if email_regex().is_match(email) {
+        Ok(())
+    } else {
+        Err(MpesaError::Message(
+            "Invalid email, must be in the format name@example.com",
+        ))
+    }
+}
+
+#[cfg(feature = "bill_manager")]
+/// Validates a phone number in the local `07XXXXXXXX` or `011XXXXXXX` format, returning
+/// `error` if it is in any other format
+pub(crate) fn validate_local_phone_number(
+    phone_number: &str,
+    error: &'static str,
+) -> MpesaResult<()> {
+    if phone_number.len() == 10 && phone_number.starts_with('0') && phone_number.validate().is_ok()
+    {
+        Ok(())
+    } else {
+        Err(MpesaError::Message(error))
+    }
+}
+
+/// Normalizes a phone number in the local (`0712345678`, `712345678`) or international
+/// (`+254712345678`) format to the `254712345678` format expected by the Safaricom API.
+///
+/// Numbers that are not in any of these formats are returned unchanged, to be rejected by
+/// validation.
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::validator::normalize_msisdn;
+///
+/// assert_eq!(normalize_msisdn("0712345678"), "254712345678");
+/// assert_eq!(normalize_msisdn("+254712345678"), "254712345678");
+/// assert_eq!(normalize_msisdn("254712345678"), "254712345678");
+/// ```
+pub fn normalize_msisdn(phone_number: &str) -> Cow<'_, str> {
+    let trimmed = phone_number.trim();
+    let digits = trimmed.strip_prefix('+').unwrap_or(trimmed);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Cow::Borrowed(phone_number);
+    }
+
+    match digits.len() {
+        12 if digits.starts_with("254") => Cow::Borrowed(digits),
+        10 if digits.starts_with('0') => Cow::Owned(format!("254{}", &digits[1..])),
+        9 if digits.starts_with('7') || digits.starts_with('1') => {
+            Cow::Owned(format!("254{digits}"))
+        }
+        _ => Cow::Borrowed(phone_number),
+    }
+}
+
+/// Returns `true` if `value` is one of the test MSISDNs or shortcodes documented for the Safaricom
+/// sandbox. Phone numbers are recognized in any of the formats accepted by `normalize_msisdn`.
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::validator::is_sandbox_test_number;
+///
+/// assert!(is_sandbox_test_number("0708374149"));
+/// assert!(is_sandbox_test_number("174379"));
+/// assert!(is_sandbox_test_number("600496"));
+/// assert!(!is_sandbox_test_number("600123"));
+/// assert!(!is_sandbox_test_number("254712345678"));
+/// ```
+pub fn is_sandbox_test_number(value: &str) -> bool {
+    let value = value.trim();
+    normalize_msisdn(value) == SANDBOX_TEST_MSISDN
+        || value == SANDBOX_EXPRESS_SHORTCODE
+        || value
+            .parse::<u32>()
+            .is_ok_and(|shortcode| value.len() == 6 && SANDBOX_SHORTCODES.contains(&shortcode))
+}
+
+/// Checks that Safaricom can deliver callbacks to `url`: it must use https and its host must not
+/// be `localhost` or a loopback, private or link-local address. Callbacks sent to other urls are
+/// silently dropped by Safaricom.
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::validator::validate_callback_url;
+///
+/// assert!(validate_callback_url("https://pay.example.com/mpesa/callback").is_ok());
+/// assert!(validate_callback_url("http://pay.example.com/mpesa/callback").is_err());
+/// assert!(validate_callback_url("https://192.168.1.10/mpesa/callback").is_err());
+/// ```
+///
+/// # Errors
+/// Returns a `Message` describing why callbacks cannot be delivered to `url`
+pub fn validate_callback_url(url: &str) -> MpesaResult<()> {
+    let url = Url::parse(url).map_err(|_| MpesaError::Message("Invalid callback url"))?;
+    if url.scheme() != "https" {
+        return Err(MpesaError::Message(
+            "Callback urls must use https, Safaricom does not deliver callbacks over http",
+        ));
+    }
+    let routable = match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost") && !domain.ends_with(".local")
+        }
+        Some(Host::Ipv4(ip)) => is_public_ip(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public_ip(IpAddr::V6(ip)),
+        None => false,
+    };
+    if !routable {
+        return Err(MpesaError::Message(
+            "Callback urls must be publicly routable, Safaricom cannot deliver callbacks to localhost or private addresses",
+        ));
+    }
+    Ok(())
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The kinds of party a transaction is sent from or to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PartyType {
+    /// A phone number, in any of the formats accepted by `normalize_msisdn`
+    Msisdn,
+    /// A paybill or till number, 5 to 7 digits long
+    ShortCode,
+}
+
+impl fmt::Display for PartyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartyType::Msisdn => write!(f, "an MSISDN"),
+            PartyType::ShortCode => write!(f, "a shortcode"),
+        }
+    }
+}
+
+impl PartyType {
+    /// Returns the type of `party`, `None` if it is neither a phone number nor a shortcode
+    pub fn of(party: &str) -> Option<PartyType> {
+        let party = party.trim();
+        if normalize_msisdn(party).as_ref().validate().is_ok() {
+            Some(PartyType::Msisdn)
+        } else if (5..=7).contains(&party.len()) && party.bytes().all(|b| b.is_ascii_digit()) {
+            Some(PartyType::ShortCode)
+        } else {
+            None
+        }
+    }
+
+    /// Checks that `value`, the `party` (`PartyA` or `PartyB`) of a request with `command_id`,
+    /// is of this type
+    ///
+    /// # Errors
+    /// Returns `MpesaError::InvalidParty` if it is not
+    pub fn check(self, command_id: CommandId, party: &'static str, value: &str) -> MpesaResult<()> {
+        if PartyType::of(value) == Some(self) {
+            Ok(())
+        } else {
+            Err(MpesaError::InvalidParty {
+                command_id,
+                party,
+                expected: self,
+            })
+        }
+    }
+}
+
+/// Returns the types `PartyA` and `PartyB` must have in requests with `command_id`, `None` for
+/// commands whose requests do not have both parties.
+///
+/// M-Pesa Express requests, which are always sent from the phone number of the customer to a
+/// shortcode, are not covered by the table since they use `BusinessBuyGoods` for till numbers.
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::validator::{party_types, PartyType};
+/// use mpesa::CommandId;
+///
+/// assert_eq!(
+///     party_types(CommandId::BusinessPayment),
+///     Some((PartyType::ShortCode, PartyType::Msisdn))
+/// );
+/// assert_eq!(party_types(CommandId::AccountBalance), None);
+/// ```
+pub fn party_types(command_id: CommandId) -> Option<(PartyType, PartyType)> {
+    match command_id {
+        CommandId::SalaryPayment | CommandId::BusinessPayment | CommandId::PromotionPayment => {
+            Some((PartyType::ShortCode, PartyType::Msisdn))
+        }
+        CommandId::CustomerPayBillOnline | CommandId::CustomerBuyGoodsOnline => {
+            Some((PartyType::Msisdn, PartyType::ShortCode))
+        }
+        CommandId::BusinessPayBill
+        | CommandId::BusinessBuyGoods
+        | CommandId::DisburseFundsToBusiness
+        | CommandId::BusinessToBusinessTransfer
+        | CommandId::BusinessTransferFromMMFToUtility => {
+            Some((PartyType::ShortCode, PartyType::ShortCode))
+        }
+        _ => None,
+    }
+}
 
-use crate::{MpesaError, MpesaResult};
+/// Checks `party_a` and `party_b` against the types `party_types` returns for `command_id`
+///
+/// # Example
+///
+/// ```rust
+/// use mpesa::validator::validate_parties;
+/// use mpesa::CommandId;
+///
+/// assert!(validate_parties(CommandId::BusinessPayment, "600496", "254708374149").is_ok());
+/// assert_eq!(
+///     validate_parties(CommandId::BusinessPayment, "600496", "600000")
+///         .unwrap_err()
+///         .to_string(),
+///     "BusinessPayment requires PartyB to be an MSISDN"
+/// );
+/// ```
+///
+/// # Errors
+/// Returns `MpesaError::InvalidParty` for the first party of the wrong type
+pub fn validate_parties(command_id: CommandId, party_a: &str, party_b: &str) -> MpesaResult<()> {
+    let Some((type_a, type_b)) = party_types(command_id) else {
+        return Ok(());
+    };
+    type_a.check(command_id, "PartyA", party_a)?;
+    type_b.check(command_id, "PartyB", party_b)
+}
 
 pub trait PhoneNumberValidator {
     fn validate(&self) -> MpesaResult<()>;
@@ -8,14 +290,7 @@ pub trait PhoneNumberValidator {
 
 impl PhoneNumberValidator for &str {
     fn validate(&self) -> MpesaResult<()> {
-        let phone_regex =
-            Regex::new(r"^(254\d{9}|07\d{8}|011\d{7}|7\d{8}|1\d{8})$").map_err(|_| {
-                MpesaError::Message(
-                "Invalid phone number, must be in the format 2547XXXXXXXX, 07XXXXXXXX, 011XXXXXXX",
-            )
-            })?;
-
-        if phone_regex.is_match(self) {
+        if phone_regex().is_match(self) {
             Ok(())
         } else {
             Err(MpesaError::Message(
@@ -65,6 +340,67 @@ mod tests {
         assert!("a".validate().is_err());
     }
 
+    #[test]
+    #[cfg(feature = "bill_manager")]
+    fn test_validate_email() {
+        assert!(validate_email("email@test.com").is_ok());
+        assert!(validate_email("first.last+tag@mail.example.co.ke").is_ok());
+        assert!(validate_email("email@test").is_err());
+        assert!(validate_email("email.test.com").is_err());
+        assert!(validate_email("email@@test.com").is_err());
+        assert!(validate_email("email @test.com").is_err());
+        assert!(validate_email("@test.com").is_err());
+        assert!(validate_email("email@test.").is_err());
+        assert!(validate_email("").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "bill_manager")]
+    fn test_validate_local_phone_number() {
+        assert!(validate_local_phone_number("0712345678", "invalid").is_ok());
+        assert!(validate_local_phone_number("0112345678", "invalid").is_ok());
+        assert!(validate_local_phone_number("254712345678", "invalid").is_err());
+        assert!(validate_local_phone_number("712345678", "invalid").is_err());
+        assert!(validate_local_phone_number("071234567", "invalid").is_err());
+        assert!(validate_local_phone_number("07123456789", "invalid").is_err());
+        assert!(validate_local_phone_number("071234567a", "invalid").is_err());
+    }
+
+    #[test]
+    fn test_normalize_msisdn() {
+        assert_eq!(normalize_msisdn("254712345678"), "254712345678");
+        assert_eq!(normalize_msisdn("+254712345678"), "254712345678");
+        assert_eq!(normalize_msisdn("0712345678"), "254712345678");
+        assert_eq!(normalize_msisdn("0112345678"), "254112345678");
+        assert_eq!(normalize_msisdn("712345678"), "254712345678");
+        assert_eq!(normalize_msisdn(" 0712345678 "), "254712345678");
+        assert_eq!(normalize_msisdn("07123456789"), "07123456789");
+        assert_eq!(normalize_msisdn("+1 555 0100"), "+1 555 0100");
+        assert!(matches!(normalize_msisdn("254712345678"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_is_sandbox_test_number() {
+        assert!(is_sandbox_test_number("254708374149"));
+        assert!(is_sandbox_test_number("+254708374149"));
+        assert!(is_sandbox_test_number("0708374149"));
+        assert!(is_sandbox_test_number("174379"));
+        assert!(is_sandbox_test_number("600000"));
+        assert!(is_sandbox_test_number("600638"));
+        assert!(!is_sandbox_test_number("600123"));
+        assert!(!is_sandbox_test_number("600999"));
+        assert!(!is_sandbox_test_number("601000"));
+        assert!(!is_sandbox_test_number("0600000"));
+        assert!(!is_sandbox_test_number("254712345678"));
+        assert!(!is_sandbox_test_number("247247"));
+        assert!(!is_sandbox_test_number(""));
+    }
+
+    #[test]
+    fn test_phone_regex_is_compiled_once() {
+        assert!(std::ptr::eq(phone_regex(), phone_regex()));
+    }
+
     #[test]
     fn test_validate_phone_string() {
         assert!("254712345678".to_string().validate().is_ok());
@@ -101,4 +437,44 @@ mod tests {
         assert!(2u64.validate().is_err());
         assert!(0u64.validate().is_err());
     }
+
+    #[test]
+    fn test_party_types() {
+        assert_eq!(PartyType::of("254708374149"), Some(PartyType::Msisdn));
+        assert_eq!(PartyType::of("+254708374149"), Some(PartyType::Msisdn));
+        assert_eq!(PartyType::of("0708374149"), Some(PartyType::Msisdn));
+        assert_eq!(PartyType::of("174379"), Some(PartyType::ShortCode));
+        assert_eq!(PartyType::of("12345"), Some(PartyType::ShortCode));
+        assert_eq!(PartyType::of("1234567"), Some(PartyType::ShortCode));
+        assert_eq!(PartyType::of("1234"), None);
+        assert_eq!(PartyType::of("17437a"), None);
+
+        assert!(validate_parties(CommandId::CustomerPayBillOnline, "0708374149", "174379").is_ok());
+        assert!(validate_parties(CommandId::BusinessPayBill, "600496", "600000").is_ok());
+        assert!(validate_parties(CommandId::TransactionStatusQuery, "a", "b").is_ok());
+        assert_eq!(
+            validate_parties(CommandId::BusinessBuyGoods, "254708374149", "600000")
+                .unwrap_err()
+                .to_string(),
+            "BusinessBuyGoods requires PartyA to be a shortcode"
+        );
+    }
+
+    #[test]
+    fn test_validate_callback_url() {
+        assert!(validate_callback_url("https://pay.example.com/mpesa/stk").is_ok());
+        assert!(validate_callback_url("https://41.90.1.10/mpesa/stk").is_ok());
+        assert!(validate_callback_url("https://[2001:db8::1]/mpesa/stk").is_ok());
+        assert!(validate_callback_url("http://pay.example.com/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://localhost:8080/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://api.localhost/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://127.0.0.1/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://10.0.0.5/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://172.16.8.1/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://192.168.1.10/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://[::1]/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://[fd12:3456::1]/mpesa/stk").is_err());
+        assert!(validate_callback_url("https://[::ffff:10.0.0.5]/mpesa/stk").is_err());
+        assert!(validate_callback_url("mpesa/stk").is_err());
+    }
 }
@@ -129,3 +129,93 @@ async fn account_balance_fails_if_queue_timeout_url_is_not_provided() {
         panic!("Expected error");
     }
 }
+
+#[tokio::test]
+async fn balance_poller_keeps_the_latest_balances_of_each_shortcode() {
+    use mpesa::balances::BalancePoller;
+    use mpesa::callbacks::ResultCallback;
+    use wiremock::matchers::body_partial_json;
+
+    let (client, server) = get_mpesa_client!();
+    for (party_a, conversation_id) in [
+        ("600496", "AG_20230206_201056794190723278ff"),
+        ("600497", "AG_20230206_201056794190723279aa"),
+    ] {
+        Mock::given(method("POST"))
+            .and(path("/mpesa/accountbalance/v1/query"))
+            .and(body_partial_json(json!({ "PartyA": party_a })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "OriginatorConversationID": "29464-48063588-1",
+                "ConversationID": conversation_id,
+                "ResponseDescription": "Accept the service request successfully.",
+                "ResponseCode": "0"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let poller = BalancePoller::new(client)
+        .shortcode("testapi496", "600496")
+        .shortcode("testapi497", "600497")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err");
+    let requests = poller.poll().await;
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|request| request.result.is_ok()));
+    assert!(poller.snapshot().is_empty());
+
+    let result = |conversation_id: &str, result_code: i32, balances: &str| {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": result_code,
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": "29464-48063588-1",
+                "ConversationID": conversation_id,
+                "ResultParameters": {
+                    "ResultParameter": [
+                        { "Key": "AccountBalance", "Value": balances },
+                        { "Key": "BOCompletedTime", "Value": 20230206201057i64 }
+                    ]
+                }
+            }
+        });
+        ResultCallback::from_json(body.to_string().as_bytes()).unwrap()
+    };
+
+    let balance = poller
+        .on_result(&result(
+            "AG_20230206_201056794190723278ff",
+            0,
+            "Working Account|KES|46713.00|46713.00|0.00|0.00&Utility Account|KES|49217.00|49217.00|0.00|0.00",
+        ))
+        .unwrap()
+        .unwrap();
+    assert_eq!(balance.shortcode, "600496");
+    assert_eq!(balance.completed_time.as_deref(), Some("20230206201057"));
+    assert_eq!(
+        balance.account("Utility Account").unwrap().available,
+        49217.0
+    );
+
+    // A failed result leaves the shortcode without balances, and results are handled once
+    assert!(poller
+        .on_result(&result("AG_20230206_201056794190723279aa", 1, ""))
+        .unwrap()
+        .is_none());
+    assert!(poller
+        .on_result(&result(
+            "AG_20230206_201056794190723278ff",
+            0,
+            "Utility Account|KES|0.00|0.00|0.00|0.00"
+        ))
+        .unwrap()
+        .is_none());
+
+    let snapshot = poller.snapshot();
+    assert_eq!(snapshot.len(), 1);
+    assert!(snapshot.get("600497").is_none());
+    assert_eq!(snapshot.total_available("Utility Account", "KES"), 49217.0);
+    assert_eq!(poller.balance("600496"), Some(balance));
+}
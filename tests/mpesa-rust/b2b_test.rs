@@ -1,6 +1,6 @@
 use mpesa::MpesaError;
 use serde_json::json;
-use wiremock::matchers::{method, path};
+use wiremock::matchers::{body_partial_json, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 use crate::get_mpesa_client;
@@ -16,6 +16,18 @@ async fn b2b_success() {
     });
     Mock::given(method("POST"))
         .and(path("/mpesa/b2b/v1/paymentrequest"))
+        .and(body_partial_json(json!({
+            "Initiator": "testapi496",
+            "CommandID": "BusinessToBusinessTransfer",
+            "Amount": 1000.0,
+            "PartyA": "600496",
+            "SenderIdentifierType": "4",
+            "PartyB": "600000",
+            "RecieverIdentifierType": "4",
+            "QueueTimeOutURL": "https://testdomain.com/err",
+            "ResultURL": "https://testdomain.com/ok",
+            "AccountReference": "254708374149"
+        })))
         .respond_with(ResponseTemplate::new(200).set_body_json(sample_response_body))
         .expect(1)
         .mount(&server)
@@ -141,3 +153,94 @@ async fn b2b_fails_if_no_party_b_is_provided() {
         panic!("Expected error");
     }
 }
+
+#[tokio::test]
+async fn mmf_to_utility_transfer_success() {
+    use wiremock::matchers::body_partial_json;
+
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2b/v1/paymentrequest"))
+        .and(body_partial_json(json!({
+            "CommandID": "BusinessTransferFromMMFToUtility",
+            "PartyA": "600496",
+            "SenderIdentifierType": "4",
+            "PartyB": "600496",
+            "RecieverIdentifierType": "4"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let response = client
+        .transfer_mmf_to_utility("testapi496")
+        .party_a("600496")
+        .amount(1000)
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.conversation_id, "AG_20230206_201056794190723278ff");
+    assert_eq!(response.response_code, "0");
+}
+
+#[tokio::test]
+async fn mmf_to_utility_transfer_fails_across_organizations() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2b/v1/paymentrequest"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let builder = client
+        .transfer_mmf_to_utility("testapi496")
+        .party_a("600496")
+        .party_b("600000")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err");
+    assert_eq!(builder.validate_all().unwrap_err().len(), 2);
+    let error = builder.amount(1000).send().await.unwrap_err();
+    let MpesaError::Message(msg) = error else {
+        panic!("Expected MpesaError::Message, but found {}", error);
+    };
+    assert_eq!(
+        msg,
+        "party_a and party_b must be the shortcode of the same organization"
+    );
+}
+
+#[test]
+fn mmf_to_utility_transfer_result_is_parsed() {
+    use mpesa::callbacks::MmfTransferResult;
+
+    let body = json!({
+        "Result": {
+            "ResultType": 0,
+            "ResultCode": 0,
+            "ResultDesc": "The service request is processed successfully.",
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "TransactionID": "QKA81LK5CY",
+            "ResultParameters": {
+                "ResultParameter": [
+                    { "Key": "Amount", "Value": "1000.00" },
+                    { "Key": "TransCompletedTime", "Value": "20230206201057" }
+                ]
+            }
+        }
+    });
+    let result = MmfTransferResult::from_json(body.to_string().as_bytes()).unwrap();
+    assert!(result.is_success());
+    assert_eq!(result.amount, Some(1000.0));
+    assert_eq!(
+        result.trans_completed_time.as_deref(),
+        Some("20230206201057")
+    );
+}
@@ -1,6 +1,8 @@
+use mpesa::metadata::Metadata;
+use mpesa::services::{CallbackUrls, PartyA, PartyB};
 use mpesa::MpesaError;
 use serde_json::json;
-use wiremock::matchers::{method, path};
+use wiremock::matchers::{body_partial_json, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 use crate::get_mpesa_client;
@@ -39,6 +41,40 @@ async fn b2c_success() {
     assert_eq!(response.response_code, "0");
 }
 
+#[tokio::test]
+async fn b2c_takes_typed_parties_and_callback_urls() {
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_partial_json(json!({
+            "PartyA": "600496",
+            "PartyB": "254708374149",
+            "QueueTimeOutURL": "https://testdomain.com/err",
+            "ResultURL": "https://testdomain.com/ok"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let response = client
+        .b2c("testapi496")
+        .party_pair(PartyA("600496"), PartyB("254708374149"))
+        .callback_urls(CallbackUrls::new(
+            "https://testdomain.com/err",
+            "https://testdomain.com/ok",
+        ))
+        .amount(1000)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "0");
+}
+
 #[tokio::test]
 async fn b2c_fails_if_no_amount_is_provided() {
     let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
@@ -203,3 +239,496 @@ async fn b2c_fails_if_no_queue_timeout_is_provided() {
         panic!("Expected error");
     }
 }
+
+#[tokio::test]
+async fn b2c_validate_all_reports_every_missing_field() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let errors = client
+        .b2c("testapi496")
+        .party_a("600496")
+        .validate_all()
+        .unwrap_err();
+
+    assert_eq!(errors.len(), 4);
+    assert_eq!(
+        errors.to_string(),
+        "amount is required; party_b is required; queue_timeout_url is required; result_url is required"
+    );
+    assert!(matches!(
+        MpesaError::from(errors),
+        MpesaError::Validation(_)
+    ));
+}
+
+#[tokio::test]
+async fn b2c_to_curl_hides_the_security_credential() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    let curl = client
+        .b2c("testapi496")
+        .party_a("600496")
+        .party_b("254708374149")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .amount(1000)
+        .remarks("it's a test")
+        .to_curl()
+        .unwrap();
+
+    assert!(curl.starts_with(&format!(
+        "curl -X POST '{}/mpesa/b2c/v1/paymentrequest'",
+        server.uri()
+    )));
+    assert!(curl.contains("-H \"Authorization: Bearer $MPESA_ACCESS_TOKEN\""));
+    assert!(curl.contains(r#""SecurityCredential":"<SECURITY_CREDENTIAL>""#));
+    assert!(curl.contains(r#""Remarks":"it'\''s a test""#));
+}
+
+#[tokio::test]
+async fn b2c_summary_masks_the_phone_number() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let summary = client
+        .b2c("testapi496")
+        .party_a("600496")
+        .party_b("0708374149")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .amount(1000)
+        .summary()
+        .unwrap();
+
+    assert_eq!(
+        summary,
+        "B2C 1,000 KES 600496→2547****149 via BusinessPayment"
+    );
+}
+
+#[tokio::test]
+async fn b2c_routed_is_sent_as_a_weighted_initiator() {
+    use mpesa::Mpesa;
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .initiator("ops_1", "ops_1_password", 1)
+    .initiator("retired", "retired_password", 0)
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_partial_json(json!({ "InitiatorName": "ops_1" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    for _ in 0..2 {
+        let response = client
+            .b2c_routed()
+            .unwrap()
+            .party_a("600496")
+            .party_b("254708374149")
+            .result_url("https://testdomain.com/ok")
+            .timeout_url("https://testdomain.com/err")
+            .amount(1000)
+            .send_with_meta()
+            .await
+            .unwrap();
+        assert_eq!(response.initiator.as_deref(), Some("ops_1"));
+    }
+}
+
+#[tokio::test]
+#[cfg(feature = "test-utils")]
+async fn b2c_payload_is_deterministic_with_a_stub_signer() {
+    use mpesa::test_utils::StubSigner;
+    use mpesa::Mpesa;
+    use wiremock::matchers::body_json;
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .credential_signer(StubSigner::default())
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_json(json!({
+            "InitiatorName": "testapi496",
+            "SecurityCredential": "stub_security_credential",
+            "CommandID": "BusinessPayment",
+            "Amount": 1000.0,
+            "PartyA": "600496",
+            "PartyB": "254708374149",
+            "Remarks": "None",
+            "QueueTimeOutURL": "https://testdomain.com/err",
+            "ResultURL": "https://testdomain.com/ok",
+            "Occasion": "None"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client
+        .b2c("testapi496")
+        .party_a("600496")
+        .party_b("254708374149")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .amount(1000)
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn payout_supervisor_reroutes_failed_payouts() {
+    use mpesa::callbacks::ResultCallback;
+    use mpesa::payout::{MemoryPayoutStore, Payout, PayoutAction, PayoutSupervisor};
+    use wiremock::matchers::body_partial_json;
+
+    let (client, server) = get_mpesa_client!();
+    for (party_b, conversation_id) in [
+        ("254708374149", "AG_20230206_201056794190723278ff"),
+        ("254708374150", "AG_20230206_201056794190723279aa"),
+    ] {
+        Mock::given(method("POST"))
+            .and(path("/mpesa/b2c/v1/paymentrequest"))
+            .and(body_partial_json(json!({ "PartyB": party_b })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "OriginatorConversationID": "29464-48063588-1",
+                "ConversationID": conversation_id,
+                "ResponseDescription": "Accept the service request successfully.",
+                "ResponseCode": "0"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let supervisor = PayoutSupervisor::new(
+        client,
+        MemoryPayoutStore::default(),
+        |payout: &Payout, result: &ResultCallback| match result.result_code.as_str() {
+            "2040" if payout.attempt == 0 => PayoutAction::Reroute("254708374150".to_owned()),
+            _ => PayoutAction::Park(result.result_desc.clone()),
+        },
+    );
+    let payout = Payout::new("testapi496", "600496", "254708374149", 1000)
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err");
+    supervisor.send(payout).await.unwrap();
+
+    // Payouts are kept by the `OriginatorConversationID` the supervisor sends them with
+    let originator_conversation_ids = || async {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|req| req.url.path() == "/mpesa/b2c/v1/paymentrequest")
+            .map(|req| {
+                req.body_json::<serde_json::Value>().unwrap()["OriginatorConversationID"]
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>()
+    };
+    let result = |originator_conversation_id: &str, conversation_id: &str, result_code: i32| {
+        let body = json!({
+            "Result": {
+                "ResultType": 0,
+                "ResultCode": result_code,
+                "ResultDesc": "The receiver is not registered for M-Pesa.",
+                "OriginatorConversationID": originator_conversation_id,
+                "ConversationID": conversation_id
+            }
+        });
+        ResultCallback::from_json(body.to_string().as_bytes()).unwrap()
+    };
+    let first = originator_conversation_ids().await.remove(0);
+
+    let decision = supervisor
+        .on_result(&result(&first, "AG_20230206_201056794190723278ff", 2040))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        decision.action,
+        PayoutAction::Reroute("254708374150".to_owned())
+    );
+    assert_eq!(
+        decision.next_conversation_id.as_deref(),
+        Some("AG_20230206_201056794190723279aa")
+    );
+
+    // A result delivered twice is handled once
+    assert!(supervisor
+        .on_result(&result(&first, "AG_20230206_201056794190723278ff", 2040))
+        .await
+        .unwrap()
+        .is_none());
+
+    // The rerouted payout fails again and is parked
+    let rerouted = originator_conversation_ids().await.remove(1);
+    assert_ne!(rerouted, first);
+    let decision = supervisor
+        .on_result(&result(&rerouted, "AG_20230206_201056794190723279aa", 2040))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(decision.payout.attempt, 1);
+    assert!(matches!(decision.action, PayoutAction::Park(_)));
+    assert_eq!(supervisor.store().decisions().len(), 2);
+}
+
+#[tokio::test]
+async fn b2c_sends_metadata_in_the_occasion() {
+    let (client, server) = get_mpesa_client!();
+    let sample_response_body = json!({
+        "OriginatorConversationID": "29464-48063588-1",
+        "ConversationID": "AG_20230206_201056794190723278ff",
+        "ResponseDescription": "Accept the service request successfully.",
+        "ResponseCode": "0"
+    });
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_partial_json(json!({
+            "Occasion": "order=A-1029;tenant=acme",
+            "Remarks": "Refund"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(sample_response_body))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let metadata = Metadata::new()
+        .with("tenant", "acme")
+        .with("order", "A-1029");
+    client
+        .b2c("testapi496")
+        .party_a("600496")
+        .party_b("254708374149")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .amount(1000)
+        .remarks("Refund")
+        .metadata(&metadata)
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+
+    let too_long = metadata.with("note", "x".repeat(100));
+    let Err(MpesaError::Message(msg)) = client.b2c("testapi496").metadata(&too_long) else {
+        panic!("Expected MpesaError::Message");
+    };
+    assert!(msg.starts_with("metadata is longer than"));
+}
+
+#[tokio::test]
+async fn b2c_duplicates_are_reported_with_the_original_conversation_id() {
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Duplicate detected",
+            "ResponseCode": "15"
+        })))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "requestId": "11728-2929992-1",
+            "errorCode": "15",
+            "errorMessage": "Duplicate detected"
+        })))
+        .mount(&server)
+        .await;
+
+    let send = || {
+        client
+            .b2c("testapi496")
+            .party_a("600496")
+            .party_b("254708374149")
+            .result_url("https://testdomain.com/ok")
+            .timeout_url("https://testdomain.com/err")
+            .amount(1000)
+            .send()
+    };
+    match send().await {
+        Err(MpesaError::Duplicate {
+            original_conversation_id,
+        }) => assert_eq!(
+            original_conversation_id.as_deref(),
+            Some("AG_20230206_201056794190723278ff")
+        ),
+        other => panic!("Expected MpesaError::Duplicate, but found {other:?}"),
+    }
+    match send().await {
+        Err(MpesaError::Duplicate {
+            original_conversation_id: None,
+        }) => {}
+        other => panic!("Expected MpesaError::Duplicate, but found {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn b2c_amount_can_be_set_in_cents() {
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_partial_json(json!({ "Amount": 1500.0 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    client
+        .b2c("testapi496")
+        .party_a("600496")
+        .party_b("254708374149")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .amount_cents(150_000)
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn b2c_rejects_parties_of_the_wrong_type() {
+    use mpesa::validator::PartyType;
+    use mpesa::CommandId;
+
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let builder = client
+        .b2c("testapi496")
+        .command_id(CommandId::SalaryPayment)
+        .party_a("600496")
+        .party_b("600000")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .amount(1000);
+
+    assert!(builder.validate_all().is_err());
+    let err = builder.send().await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "SalaryPayment requires PartyB to be an MSISDN"
+    );
+    assert!(matches!(
+        err,
+        MpesaError::InvalidParty {
+            command_id: CommandId::SalaryPayment,
+            party: "PartyB",
+            expected: PartyType::Msisdn,
+        }
+    ));
+}
+
+#[tokio::test]
+async fn b2c_only_pays_known_beneficiaries() {
+    use mpesa::beneficiary::{Beneficiary, BeneficiaryBook};
+
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_partial_json(json!({ "PartyB": "254708374149" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let beneficiaries = BeneficiaryBook::default().with(
+        Beneficiary::new("0708374149", "John Doe")
+            .unwrap()
+            .max_amount(5000),
+    );
+    let payout = |party_b, amount| {
+        client
+            .b2c("testapi496")
+            .party_a("600496")
+            .party_b(party_b)
+            .amount(amount)
+            .result_url("https://testdomain.com/ok")
+            .timeout_url("https://testdomain.com/err")
+            .only_known_beneficiaries(&beneficiaries)
+    };
+
+    payout("254708374149", 1000).send().await.unwrap();
+
+    let error = payout("254708374148", 1000).send().await.unwrap_err();
+    assert!(matches!(error, MpesaError::UnknownBeneficiary(msisdn) if msisdn == "254708374148"));
+    let error = payout("254708374149", 5001).send().await.unwrap_err();
+    assert!(matches!(
+        error,
+        MpesaError::BeneficiaryLimitExceeded { amount, .. } if amount == 5001.0
+    ));
+    assert_eq!(
+        payout("254708374148", 1000)
+            .validate_all()
+            .unwrap_err()
+            .len(),
+        1
+    );
+}
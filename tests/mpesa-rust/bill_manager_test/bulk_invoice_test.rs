@@ -66,3 +66,40 @@ async fn bulk_invoice_fails_if_invoices_is_empty() {
         panic!("Expected Error")
     }
 }
+
+#[tokio::test]
+async fn bulk_invoice_fails_if_an_invoice_is_invalid() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/bulk-invoicing"))
+        .respond_with(sample_response())
+        .expect(0)
+        .mount(&server)
+        .await;
+    if let Err(e) = client
+        .bulk_invoice()
+        .invoices(vec![Invoice {
+            amount: 1000.0,
+            account_reference: "John Doe",
+            billed_full_name: "John Doe",
+            billed_period: "Agosti 2021",
+            billed_phone_number: "0712345678",
+            due_date: Utc::now(),
+            external_reference: "INV2345",
+            invoice_items: None,
+            invoice_name: "Invoice 001",
+        }])
+        .send()
+        .await
+    {
+        let MpesaError::Message(msg) = e else {
+            panic!("Expected MpesaError::Message, but found {}", e);
+        };
+        assert_eq!(
+            msg,
+            "Invalid billed_period, must be in the format \"Month Year\" e.g. \"March 2023\""
+        );
+    } else {
+        panic!("Expected Error")
+    }
+}
@@ -172,3 +172,122 @@ async fn onboard_fails_if_short_code_is_provided() {
         panic!("Expected error")
     }
 }
+
+#[tokio::test]
+async fn onboard_fails_if_email_is_invalid() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/optin"))
+        .respond_with(sample_response())
+        .expect(0)
+        .mount(&server)
+        .await;
+    if let Err(e) = client
+        .onboard()
+        .callback_url("https://testdomain.com/true")
+        .email("email.test.com")
+        .logo("https://file.domain/file.png")
+        .official_contact("0712345678")
+        .short_code("600496")
+        .send()
+        .await
+    {
+        let MpesaError::Message(msg) = e else {
+            panic!("Expected MpesaError::Message, but found {}", e);
+        };
+        assert_eq!(msg, "Invalid email, must be in the format name@example.com");
+    } else {
+        panic!("Expected error")
+    }
+}
+
+#[tokio::test]
+async fn onboard_fails_if_official_contact_is_invalid() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/optin"))
+        .respond_with(sample_response())
+        .expect(0)
+        .mount(&server)
+        .await;
+    if let Err(e) = client
+        .onboard()
+        .callback_url("https://testdomain.com/true")
+        .email("email@test.com")
+        .logo("https://file.domain/file.png")
+        .official_contact("254712345678")
+        .short_code("600496")
+        .send()
+        .await
+    {
+        let MpesaError::Message(msg) = e else {
+            panic!("Expected MpesaError::Message, but found {}", e);
+        };
+        assert_eq!(
+            msg,
+            "Invalid official_contact, must be in the format 07XXXXXXXX"
+        );
+    } else {
+        panic!("Expected error")
+    }
+}
+
+#[tokio::test]
+async fn onboard_skips_format_checks_when_validation_is_disabled() {
+    use mpesa::Mpesa;
+    use wiremock::matchers::query_param;
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        "consumer_key",
+        "consumer_secret",
+        TestEnvironment::new(&server).await,
+    )
+    .validation(false)
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .and(query_param("grant_type", "client_credentials"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/optin"))
+        .respond_with(sample_response())
+        .expect(1)
+        .mount(&server)
+        .await;
+    let response = client
+        .onboard()
+        .callback_url("https://testdomain.com/true")
+        .email("billing")
+        .logo("https://file.domain/file.png")
+        .official_contact("254712345678")
+        .short_code("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.app_key, "kfpB9X4o0H");
+
+    // Required fields are still checked
+    let err = client
+        .onboard()
+        .callback_url("https://testdomain.com/true")
+        .logo("https://file.domain/file.png")
+        .official_contact("254712345678")
+        .short_code("600496")
+        .send()
+        .await
+        .unwrap_err();
+    let MpesaError::Message(msg) = err else {
+        panic!("Expected MpesaError::Message, but found {}", err);
+    };
+    assert_eq!(msg, "email is required");
+}
@@ -279,3 +279,93 @@ async fn reconciliation_fails_if_no_transaction_id_is_provided() {
         panic!("Expected error")
     }
 }
+
+#[tokio::test]
+async fn e_receipt_is_sent_for_a_c2b_confirmation() {
+    use mpesa::callbacks::C2bTransaction;
+    use wiremock::matchers::body_json;
+
+    let (client, server) = get_mpesa_client!();
+    let transaction: C2bTransaction = serde_json::from_value(json!({
+        "TransactionType": "Pay Bill",
+        "TransID": "RKTQDM7W6S",
+        "TransTime": "20240101043015",
+        "TransAmount": "10.00",
+        "BusinessShortCode": "600638",
+        "BillRefNumber": "ACC-001",
+        "InvoiceNumber": "",
+        "OrgAccountBalance": "49197.00",
+        "ThirdPartyTransID": "",
+        "MSISDN": "254708374149",
+        "FirstName": "John",
+        "MiddleName": "",
+        "LastName": "Doe"
+    }))
+    .unwrap();
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/reconciliation"))
+        .and(body_json(json!({
+            "accountReference": "ACC-001",
+            "externalReference": "ACC-001",
+            "fullName": "John Doe",
+            "invoiceName": "ACC-001",
+            "paidAmount": 10.0,
+            "paymentDate": "2024-01-01T01:30:15Z",
+            "phoneNumber": "254708374149",
+            "transactionId": "RKTQDM7W6S"
+        })))
+        .respond_with(sample_response())
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client.send_e_receipt(&transaction).await.unwrap();
+    assert_eq!(response.response_code, "200");
+}
+
+#[tokio::test]
+async fn e_receipt_is_sent_for_a_ledger_entry() {
+    use mpesa::callbacks::C2bConfirmation;
+    use mpesa::ledger::LedgerEntry;
+    use wiremock::matchers::body_json;
+
+    let (client, server) = get_mpesa_client!();
+    let confirmation: C2bConfirmation = serde_json::from_value(json!({
+        "TransactionType": "Pay Bill",
+        "TransID": "RKTQDM7W6S",
+        "TransTime": "20240101043015",
+        "TransAmount": "10.00",
+        "BusinessShortCode": "600638",
+        "BillRefNumber": "ACC-001",
+        "MSISDN": "254708374149",
+        "FirstName": "John",
+        "LastName": "Doe"
+    }))
+    .unwrap();
+    let entry = LedgerEntry::from(confirmation);
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/reconciliation"))
+        .and(body_json(json!({
+            "accountReference": "ACC-001",
+            "externalReference": "ACC-001",
+            "fullName": "John Doe",
+            "invoiceName": "ACC-001",
+            "paidAmount": 10.0,
+            "paymentDate": "2024-01-01T01:30:15Z",
+            "phoneNumber": "254708374149",
+            "transactionId": "RKTQDM7W6S"
+        })))
+        .respond_with(sample_response())
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client
+        .reconciliation()
+        .ledger_entry(&entry)
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "200");
+}
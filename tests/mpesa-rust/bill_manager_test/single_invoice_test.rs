@@ -285,3 +285,71 @@ async fn single_invoice_fails_if_no_invoice_name_is_provided() {
         panic!("Expected error")
     }
 }
+
+#[tokio::test]
+async fn single_invoice_fails_if_billed_period_is_invalid() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/single-invoicing"))
+        .respond_with(sample_response())
+        .expect(0)
+        .mount(&server)
+        .await;
+    if let Err(e) = client
+        .single_invoice()
+        .amount(1000.0)
+        .account_reference("John Doe")
+        .billed_full_name("John Doe")
+        .billed_period("08/2021")
+        .billed_phone_number("0712345678")
+        .due_date(Utc::now())
+        .external_reference("INV2345")
+        .invoice_name("Invoice 001")
+        .send()
+        .await
+    {
+        let MpesaError::Message(msg) = e else {
+            panic!("Expected MpesaError::Message, but found {}", e);
+        };
+        assert_eq!(
+            msg,
+            "Invalid billed_period, must be in the format \"Month Year\" e.g. \"March 2023\""
+        );
+    } else {
+        panic!("Expected error")
+    }
+}
+
+#[tokio::test]
+async fn single_invoice_fails_if_billed_phone_number_is_invalid() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/v1/billmanager-invoice/single-invoicing"))
+        .respond_with(sample_response())
+        .expect(0)
+        .mount(&server)
+        .await;
+    if let Err(e) = client
+        .single_invoice()
+        .amount(1000.0)
+        .account_reference("John Doe")
+        .billed_full_name("John Doe")
+        .billed_period("August 2021")
+        .billed_phone_number("254712345678")
+        .due_date(Utc::now())
+        .external_reference("INV2345")
+        .invoice_name("Invoice 001")
+        .send()
+        .await
+    {
+        let MpesaError::Message(msg) = e else {
+            panic!("Expected MpesaError::Message, but found {}", e);
+        };
+        assert_eq!(
+            msg,
+            "Invalid billed_phone_number, must be in the format 07XXXXXXXX"
+        );
+    } else {
+        panic!("Expected error")
+    }
+}
@@ -124,3 +124,241 @@ async fn c2b_register_fails_if_validation_url_is_not_provided() {
         panic!("Expected error");
     }
 }
+
+#[tokio::test]
+async fn c2b_register_bulk_reports_the_outcome_of_each_short_code() {
+    use wiremock::matchers::body_partial_json;
+
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .and(body_partial_json(json!({
+            "ShortCode": "600000",
+            "ValidationURL": "https://testdomain.com/600000/validation"
+        })))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "requestId": "11728-2929992-1",
+            "errorCode": "400.003.02",
+            "errorMessage": "Bad Request - Invalid ShortCode"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-1",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let registrations = client
+        .c2b_register_bulk()
+        .short_codes(["600496", "600000"])
+        .short_code("600638")
+        .validation_url("https://testdomain.com/{short_code}/validation")
+        .confirmation_url("https://testdomain.com/{short_code}/confirmation")
+        .concurrency(2)
+        .send()
+        .await
+        .unwrap();
+
+    let short_codes = registrations
+        .iter()
+        .map(|registration| registration.short_code.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(short_codes, ["600496", "600000", "600638"]);
+    assert_eq!(
+        registrations[0].confirmation_url,
+        "https://testdomain.com/600496/confirmation"
+    );
+    assert!(registrations[0].result.is_ok());
+    assert!(matches!(
+        &registrations[1].result,
+        Err(MpesaError::Service(e)) if e.error_code == "400.003.02"
+    ));
+    assert!(registrations[2].result.is_ok());
+}
+
+#[tokio::test]
+async fn c2b_register_bulk_fails_if_a_url_is_not_provided() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let error = client
+        .c2b_register_bulk()
+        .short_code("600496")
+        .validation_url("https://testdomain.com/{short_code}/validation")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "confirmation_url is required");
+}
+
+#[tokio::test]
+async fn c2b_register_fails_if_a_url_is_not_reachable_by_safaricom() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let err = client
+        .c2b_register()
+        .short_code("600496")
+        .confirmation_url("https://testdomain.com/true")
+        .validation_url("http://127.0.0.1:8080/valid")
+        .send()
+        .await
+        .unwrap_err();
+    let MpesaError::Message(msg) = err else {
+        panic!("Expected MpesaError::Message, but found {}", err);
+    };
+    assert_eq!(
+        msg,
+        "Callback urls must use https, Safaricom does not deliver callbacks over http"
+    );
+}
+
+#[tokio::test]
+async fn c2b_register_is_queued_while_the_api_is_down() {
+    use std::time::{Duration, SystemTime};
+
+    use mpesa::queue::Delivery;
+    use mpesa::status::{MaintenanceSchedule, MaintenanceWindow};
+    use mpesa::storage::MemoryStorage;
+    use mpesa::Mpesa;
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let now = SystemTime::now();
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .maintenance_schedule(MaintenanceSchedule::new().with(MaintenanceWindow::new(
+        now - Duration::from_secs(60),
+        now + Duration::from_secs(3600),
+    )))
+    .degradation_queue(MemoryStorage::default())
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .respond_with(ResponseTemplate::new(503).set_body_json(json!({
+            "requestId": "11728-2929992-1",
+            "errorCode": "503.001.01",
+            "errorMessage": "Service Unavailable"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let delivery = client
+        .c2b_register()
+        .short_code("600496")
+        .confirmation_url("https://testdomain.com/true")
+        .validation_url("https://testdomain.com/valid")
+        .send_or_queue()
+        .await
+        .unwrap();
+    let Delivery::Queued(queued) = delivery else {
+        panic!("the request should be queued");
+    };
+    assert_eq!(queued.body["ShortCode"], "600496");
+    assert_eq!(client.queued_requests().await.unwrap(), vec![queued]);
+
+    server.reset().await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-1",
+            "ResponseCode": "0",
+            "ResponseDescription": "Accept the service request successfully."
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let replayed = client.flush_queue().await.unwrap();
+    assert_eq!(replayed.len(), 1);
+    assert_eq!(
+        replayed[0].result.as_ref().unwrap()["ResponseCode"],
+        json!("0")
+    );
+    assert!(client.queued_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn c2b_register_that_timed_out_is_not_queued() {
+    use std::time::Duration;
+
+    use mpesa::storage::MemoryStorage;
+    use mpesa::{Mpesa, MpesaError, RetryPolicy, ServiceCategory};
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .timeout(Duration::from_millis(200))
+    .retry_policy(ServiceCategory::Query, RetryPolicy::new(0, Duration::ZERO))
+    .degradation_queue(MemoryStorage::default())
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    // The API receives the request but its response does not arrive in time
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_secs(2))
+                .set_body_json(json!({
+                    "OriginatorCoversationID": "29464-48063588-1",
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Accept the service request successfully."
+                })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let err = client
+        .c2b_register()
+        .short_code("600496")
+        .confirmation_url("https://testdomain.com/true")
+        .validation_url("https://testdomain.com/valid")
+        .send_or_queue()
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, MpesaError::NetworkError(e) if e.is_timeout()));
+    assert!(client.queued_requests().await.unwrap().is_empty());
+}
@@ -1,6 +1,6 @@
-use mpesa::MpesaError;
+use mpesa::{CommandId, MpesaError};
 use serde_json::json;
-use wiremock::matchers::{method, path};
+use wiremock::matchers::{body_partial_json, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 use crate::get_mpesa_client;
@@ -130,6 +130,72 @@ async fn c2b_simulate_fails_if_no_bill_ref_number_is_provided() {
     }
 }
 
+#[tokio::test]
+async fn c2b_simulate_pays_a_till_number_without_bill_ref_number() {
+    let (client, server) = get_mpesa_client!();
+    let sample_response_body = json!({
+        "OriginatorCoversationID": "29464-48063588-1",
+        "ResponseDescription": "Accept the service request successfully.",
+        "ResponseCode": "0"
+    });
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .and(body_partial_json(json!({
+            "CommandID": "CustomerBuyGoodsOnline",
+            "BillRefNumber": ""
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(sample_response_body))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let response = client
+        .c2b_simulate()
+        .buy_goods()
+        .amount(1000)
+        .msisdn("254700000000")
+        .short_code("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "0");
+}
+
+#[tokio::test]
+async fn c2b_simulate_fails_if_a_till_payment_has_a_bill_ref_number() {
+    let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let builder = client
+        .c2b_simulate()
+        .command_id(CommandId::CustomerBuyGoodsOnline)
+        .amount(1000)
+        .bill_ref_number("2")
+        .msisdn("254700000000")
+        .short_code("600496");
+    assert_eq!(builder.validate_all().unwrap_err().len(), 1);
+    let Err(MpesaError::Message(msg)) = builder.send().await else {
+        panic!("Expected MpesaError::Message");
+    };
+    assert_eq!(
+        msg,
+        "bill_ref_number must be empty when paying a till number"
+    );
+
+    let res = client
+        .c2b_simulate()
+        .command_id(CommandId::BusinessBuyGoods)
+        .amount(1000)
+        .msisdn("254700000000")
+        .short_code("600496")
+        .send()
+        .await;
+    assert!(matches!(res, Err(MpesaError::Message(_))));
+}
+
 #[tokio::test]
 async fn c2b_simulate_fails_if_no_msisdn_is_provided() {
     let (client, server) = get_mpesa_client!(expected_auth_requests = 0);
@@ -160,3 +226,79 @@ async fn c2b_simulate_fails_if_no_msisdn_is_provided() {
         panic!("Expected error")
     }
 }
+
+#[tokio::test]
+async fn c2b_simulate_is_rejected_in_production() {
+    let client = mpesa::Mpesa::new("consumer_key", "consumer_secret", mpesa::Production);
+    let res = client
+        .c2b_simulate()
+        .amount(1000)
+        .bill_ref_number("2")
+        .msisdn("254700000000")
+        .short_code("600496")
+        .send()
+        .await;
+    assert!(matches!(
+        res,
+        Err(MpesaError::SandboxOnly(mpesa::Service::C2bSimulate))
+    ));
+}
+
+#[tokio::test]
+async fn c2b_simulate_amounts_are_sent_with_two_decimals() {
+    use mpesa::Mpesa;
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-1",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .fixed_decimal_amounts(true)
+    .build()
+    .unwrap();
+    let simulate = |amount: f64| {
+        client
+            .c2b_simulate()
+            .amount(amount)
+            .bill_ref_number("2")
+            .msisdn("254705912645")
+            .short_code("600496")
+            .send()
+    };
+
+    simulate(0.1 + 0.2).await.unwrap();
+    let requests = server.received_requests().await.unwrap();
+    let body = String::from_utf8(requests.last().unwrap().body.clone()).unwrap();
+    assert!(body.contains(r#""Amount":0.30,"#), "{body}");
+
+    // Non-finite amounts are rejected before anything is sent
+    for amount in [f64::NAN, f64::INFINITY] {
+        assert!(matches!(
+            simulate(amount).await,
+            Err(MpesaError::ParseError(_))
+        ));
+    }
+}
@@ -0,0 +1,1331 @@
+use std::time::Duration;
+
+use crate::get_mpesa_client;
+
+#[tokio::test]
+async fn token_info_reports_cached_token_expiry() {
+    let (client, _server) = get_mpesa_client!();
+
+    assert!(client.is_connected().await);
+
+    let token_info = client.token_info().await;
+    assert!(token_info.is_cached());
+    assert!(token_info.expires_in.unwrap() <= Duration::from_secs(3600));
+    assert!(token_info.expires_at.is_some());
+}
+
+#[tokio::test]
+async fn token_info_reports_the_lifetime_the_token_was_issued_with() {
+    use mpesa::Mpesa;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "short_lived_access_token",
+            "expires_in": "120"
+        })))
+        .mount(&server)
+        .await;
+    // Credentials of their own, as tokens are cached per consumer key across clients
+    let client = Mpesa::new(
+        "short_lived_consumer_key",
+        "short_lived_consumer_secret",
+        TestEnvironment::new(&server).await,
+    );
+
+    assert!(client.is_connected().await);
+
+    let expires_in = client.token_info().await.expires_in.unwrap();
+    assert!(expires_in <= Duration::from_secs(120));
+    assert!(expires_in > Duration::from_secs(110));
+}
+
+#[tokio::test]
+async fn health_check_reports_auth_latency_and_base_url() {
+    let (client, server) = get_mpesa_client!();
+
+    let health = client.health_check().await;
+    assert!(health.is_healthy());
+    assert!(health.auth.is_ok());
+    assert_eq!(health.base_url, server.uri());
+    #[cfg(feature = "openssl")]
+    assert!(health.certificate_validity.is_some());
+}
+
+#[test]
+fn response_error_can_be_constructed_outside_the_crate() {
+    use mpesa::{MpesaError, ResponseError};
+
+    let error = MpesaError::Service(ResponseError::new(
+        "11728-2929992-1",
+        "401.002.01",
+        "Error Occurred - Invalid Access Token",
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Service error: requestID: 11728-2929992-1, errorCode:401.002.01, errorMessage:Error Occurred - Invalid Access Token"
+    );
+}
+
+/// A client retrying queries without waiting, and payments `payment_retries` times if set,
+/// authenticated against `server`
+async fn retrying_client(
+    server: &wiremock::MockServer,
+    payment_retries: Option<u32>,
+) -> mpesa::Mpesa {
+    use mpesa::{Mpesa, RetryPolicy, ServiceCategory};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(server)
+        .await;
+    let mut builder = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(server).await,
+    )
+    .retry_policy(ServiceCategory::Query, RetryPolicy::new(2, Duration::ZERO));
+    if let Some(retries) = payment_retries {
+        builder = builder.retry_policy(
+            ServiceCategory::Payment,
+            RetryPolicy::new(retries, Duration::ZERO),
+        );
+    }
+    builder.build().unwrap()
+}
+
+fn service_unavailable() -> wiremock::ResponseTemplate {
+    wiremock::ResponseTemplate::new(503).set_body_json(serde_json::json!({
+        "requestId": "11728-2929992-1",
+        "errorCode": "503.001.01",
+        "errorMessage": "Service Unavailable"
+    }))
+}
+
+#[tokio::test]
+async fn queries_are_retried_after_transient_errors() {
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    let client = retrying_client(&server, None).await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .respond_with(service_unavailable())
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "0");
+}
+
+#[tokio::test]
+async fn payments_are_not_retried_by_default() {
+    use mpesa::{Mpesa, MpesaError};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer};
+
+    let server = MockServer::start().await;
+    let default_client = retrying_client(&server, None).await;
+    let retrying_client = retrying_client(&server, Some(1)).await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(service_unavailable())
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let send = |client: &Mpesa| {
+        let client = client.clone();
+        async move {
+            client
+                .b2c("testapi496")
+                .party_a("600496")
+                .party_b("254708374149")
+                .result_url("https://testdomain.com/ok")
+                .timeout_url("https://testdomain.com/err")
+                .amount(1000)
+                .send()
+                .await
+        }
+    };
+
+    // Sent once by the client with the default payment policy, twice by the retrying client
+    for client in [&default_client, &retrying_client] {
+        match send(client).await {
+            Err(MpesaError::Service(e)) => assert_eq!(e.error_code, "503.001.01"),
+            other => panic!("expected a service error, got {other:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn builders_fall_back_to_the_default_urls_of_the_client() {
+    use mpesa::{CommandId, Mpesa};
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .default_result_url("https://pay.example.com/mpesa/result/")
+    .default_timeout_url("https://pay.example.com/mpesa/timeout")
+    .default_callback_url("https://pay.example.com/mpesa/callback")
+    .build()
+    .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .and(body_partial_json(json!({
+            "ResultURL": "https://pay.example.com/mpesa/result/b2c",
+            "QueueTimeOutURL": "https://pay.example.com/mpesa/timeout"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .and(body_partial_json(json!({
+            "CallBackURL": "https://pay.example.com/mpesa/callback/stk"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let b2c = client
+        .b2c("testapi496")
+        .party_a("600496")
+        .party_b("254708374149")
+        .result_url("b2c")
+        .amount(1000);
+    assert!(b2c.validate_all().is_ok());
+    b2c.send().await.unwrap();
+
+    client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(CommandId::BusinessBuyGoods)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .callback_path("stk")
+        .unwrap()
+        .build()
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn deadline_covers_the_retries_of_a_request() {
+    use std::time::Instant;
+
+    use mpesa::MpesaError;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer};
+
+    let server = MockServer::start().await;
+    let client = retrying_client(&server, None).await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .respond_with(service_unavailable().set_delay(Duration::from_millis(100)))
+        .mount(&server)
+        .await;
+
+    // Each of the 3 attempts takes 100ms, the deadline passes during the second one
+    let started = Instant::now();
+    let request = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496");
+    let result = mpesa::with_deadline(started + Duration::from_millis(150), request.send()).await;
+    assert!(matches!(result, Err(MpesaError::DeadlineExceeded)));
+    assert!(started.elapsed() < Duration::from_millis(300));
+
+    let request = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496");
+    let result =
+        mpesa::with_deadline(Instant::now() + Duration::from_secs(10), request.send()).await;
+    assert!(matches!(result, Err(MpesaError::Service(_))));
+}
+
+#[tokio::test]
+#[cfg(feature = "compression")]
+async fn compressed_responses_are_decompressed() {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde_json::json;
+    use wiremock::matchers::{headers, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    let client = retrying_client(&server, None).await;
+    let mut body = GzEncoder::new(Vec::new(), Compression::default());
+    body.write_all(
+        json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })
+        .to_string()
+        .as_bytes(),
+    )
+    .unwrap();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .and(headers("accept-encoding", vec!["gzip", "br"]))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-encoding", "gzip")
+                .insert_header("content-type", "application/json")
+                .set_body_bytes(body.finish().unwrap()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.conversation_id, "AG_20230206_201056794190723278ff");
+}
+
+#[tokio::test]
+async fn hostname_is_resolved_to_the_pinned_address() {
+    use mpesa::Mpesa;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let host = format!("api.safaricom.invalid:{}", server.address().port());
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .and(header("host", host.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The `.invalid` top level domain never resolves, requests only reach the server through the
+    // pinned address
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment {
+            server_url: format!("http://{host}"),
+        },
+    )
+    .resolve("api.safaricom.invalid", *server.address())
+    .build()
+    .unwrap();
+
+    let response = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "0");
+}
+
+#[tokio::test]
+async fn requests_are_sent_through_the_proxy() {
+    use mpesa::{Mpesa, Proxy};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The `.invalid` top level domain never resolves, requests only reach the server through the
+    // proxy, played by the server itself
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment {
+            server_url: "http://api.safaricom.invalid".to_owned(),
+        },
+    )
+    .proxy(Proxy::http(server.uri()).unwrap())
+    .build()
+    .unwrap();
+
+    let response = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "0");
+}
+
+#[tokio::test]
+#[cfg(feature = "socks")]
+async fn requests_are_sent_through_a_socks5_proxy() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use mpesa::{Mpesa, Proxy};
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    /// Accepts SOCKS5 connections without authentication, connecting each to `upstream` whatever
+    /// the address asked for, and records the hostnames asked for
+    fn socks5_proxy(upstream: std::net::SocketAddr) -> (String, std::sync::mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (hosts, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let mut client = client.unwrap();
+                let mut greeting = [0; 2];
+                client.read_exact(&mut greeting).unwrap();
+                let mut methods = vec![0; greeting[1] as usize];
+                client.read_exact(&mut methods).unwrap();
+                client.write_all(&[5, 0]).unwrap();
+
+                // VER CMD RSV ATYP, a domain name prefixed with its length, and the port
+                let mut request = [0; 5];
+                client.read_exact(&mut request).unwrap();
+                assert_eq!(request[3], 3, "the hostname is resolved by the proxy");
+                let mut host = vec![0; request[4] as usize + 2];
+                client.read_exact(&mut host).unwrap();
+                host.truncate(host.len() - 2);
+                hosts.send(String::from_utf8(host).unwrap()).unwrap();
+                client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+                let server = TcpStream::connect(upstream).unwrap();
+                let (mut client_reader, mut server_writer) =
+                    (client.try_clone().unwrap(), server.try_clone().unwrap());
+                let (mut server_reader, mut client_writer) = (server, client);
+                std::thread::spawn(move || {
+                    std::io::copy(&mut client_reader, &mut server_writer).ok()
+                });
+                std::thread::spawn(move || {
+                    std::io::copy(&mut server_reader, &mut client_writer).ok()
+                });
+            }
+        });
+        (format!("socks5h://{address}"), received)
+    }
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .and(header("host", "api.safaricom.invalid"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorConversationID": "29464-48063588-1",
+            "ConversationID": "AG_20230206_201056794190723278ff",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let (proxy, hosts) = socks5_proxy(*server.address());
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment {
+            server_url: "http://api.safaricom.invalid".to_owned(),
+        },
+    )
+    .proxy(Proxy::all(proxy).unwrap())
+    .build()
+    .unwrap();
+
+    let response = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.response_code, "0");
+    assert_eq!(hosts.recv().unwrap(), "api.safaricom.invalid");
+}
+
+#[tokio::test]
+async fn preflight_reports_every_check() {
+    use mpesa::{Mpesa, PreflightCheck};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The `.invalid` top level domain never resolves
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .initiator("ops_1", "ops_1_password", 1)
+    .default_result_url("https://pay.example.invalid/mpesa/result")
+    .build()
+    .unwrap();
+
+    let preflight = client.preflight().await;
+    let checks: Vec<_> = preflight
+        .results
+        .iter()
+        .map(|result| (result.check.clone(), result.result.is_ok()))
+        .collect();
+    assert_eq!(
+        checks,
+        [
+            (PreflightCheck::Certificate, true),
+            (PreflightCheck::InitiatorPassword(None), true),
+            (
+                PreflightCheck::InitiatorPassword(Some("ops_1".to_owned())),
+                true
+            ),
+            (PreflightCheck::Authentication(0), true),
+            (PreflightCheck::Dns("127.0.0.1".to_owned()), true),
+            (PreflightCheck::Dns("pay.example.invalid".to_owned()), false),
+        ]
+    );
+    assert!(!preflight.is_ok());
+    assert!(preflight
+        .to_string()
+        .contains("FAIL dns of pay.example.invalid"));
+}
+
+#[tokio::test]
+async fn requests_with_the_same_idempotency_key_are_sent_once() {
+    use mpesa::idempotency::MemoryIdempotencyStore;
+    use mpesa::{Mpesa, MpesaError};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(service_unavailable())
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ConversationID": "AG_20191219_00005797af5d7d75f652",
+            "OriginatorConversationID": "16740-34861180-1",
+            "ResponseCode": "0",
+            "ResponseDescription": "Accept the service request successfully."
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .idempotency_store(MemoryIdempotencyStore::default())
+    .build()
+    .unwrap();
+
+    let send = |key: &str| {
+        let client = client.idempotent(key);
+        async move {
+            client
+                .b2c("testapi496")
+                .party_a("600496")
+                .party_b("254708374149")
+                .result_url("https://testdomain.com/ok")
+                .timeout_url("https://testdomain.com/err")
+                .amount(1000)
+                .send()
+                .await
+        }
+    };
+
+    // A failed request does not keep its key, so it can be sent again
+    assert!(matches!(
+        send("order-1029").await,
+        Err(MpesaError::Service(_))
+    ));
+    let response = send("order-1029").await.unwrap();
+    assert_eq!(response.conversation_id, "AG_20191219_00005797af5d7d75f652");
+    let resent = send("order-1029").await.unwrap();
+    assert_eq!(resent.conversation_id, response.conversation_id);
+}
+
+#[tokio::test]
+async fn idempotent_duplicates_return_the_original_conversation_id() {
+    use mpesa::idempotency::MemoryIdempotencyStore;
+    use mpesa::Mpesa;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/b2c/v1/paymentrequest"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "requestId": "11728-2929992-1",
+            "errorCode": "15",
+            "errorMessage": "Duplicate detected",
+            "conversationId": "AG_20191219_00005797af5d7d75f652"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .idempotency_store(MemoryIdempotencyStore::default())
+    .build()
+    .unwrap();
+
+    let send = || {
+        let client = client.idempotent("order-1029");
+        async move {
+            client
+                .b2c("testapi496")
+                .party_a("600496")
+                .party_b("254708374149")
+                .result_url("https://testdomain.com/ok")
+                .timeout_url("https://testdomain.com/err")
+                .amount(1000)
+                .send()
+                .await
+        }
+    };
+
+    // The first send was accepted but its response lost, so the API reports the resend
+    let response = send().await.unwrap();
+    assert_eq!(response.conversation_id, "AG_20191219_00005797af5d7d75f652");
+    assert_eq!(response.response_code, "0");
+    let resent = send().await.unwrap();
+    assert_eq!(resent.conversation_id, response.conversation_id);
+}
+
+#[tokio::test]
+async fn send_with_meta_returns_the_http_metadata_of_the_response() {
+    use mpesa::idempotency::MemoryIdempotencyStore;
+    use mpesa::Mpesa;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("x-correlation-id", "8d1e6f3a-2c1f-4f0e-9b7a-5e3c1d2a4b6f")
+                .set_body_json(json!({
+                    "OriginatorCoversationID": "29464-48063588-1",
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Accept the service request successfully."
+                })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .idempotency_store(MemoryIdempotencyStore::default())
+    .build()
+    .unwrap();
+
+    let send = || {
+        let client = client.idempotent("order-1029");
+        async move {
+            client
+                .c2b_simulate()
+                .short_code("600496")
+                .msisdn("254708374149")
+                .amount(1000)
+                .bill_ref_number("A-1029")
+                .send_with_meta()
+                .await
+                .unwrap()
+        }
+    };
+
+    let sent = send().await;
+    assert_eq!(sent.response.response_code, "0");
+    let meta = sent.meta.unwrap();
+    assert_eq!(meta.status.as_u16(), 200);
+    assert_eq!(
+        meta.request_id(),
+        Some("8d1e6f3a-2c1f-4f0e-9b7a-5e3c1d2a4b6f")
+    );
+    assert!(meta.latency > Duration::ZERO);
+    assert_eq!(sent.client_request_id.len(), 36);
+
+    // Responses kept by the idempotency store were not received from the API
+    let resent = send().await;
+    assert_eq!(resent.response.response_code, "0");
+    assert!(resent.meta.is_none());
+    assert_ne!(resent.client_request_id, sent.client_request_id);
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_requests_in_flight() {
+    use mpesa::MpesaError;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(200))
+                .set_body_json(json!({
+                    "OriginatorCoversationID": "29464-48063588-1",
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Accept the service request successfully."
+                })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let send = || {
+        client
+            .c2b_simulate()
+            .short_code("600496")
+            .msisdn("254708374149")
+            .amount(1000)
+            .bill_ref_number("A-1029")
+            .send()
+    };
+
+    let in_flight = send();
+    let shutdown = async {
+        while client.in_flight_requests() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        client.shutdown(Duration::from_secs(5)).await
+    };
+    let (sent, shutdown) = tokio::join!(in_flight, shutdown);
+
+    assert_eq!(sent.unwrap().response_code, "0");
+    assert!(shutdown.is_ok());
+    assert!(client.is_shut_down());
+    assert_eq!(client.in_flight_requests(), 0);
+    assert!(matches!(send().await, Err(MpesaError::ShutDown)));
+}
+
+#[tokio::test]
+async fn cancelled_requests_are_aborted_or_completed_by_service() {
+    use mpesa::{CancellationToken, Mpesa, MpesaError, OnCancel, Service};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(200))
+                .set_body_json(json!({
+                    "OriginatorCoversationID": "29464-48063588-1",
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Accept the service request successfully."
+                })),
+        )
+        .mount(&server)
+        .await;
+    let server = &server;
+    let build = |on_cancel| async move {
+        Mpesa::builder(
+            dotenvy::var("CONSUMER_KEY").unwrap(),
+            dotenvy::var("CONSUMER_SECRET").unwrap(),
+            TestEnvironment::new(server).await,
+        )
+        .on_cancel(Service::C2bSimulate, on_cancel)
+        .build()
+        .unwrap()
+    };
+    let simulate = |client: Mpesa| async move {
+        client
+            .c2b_simulate()
+            .short_code("600496")
+            .msisdn("254708374149")
+            .amount(1000)
+            .bill_ref_number("A-1029")
+            .send()
+            .await
+    };
+    let cancel_soon = |token: CancellationToken| async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        token.cancel();
+    };
+
+    let client = build(OnCancel::Abort).await;
+    let token = CancellationToken::new();
+    let (sent, ()) = tokio::join!(
+        simulate(client.with_cancellation(token.clone())),
+        cancel_soon(token.clone())
+    );
+    assert!(matches!(sent, Err(MpesaError::Cancelled)));
+    assert_eq!(client.in_flight_requests(), 0);
+    // Requests sent with a cancelled token are not sent at all
+    let sent = simulate(client.with_cancellation(token)).await;
+    assert!(matches!(sent, Err(MpesaError::Cancelled)));
+
+    let client = build(OnCancel::Complete).await;
+    let token = CancellationToken::new();
+    let (sent, ()) = tokio::join!(
+        simulate(client.with_cancellation(token.clone())),
+        cancel_soon(token)
+    );
+    assert!(matches!(sent, Err(MpesaError::Cancelled)));
+    assert_eq!(client.in_flight_requests(), 1);
+    assert!(client.shutdown(Duration::from_secs(5)).await.is_ok());
+    assert_eq!(client.in_flight_requests(), 0);
+
+    let simulated = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|req| req.url.path() == "/mpesa/c2b/v1/simulate")
+        .count();
+    assert_eq!(simulated, 2);
+}
+
+#[tokio::test]
+async fn waiting_requests_are_sent_by_priority() {
+    use mpesa::{Mpesa, Priority};
+    use serde_json::{json, Value};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "OriginatorCoversationID": "29464-48063588-1",
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Accept the service request successfully."
+                }))
+                .set_delay(Duration::from_millis(100)),
+        )
+        .expect(3)
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .max_concurrent_requests(1)
+    .build()
+    .unwrap();
+    let simulate = |client: Mpesa, bill_ref_number: &'static str| async move {
+        client
+            .c2b_simulate()
+            .short_code("600496")
+            .msisdn("254708374149")
+            .amount(1000)
+            .bill_ref_number(bill_ref_number)
+            .send()
+            .await
+    };
+
+    let (first, low, high) = tokio::join!(
+        simulate(client.clone(), "first"),
+        simulate(client.with_priority(Priority::Low), "low"),
+        simulate(client.with_priority(Priority::High), "high"),
+    );
+    assert!(first.is_ok() && low.is_ok() && high.is_ok());
+
+    let sent = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|req| req.url.path() == "/mpesa/c2b/v1/simulate")
+        .map(|req| req.body_json::<Value>().unwrap()["BillRefNumber"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(sent, ["first", "high", "low"]);
+}
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn chaos_latency_counts_toward_deadlines() {
+    use std::time::Instant;
+
+    use mpesa::test_utils::Chaos;
+    use mpesa::{Mpesa, MpesaError};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-1",
+            "ResponseCode": "0",
+            "ResponseDescription": "Accept the service request successfully."
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .chaos(Chaos::new().latency(Duration::from_millis(100)))
+    .build()
+    .unwrap();
+    let simulate = || {
+        client
+            .c2b_simulate()
+            .short_code("600496")
+            .msisdn("254708374149")
+            .amount(1000)
+            .bill_ref_number("A-1029")
+    };
+
+    let started = Instant::now();
+    simulate().send().await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    let deadline = Instant::now() + Duration::from_millis(50);
+    assert!(matches!(
+        mpesa::with_deadline(deadline, simulate().send()).await,
+        Err(MpesaError::DeadlineExceeded)
+    ));
+}
+
+#[tokio::test]
+async fn requests_failing_during_maintenance_are_not_retried() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use mpesa::MpesaError;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    let client = retrying_client(&server, None).await;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    Mock::given(method("GET"))
+        .and(path("/status.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "maintenance": [{
+                "start": now.as_secs() - 60,
+                "end": now.as_secs() + 3600,
+                "description": "M-Pesa system upgrade"
+            }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/accountbalance/v1/query"))
+        .respond_with(service_unavailable())
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(!client.is_maintenance_window());
+    client
+        .fetch_maintenance_schedule(&format!("{}/status.json", server.uri()))
+        .await
+        .unwrap();
+    assert!(client.is_maintenance_window());
+
+    let result = client
+        .account_balance("testapi496")
+        .result_url("https://testdomain.com/ok")
+        .timeout_url("https://testdomain.com/err")
+        .party_a("600496")
+        .send()
+        .await;
+    match result {
+        Err(MpesaError::Maintenance(window)) => {
+            assert_eq!(window.description.as_deref(), Some("M-Pesa system upgrade"));
+        }
+        other => panic!("expected a maintenance error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+#[cfg(feature = "demo")]
+async fn sandbox_demo_records_every_step() {
+    use mpesa::demo::{DemoStepKind, SandboxDemo};
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    let client = retrying_client(&server, None).await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/registerurl"))
+        .and(body_partial_json(json!({
+            "ValidationURL": "https://pay.example.com/mpesa/c2b/validation"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-1",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .respond_with(service_unavailable())
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpushquery/v1/query"))
+        .and(body_partial_json(json!({
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ResponseCode": "0",
+            "ResponseDescription": "The service request has been accepted successsfully",
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResultCode": 1032,
+            "ResultDesc": "Request cancelled by user"
+        })))
+        .mount(&server)
+        .await;
+
+    let transcript = SandboxDemo::new(&client, "https://pay.example.com/mpesa/")
+        .query_delay(Duration::ZERO)
+        .run()
+        .await;
+
+    let kinds: Vec<_> = transcript.steps.iter().map(|step| step.kind()).collect();
+    assert_eq!(
+        kinds,
+        [
+            DemoStepKind::RegisterUrls,
+            DemoStepKind::SimulateC2b,
+            DemoStepKind::StkPush,
+            DemoStepKind::StkQuery
+        ]
+    );
+    assert!(!transcript.is_success());
+    let failures: Vec<_> = transcript.failures().map(|step| step.kind()).collect();
+    assert_eq!(failures, [DemoStepKind::SimulateC2b]);
+    assert_eq!(transcript.steps[3].summary(), "Request cancelled by user");
+}
+
+#[tokio::test]
+async fn shadow_mode_mirrors_requests_and_reports_differences() {
+    use std::time::Duration;
+
+    use mpesa::shadow::{Difference, Shadow};
+    use mpesa::Mpesa;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::helpers::TestEnvironment;
+
+    let server = MockServer::start().await;
+    let shadow_server = MockServer::start().await;
+    let (reports, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let shadow = Shadow::new(retrying_client(&shadow_server, None).await, move |report| {
+        let _ = reports.send(report.differences.clone());
+    });
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .shadow(shadow)
+    .build()
+    .unwrap();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .and(body_partial_json(json!({ "Amount": 1000.0 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-1",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/c2b/v1/simulate"))
+        .and(body_partial_json(json!({ "Amount": 0 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "OriginatorCoversationID": "29464-48063588-2",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": 0
+        })))
+        .expect(1)
+        .mount(&shadow_server)
+        .await;
+
+    let response = client
+        .c2b_simulate()
+        .amount(1000)
+        .bill_ref_number("2")
+        .msisdn("254700000000")
+        .short_code("600496")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.originator_conversation_id, "29464-48063588-1");
+    // the shadow request is reported by a task of its own
+    let differences = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .unwrap();
+    assert_eq!(
+        differences,
+        Some(vec![Difference::FieldType {
+            field: "ResponseCode".to_owned(),
+            primary: "string",
+            shadow: "number"
+        }])
+    );
+}
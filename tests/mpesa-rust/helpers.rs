@@ -103,8 +103,6 @@ macro_rules! get_mpesa_client {
 
 #[cfg(test)]
 mod tests {
-    use crate::get_mpesa_client;
-
     #[tokio::test]
     async fn test_client_will_not_authenticate_with_wrong_credentials() {
         let client = get_mpesa_client!(
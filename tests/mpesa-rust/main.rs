@@ -10,6 +10,8 @@ mod bill_manager_test;
 mod c2b_register_test;
 #[cfg(test)]
 mod c2b_simulate_test;
+#[cfg(test)]
+mod client_test;
 
 mod dynamic_qr_tests;
 mod helpers;
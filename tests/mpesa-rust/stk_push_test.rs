@@ -139,3 +139,581 @@ async fn express_request_test_using_struct_initialization() {
         "Success. Request accepted for processing"
     );
 }
+
+#[tokio::test]
+async fn stk_push_retries_once_with_a_new_token_on_invalid_access_token() {
+    let (client, server) = get_mpesa_client!();
+    let sample_response_body = json!({
+        "MerchantRequestID": "16813-1590513-1",
+        "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+        "ResponseDescription": "Accept the service request successfully.",
+        "ResponseCode": "0",
+        "CustomerMessage": "Success. Request accepted for processing"
+    });
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "requestId": "11728-2929992-1",
+            "errorCode": "404.001.03",
+            "errorMessage": "Invalid Access Token"
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(sample_response_body))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let response = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(mpesa::CommandId::BusinessBuyGoods)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.checkout_request_id, "ws_CO_DMZ_12321_23423476");
+}
+
+#[tokio::test]
+async fn stk_push_does_not_retry_more_than_once_on_invalid_access_token() {
+    let (client, server) = get_mpesa_client!();
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "requestId": "11728-2929992-1",
+            "errorCode": "404.001.03",
+            "errorMessage": "Invalid Access Token"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+    let err = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(mpesa::CommandId::BusinessBuyGoods)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .send()
+        .await
+        .unwrap_err();
+
+    match err {
+        mpesa::MpesaError::Service(e) => assert_eq!(e.error_code, "404.001.03"),
+        e => panic!("expected a service error, got {e:?}"),
+    }
+}
+
+#[tokio::test]
+async fn stk_push_normalizes_phone_numbers_when_enabled() {
+    use mpesa::Mpesa;
+    use wiremock::matchers::{body_partial_json, query_param};
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .normalize_msisdn(true)
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .and(query_param("grant_type", "client_credentials"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .and(body_partial_json(json!({
+            "PartyA": "254708374149",
+            "PhoneNumber": "254708374149"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(CommandId::BusinessBuyGoods)
+        .party_a("0708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("+254708374149")
+        .amount(500)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.merchant_request_id, "16813-1590513-1");
+}
+
+#[tokio::test]
+async fn stk_push_validate_all_reports_every_problem() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let errors = client
+        .express_request()
+        .transaction_type(CommandId::BusinessPayment)
+        .party_b("174379")
+        .phone_number("not a phone number")
+        .validate_all()
+        .unwrap_err();
+
+    let messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        messages,
+        [
+            "An error has occurred while building the request: Field [business_short_code] is required",
+            "Invalid transaction type. Expected BusinessBuyGoods or CustomerPayBillOnline",
+            "An error has occurred while building the request: Field [amount] is required",
+            "An error has occurred while building the request: Field [party_a] is required",
+            "Invalid phone number, must be in the format 2547XXXXXXXX, 07XXXXXXXX, 011XXXXXXX",
+            "An error has occurred while building the request: Field [callback_url] is required",
+            "An error has occurred while building the request: Field [account_ref] is required",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn stk_push_fails_with_sandbox_test_number_when_rejected() {
+    use mpesa::{Mpesa, MpesaError};
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        "consumer_key",
+        "consumer_secret",
+        TestEnvironment::new(&server).await,
+    )
+    .reject_sandbox_test_numbers(true)
+    .build()
+    .unwrap();
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let err = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(CommandId::BusinessBuyGoods)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .send()
+        .await
+        .unwrap_err();
+
+    let MpesaError::Message(msg) = err else {
+        panic!("Expected MpesaError::Message, but found {}", err);
+    };
+    assert_eq!(
+        msg,
+        "Request contains a Safaricom sandbox test phone number or shortcode"
+    );
+}
+
+#[tokio::test]
+async fn stk_push_to_curl_hides_the_password() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let curl = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(mpesa::CommandId::BusinessBuyGoods)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .pass_key("test")
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .to_curl()
+        .unwrap();
+
+    assert!(curl.contains(r#""Password":"<PASSWORD>""#));
+    assert!(curl.contains(r#""PhoneNumber":"254708374149""#));
+}
+
+#[tokio::test]
+async fn stk_push_uses_the_configured_api_version() {
+    use mpesa::paths::{self, Endpoint};
+    use mpesa::{Mpesa, Service};
+    use wiremock::matchers::query_param;
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .api_version(Service::ExpressRequest, 3)
+    .build()
+    .unwrap();
+    assert_eq!(client.api_version(Service::ExpressRequest), 3);
+    assert_eq!(client.api_version(Service::B2c), 1);
+    assert_eq!(
+        client.endpoint_path(Endpoint::ExpressQuery),
+        "mpesa/stkpushquery/v3/query"
+    );
+    assert_eq!(client.endpoint_path(Endpoint::B2c), paths::B2C);
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .and(query_param("grant_type", "client_credentials"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v3/processrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(CommandId::BusinessBuyGoods)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.merchant_request_id, "16813-1590513-1");
+}
+
+#[tokio::test]
+async fn stk_push_is_limited_by_the_shortcode_quota() {
+    use mpesa::{Mpesa, MpesaError, Quota};
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .shortcode_quota("174379", Quota::per_minute(1))
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let request = |short_code: &'static str| {
+        client
+            .express_request()
+            .business_short_code(short_code)
+            .transaction_type(CommandId::BusinessBuyGoods)
+            .party_a("254708374149")
+            .party_b(short_code)
+            .account_ref("test")
+            .phone_number("254708374149")
+            .amount(500)
+            .try_callback_url("https://test.example.com/api")
+            .unwrap()
+            .build()
+            .unwrap()
+    };
+
+    assert!(request("174379").send().await.is_ok());
+    let error = request("174379").send().await.unwrap_err();
+    assert!(matches!(error, MpesaError::QuotaExceeded(retry_after) if retry_after.as_secs() <= 60));
+    // Other shortcodes are not limited
+    assert!(request("600000").send().await.is_ok());
+}
+
+#[tokio::test]
+async fn stk_push_rejects_callback_urls_safaricom_cannot_reach() {
+    use mpesa::{Mpesa, MpesaError};
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let build = |client: &Mpesa, callback_url: &str| {
+        client
+            .express_request()
+            .business_short_code("174379")
+            .transaction_type(CommandId::BusinessBuyGoods)
+            .party_a("254708374149")
+            .party_b("174379")
+            .account_ref("test")
+            .phone_number("254708374149")
+            .amount(500)
+            .try_callback_url(callback_url)
+            .unwrap()
+            .build()
+            .map(|_| ())
+    };
+
+    for callback_url in [
+        "http://test.example.com/api",
+        "https://localhost:8080/api",
+        "https://192.168.1.10/api",
+    ] {
+        match build(&client, callback_url) {
+            Err(MpesaError::Message(msg)) => assert!(msg.starts_with("Callback urls must")),
+            other => panic!("expected {callback_url} to be rejected, got {other:?}"),
+        }
+    }
+
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        "consumer_key",
+        "consumer_secret",
+        TestEnvironment::new(&server).await,
+    )
+    .allow_private_callback_urls(true)
+    .build()
+    .unwrap();
+    assert!(build(&client, "http://localhost:8080/api").is_ok());
+}
+
+#[tokio::test]
+async fn stk_push_amount_in_cents_must_be_whole_shillings() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let mut builder = client.express_request();
+    assert!(builder.amount_cents(150_000).is_ok());
+    let err = builder.amount_cents(150_050).map(|_| ()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "M-Pesa Express amounts must be whole shillings"
+    );
+}
+
+#[tokio::test]
+async fn stk_push_template_stamps_requests_per_customer() {
+    use wiremock::matchers::body_partial_json;
+
+    let (client, server) = get_mpesa_client!();
+    let template = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(CommandId::CustomerPayBillOnline)
+        .party_a("254708374149")
+        .party_b("174379")
+        .account_ref("template")
+        .phone_number("254708374149")
+        .amount(1)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap()
+        .into_template();
+
+    for (phone_number, amount, account_ref) in [
+        ("254708374150", 250, "A-1029"),
+        ("254708374151", 900, "A-1030"),
+    ] {
+        Mock::given(method("POST"))
+            .and(path("/mpesa/stkpush/v1/processrequest"))
+            .and(body_partial_json(json!({
+                "BusinessShortCode": "174379",
+                "PartyA": phone_number,
+                "PhoneNumber": phone_number,
+                "Amount": amount,
+                "AccountReference": account_ref
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "MerchantRequestID": "16813-1590513-1",
+                "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+                "ResponseDescription": "Accept the service request successfully.",
+                "ResponseCode": "0",
+                "CustomerMessage": "Success. Request accepted for processing"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let account_ref = account_ref.to_owned();
+        template
+            .stamp(phone_number, amount, &account_ref)
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+    }
+
+    assert!(template.stamp("12345", 250, "A-1031").is_err());
+}
+
+#[tokio::test]
+async fn stk_push_is_paid_from_a_phone_number_to_a_shortcode() {
+    let (client, _server) = get_mpesa_client!(expected_auth_requests = 0);
+    let err = client
+        .express_request()
+        .business_short_code("174379")
+        .transaction_type(CommandId::CustomerPayBillOnline)
+        .party_a("174379")
+        .party_b("254708374149")
+        .account_ref("test")
+        .phone_number("254708374149")
+        .amount(500)
+        .try_callback_url("https://test.example.com/api")
+        .unwrap()
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "CustomerPayBillOnline requires PartyA to be an MSISDN"
+    );
+}
+
+#[tokio::test]
+async fn stk_push_guard_refuses_identical_pushes_within_its_window() {
+    use std::time::Duration;
+
+    use mpesa::storage::MemoryStorage;
+    use mpesa::{Mpesa, MpesaError};
+    use wiremock::MockServer;
+
+    use crate::helpers::TestEnvironment;
+
+    dotenvy::dotenv().ok();
+    let server = MockServer::start().await;
+    let client = Mpesa::builder(
+        dotenvy::var("CONSUMER_KEY").unwrap(),
+        dotenvy::var("CONSUMER_SECRET").unwrap(),
+        TestEnvironment::new(&server).await,
+    )
+    .stk_push_guard(MemoryStorage::default(), Duration::from_secs(60))
+    .build()
+    .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/oauth/v1/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "dummy_access_token",
+            "expires_in": "3600"
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/mpesa/stkpush/v1/processrequest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "MerchantRequestID": "16813-1590513-1",
+            "CheckoutRequestID": "ws_CO_DMZ_12321_23423476",
+            "ResponseDescription": "Accept the service request successfully.",
+            "ResponseCode": "0",
+            "CustomerMessage": "Success. Request accepted for processing"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let request = |amount: u32| {
+        client
+            .express_request()
+            .business_short_code("174379")
+            .transaction_type(CommandId::CustomerPayBillOnline)
+            .party_a("254708374149")
+            .party_b("174379")
+            .account_ref("order-1042")
+            .phone_number("254708374149")
+            .amount(amount)
+            .try_callback_url("https://test.example.com/api")
+            .unwrap()
+            .build()
+            .unwrap()
+    };
+
+    assert!(request(500).send().await.is_ok());
+    let error = request(500).send().await.unwrap_err();
+    assert!(matches!(
+        error,
+        MpesaError::DuplicateStkPush { checkout_request_id: Some(id) } if id == "ws_CO_DMZ_12321_23423476"
+    ));
+    // A push for another amount is not a duplicate
+    assert!(request(750).send().await.is_ok());
+}